@@ -37,6 +37,7 @@
 use flate2::{read::GzDecoder, Compression, GzBuilder};
 use md5::{Digest, Md5};
 use std::{
+    collections::HashMap,
     convert::TryInto,
     ffi::CStr,
     fmt::{Display, Error as FmtError, Formatter},
@@ -52,7 +53,7 @@ use tectonic_io_base::{
     digest::DigestData, normalize_tex_path, InputFeatures, InputHandle, IoProvider, OpenResult,
     OutputHandle,
 };
-use tectonic_status_base::{tt_error, tt_warning, MessageKind, StatusBackend};
+use tectonic_status_base::{tt_error, tt_warning, MessageKind, SourceLocation, StatusBackend};
 
 /// Possible failures for "system request" calls to the driver.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -331,6 +332,11 @@ pub struct CoreBridgeState<'a> {
     /// recent input didn't have a filesystem path (it came from a bundle or
     /// memory or something else).
     latest_input_path: Option<PathBuf>,
+
+    /// The filesystem paths of the inputs opened so far, by the names that the
+    /// engine asked for and the names that they were found under, so that
+    /// diagnostics can say where their files really are.
+    input_paths: HashMap<String, PathBuf>,
 }
 
 impl<'a> CoreBridgeState<'a> {
@@ -347,6 +353,7 @@ impl<'a> CoreBridgeState<'a> {
             output_handles: Vec::new(),
             input_handles: Vec::new(),
             latest_input_path: None,
+            input_paths: HashMap::new(),
             fs_emulation_settings,
         }
     }
@@ -582,6 +589,11 @@ impl<'a> CoreBridgeState<'a> {
             }
         };
 
+        if let Some(p) = &path {
+            self.input_paths.insert(name.to_string(), p.clone());
+            self.input_paths.insert(ih.name().to_owned(), p.clone());
+        }
+
         self.input_handles.push(Box::new(ih));
         self.latest_input_path = path;
         &mut **self.input_handles.last_mut().unwrap()
@@ -602,6 +614,10 @@ impl<'a> CoreBridgeState<'a> {
             }
         };
 
+        if let Some(p) = &path {
+            self.input_paths.insert(ih.name().to_owned(), p.clone());
+        }
+
         self.input_handles.push(Box::new(ih));
         self.latest_input_path = path;
         &mut **self.input_handles.last_mut().unwrap()
@@ -792,6 +808,12 @@ impl SecuritySettings {
         !self.disable_insecures
     }
 
+    /// Query whether document build hooks, arbitrary commands run before and
+    /// after a build, are allowed to be executed.
+    pub fn allow_build_hooks(&self) -> bool {
+        !self.disable_insecures
+    }
+
     /// Query whether we're allowed to specify extra paths to read files from.
     pub fn allow_extra_search_paths(&self) -> bool {
         !self.disable_insecures
@@ -1190,6 +1212,8 @@ pub extern "C" fn ttbc_input_close(
 pub struct Diagnostic {
     message: String,
     kind: MessageKind,
+    location: Option<SourceLocation>,
+    context: String,
 }
 
 /// Create a new diagnostic that will be reported as a warning.
@@ -1198,6 +1222,8 @@ pub extern "C" fn ttbc_diag_begin_warning() -> *mut Diagnostic {
     let warning = Box::new(Diagnostic {
         message: String::new(),
         kind: MessageKind::Warning,
+        location: None,
+        context: String::new(),
     });
     Box::into_raw(warning)
 }
@@ -1208,10 +1234,30 @@ pub extern "C" fn ttbc_diag_begin_error() -> *mut Diagnostic {
     let warning = Box::new(Diagnostic {
         message: String::new(),
         kind: MessageKind::Error,
+        location: None,
+        context: String::new(),
     });
     Box::into_raw(warning)
 }
 
+/// Set the source file location that a diagnostic is about.
+///
+/// If *line* is zero or negative, the line number is unknown.
+///
+/// # Safety
+///
+/// This function is unsafe because it accepts a raw C string.
+#[no_mangle]
+pub unsafe extern "C" fn ttbc_diag_set_location(
+    diag: &mut Diagnostic,
+    file: *const libc::c_char,
+    line: libc::c_int,
+) {
+    let rfile = CStr::from_ptr(file);
+    let line = if line > 0 { Some(line as u32) } else { None };
+    diag.location = Some(SourceLocation::new(rfile.to_string_lossy(), line));
+}
+
 /// Append text to a diagnostic.
 ///
 /// # Safety
@@ -1223,6 +1269,23 @@ pub unsafe extern "C" fn ttbc_diag_append(diag: &mut Diagnostic, text: *const li
     diag.message.push_str(&rtext.to_string_lossy());
 }
 
+/// Append text to the TeX context of a diagnostic.
+///
+/// The context is only reported if the diagnostic has a source location; see
+/// [`SourceLocation::context`].
+///
+/// # Safety
+///
+/// This function is unsafe because it accepts a raw C string.
+#[no_mangle]
+pub unsafe extern "C" fn ttbc_diag_append_context(
+    diag: &mut Diagnostic,
+    text: *const libc::c_char,
+) {
+    let rtext = CStr::from_ptr(text);
+    diag.context.push_str(&rtext.to_string_lossy());
+}
+
 /// "Finish" a diagnostic: report it to the driver and free the diagnostic object.
 ///
 /// # Safety
@@ -1231,9 +1294,29 @@ pub unsafe extern "C" fn ttbc_diag_append(diag: &mut Diagnostic, text: *const li
 #[no_mangle]
 pub unsafe extern "C" fn ttbc_diag_finish(es: &mut CoreBridgeState, diag: *mut Diagnostic) {
     // By creating the box, we will free the diagnostic when this function exits.
-    let rdiag = Box::from_raw(diag);
-    es.status
-        .report(rdiag.kind, format_args!("{}", rdiag.message), None);
+    let mut rdiag = Box::from_raw(diag);
+
+    if let Some(loc) = &mut rdiag.location {
+        loc.context = rdiag
+            .context
+            .lines()
+            .map(|l| l.trim_end().to_owned())
+            .filter(|l| !l.is_empty())
+            .collect();
+        loc.path = es
+            .input_paths
+            .get(normalize_tex_path(&loc.file).as_ref())
+            .cloned();
+    }
+
+    match &rdiag.location {
+        Some(loc) => es
+            .status
+            .report_at(rdiag.kind, loc, format_args!("{}", rdiag.message), None),
+        None => es
+            .status
+            .report(rdiag.kind, format_args!("{}", rdiag.message), None),
+    }
 }
 
 /// Run a shell command
@@ -311,6 +311,17 @@ ttbc_diagnostic_t *ttbc_diag_begin_warning(void);
  */
 ttbc_diagnostic_t *ttbc_diag_begin_error(void);
 
+/**
+ * Set the source file location that a diagnostic is about.
+ *
+ * If *line* is zero or negative, the line number is unknown.
+ *
+ * # Safety
+ *
+ * This function is unsafe because it accepts a raw C string.
+ */
+void ttbc_diag_set_location(ttbc_diagnostic_t *diag, const char *file, int line);
+
 /**
  * Append text to a diagnostic.
  *
@@ -320,6 +331,18 @@ ttbc_diagnostic_t *ttbc_diag_begin_error(void);
  */
 void ttbc_diag_append(ttbc_diagnostic_t *diag, const char *text);
 
+/**
+ * Append text to the TeX context of a diagnostic.
+ *
+ * The context is only reported if the diagnostic has a source location; see
+ * [`SourceLocation::context`].
+ *
+ * # Safety
+ *
+ * This function is unsafe because it accepts a raw C string.
+ */
+void ttbc_diag_append_context(ttbc_diagnostic_t *diag, const char *text);
+
 /**
  * "Finish" a diagnostic: report it to the driver and free the diagnostic object.
  */
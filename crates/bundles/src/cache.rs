@@ -15,15 +15,29 @@
 //! which you’ll pass to [`Cache::open()`]. When using this function, you must
 //! explicitly specify the concrete [`CacheBackend`] type that will service
 //! backend requests.
+//!
+//! The cache records when each bundle was last used, so that data belonging to
+//! bundles that are no longer needed can be removed with [`Cache::gc()`]. Every
+//! open [`CachingBundle`] holds a shared lock on a file in the cache root, and
+//! garbage collection only proceeds if it can take an exclusive lock on that
+//! file, so that it never pulls files out from under another process.
+//!
+//! Several processes can safely share a cache. Files are written under
+//! temporary names and then renamed into place, so that other processes never
+//! see partial data. While a process pulls a bundle’s index or downloads files
+//! from it, it holds an exclusive lock on a file in the `locks` subdirectory of
+//! the cache; other processes wait for the lock and then use whatever was
+//! fetched in the meantime, rather than fetching it again.
 
 use fs2::FileExt;
 use std::{
-    collections::HashMap,
-    env,
+    collections::{HashMap, HashSet},
+    env, fmt,
     fs::{self, File},
     io::{BufRead, BufReader, Error as IoError, ErrorKind as IoErrorKind, Read, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    time::{Duration, SystemTime},
 };
 use tectonic_errors::prelude::*;
 use tectonic_io_base::{
@@ -33,7 +47,7 @@ use tectonic_io_base::{
 };
 use tectonic_status_base::{tt_warning, StatusBackend};
 
-use crate::Bundle;
+use crate::{mirrors::MirrorList, Bundle};
 
 /// A cache of data from one or more bundles using the local filesystem.
 #[derive(Debug)]
@@ -89,10 +103,537 @@ impl Cache {
         only_cached: bool,
         status: &mut dyn StatusBackend,
     ) -> Result<CachingBundle<CB>> {
-        CachingBundle::new(url, only_cached, status, &self.root)
+        self.open_with_mirrors(url, &[], only_cached, status)
+    }
+
+    /// Open a bundle through the cache layer, with mirrors.
+    ///
+    /// This is like [`Self::open`], but the bundle at *url* is also served by
+    /// the *mirrors*, which are tried in turn if it can't be reached. The
+    /// bundle is identified in the cache by *url*, so its cached files are
+    /// reused no matter which mirror they came from. Mirrors that fail are
+    /// remembered, and tried last for a while afterwards.
+    pub fn open_with_mirrors<CB: CacheBackend>(
+        &mut self,
+        url: &str,
+        mirrors: &[String],
+        only_cached: bool,
+        status: &mut dyn StatusBackend,
+    ) -> Result<CachingBundle<CB>> {
+        CachingBundle::new(url, mirrors, only_cached, status, &self.root)
+    }
+
+    /// Open a bundle through the cache layer, checking for updated content.
+    ///
+    /// Unlike [`Self::open`], this forgets the cached information about what
+    /// *url* resolves to, so that the backend is always contacted to resolve
+    /// the URL and fetch its index afresh. If the URL now points to different
+    /// content, the returned bundle reflects that. Files that were cached
+    /// previously are still reused where possible.
+    pub fn open_refreshed<CB: CacheBackend>(
+        &mut self,
+        url: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<CachingBundle<CB>> {
+        self.open_refreshed_with_mirrors(url, &[], status)
+    }
+
+    /// Open a bundle through the cache layer with mirrors, checking for updated
+    /// content.
+    ///
+    /// This combines [`Self::open_refreshed`] and [`Self::open_with_mirrors`].
+    pub fn open_refreshed_with_mirrors<CB: CacheBackend>(
+        &mut self,
+        url: &str,
+        mirrors: &[String],
+        status: &mut dyn StatusBackend,
+    ) -> Result<CachingBundle<CB>> {
+        let digest_path = url_digest_path(&self.root, url)?;
+
+        match fs::remove_file(&digest_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == IoErrorKind::NotFound => {}
+            Err(e) => bail!("failed to remove `{}`: {}", digest_path.display(), e),
+        }
+
+        CachingBundle::new(url, mirrors, false, status, &self.root)
+    }
+
+    /// Seed the cache with files from an exported bundle archive.
+    ///
+    /// The *archive* is read with [`crate::export::read_archive`], so it may be
+    /// a ZIP or tar file or a directory. It must contain a `SHA256SUM` file
+    /// giving the digest of the bundle that its files came from, as written by
+    /// [`crate::export::export_bundle`]. The files are added to the cache for
+    /// that bundle, and *url* is recorded as resolving to it.
+    ///
+    /// Bundles opened through a seeded cache serve the imported files, even if
+    /// the backend can’t be reached. The rest of the bundle only becomes
+    /// available once the backend has been contacted successfully.
+    pub fn import_archive<P: AsRef<Path>>(
+        &mut self,
+        url: &str,
+        archive: P,
+        status: &mut dyn StatusBackend,
+    ) -> Result<CacheImport> {
+        let archive = archive.as_ref();
+        let _usage_lock = lock_cache_for_use(&self.root, status);
+        let data_base = ensure_cache_dir(&self.root, "files")?;
+        let manifest_base = ensure_cache_dir(&self.root, "manifests")?;
+        let mut digest = None;
+        let mut files = Vec::new();
+
+        crate::export::read_archive(archive, |name, data| {
+            if name == digest::DIGEST_NAME {
+                let text = String::from_utf8_lossy(&data);
+                digest = Some(atry!(
+                    DigestData::from_str(text.trim());
+                    ["corrupted SHA256 digest data"]
+                ));
+            } else if !name.contains(['\n', '\r']) {
+                let file_digest = save_cached_data(&data_base, &data)?;
+                files.push((name.to_owned(), data.len() as u64, file_digest));
+            }
+
+            Ok(())
+        })?;
+
+        let digest = a_ok_or!(
+            digest;
+            ["`{}` does not contain a SHA256SUM file", archive.display()]
+        );
+
+        let manifest_path = make_txt_path(&manifest_base, &digest.to_string());
+        let mut man = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .read(true)
+            .open(&manifest_path)?;
+        atry!(
+            man.lock_exclusive();
+            ["failed to lock manifest file \"{}\" for writing", manifest_path.display()]
+        );
+
+        let mut known = HashSet::new();
+        let mut text = String::new();
+        man.read_to_string(&mut text)?;
+
+        for line in text.lines() {
+            if let Some(name) = line.rsplitn(3, ' ').nth(2) {
+                known.insert(name.to_owned());
+            }
+        }
+
+        let n_files = files.len();
+        let mut n_new = 0;
+
+        for (name, length, file_digest) in files {
+            if known.insert(name.clone()) {
+                writeln!(man, "{} {} {}", name, length, file_digest.to_string())?;
+                n_new += 1;
+            }
+        }
+
+        let digest_text = digest.to_string();
+        file_create_write(url_digest_path(&self.root, url)?, |f| {
+            writeln!(f, "{}", &digest_text)
+        })?;
+        mark_bundle_used(&self.root, &digest);
+
+        Ok(CacheImport {
+            digest,
+            n_files,
+            n_new,
+        })
+    }
+
+    /// Summarize the contents of this cache.
+    ///
+    /// This lists every bundle that the cache holds data for, along with the
+    /// total number and size of the cached files. Since the cache might be in
+    /// use by other processes, the results are only a snapshot.
+    pub fn summarize(&self) -> Result<CacheSummary> {
+        let state = CacheState::load(&self.root)?;
+
+        let mut bundles: Vec<_> = state
+            .bundles
+            .iter()
+            .map(|(digest, b)| CachedBundle {
+                digest: *digest,
+                resolved_url: fs::read_to_string(make_txt_path(
+                    &self.root.join("redirects"),
+                    &digest.to_string(),
+                ))
+                .ok(),
+                last_used: b.last_used,
+                n_files: b.files.len(),
+                size: b.files.iter().filter_map(|f| state.data.get(f)).sum(),
+            })
+            .collect();
+
+        // Most recently used first.
+        bundles.sort_by_key(|b| std::cmp::Reverse(b.last_used));
+
+        Ok(CacheSummary {
+            bundles,
+            n_data_files: state.data.len(),
+            data_size: state.data.values().sum(),
+        })
+    }
+
+    /// Remove the cached data of bundles that are no longer in use.
+    ///
+    /// Bundles that haven't been used for [`GcOptions::max_age`] are removed.
+    /// Then, if the cached file data take up more than [`GcOptions::max_size`]
+    /// bytes, bundles are removed in order of least recent use until they no
+    /// longer do. Cached files that aren't part of any remaining bundle are
+    /// deleted.
+    ///
+    /// This fails if any other process has a bundle from this cache open.
+    pub fn gc(&mut self, options: &GcOptions, status: &mut dyn StatusBackend) -> Result<GcReport> {
+        let _lock = self.lock_for_gc()?;
+        let state = CacheState::load(&self.root)?;
+        let now = SystemTime::now();
+        let mut doomed = HashSet::new();
+
+        if let Some(max_age) = options.max_age {
+            for (digest, b) in &state.bundles {
+                let age = b
+                    .last_used
+                    .and_then(|t| now.duration_since(t).ok())
+                    .unwrap_or(Duration::MAX);
+
+                if b.last_used.is_none() || age >= max_age {
+                    doomed.insert(*digest);
+                }
+            }
+        }
+
+        if let Some(max_size) = options.max_size {
+            let mut refcounts: HashMap<&str, usize> = HashMap::new();
+
+            for (digest, b) in &state.bundles {
+                if !doomed.contains(digest) {
+                    for f in &b.files {
+                        *refcounts.entry(f.as_str()).or_default() += 1;
+                    }
+                }
+            }
+
+            let mut size: u64 = refcounts.keys().filter_map(|f| state.data.get(*f)).sum();
+
+            let mut lru: Vec<_> = state
+                .bundles
+                .iter()
+                .filter(|(digest, _)| !doomed.contains(*digest))
+                .collect();
+            lru.sort_by_key(|(_, b)| b.last_used);
+
+            for (digest, b) in lru {
+                if size <= max_size {
+                    break;
+                }
+
+                doomed.insert(*digest);
+
+                for f in &b.files {
+                    if let Some(n) = refcounts.get_mut(f.as_str()) {
+                        *n -= 1;
+
+                        if *n == 0 {
+                            size -= state.data.get(f).copied().unwrap_or(0);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.remove_bundles(state, &doomed, status)
+    }
+
+    /// Remove all of the data in this cache.
+    ///
+    /// Like [`Self::gc`], this fails if any other process has a bundle from
+    /// this cache open.
+    pub fn purge(&mut self, status: &mut dyn StatusBackend) -> Result<GcReport> {
+        let _lock = self.lock_for_gc()?;
+        let state = CacheState::load(&self.root)?;
+        let doomed = state.bundles.keys().copied().collect();
+        let report = self.remove_bundles(state, &doomed, status)?;
+
+        for (path, _) in list_dir(&self.root.join(MIRROR_HEALTH_DIR))? {
+            remove_cached_file(&path)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Take the exclusive lock that allows files to be removed from the cache.
+    fn lock_for_gc(&self) -> Result<File> {
+        let lock_path = self.root.join(LOCK_FILE_NAME);
+        let lock = atry!(
+            fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path);
+            ["failed to open lock file `{}`", lock_path.display()]
+        );
+
+        match lock.try_lock_exclusive() {
+            Ok(()) => Ok(lock),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => bail!(
+                "the cache at `{}` is in use by another process; try again once it has finished",
+                self.root.display()
+            ),
+            Err(e) => bail!("failed to lock `{}`: {}", lock_path.display(), e),
+        }
+    }
+
+    /// Remove the records of the specified bundles, and then any cached file
+    /// data that no remaining bundle uses.
+    fn remove_bundles(
+        &mut self,
+        state: CacheState,
+        doomed: &HashSet<DigestData>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<GcReport> {
+        let mut report = GcReport::default();
+
+        for digest in doomed {
+            let digest_text = digest.to_string();
+
+            for dir in BUNDLE_RECORD_DIRS {
+                remove_cached_file(&make_txt_path(&self.root.join(dir), &digest_text))?;
+            }
+
+            remove_cached_file(&lock_item_path(
+                &self.root,
+                &format!("bundle-{digest_text}"),
+            ))?;
+
+            report.removed_bundles.push(*digest);
+        }
+
+        report.removed_bundles.sort();
+
+        // Forget about URLs that resolve to bundles that are gone.
+
+        let remaining: HashSet<_> = state
+            .bundles
+            .keys()
+            .filter(|d| !doomed.contains(*d))
+            .collect();
+
+        for (path, name) in list_dir(&self.root.join("urls"))? {
+            let digest = fs::read_to_string(&path)
+                .ok()
+                .and_then(|t| DigestData::from_str(t.trim()).ok());
+
+            if !digest.map(|d| remaining.contains(&d)).unwrap_or(false) {
+                remove_cached_file(&path)?;
+                remove_cached_file(&lock_item_path(&self.root, &format!("url-{name}")))?;
+            }
+        }
+
+        // Finally, the file data.
+
+        let referenced: HashSet<&str> = state
+            .bundles
+            .iter()
+            .filter(|(d, _)| !doomed.contains(*d))
+            .flat_map(|(_, b)| b.files.iter().map(|f| f.as_str()))
+            .collect();
+
+        let data_base = self.root.join("files");
+
+        for (hex, size) in &state.data {
+            if referenced.contains(hex.as_str()) {
+                continue;
+            }
+
+            let path = data_base.join(&hex[..2]).join(&hex[2..]);
+
+            if let Err(e) = remove_cached_file(&path) {
+                tt_warning!(status, "failed to remove cached file `{}`", path.display(); e);
+                continue;
+            }
+
+            report.n_files_removed += 1;
+            report.bytes_freed += size;
+        }
+
+        for (path, _) in list_dir(&data_base)? {
+            // This only succeeds if the directory is empty, which is what we
+            // want.
+            let _ = fs::remove_dir(path);
+        }
+
+        Ok(report)
     }
 }
 
+/// The name of the file in the cache root that processes lock to coordinate
+/// with garbage collection.
+const LOCK_FILE_NAME: &str = "cache.lock";
+
+/// The cache subdirectory where [`MirrorList`] records failed mirrors.
+const MIRROR_HEALTH_DIR: &str = "mirrors";
+
+/// The cache subdirectories that contain per-bundle records, each named after
+/// a bundle digest.
+const BUNDLE_RECORD_DIRS: &[&str] = &["indexes", "manifests", "redirects", "used"];
+
+/// Settings for [`Cache::gc`].
+#[derive(Clone, Debug, Default)]
+pub struct GcOptions {
+    /// Remove bundles that haven't been used for at least this long.
+    pub max_age: Option<Duration>,
+
+    /// Remove the least recently used bundles until the cached file data take
+    /// up no more than this many bytes.
+    pub max_size: Option<u64>,
+}
+
+/// The results of a [`Cache::gc`] or [`Cache::purge`] operation.
+#[derive(Clone, Debug, Default)]
+pub struct GcReport {
+    /// The digests of the bundles that were removed.
+    pub removed_bundles: Vec<DigestData>,
+
+    /// The number of cached files that were deleted.
+    pub n_files_removed: usize,
+
+    /// The total size of the deleted files, in bytes.
+    pub bytes_freed: u64,
+}
+
+/// A summary of the contents of a [`Cache`], as returned by
+/// [`Cache::summarize`].
+#[derive(Clone, Debug)]
+pub struct CacheSummary {
+    /// The bundles that the cache holds data for, most recently used first.
+    pub bundles: Vec<CachedBundle>,
+
+    /// The number of cached files, which may be shared between bundles.
+    pub n_data_files: usize,
+
+    /// The total size of the cached files, in bytes.
+    pub data_size: u64,
+}
+
+/// Information about one of the bundles in a [`Cache`].
+#[derive(Clone, Debug)]
+pub struct CachedBundle {
+    /// The digest of the bundle.
+    pub digest: DigestData,
+
+    /// The URL that the bundle was resolved to, if known.
+    pub resolved_url: Option<String>,
+
+    /// When the bundle was last opened, if known.
+    pub last_used: Option<SystemTime>,
+
+    /// The number of the bundle’s files that are cached.
+    pub n_files: usize,
+
+    /// The total size of the bundle’s cached files, in bytes.
+    pub size: u64,
+}
+
+/// What [`Cache::gc`] needs to know about the contents of a cache.
+#[derive(Debug, Default)]
+struct CacheState {
+    /// Information about each bundle, keyed by its digest.
+    bundles: HashMap<DigestData, BundleState>,
+
+    /// The sizes of all of the cached file data, keyed by their hex digests.
+    data: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct BundleState {
+    /// When the bundle was last used.
+    last_used: Option<SystemTime>,
+
+    /// The hex digests of the bundle’s cached files.
+    files: HashSet<String>,
+}
+
+impl CacheState {
+    fn load(root: &Path) -> Result<Self> {
+        let mut state = CacheState::default();
+
+        for dir in BUNDLE_RECORD_DIRS {
+            for (path, name) in list_dir(&root.join(dir))? {
+                let digest = match name
+                    .strip_suffix(".txt")
+                    .and_then(|d| DigestData::from_str(d).ok())
+                {
+                    Some(d) => d,
+                    None => continue,
+                };
+
+                let bundle = state.bundles.entry(digest).or_default();
+
+                // The usage record is authoritative, but bundles that were
+                // cached by older versions of Tectonic won't have one, so fall
+                // back to the other records.
+                if *dir == "used" || bundle.last_used.is_none() {
+                    if let Ok(t) = fs::metadata(&path).and_then(|md| md.modified()) {
+                        bundle.last_used = Some(t);
+                    }
+                }
+
+                if *dir == "manifests" {
+                    let text = atry!(
+                        fs::read_to_string(&path);
+                        ["failed to read manifest `{}`", path.display()]
+                    );
+
+                    for line in text.lines() {
+                        if let Some(d) = line.rsplit(' ').next() {
+                            if d.len() == digest::DIGEST_LEN {
+                                bundle.files.insert(d.to_owned());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (subdir, prefix) in list_dir(&root.join("files"))? {
+            if prefix.len() != 2 || !subdir.is_dir() {
+                continue;
+            }
+
+            for (path, rest) in list_dir(&subdir)? {
+                if let Ok(md) = fs::metadata(&path) {
+                    if md.is_file() {
+                        state.data.insert(format!("{prefix}{rest}"), md.len());
+                    }
+                }
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+/// Information about files imported into a cache with
+/// [`Cache::import_archive`].
+#[derive(Clone, Debug)]
+pub struct CacheImport {
+    /// The digest of the bundle that the files belong to.
+    pub digest: DigestData,
+
+    /// The number of files in the archive, not counting the `SHA256SUM` file.
+    pub n_files: usize,
+
+    /// The number of files that weren't already in the cache.
+    pub n_new: usize,
+}
+
 /// Information describing a cache backend.
 ///
 /// This type is returned by a [`CacheBackend`] on a "pull", a first-time
@@ -175,21 +716,158 @@ pub trait CacheBackend: Sized {
     /// Backend-specific retrieval information can be passed in the
     /// [`Self::FileInfo`] item, which is constructed from the backend’s index
     /// information. The file should be returned as one large byte vector.
+    ///
+    /// The caller reports the download as a task in progress, so the backend
+    /// may report the number of bytes received with
+    /// [`StatusBackend::progress_update`].
     fn get_file(
         &mut self,
         name: &str,
         info: &Self::FileInfo,
         status: &mut dyn StatusBackend,
     ) -> Result<Vec<u8>>;
+
+    /// Obtain several files from the backend.
+    ///
+    /// This is used to prefetch files in bulk. The results are returned in the
+    /// same order as *files*. Backends that can fetch files concurrently, or
+    /// more efficiently in batches, should override the default
+    /// implementation, which calls [`Self::get_file`] for each file in turn.
+    ///
+    /// As with [`Self::get_file`], the backend may report progress, but as the
+    /// total number of bytes received for all of the files.
+    fn get_files(
+        &mut self,
+        files: &[(String, Self::FileInfo)],
+        status: &mut dyn StatusBackend,
+    ) -> Vec<Result<Vec<u8>>> {
+        let mut done = 0;
+
+        files
+            .iter()
+            .map(|(name, info)| {
+                let result = self.get_file(name, info, status);
+
+                if let Ok(data) = &result {
+                    done += data.len() as u64;
+                    status.progress_update(done);
+                }
+
+                result
+            })
+            .collect()
+    }
+
+    /// Get the size of a file in bytes from its [`Self::FileInfo`], if the
+    /// index records it.
+    ///
+    /// This is used for diagnostics. The default implementation returns
+    /// `None`.
+    fn file_size(_info: &Self::FileInfo) -> Option<u64> {
+        None
+    }
+
+    /// Get the digest of a file's contents from its [`Self::FileInfo`], if the
+    /// index records it.
+    ///
+    /// If this is known, and data with the same digest have already been
+    /// cached for another bundle, the cache uses those data rather than
+    /// fetching the file again. The default implementation returns `None`.
+    fn file_digest(_info: &Self::FileInfo) -> Option<DigestData> {
+        None
+    }
+
+    /// Tell the backend about the mirrors serving its bundle.
+    ///
+    /// This is called once the backend has been opened. The *current* mirror
+    /// is the one that it was opened from, if that is known. Backends that can
+    /// switch to another mirror when a file can't be fetched should override
+    /// the default implementation, which ignores the mirrors.
+    fn set_mirrors(&mut self, _mirrors: &MirrorList, _current: Option<&str>) {}
+}
+
+/// Information about the local cache of a [`CachingBundle`].
+#[derive(Clone, Debug)]
+pub struct CacheInfo {
+    /// The root directory of the cache.
+    pub root: PathBuf,
+
+    /// The URL that the bundle was opened with.
+    pub start_url: String,
+
+    /// The URL that the start URL resolved to.
+    pub resolved_url: String,
+
+    /// The number of the bundle’s files that are cached locally.
+    pub n_cached_files: usize,
+
+    /// The total size of the bundle’s locally cached files, in bytes.
+    pub cached_size: u64,
+}
+
+/// A problem with a locally cached file, found by [`CachingBundle::verify`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CacheProblem {
+    /// The cached data for the named file are missing.
+    Missing(String),
+
+    /// The cached data for the named file don't match the digest recorded
+    /// when they were downloaded.
+    WrongDigest(String),
+
+    /// The cached data for a file have the wrong size.
+    WrongSize {
+        /// The name of the file.
+        name: String,
+
+        /// The size of the file according to the bundle.
+        expected: u64,
+
+        /// The size of the cached data.
+        actual: u64,
+    },
+
+    /// The named file is cached, but isn't listed in the bundle’s index.
+    NotInIndex(String),
+}
+
+impl fmt::Display for CacheProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheProblem::Missing(name) => write!(f, "`{name}`: cached data are missing"),
+            CacheProblem::WrongDigest(name) => {
+                write!(f, "`{name}`: cached data do not match their digest")
+            }
+            CacheProblem::WrongSize {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "`{name}`: cached data have size {actual}, but should have size {expected}"
+            ),
+            CacheProblem::NotInIndex(name) => {
+                write!(f, "`{name}`: cached, but not in the bundle index")
+            }
+        }
+    }
+}
+
+/// The results of verifying a bundle’s locally cached files.
+#[derive(Clone, Debug, Default)]
+pub struct CacheVerification {
+    /// The number of cached files that were checked.
+    pub n_checked: usize,
+
+    /// The problems that were found.
+    pub problems: Vec<CacheProblem>,
 }
 
 /// Information about a cached file.
 #[derive(Clone, Copy, Debug)]
 struct CachedFileInfo {
     /// The length of the file in bytes.
-    ///
-    /// This field isn't currently used, but seems handy to keep around.
-    _length: u64,
+    length: u64,
 
     /// The digest of the file contents.
     ///
@@ -217,6 +895,9 @@ pub struct CachingBundle<CB: CacheBackend> {
     /// never change once published.
     start_url: String,
 
+    /// The locations serving the bundle, starting with [`Self::start_url`].
+    mirrors: MirrorList,
+
     /// The "resolved" URL for the backing data.
     ///
     /// The bundle data located at this URL should never change.
@@ -287,6 +968,15 @@ pub struct CachingBundle<CB: CacheBackend> {
     /// This directory contains the actual cached file contents, in a directory
     /// structured based on the digest of each file’s content.
     data_base: PathBuf,
+
+    /// The root directory of the cache.
+    cache_root: PathBuf,
+
+    /// A shared lock on the cache, held for as long as the bundle is open, to
+    /// prevent garbage collection from removing files that we might need.
+    ///
+    /// This is `None` if the lock couldn't be taken.
+    _usage_lock: Option<File>,
 }
 
 /// A locally-cached analogue of [`BackendPullData`].
@@ -308,123 +998,131 @@ struct CachedPullData<FI> {
 impl<CB: CacheBackend> CachingBundle<CB> {
     fn new(
         start_url: &str,
+        mirrors: &[String],
         only_cached: bool,
         status: &mut dyn StatusBackend,
         cache_root: &Path,
     ) -> Result<Self> {
+        // Make sure that the cache isn't garbage-collected while we're using it.
+        let usage_lock = lock_cache_for_use(cache_root, status);
+
         // Set up our paths.
-        let digest_path =
-            ensure_cache_dir(cache_root, "urls")?.join(app_dirs::app_dirs2::sanitized(start_url));
+        let digest_path = url_digest_path(cache_root, start_url)?;
         let resolved_base = ensure_cache_dir(cache_root, "redirects")?;
         let index_base = ensure_cache_dir(cache_root, "indexes")?;
         let manifest_base = ensure_cache_dir(cache_root, "manifests")?;
         let data_base = ensure_cache_dir(cache_root, "files")?;
 
+        let mirrors = {
+            let mut m = MirrorList::new(
+                std::iter::once(start_url).chain(mirrors.iter().map(|s| s.as_str())),
+            );
+            m.set_health_dir(cache_root.join(MIRROR_HEALTH_DIR));
+            m
+        };
+
         // The whole point of this cache is to avoid connecting to the backend
         // if at all possible. So we first see if we have cached the "pull data"
         // that describe the overall backend contents.
 
         let mut backend = None;
+        let mut only_cached = only_cached;
+
+        let mut cached_pull_data =
+            load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)?;
+
+        // If we need to pull, another process might be doing the same thing
+        // right now. Wait for it to finish, and then check whether it has left
+        // us everything that we need. The lock is held until we've saved our
+        // own pull data.
+        let _pull_lock = if cached_pull_data.is_none() {
+            let lock = lock_cache_item(
+                cache_root,
+                &format!("url-{}", app_dirs::app_dirs2::sanitized(start_url)),
+            )?;
+            cached_pull_data =
+                load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)?;
+            Some(lock)
+        } else {
+            None
+        };
 
-        let cached_pull_data =
-            match load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)? {
-                Some(c) => c,
-                None => {
-                    // Some portion of the required cached data is missing. We need to
-                    // do a complete pull and then cache the results.
-
-                    let (new_backend, pull_data) = CB::open_with_pull(start_url, status)?;
-                    backend = Some(new_backend);
-
-                    let digest_text = pull_data.digest.to_string();
-                    file_create_write(&digest_path, |f| writeln!(f, "{}", &digest_text))?;
-                    file_create_write(make_txt_path(&resolved_base, &digest_text), |f| {
-                        f.write_all(pull_data.resolved_url.as_bytes())
-                    })?;
-                    file_create_write(make_txt_path(&index_base, &digest_text), |f| {
-                        f.write_all(pull_data.index.as_bytes())
-                    })?;
-
-                    // Now that we've done that, load_cached_pull_data() really ought to succeed ...
-                    atry!(
-                        load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)?;
-                        ["cache files missing even after they were created"]
-                    )
+        let cached_pull_data = match cached_pull_data {
+            Some(c) => c,
+            None => {
+                // Some portion of the required cached data is missing. We need to
+                // do a complete pull and then cache the results -- unless the
+                // cache was seeded with `Cache::import_archive()`, in which case
+                // we can get by with the imported files if we can't, or mustn't,
+                // connect to the backend.
+
+                let seeded = load_seeded_digest(&digest_path, &manifest_base)?.map(|digest| {
+                    CachedPullData {
+                        digest,
+                        resolved_url: start_url.to_owned(),
+                        index: HashMap::new(),
+                    }
+                });
+
+                let pulled = match seeded {
+                    Some(_) if only_cached => None,
+                    _ => match pull_from_mirrors::<CB>(&mirrors, status) {
+                        Ok(r) => Some(r),
+                        Err(e) if seeded.is_some() => {
+                            tt_warning!(status, "failed to connect to the bundle backend; \
+                                                     only files imported into the cache will be available"; e);
+                            None
+                        }
+                        Err(e) => return Err(e),
+                    },
+                };
+
+                match (pulled, seeded) {
+                    (Some((new_backend, pull_data)), _) => {
+                        backend = Some(new_backend);
+
+                        let digest_text = pull_data.digest.to_string();
+                        file_create_write(&digest_path, |f| writeln!(f, "{}", &digest_text))?;
+                        file_create_write(make_txt_path(&resolved_base, &digest_text), |f| {
+                            f.write_all(pull_data.resolved_url.as_bytes())
+                        })?;
+                        file_create_write(make_txt_path(&index_base, &digest_text), |f| {
+                            f.write_all(pull_data.index.as_bytes())
+                        })?;
+
+                        // Now that we've done that, load_cached_pull_data() really ought to succeed ...
+                        atry!(
+                            load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)?;
+                            ["cache files missing even after they were created"]
+                        )
+                    }
+
+                    (None, seeded) => {
+                        // We only skip the pull if the cache was seeded.
+                        only_cached = true;
+                        a_ok_or!(seeded; ["cache files missing for seeded bundle"])
+                    }
                 }
-            };
+            }
+        };
 
         // We call this `cached_digest`, but if `backend` is Some, it is a
         // validated, fresh digest.
 
         let cached_digest = cached_pull_data.digest;
+        mark_bundle_used(cache_root, &cached_digest);
 
         // Now that we have the backend content digest, we know which manifest
         // to use. Read it in, if it exists.
 
         let manifest_path = make_txt_path(&manifest_base, &cached_digest.to_string());
-        let mut contents = HashMap::new();
-
-        match try_open_file(&manifest_path) {
-            OpenResult::NotAvailable => {}
-            OpenResult::Err(e) => {
-                return Err(e);
-            }
-            OpenResult::Ok(mfile) => {
-                // Note that the lock is released when the file is closed,
-                // which is good since BufReader::new() and BufReader::lines()
-                // consume their objects.
-                if let Err(e) = mfile.lock_shared() {
-                    tt_warning!(status, "failed to lock manifest file \"{}\" for reading; this might be fine",
-                                manifest_path.display(); e.into());
-                }
-
-                let f = BufReader::new(mfile);
-
-                for res in f.lines() {
-                    let line = res?;
-                    let mut bits = line.rsplitn(3, ' ');
-
-                    let (original_name, length, digest) =
-                        match (bits.next(), bits.next(), bits.next(), bits.next()) {
-                            (Some(s), Some(t), Some(r), None) => (r, t, s),
-                            _ => continue,
-                        };
-
-                    let name = original_name.to_owned();
-
-                    let length = match length.parse::<u64>() {
-                        Ok(l) => l,
-                        Err(_) => continue,
-                    };
-
-                    let digest = if digest == "-" {
-                        continue;
-                    } else {
-                        match DigestData::from_str(digest) {
-                            Ok(d) => d,
-                            Err(e) => {
-                                tt_warning!(status, "ignoring bad digest data \"{}\" for \"{}\" in \"{}\"",
-                                            &digest, original_name, manifest_path.display() ; e);
-                                continue;
-                            }
-                        }
-                    };
-
-                    contents.insert(
-                        name,
-                        CachedFileInfo {
-                            _length: length,
-                            digest,
-                        },
-                    );
-                }
-            }
-        }
+        let contents = load_manifest(&manifest_path, status)?;
 
         // All set.
 
         Ok(CachingBundle {
             start_url: start_url.to_owned(),
+            mirrors,
             resolved_url: cached_pull_data.resolved_url,
             digest_path,
             cached_digest,
@@ -435,9 +1133,47 @@ impl<CB: CacheBackend> CachingBundle<CB> {
             only_cached,
             backend,
             index: cached_pull_data.index,
+            cache_root: cache_root.to_owned(),
+            _usage_lock: usage_lock,
         })
     }
 
+    /// Take the lock that must be held while downloading files from the
+    /// backend.
+    ///
+    /// Other processes might have downloaded files while we waited for the
+    /// lock, so the manifest is reloaded once we have it. The lock is released
+    /// when the returned file is closed.
+    fn lock_for_download(&mut self, status: &mut dyn StatusBackend) -> Result<File> {
+        let lock = lock_cache_item(
+            &self.cache_root,
+            &format!("bundle-{}", self.cached_digest.to_string()),
+        )?;
+        self.contents
+            .extend(load_manifest(&self.manifest_path, status)?);
+        Ok(lock)
+    }
+
+    /// If the backend knows the digest of a file, and data with that digest
+    /// have already been cached, record the file as cached for this bundle too
+    /// and return the path of its data.
+    fn reuse_cached_data(&mut self, name: &str, info: &CB::FileInfo) -> Result<Option<PathBuf>> {
+        let digest = match CB::file_digest(info) {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+
+        let path = cached_data_path(&self.data_base, &digest);
+
+        let length = match fs::metadata(&path) {
+            Ok(md) => md.len(),
+            Err(_) => return Ok(None),
+        };
+
+        self.save_to_manifest(name, length, digest)?;
+        Ok(Some(path))
+    }
+
     /// Save data about a file to our local cache manifest.
     fn save_to_manifest(&mut self, name: &str, length: u64, digest: DigestData) -> Result<()> {
         let digest_text = digest.to_string();
@@ -464,17 +1200,71 @@ impl<CB: CacheBackend> CachingBundle<CB> {
             writeln!(man, "{name} {length} {digest_text}")?;
         }
 
-        self.contents.insert(
-            name.to_owned(),
-            CachedFileInfo {
-                _length: length,
-                digest,
-            },
-        );
+        self.contents
+            .insert(name.to_owned(), CachedFileInfo { length, digest });
 
         Ok(())
     }
 
+    /// Check the integrity of the locally cached files.
+    ///
+    /// Every cached file is re-hashed and checked against the digest that was
+    /// recorded when it was downloaded, and its size is checked against the
+    /// bundle’s index. This never connects to the backend.
+    pub fn verify(&mut self, status: &mut dyn StatusBackend) -> Result<CacheVerification> {
+        let mut names: Vec<_> = self.contents.keys().cloned().collect();
+        names.sort();
+
+        let mut result = CacheVerification::default();
+
+        for name in names {
+            let info = self.contents[&name];
+            result.n_checked += 1;
+
+            let expected_size = match self.index.get(&name) {
+                Some(fi) => CB::file_size(fi).unwrap_or(info.length),
+                None if self.index.is_empty() => info.length,
+                None => {
+                    result.problems.push(CacheProblem::NotInIndex(name));
+                    continue;
+                }
+            };
+
+            let path = cached_data_path(&self.data_base, &info.digest);
+
+            let mut f = match File::open(&path) {
+                Ok(f) => f,
+                Err(ref e) if e.kind() == IoErrorKind::NotFound => {
+                    result.problems.push(CacheProblem::Missing(name));
+                    continue;
+                }
+                Err(e) => {
+                    tt_warning!(status, "couldn't open cached data for \"{}\"", name; e.into());
+                    result.problems.push(CacheProblem::Missing(name));
+                    continue;
+                }
+            };
+
+            let mut digest_builder = digest::create();
+            let actual = atry!(
+                std::io::copy(&mut f, &mut digest_builder);
+                ["failed to read cached data file `{}`", path.display()]
+            );
+
+            if actual != expected_size {
+                result.problems.push(CacheProblem::WrongSize {
+                    name,
+                    expected: expected_size,
+                    actual,
+                });
+            } else if DigestData::from(digest_builder) != info.digest {
+                result.problems.push(CacheProblem::WrongDigest(name));
+            }
+        }
+
+        Ok(result)
+    }
+
     /// Ensure that the backend is connected and valid.
     ///
     /// Here we do a "quick check" to see if the backend's digest is what we
@@ -493,13 +1283,14 @@ impl<CB: CacheBackend> CachingBundle<CB> {
         // Do the quick check. If anything goes wrong, eat the error and try a
         // fresh pull.
         if let Some(info) = self.index.get(digest::DIGEST_NAME) {
-            if let Ok(Some((backend, digest))) =
+            if let Ok(Some((mut backend, digest))) =
                 CB::open_with_quick_check(&self.resolved_url, info, status)
             {
                 if self.cached_digest == digest {
                     // We managed to pull some data that match the digest. We
                     // can be quite confident that the bundle is what we expect
                     // it to be.
+                    backend.set_mirrors(&self.mirrors, None);
                     self.backend = Some(backend);
                     return Ok(());
                 }
@@ -508,7 +1299,7 @@ impl<CB: CacheBackend> CachingBundle<CB> {
 
         // The quick check failed. Try to pull all data to make sure that it
         // wasn't a network error or that the resolved URL hasn't been updated.
-        let (new_backend, pull_data) = CB::open_with_pull(&self.start_url, status)?;
+        let (new_backend, pull_data) = pull_from_mirrors::<CB>(&self.mirrors, status)?;
 
         if self.cached_digest != pull_data.digest {
             // Crap! The backend isn't what we thought it was. We may have been
@@ -554,19 +1345,38 @@ impl<CB: CacheBackend> CachingBundle<CB> {
             };
         }
 
-        // No, it's not. Are we in cache-only mode?
+        // No, it's not. Is the file in the backend at all?
+        let info = match self.index.get(name).cloned() {
+            Some(info) => info,
+            None => return OpenResult::NotAvailable,
+        };
+
+        // Maybe we already have its data from another bundle.
+        match self.reuse_cached_data(name, &info) {
+            Ok(Some(p)) => return OpenResult::Ok(p),
+            Ok(None) => {}
+            Err(e) => return OpenResult::Err(e),
+        }
+
+        // Are we in cache-only mode?
         if self.only_cached {
             return OpenResult::NotAvailable;
         }
 
-        // Is the file in the backend at all?
-        let info = match self.index.get(name).cloned() {
-            Some(info) => info,
-            None => return OpenResult::NotAvailable,
+        // Yes, it is. Another process might be fetching it right now, so wait
+        // until we have the download lock and then check whether it's turned
+        // up in the meantime.
+        let _download_lock = match self.lock_for_download(status) {
+            Ok(l) => l,
+            Err(e) => return OpenResult::Err(e),
         };
 
-        // Yes, it is. Time to fetch it! In order to do that, we need to ensure
-        // that we have a valid backend connection.
+        if let Some(info) = self.contents.get(name) {
+            return OpenResult::Ok(cached_data_path(&self.data_base, &info.digest));
+        }
+
+        // Time to fetch it! In order to do that, we need to ensure that we have
+        // a valid backend connection.
         if let Err(e) = self.ensure_backend_validity(status) {
             return OpenResult::Err(e);
         }
@@ -579,50 +1389,22 @@ impl<CB: CacheBackend> CachingBundle<CB> {
         // have returned incorrect data for previous requests that hit the
         // cache.
 
-        let content = match self.backend.as_mut().unwrap().get_file(name, &info, status) {
+        status.progress_start(&format!("downloading {name}"), CB::file_size(&info));
+        let content = self.backend.as_mut().unwrap().get_file(name, &info, status);
+        status.progress_finish();
+
+        let content = match content {
             Ok(c) => c,
             Err(e) => return OpenResult::Err(e),
         };
 
         let length = content.len();
 
-        let mut digest_builder = digest::create();
-        digest_builder.update(&content);
-        let digest = DigestData::from(digest_builder);
-
-        let final_path = match digest.create_two_part_path(&self.data_base) {
-            Ok(p) => p,
+        let digest = match save_cached_data(&self.data_base, &content) {
+            Ok(d) => d,
             Err(e) => return OpenResult::Err(e),
         };
 
-        // Perform a racy check for the destination existing, because this
-        // matters on Windows: if the destination is already there, we'll get
-        // an error because the destination is marked read-only. Assuming
-        // non-pathological filesystem manipulation, though, we'll only be
-        // subject to the race once.
-
-        if !final_path.exists() {
-            if let Err(e) = file_create_write(&final_path, |f| f.write_all(&content)) {
-                return OpenResult::Err(e);
-            }
-
-            // Now we can make the file readonly. It would be nice to set the
-            // permissions using the already-open file handle owned by the
-            // tempfile, but mkstemp doesn't give us access.
-            let mut perms = match fs::metadata(&final_path) {
-                Ok(p) => p,
-                Err(e) => {
-                    return OpenResult::Err(e.into());
-                }
-            }
-            .permissions();
-            perms.set_readonly(true);
-
-            if let Err(e) = fs::set_permissions(&final_path, perms) {
-                return OpenResult::Err(e.into());
-            }
-        }
-
         // And finally add a record of this file to our manifest. Note that
         // we're opening and closing the manifest every time we cache a new
         // file; not so efficient, but whatever.
@@ -631,7 +1413,7 @@ impl<CB: CacheBackend> CachingBundle<CB> {
             return OpenResult::Err(e);
         }
 
-        OpenResult::Ok(final_path)
+        OpenResult::Ok(cached_data_path(&self.data_base, &digest))
     }
 }
 
@@ -669,8 +1451,165 @@ impl<CB: CacheBackend> Bundle for CachingBundle<CB> {
         if !self.only_cached {
             self.ensure_backend_validity(status)?;
         }
+
+        // If the cache was seeded from an archive and we haven't been able to
+        // get the index, the cached files are all that we know about.
+        if self.index.is_empty() {
+            return Ok(self.contents.keys().cloned().collect());
+        }
+
         Ok(self.index.keys().cloned().collect())
     }
+
+    fn all_files_with_sizes(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        if !self.only_cached {
+            self.ensure_backend_validity(status)?;
+        }
+
+        if self.index.is_empty() {
+            return Ok(self
+                .contents
+                .iter()
+                .map(|(name, c)| (name.clone(), Some(c.length)))
+                .collect());
+        }
+
+        Ok(self
+            .index
+            .iter()
+            .map(|(name, fi)| {
+                let size = CB::file_size(fi).or_else(|| self.contents.get(name).map(|c| c.length));
+                (name.clone(), size)
+            })
+            .collect())
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        // The index and the cached files are already loaded, so this doesn't
+        // need the backend. If the index is empty, the cached files are all
+        // that we know about, as in `all_files`.
+        Some(
+            names
+                .iter()
+                .filter(|name| self.index.contains_key(*name) || self.contents.contains_key(*name))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    fn cache_info(&self) -> Option<CacheInfo> {
+        Some(CacheInfo {
+            root: self.cache_root.clone(),
+            start_url: self.start_url.clone(),
+            resolved_url: self.resolved_url.clone(),
+            n_cached_files: self.contents.len(),
+            cached_size: self.contents.values().map(|c| c.length).sum(),
+        })
+    }
+
+    fn verify_cache(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<CacheVerification>> {
+        self.verify(status).map(Some)
+    }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<usize> {
+        if self.only_cached {
+            return Ok(0);
+        }
+
+        // Don't bother with the download lock if there's nothing to fetch.
+        if names
+            .iter()
+            .all(|name| self.contents.contains_key(name) || !self.index.contains_key(name))
+        {
+            return Ok(0);
+        }
+
+        let _download_lock = self.lock_for_download(status)?;
+        let mut seen = HashSet::new();
+        let mut wanted: Vec<(String, CB::FileInfo)> = names
+            .iter()
+            .filter(|name| !self.contents.contains_key(*name) && seen.insert(*name))
+            .filter_map(|name| {
+                self.index
+                    .get(name)
+                    .map(|info| (name.clone(), info.clone()))
+            })
+            .collect();
+
+        // There's no need to fetch files whose data we already have.
+        wanted.retain(|(name, info)| !matches!(self.reuse_cached_data(name, info), Ok(Some(_))));
+
+        if wanted.is_empty() {
+            return Ok(0);
+        }
+
+        self.ensure_backend_validity(status)?;
+
+        let total = wanted
+            .iter()
+            .map(|(_, info)| CB::file_size(info))
+            .sum::<Option<u64>>();
+        status.progress_start(&format!("downloading {} files", wanted.len()), total);
+        let results = self.backend.as_mut().unwrap().get_files(&wanted, status);
+        status.progress_finish();
+
+        let mut n_fetched = 0;
+
+        for ((name, _), result) in wanted.iter().zip(results) {
+            let saved = result.and_then(|content| {
+                let digest = save_cached_data(&self.data_base, &content)?;
+                self.save_to_manifest(name, content.len() as u64, digest)
+            });
+
+            match saved {
+                Ok(()) => n_fetched += 1,
+                Err(e) => tt_warning!(status, "failed to prefetch \"{}\"", name; e),
+            }
+        }
+
+        Ok(n_fetched)
+    }
+}
+
+/// Pull a backend's data from the first of its mirrors that works.
+///
+/// Each mirror that fails is recorded as such, and a warning is issued if there
+/// is another one to try. If they all fail, the last error is returned.
+fn pull_from_mirrors<CB: CacheBackend>(
+    mirrors: &MirrorList,
+    status: &mut dyn StatusBackend,
+) -> Result<(CB, BackendPullData)> {
+    let ordered = mirrors.ordered();
+    let mut urls = ordered.iter().peekable();
+
+    loop {
+        let url = a_ok_or!(urls.next(); ["no locations given for the bundle"]);
+
+        match CB::open_with_pull(url, status) {
+            Ok((mut backend, pull_data)) => {
+                mirrors.record_success(url);
+                backend.set_mirrors(mirrors, Some(url));
+                return Ok((backend, pull_data));
+            }
+
+            Err(e) => {
+                mirrors.record_failure(url);
+
+                match urls.peek() {
+                    Some(next) => {
+                        tt_warning!(status, "failed to open the bundle at `{}`; trying `{}` instead", url, next; e)
+                    }
+                    None => return Err(e),
+                }
+            }
+        }
+    }
 }
 
 /// Load the cached "pull" data for a backend.
@@ -731,7 +1670,227 @@ fn load_cached_pull_data<CB: CacheBackend>(
     }
 }
 
+/// Load the manifest of the files cached for a bundle.
+///
+/// If the manifest doesn’t exist, nothing has been cached yet.
+fn load_manifest(
+    manifest_path: &Path,
+    status: &mut dyn StatusBackend,
+) -> Result<HashMap<String, CachedFileInfo>> {
+    let mut contents = HashMap::new();
+
+    match try_open_file(manifest_path) {
+        OpenResult::NotAvailable => {}
+        OpenResult::Err(e) => {
+            return Err(e);
+        }
+        OpenResult::Ok(mfile) => {
+            // Note that the lock is released when the file is closed,
+            // which is good since BufReader::new() and BufReader::lines()
+            // consume their objects.
+            if let Err(e) = mfile.lock_shared() {
+                tt_warning!(status, "failed to lock manifest file \"{}\" for reading; this might be fine",
+                            manifest_path.display(); e.into());
+            }
+
+            let f = BufReader::new(mfile);
+
+            for res in f.lines() {
+                let line = res?;
+                let mut bits = line.rsplitn(3, ' ');
+
+                let (original_name, length, digest) =
+                    match (bits.next(), bits.next(), bits.next(), bits.next()) {
+                        (Some(s), Some(t), Some(r), None) => (r, t, s),
+                        _ => continue,
+                    };
+
+                let name = original_name.to_owned();
+
+                let length = match length.parse::<u64>() {
+                    Ok(l) => l,
+                    Err(_) => continue,
+                };
+
+                let digest = if digest == "-" {
+                    continue;
+                } else {
+                    match DigestData::from_str(digest) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            tt_warning!(status, "ignoring bad digest data \"{}\" for \"{}\" in \"{}\"",
+                                        &digest, original_name, manifest_path.display() ; e);
+                            continue;
+                        }
+                    }
+                };
+
+                contents.insert(name, CachedFileInfo { length, digest });
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Load the digest of a bundle that the cache was seeded with.
+///
+/// This is the case if the digest file for the start URL exists and a manifest
+/// for that digest exists, even though other cached pull data are missing. If
+/// that's not the case, return None.
+fn load_seeded_digest(digest_path: &Path, manifest_base: &Path) -> Result<Option<DigestData>> {
+    let digest_text = match fs::read_to_string(digest_path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let digest = match DigestData::from_str(digest_text.trim()) {
+        Ok(d) => d,
+        Err(_) => return Ok(None),
+    };
+
+    if make_txt_path(manifest_base, &digest.to_string()).exists() {
+        Ok(Some(digest))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Save file data in the cache, returning the digest of the data.
+///
+/// The data are stored in a read-only file whose path is derived from the
+/// digest.
+fn save_cached_data(data_base: &Path, content: &[u8]) -> Result<DigestData> {
+    let mut digest_builder = digest::create();
+    digest_builder.update(content);
+    let digest = DigestData::from(digest_builder);
+
+    let final_path = digest.create_two_part_path(data_base)?;
+
+    // Perform a racy check for the destination existing, because this
+    // matters on Windows: if the destination is already there, we'll get
+    // an error because the destination is marked read-only. Assuming
+    // non-pathological filesystem manipulation, though, we'll only be
+    // subject to the race once.
+
+    if !final_path.exists() {
+        let dir = final_path.parent().unwrap();
+        let mut temp = atry!(
+            tempfile::Builder::new().tempfile_in(dir);
+            ["couldn't create a temporary file in {}", dir.display()]
+        );
+        atry!(
+            temp.write_all(content);
+            ["couldn't write to {}", temp.path().display()]
+        );
+
+        // Make the file readonly before it gets its final name, so that other
+        // processes never see it writable.
+        let mut perms = temp.as_file().metadata()?.permissions();
+        perms.set_readonly(true);
+        temp.as_file().set_permissions(perms)?;
+
+        // If another process has saved the same data in the meantime, the
+        // rename might fail on Windows; that's fine, since the data are the
+        // same.
+        if let Err(e) = temp.persist(&final_path) {
+            if !final_path.exists() {
+                bail!("couldn't save {}: {}", final_path.display(), e.error);
+            }
+        }
+    }
+
+    Ok(digest)
+}
+
+/// Take a shared lock on a cache, which prevents [`Cache::gc`] from running
+/// until the returned file is closed.
+///
+/// Locking is advisory and might not be supported by every filesystem, so
+/// failure isn't fatal: we just warn and return None.
+fn lock_cache_for_use(cache_root: &Path, status: &mut dyn StatusBackend) -> Option<File> {
+    let lock_path = cache_root.join(LOCK_FILE_NAME);
+
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .and_then(|f| f.lock_shared().map(|_| f));
+
+    match result {
+        Ok(f) => Some(f),
+        Err(e) => {
+            tt_warning!(status, "failed to lock the cache file \"{}\"; this might be fine",
+                        lock_path.display(); e.into());
+            None
+        }
+    }
+}
+
+/// Record that a bundle was just used, for the benefit of [`Cache::gc`].
+///
+/// The time of use is the modification time of the bundle’s record in the
+/// `used` subdirectory of the cache.
+fn mark_bundle_used(cache_root: &Path, digest: &DigestData) {
+    // This is only bookkeeping, so if the cache is read-only or something,
+    // there's no need to bother the user about it.
+    let _ = ensure_cache_dir(cache_root, "used").and_then(|used_base| {
+        let f = File::create(make_txt_path(&used_base, &digest.to_string()))?;
+        f.set_modified(SystemTime::now())?;
+        Ok(())
+    });
+}
+
+/// List the entries of a directory, returning their paths and names. If the
+/// directory doesn’t exist, the list is empty.
+fn list_dir(dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => bail!("failed to read directory `{}`: {}", dir.display(), e),
+    };
+
+    let mut items = Vec::new();
+
+    for entry in entries {
+        let entry = entry?;
+
+        if let Ok(name) = entry.file_name().into_string() {
+            items.push((entry.path(), name));
+        }
+    }
+
+    Ok(items)
+}
+
+/// Remove a file from the cache, if it exists.
+///
+/// Cached file data are read-only, which prevents their removal on Windows, so
+/// if the first attempt fails, we make the file writable and try again.
+fn remove_cached_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(()),
+        Err(_) => {}
+    }
+
+    let mut perms = fs::metadata(path)?.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    perms.set_readonly(false);
+    fs::set_permissions(path, perms)?;
+    atry!(
+        fs::remove_file(path);
+        ["failed to remove `{}`", path.display()]
+    );
+    Ok(())
+}
+
 /// A convenience method to provide a better error message when writing to a created file.
+///
+/// The data are written to a temporary file that is then renamed to *path*, so
+/// that other processes never see a partially-written file.
 fn file_create_write<P, F, E>(path: P, write_fn: F) -> Result<()>
 where
     P: AsRef<Path>,
@@ -739,17 +1898,49 @@ where
     E: std::error::Error + 'static + Sync + Send,
 {
     let path = path.as_ref();
-    let mut f = atry!(
-        File::create(path);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp = atry!(
+        tempfile::Builder::new().tempfile_in(dir);
         ["couldn't open {} for writing", path.display()]
     );
     atry!(
-        write_fn(&mut f);
+        write_fn(temp.as_file_mut());
+        ["couldn't write to {}", path.display()]
+    );
+    atry!(
+        temp.persist(path);
         ["couldn't write to {}", path.display()]
     );
     Ok(())
 }
 
+/// Take an exclusive lock on a named file in the `locks` subdirectory of the
+/// cache, waiting for any other process that holds it to let go.
+///
+/// The lock is released when the returned file is closed.
+fn lock_cache_item(cache_root: &Path, name: &str) -> Result<File> {
+    ensure_cache_dir(cache_root, "locks")?;
+    let lock_path = lock_item_path(cache_root, name);
+    let lock = atry!(
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path);
+        ["failed to open lock file `{}`", lock_path.display()]
+    );
+    atry!(
+        lock.lock_exclusive();
+        ["failed to lock `{}`", lock_path.display()]
+    );
+    Ok(lock)
+}
+
+/// Get the path of a lock file taken with [`lock_cache_item`].
+fn lock_item_path(cache_root: &Path, name: &str) -> PathBuf {
+    cache_root.join("locks").join(format!("{name}.lock"))
+}
+
 /// Ensure that a directory exists.
 fn ensure_cache_dir(root: &Path, path: &str) -> Result<PathBuf> {
     let full_path = root.join(path);
@@ -760,6 +1951,21 @@ fn ensure_cache_dir(root: &Path, path: &str) -> Result<PathBuf> {
     Ok(full_path)
 }
 
+/// Get the path of the file recording the digest of the bundle that a start
+/// URL resolves to.
+fn url_digest_path(cache_root: &Path, url: &str) -> Result<PathBuf> {
+    Ok(ensure_cache_dir(cache_root, "urls")?.join(app_dirs::app_dirs2::sanitized(url)))
+}
+
+/// Get the path where the data of a cached file are stored.
+///
+/// This matches [`DigestData::create_two_part_path`], but doesn't create any
+/// directories.
+fn cached_data_path(data_base: &Path, digest: &DigestData) -> PathBuf {
+    let hex = digest.to_string();
+    data_base.join(&hex[..2]).join(&hex[2..])
+}
+
 /// Convenience to generate a text filename
 fn make_txt_path(base: &Path, name: &str) -> PathBuf {
     base.join(name).with_extension("txt")
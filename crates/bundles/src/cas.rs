@@ -0,0 +1,687 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! The compressed, content-addressed bundle backend.
+//!
+//! The main type offered by this module is the [`ContentAddressedBackend`]
+//! struct, which, like [`crate::itar::IndexedTarBackend`], is meant to be used
+//! through the [`crate::cache::CachingBundle`] framework. Rather than one big
+//! tar file, a content-addressed bundle consists of:
+//!
+//! - an index file, whose name ends in `.cabundle`, holding the
+//!   zstd-compressed text of the bundle index. Each line gives the name,
+//!   length, and SHA256 digest of one file. The bundle digest is provided by a
+//!   `SHA256SUM` file, as usual.
+//! - a `blobs` directory next to the index, holding the zstd-compressed data
+//!   of each file at a path derived from the digest of its uncompressed
+//!   contents, like `blobs/3f/a2...c9.zst`.
+//!
+//! The URL of a content-addressed bundle is the URL of its index file. Since
+//! files are identified by their contents, several versions of a bundle can
+//! share a single `blobs` directory, and the local cache can reuse the data of
+//! files that are unchanged between versions without downloading them again.
+//!
+//! Existing bundles can be converted to this format with [`convert_bundle`].
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{Read, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+use tectonic_errors::prelude::*;
+use tectonic_geturl::{config::default_config, DefaultBackend, GetUrlBackend};
+use tectonic_io_base::{
+    digest::{self, Digest, DigestData},
+    OpenResult,
+};
+use tectonic_status_base::{progress::ProgressReader, tt_note, tt_warning, StatusBackend};
+
+use crate::{
+    cache::{BackendPullData, CacheBackend},
+    Bundle,
+};
+
+/// The file extension of the index of a content-addressed bundle.
+pub const INDEX_EXTENSION: &str = "cabundle";
+
+/// The maximum number of concurrent requests made when fetching many files.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// The zstd compression level used for blobs and indexes.
+///
+/// Bundles are converted once and downloaded many times, so it's worth
+/// compressing them hard.
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// Check whether a bundle URL refers to a content-addressed bundle, rather
+/// than an indexed tar file.
+pub fn is_content_addressed_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit_once('.')
+        .map(|(_, ext)| ext == INDEX_EXTENSION)
+        .unwrap_or(false)
+}
+
+/// The internal file-information struct used by the
+/// [`ContentAddressedBackend`].
+#[derive(Clone, Copy, Debug)]
+pub struct FileInfo {
+    length: u64,
+    digest: DigestData,
+}
+
+/// A web-based backend for compressed, content-addressed bundles.
+///
+/// This type implements the [`CacheBackend`] trait and so can be used for
+/// web-based bundle access through the [`crate::cache::CachingBundle`]
+/// framework.
+///
+/// As with [`crate::itar::IndexedTarBackend`], the type parameter is the
+/// get-URL backend used to make requests, which only needs to be given in
+/// tests.
+#[derive(Debug)]
+pub struct ContentAddressedBackend<B: GetUrlBackend = DefaultBackend> {
+    /// The URL of the directory containing the bundle index, with a trailing
+    /// slash.
+    base_url: String,
+
+    geturl_backend: PhantomData<B>,
+}
+
+impl<B: GetUrlBackend> ContentAddressedBackend<B> {
+    fn new(resolved_url: &str) -> Self {
+        let base_url = match resolved_url.rfind('/') {
+            Some(i) => resolved_url[..=i].to_owned(),
+            None => String::new(),
+        };
+
+        ContentAddressedBackend {
+            base_url,
+            geturl_backend: PhantomData,
+        }
+    }
+}
+
+impl<B: GetUrlBackend> CacheBackend for ContentAddressedBackend<B> {
+    type FileInfo = FileInfo;
+
+    fn open_with_pull(
+        start_url: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<(Self, BackendPullData)> {
+        // Step 1: resolve URL
+        let mut geturl_backend = B::default();
+        let resolved_url = geturl_backend.resolve_url(start_url, status)?;
+
+        // Step 2: fetch index
+        let index = {
+            let mut index = String::new();
+            tt_note!(status, "downloading index {}", resolved_url);
+            zstd::stream::read::Decoder::new(geturl_backend.get_url(&resolved_url, status)?)?
+                .read_to_string(&mut index)?;
+            index
+        };
+
+        // Step 3: get digest, setting up instance as we go
+
+        let mut cache_backend = Self::new(&resolved_url);
+
+        let digest_info = {
+            let mut digest_info = None;
+
+            for line in index.lines() {
+                if let Ok((name, info)) = Self::parse_index_line(line) {
+                    if name == digest::DIGEST_NAME {
+                        digest_info = Some(info);
+                        break;
+                    }
+                }
+            }
+
+            atry!(
+                digest_info;
+                ["backend does not provide needed {} file", digest::DIGEST_NAME]
+            )
+        };
+
+        let digest_text =
+            String::from_utf8(cache_backend.get_file(digest::DIGEST_NAME, &digest_info, status)?)
+                .map_err(|e| e.utf8_error())?;
+        let digest = DigestData::from_str(digest_text.trim())?;
+
+        // All done.
+        Ok((
+            cache_backend,
+            BackendPullData {
+                resolved_url,
+                digest,
+                index,
+            },
+        ))
+    }
+
+    fn open_with_quick_check(
+        resolved_url: &str,
+        digest_file_info: &Self::FileInfo,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<(Self, DigestData)>> {
+        let mut cache_backend = Self::new(resolved_url);
+
+        if let Ok(d) = cache_backend.get_file(digest::DIGEST_NAME, digest_file_info, status) {
+            if let Ok(d) = String::from_utf8(d) {
+                if let Ok(d) = DigestData::from_str(d.trim()) {
+                    return Ok(Some((cache_backend, d)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn parse_index_line(line: &str) -> Result<(String, Self::FileInfo)> {
+        let mut bits = line.split_whitespace();
+
+        if let (Some(name), Some(length), Some(digest)) = (bits.next(), bits.next(), bits.next()) {
+            Ok((
+                name.to_owned(),
+                FileInfo {
+                    length: length.parse::<u64>()?,
+                    digest: DigestData::from_str(digest)?,
+                },
+            ))
+        } else {
+            bail!("malformed index line");
+        }
+    }
+
+    fn get_file(
+        &mut self,
+        name: &str,
+        info: &Self::FileInfo,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<u8>> {
+        tt_note!(status, "downloading {}", name);
+        fetch_blob::<B>(&blob_url(&self.base_url, &info.digest), info, status)
+    }
+
+    fn get_files(
+        &mut self,
+        files: &[(String, Self::FileInfo)],
+        status: &mut dyn StatusBackend,
+    ) -> Vec<Result<Vec<u8>>> {
+        tt_note!(status, "downloading {} files", files.len());
+
+        // Each file is a separate blob, so there's nothing to coalesce. Just
+        // fetch them concurrently. The status backend can't be shared between
+        // threads, so warnings about retries are dropped, but failures are
+        // reported once all of the threads are done. The threads report the
+        // sizes of the files that they've fetched, so that we can report
+        // progress.
+
+        let next_file = &AtomicUsize::new(0);
+        let n_threads = files.len().min(MAX_CONCURRENT_REQUESTS);
+        let base_url = self.base_url.as_str();
+        let (progress_tx, progress_rx) = mpsc::channel();
+
+        let mut fetched: Vec<(usize, Result<Vec<u8>>)> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..n_threads)
+                .map(|_| {
+                    let progress_tx = progress_tx.clone();
+
+                    scope.spawn(move || {
+                        let mut status = tectonic_status_base::NoopStatusBackend::default();
+                        let mut results = Vec::new();
+
+                        loop {
+                            let i = next_file.fetch_add(1, Ordering::SeqCst);
+
+                            let (_, info) = match files.get(i) {
+                                Some(f) => f,
+                                None => break,
+                            };
+
+                            let url = blob_url(base_url, &info.digest);
+                            let result = fetch_blob::<B>(&url, info, &mut status);
+
+                            if result.is_ok() {
+                                let _ = progress_tx.send(info.length);
+                            }
+
+                            results.push((i, result));
+                        }
+
+                        results
+                    })
+                })
+                .collect();
+
+            // This ends once all of the threads are done with their senders.
+            drop(progress_tx);
+            let mut done = 0;
+
+            for n in progress_rx {
+                done += n;
+                status.progress_update(done);
+            }
+
+            threads
+                .into_iter()
+                .flat_map(|t| t.join().expect("download thread panicked"))
+                .collect()
+        });
+
+        fetched.sort_by_key(|(i, _)| *i);
+
+        fetched
+            .into_iter()
+            .map(|(i, result)| {
+                result.map_err(|e| {
+                    tt_warning!(status, "failure downloading \"{}\"", files[i].0; e);
+                    anyhow!("failed to retrieve \"{}\" from the network", files[i].0)
+                })
+            })
+            .collect()
+    }
+
+    fn file_size(info: &Self::FileInfo) -> Option<u64> {
+        Some(info.length)
+    }
+
+    fn file_digest(info: &Self::FileInfo) -> Option<DigestData> {
+        Some(info.digest)
+    }
+}
+
+/// Get the URL of the blob holding the file with the given digest.
+fn blob_url(base_url: &str, digest: &DigestData) -> String {
+    let hex = digest.to_string();
+    format!("{}blobs/{}/{}.zst", base_url, &hex[..2], &hex[2..])
+}
+
+/// Download and decompress a blob, checking that its contents are what we
+/// expect, and retrying a few times in case of transient network problems.
+///
+/// The amount of decompressed data is reported as the progress of the current
+/// task.
+fn fetch_blob<B: GetUrlBackend>(
+    url: &str,
+    info: &FileInfo,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<u8>> {
+    let config = default_config();
+    let mut last_error = None;
+
+    for attempt in 0..config.max_attempts.max(1) {
+        if attempt > 0 {
+            thread::sleep(config.retry_delay(attempt));
+        }
+
+        let result = B::new_with_config(config.clone())
+            .get_url(url, status)
+            .and_then(|r| {
+                let mut data = Vec::new();
+                let decoder = zstd::stream::read::Decoder::new(r)?;
+                ProgressReader::new(decoder, status).read_to_end(&mut data)?;
+                Ok(data)
+            });
+
+        let data = match result {
+            Ok(d) => d,
+            Err(e) => {
+                tt_warning!(status, "failure requesting \"{}\" from network", url; e);
+                last_error = Some(anyhow!("failed to retrieve \"{}\"", url));
+                continue;
+            }
+        };
+
+        // A mismatch isn't going to fix itself, so don't retry.
+        let mut dc = digest::create();
+        dc.update(&data);
+
+        ensure!(
+            data.len() as u64 == info.length && DigestData::from(dc) == info.digest,
+            "the contents of \"{}\" don't match the bundle index",
+            url
+        );
+
+        return Ok(data);
+    }
+
+    Err(last_error.unwrap())
+}
+
+/// Information about a bundle converted with [`convert_bundle`].
+#[derive(Clone, Debug)]
+pub struct ConvertedBundle {
+    /// The path of the index file that was written.
+    pub index_path: PathBuf,
+
+    /// The digest of the bundle.
+    pub digest: DigestData,
+
+    /// The number of files in the bundle, not counting the `SHA256SUM` file.
+    pub n_files: usize,
+
+    /// The number of blobs that were written. Blobs that were already present
+    /// in the output directory are reused.
+    pub n_new_blobs: usize,
+
+    /// The total size of the files in the bundle, in bytes.
+    pub size: u64,
+
+    /// The total size of the blobs that were written, in bytes.
+    pub new_blobs_size: u64,
+}
+
+/// Convert a bundle to the content-addressed format.
+///
+/// Every file in *bundle* is read and stored in the `blobs` subdirectory of
+/// *output_dir*, and the index is written to `<name>.cabundle` in the same
+/// directory. Blobs that already exist are left alone, so several versions of
+/// a bundle can be converted into the same directory and will share the data
+/// of their common files. Files whose names contain whitespace can't be
+/// represented in the index and are skipped.
+pub fn convert_bundle<P: AsRef<Path>>(
+    bundle: &mut dyn Bundle,
+    output_dir: P,
+    name: &str,
+    status: &mut dyn StatusBackend,
+) -> Result<ConvertedBundle> {
+    let output_dir = output_dir.as_ref();
+    let blobs_dir = output_dir.join("blobs");
+    let index_path = output_dir.join(format!("{name}.{INDEX_EXTENSION}"));
+    let digest = bundle.get_digest(status)?;
+
+    let names: BTreeSet<String> = bundle
+        .all_files(status)?
+        .into_iter()
+        .filter(|n| n != digest::DIGEST_NAME)
+        .collect();
+
+    let mut index = String::new();
+    let mut result = ConvertedBundle {
+        index_path: index_path.clone(),
+        digest,
+        n_files: 0,
+        n_new_blobs: 0,
+        size: 0,
+        new_blobs_size: 0,
+    };
+
+    for name in &names {
+        if name.contains(char::is_whitespace) {
+            tt_warning!(
+                status,
+                "skipping `{}`; bundle file names may not contain whitespace",
+                name
+            );
+            continue;
+        }
+
+        let data = match bundle.input_open_name(name, status) {
+            OpenResult::Ok(mut ih) => {
+                let mut data = Vec::new();
+                atry!(
+                    ih.read_to_end(&mut data);
+                    ["failed to read `{}` from the bundle", name]
+                );
+                data
+            }
+            OpenResult::NotAvailable => bail!("the bundle lists `{}` but doesn't provide it", name),
+            OpenResult::Err(e) => return Err(e),
+        };
+
+        add_file(&mut index, &mut result, &blobs_dir, name, &data)?;
+        result.n_files += 1;
+    }
+
+    let digest_text = digest.to_string();
+    add_file(
+        &mut index,
+        &mut result,
+        &blobs_dir,
+        digest::DIGEST_NAME,
+        digest_text.as_bytes(),
+    )?;
+
+    let compressed = zstd::stream::encode_all(index.as_bytes(), COMPRESSION_LEVEL)?;
+    atry!(
+        fs::write(&index_path, compressed);
+        ["failed to write bundle index `{}`", index_path.display()]
+    );
+
+    tt_note!(
+        status,
+        "wrote bundle index `{}` with {} files",
+        index_path.display(),
+        result.n_files
+    );
+
+    Ok(result)
+}
+
+/// Store a file's data as a blob, if needed, and add it to the index.
+fn add_file(
+    index: &mut String,
+    result: &mut ConvertedBundle,
+    blobs_dir: &Path,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut dc = digest::create();
+    dc.update(data);
+    let file_digest = DigestData::from(dc);
+    let hex = file_digest.to_string();
+
+    let blob_dir = blobs_dir.join(&hex[..2]);
+    let blob_path = blob_dir.join(format!("{}.zst", &hex[2..]));
+
+    if !blob_path.exists() {
+        atry!(
+            fs::create_dir_all(&blob_dir);
+            ["failed to create directory `{}`", blob_dir.display()]
+        );
+
+        // Write under a temporary name, so that an interrupted conversion
+        // doesn't leave a truncated blob that would be reused next time.
+        let compressed = zstd::stream::encode_all(data, COMPRESSION_LEVEL)?;
+        let mut temp = tempfile::Builder::new().tempfile_in(&blob_dir)?;
+        temp.write_all(&compressed)?;
+        atry!(
+            temp.persist(&blob_path);
+            ["failed to write blob `{}`", blob_path.display()]
+        );
+
+        result.n_new_blobs += 1;
+        result.new_blobs_size += compressed.len() as u64;
+    }
+
+    result.size += data.len() as u64;
+    index.push_str(&format!("{} {} {}\n", name, data.len(), hex));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Arguments;
+    use tectonic_errors::Error;
+    use tectonic_geturl::memory::{MemoryBackend, MemoryServer, RequestKind};
+    use tectonic_io_base::IoProvider;
+    use tectonic_status_base::{MessageKind, NoopStatusBackend};
+
+    use super::*;
+    use crate::{cache::Cache, dir::DirBundle, itar::tests::read_file};
+
+    type MemoryCasBackend = ContentAddressedBackend<MemoryBackend>;
+
+    /// Convert a bundle of *files* into *dir*, as `<name>.cabundle`.
+    fn convert(dir: &Path, name: &str, files: &[(&str, &[u8])]) {
+        let src = tempfile::tempdir().unwrap();
+
+        for (file, data) in files {
+            fs::write(src.path().join(file), data).unwrap();
+        }
+
+        let mut status = NoopStatusBackend::default();
+        convert_bundle(&mut DirBundle::new(src.path()), dir, name, &mut status).unwrap();
+    }
+
+    /// Serve the contents of *dir*, and the directories below it, from the
+    /// root of *server*.
+    fn serve_dir(server: &mut MemoryServer, dir: &Path, prefix: &str) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let path = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+
+            if entry.file_type().unwrap().is_dir() {
+                serve_dir(server, &entry.path(), &path);
+            } else {
+                server.set_file(&path, fs::read(entry.path()).unwrap());
+            }
+        }
+    }
+
+    /// The path of the blob holding *data*.
+    fn blob_path(data: &[u8]) -> String {
+        let mut dc = digest::create();
+        dc.update(data);
+        blob_url("/", &DigestData::from(dc))
+    }
+
+    fn count_gets(server: &MemoryServer, path: &str) -> usize {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.kind == RequestKind::Get && r.path == path)
+            .count()
+    }
+
+    #[test]
+    fn blobs_are_reused_across_versions() {
+        let bundle_dir = tempfile::tempdir().unwrap();
+        convert(
+            bundle_dir.path(),
+            "v1",
+            &[("a.tex", b"same"), ("b.tex", b"old")],
+        );
+        convert(
+            bundle_dir.path(),
+            "v2",
+            &[("a.tex", b"same"), ("b.tex", b"new")],
+        );
+
+        let mut server = MemoryServer::new();
+        serve_dir(&mut server, bundle_dir.path(), "");
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+        let mut status = NoopStatusBackend::default();
+
+        let mut v1 = cache
+            .open::<MemoryCasBackend>(&server.url("/v1.cabundle"), false, &mut status)
+            .unwrap();
+        assert_eq!(read_file(&mut v1, "a.tex", &mut status), b"same");
+        assert_eq!(read_file(&mut v1, "b.tex", &mut status), b"old");
+
+        server.clear_requests();
+        let mut v2 = cache
+            .open::<MemoryCasBackend>(&server.url("/v2.cabundle"), false, &mut status)
+            .unwrap();
+        assert_eq!(read_file(&mut v2, "a.tex", &mut status), b"same");
+        assert_eq!(read_file(&mut v2, "b.tex", &mut status), b"new");
+
+        assert_eq!(count_gets(&server, &blob_path(b"same")), 0);
+        assert_eq!(count_gets(&server, &blob_path(b"new")), 1);
+    }
+
+    #[test]
+    fn dropped_connections_are_retried() {
+        let bundle_dir = tempfile::tempdir().unwrap();
+        convert(bundle_dir.path(), "b", &[("a.tex", b"hello")]);
+
+        let mut server = MemoryServer::new();
+        serve_dir(&mut server, bundle_dir.path(), "");
+        let blob = blob_path(b"hello");
+        server.fail_next(&blob, 2);
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+        let mut status = NoopStatusBackend::default();
+        let mut bundle = cache
+            .open::<MemoryCasBackend>(&server.url("/b.cabundle"), false, &mut status)
+            .unwrap();
+
+        assert_eq!(read_file(&mut bundle, "a.tex", &mut status), b"hello");
+        assert_eq!(count_gets(&server, &blob), 3);
+    }
+
+    #[test]
+    fn mismatched_blobs_are_rejected() {
+        let bundle_dir = tempfile::tempdir().unwrap();
+        convert(bundle_dir.path(), "b", &[("a.tex", b"hello")]);
+
+        let mut server = MemoryServer::new();
+        serve_dir(&mut server, bundle_dir.path(), "");
+        let blob = blob_path(b"hello");
+        server.set_file(&blob, zstd::stream::encode_all(&b"jello"[..], 0).unwrap());
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+        let mut status = NoopStatusBackend::default();
+        let mut bundle = cache
+            .open::<MemoryCasBackend>(&server.url("/b.cabundle"), false, &mut status)
+            .unwrap();
+
+        assert!(matches!(
+            bundle.input_open_name("a.tex", &mut status),
+            OpenResult::Err(_)
+        ));
+
+        // A mismatch won't go away by itself, so it isn't retried.
+        assert_eq!(count_gets(&server, &blob), 1);
+    }
+
+    /// A status backend that records progress updates.
+    #[derive(Default)]
+    struct ProgressUpdates(Vec<u64>);
+
+    impl StatusBackend for ProgressUpdates {
+        fn report(&mut self, _kind: MessageKind, _args: Arguments, _err: Option<&Error>) {}
+
+        fn dump_error_logs(&mut self, _output: &[u8]) {}
+
+        fn progress_update(&mut self, done: u64) {
+            self.0.push(done);
+        }
+    }
+
+    #[test]
+    fn downloads_report_progress() {
+        let bundle_dir = tempfile::tempdir().unwrap();
+        let data = vec![b'x'; 100_000];
+        convert(bundle_dir.path(), "b", &[("big.tex", &data)]);
+
+        let mut server = MemoryServer::new();
+        serve_dir(&mut server, bundle_dir.path(), "");
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+        let mut status = ProgressUpdates::default();
+        let mut bundle = cache
+            .open::<MemoryCasBackend>(&server.url("/b.cabundle"), false, &mut status)
+            .unwrap();
+
+        status.0.clear();
+        assert_eq!(read_file(&mut bundle, "big.tex", &mut status), data);
+        assert!(status.0.len() > 1);
+        assert_eq!(status.0.last(), Some(&100_000));
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Creation of "indexed tar" bundles.
+//!
+//! The main type offered by this module is [`BundleCreator`], which gathers
+//! TeX support files from one or more source directories (such as a TeX
+//! Live-style `texmf` tree) and writes them out in the format consumed by
+//! [`crate::itar::IndexedTarBackend`]:
+//!
+//! - a plain `tar` file containing every selected file, plus a `SHA256SUM`
+//!   member holding the bundle digest;
+//! - a gzipped index, stored next to the tar file with an `.index.gz` suffix,
+//!   giving the name, byte offset, and length of each member.
+//!
+//! Bundles are flat: files are identified by their base names only, so the
+//! directory structure of the source trees is discarded. If several selected
+//! files share a name, the first one found wins. Source directories are
+//! searched in the order that they were given, and each directory is walked
+//! in sorted order, so the outcome is deterministic.
+//!
+//! The bundle digest is computed from the sorted names and SHA256 digests of
+//! the component files, in the same way that [`crate::dir::DirBundle`]
+//! computes the digest of a directory lacking a `SHA256SUM` file.
+
+use flate2::{write::GzEncoder, Compression};
+use globset::{GlobBuilder, GlobMatcher};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tectonic_errors::prelude::*;
+use tectonic_io_base::digest::{self, Digest, DigestData};
+use tectonic_status_base::{tt_note, tt_warning, StatusBackend};
+
+use crate::tar::TarWriter;
+
+/// A tool for creating "indexed tar" bundles.
+///
+/// Configure the creator with one or more source directories, and optionally
+/// some selection patterns, then call [`Self::create`] to write out the
+/// bundle.
+///
+/// Selection patterns are globs that are matched against the paths of files
+/// relative to the source directory that contains them, using `/` as the
+/// directory separator. A `*` does not match across directory separators,
+/// while `**` does. Patterns that do not contain a `/` are matched against the
+/// file's base name only, so that `*.sty` selects style files anywhere in the
+/// tree. If no inclusion patterns are given, all files are included. A file is
+/// excluded if it matches any exclusion pattern.
+#[derive(Debug, Default)]
+pub struct BundleCreator {
+    sources: Vec<PathBuf>,
+    includes: Vec<GlobMatcher>,
+    excludes: Vec<GlobMatcher>,
+}
+
+/// Information about a bundle created with [`BundleCreator::create`].
+#[derive(Clone, Debug)]
+pub struct CreatedBundle {
+    /// The path of the tar file that was written.
+    pub tar_path: PathBuf,
+
+    /// The path of the gzipped index that was written.
+    pub index_path: PathBuf,
+
+    /// The digest of the bundle.
+    pub digest: DigestData,
+
+    /// The number of files in the bundle, not counting the `SHA256SUM` file.
+    pub n_files: usize,
+
+    /// The number of selected files that were dropped because an earlier
+    /// file had the same name.
+    pub n_duplicates: usize,
+
+    /// The number of dropped duplicate files whose contents differed from the
+    /// file that was kept.
+    pub n_conflicts: usize,
+}
+
+impl BundleCreator {
+    /// Create a new bundle creator with no sources and no selection patterns.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a source directory.
+    ///
+    /// Directories are searched in the order that they are added, so that
+    /// files in earlier directories take precedence over same-named files in
+    /// later ones.
+    pub fn source<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.sources.push(dir.into());
+        self
+    }
+
+    /// Add a pattern selecting files to include in the bundle.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut Self> {
+        self.includes.push(compile_pattern(pattern)?);
+        Ok(self)
+    }
+
+    /// Add a pattern selecting files to exclude from the bundle.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut Self> {
+        self.excludes.push(compile_pattern(pattern)?);
+        Ok(self)
+    }
+
+    /// Add the patterns listed in a selection specification.
+    ///
+    /// The specification is line-oriented. Blank lines and lines starting
+    /// with `#` are ignored. Lines starting with `!` give exclusion patterns,
+    /// and all other lines give inclusion patterns.
+    pub fn selection_spec(&mut self, text: &str) -> Result<&mut Self> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(pattern) = line.strip_prefix('!') {
+                atry!(
+                    self.exclude(pattern.trim());
+                    ["bad pattern on line {} of the selection specification", i + 1]
+                );
+            } else {
+                atry!(
+                    self.include(line);
+                    ["bad pattern on line {} of the selection specification", i + 1]
+                );
+            }
+        }
+
+        Ok(self)
+    }
+
+    fn is_selected(&self, rel_path: &str) -> bool {
+        let included =
+            self.includes.is_empty() || self.includes.iter().any(|m| m.is_match(rel_path));
+        included && !self.excludes.iter().any(|m| m.is_match(rel_path))
+    }
+
+    /// Create the bundle.
+    ///
+    /// The tar file is written to *tar_path*, and the index is written to the
+    /// same path with `.index.gz` appended. Existing files are overwritten.
+    pub fn create<P: AsRef<Path>>(
+        &self,
+        tar_path: P,
+        status: &mut dyn StatusBackend,
+    ) -> Result<CreatedBundle> {
+        let tar_path = tar_path.as_ref().to_owned();
+        let index_path = crate::itar::index_path(&tar_path);
+
+        ensure!(
+            !self.sources.is_empty(),
+            "no bundle source directories given"
+        );
+
+        // Step 1: select the files, resolving duplicates.
+
+        let mut selected: HashMap<String, PathBuf> = HashMap::new();
+        let mut n_duplicates = 0;
+        let mut n_conflicts = 0;
+
+        for source in &self.sources {
+            let mut files = Vec::new();
+            atry!(
+                walk_dir(source, "", &mut files);
+                ["failed to scan bundle source directory `{}`", source.display()]
+            );
+
+            for (rel_path, full_path) in files {
+                if !self.is_selected(&rel_path) {
+                    continue;
+                }
+
+                let name = match rel_path.rsplit('/').next() {
+                    Some(n) => n.to_owned(),
+                    None => continue,
+                };
+
+                if name == digest::DIGEST_NAME {
+                    tt_warning!(
+                        status,
+                        "ignoring `{}`; the bundle digest file is generated automatically",
+                        full_path.display()
+                    );
+                    continue;
+                }
+
+                if name.contains(char::is_whitespace) {
+                    tt_warning!(
+                        status,
+                        "ignoring `{}`; bundle file names may not contain whitespace",
+                        full_path.display()
+                    );
+                    continue;
+                }
+
+                if let Some(kept) = selected.get(&name) {
+                    n_duplicates += 1;
+
+                    if digest_file(kept)? != digest_file(&full_path)? {
+                        n_conflicts += 1;
+                        tt_warning!(
+                            status,
+                            "`{}` differs from `{}`, which takes precedence",
+                            full_path.display(),
+                            kept.display()
+                        );
+                    }
+
+                    continue;
+                }
+
+                selected.insert(name, full_path);
+            }
+        }
+
+        let mut names: Vec<_> = selected.keys().cloned().collect();
+        names.sort();
+
+        // Step 2: write the tar file, computing digests as we go.
+
+        let mut tar = TarWriter::new(BufWriter::new(atry!(
+            File::create(&tar_path);
+            ["failed to create bundle file `{}`", tar_path.display()]
+        )));
+        let mut index = GzEncoder::new(
+            BufWriter::new(atry!(
+                File::create(&index_path);
+                ["failed to create bundle index `{}`", index_path.display()]
+            )),
+            Compression::default(),
+        );
+        let mut bundle_dc = digest::create();
+
+        for name in &names {
+            let path = &selected[name];
+            let mut data = Vec::new();
+            atry!(
+                File::open(path).and_then(|mut f| f.read_to_end(&mut data));
+                ["failed to read `{}`", path.display()]
+            );
+
+            let mut dc = digest::create();
+            dc.update(&data);
+            let file_digest = DigestData::from(dc);
+
+            crate::update_content_digest(&mut bundle_dc, name, &file_digest);
+
+            let offset = tar.append(name, &data)?;
+            writeln!(index, "{} {} {}", name, offset, data.len())?;
+        }
+
+        let bundle_digest = DigestData::from(bundle_dc);
+        let digest_text = bundle_digest.to_string();
+        let offset = tar.append(digest::DIGEST_NAME, digest_text.as_bytes())?;
+        writeln!(
+            index,
+            "{} {} {}",
+            digest::DIGEST_NAME,
+            offset,
+            digest_text.len()
+        )?;
+
+        tar.finish()?.flush()?;
+        index.finish()?.flush()?;
+
+        tt_note!(
+            status,
+            "wrote bundle `{}` with {} files",
+            tar_path.display(),
+            names.len()
+        );
+
+        Ok(CreatedBundle {
+            tar_path,
+            index_path,
+            digest: bundle_digest,
+            n_files: names.len(),
+            n_duplicates,
+            n_conflicts,
+        })
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<GlobMatcher> {
+    let full = if pattern.contains('/') {
+        pattern.to_owned()
+    } else {
+        format!("**/{pattern}")
+    };
+
+    Ok(atry!(
+        GlobBuilder::new(&full).literal_separator(true).build();
+        ["invalid file selection pattern `{}`", pattern]
+    )
+    .compile_matcher())
+}
+
+/// Recursively list the files in *dir*, in sorted order, as pairs of relative
+/// paths (with `/` separators) and full paths. Symbolic links are followed.
+fn walk_dir(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<std::io::Result<Vec<OsString>>>()?;
+    entries.sort();
+
+    for entry in entries {
+        let name = match entry.to_str() {
+            Some(n) => n,
+            None => bail!(
+                "cannot handle non-Unicode file name `{}`",
+                dir.join(&entry).display()
+            ),
+        };
+
+        let full_path = dir.join(name);
+        let rel_path = format!("{prefix}{name}");
+        let metadata = atry!(
+            fs::metadata(&full_path);
+            ["failed to probe `{}`", full_path.display()]
+        );
+
+        if metadata.is_dir() {
+            walk_dir(&full_path, &format!("{rel_path}/"), files)?;
+        } else if metadata.is_file() {
+            files.push((rel_path, full_path));
+        }
+    }
+
+    Ok(())
+}
+
+fn digest_file(path: &Path) -> Result<DigestData> {
+    let mut dc = digest::create();
+    let mut f = atry!(File::open(path); ["failed to open `{}`", path.display()]);
+    std::io::copy(&mut f, &mut dc)?;
+    Ok(DigestData::from(dc))
+}
@@ -6,9 +6,14 @@
 use std::{
     fs,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 use tectonic_errors::prelude::*;
-use tectonic_io_base::{filesystem::FilesystemIo, InputHandle, IoProvider, OpenResult};
+use tectonic_io_base::{
+    digest::{self, DigestData},
+    filesystem::FilesystemIo,
+    InputHandle, IoProvider, OpenResult,
+};
 use tectonic_status_base::StatusBackend;
 
 use super::Bundle;
@@ -17,9 +22,10 @@ use super::Bundle;
 ///
 /// This implementation essentially just wraps
 /// [`tectonic_io_base::filesystem::FilesystemIo`], ensuring that it is
-/// read-only, self-contained, and implements the [`Bundle`] trait. The
-/// directory should contain a file named `SHA256SUM` if the bundle fingerprint
-/// will be needed.
+/// read-only, self-contained, and implements the [`Bundle`] trait. If the
+/// directory contains a file named `SHA256SUM`, it provides the bundle
+/// fingerprint. Otherwise, the fingerprint is computed from the contents of
+/// the files in the directory.
 pub struct DirBundle(FilesystemIo);
 
 impl DirBundle {
@@ -56,6 +62,31 @@ impl IoProvider for DirBundle {
 }
 
 impl Bundle for DirBundle {
+    fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        let root = self.0.root().to_owned();
+
+        if let Ok(text) = fs::read_to_string(root.join(digest::DIGEST_NAME)) {
+            return Ok(atry!(
+                DigestData::from_str(text.trim());
+                ["corrupted SHA256 digest data in `{}`", root.display()]
+            ));
+        }
+
+        let mut names = self.all_files(status)?;
+        names.sort();
+        let mut dc = digest::create();
+
+        for name in &names {
+            let path = root.join(name);
+            let mut file_dc = digest::create();
+            let mut f = atry!(fs::File::open(&path); ["failed to open `{}`", path.display()]);
+            std::io::copy(&mut f, &mut file_dc)?;
+            crate::update_content_digest(&mut dc, name, &DigestData::from(file_dc));
+        }
+
+        Ok(DigestData::from(dc))
+    }
+
     fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
         let mut files = Vec::new();
 
@@ -73,4 +104,32 @@ impl Bundle for DirBundle {
 
         Ok(files)
     }
+
+    fn all_files_with_sizes(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        let root = self.0.root().to_owned();
+
+        Ok(self
+            .all_files(status)?
+            .into_iter()
+            .map(|name| {
+                let size = fs::metadata(root.join(&name)).ok().map(|md| md.len());
+                (name, size)
+            })
+            .collect())
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        let root = self.0.root();
+
+        Some(
+            names
+                .iter()
+                .filter(|name| matches!(fs::metadata(root.join(name)), Ok(md) if !md.is_dir()))
+                .cloned()
+                .collect(),
+        )
+    }
 }
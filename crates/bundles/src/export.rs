@@ -0,0 +1,255 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Exporting bundle contents to portable archives.
+//!
+//! This module makes it possible to take TeX support files to machines that
+//! can't reach the network. [`export_bundle`] writes a selection of files from
+//! a bundle into a ZIP or tar archive, along with a `SHA256SUM` file recording
+//! the digest of the bundle that they came from. A ZIP archive can be used
+//! directly with [`crate::zip::ZipBundle`], and an unpacked tar archive with
+//! [`crate::dir::DirBundle`]. Either kind can also be imported into a local
+//! cache with [`crate::cache::Cache::import_archive`], after which its files
+//! are available through the bundle’s usual URL.
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+use tectonic_errors::prelude::*;
+use tectonic_io_base::{
+    digest::{self, DigestData},
+    OpenResult,
+};
+use tectonic_status_base::StatusBackend;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    tar::{read_tar, TarWriter},
+    Bundle,
+};
+
+/// The kinds of archive that bundle files can be exported to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    /// A ZIP archive.
+    Zip,
+
+    /// A tar archive.
+    Tar,
+}
+
+impl ArchiveFormat {
+    /// Determine the format of an archive from the extension of its path.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("zip") => Ok(ArchiveFormat::Zip),
+            Some(e) if e.eq_ignore_ascii_case("tar") => Ok(ArchiveFormat::Tar),
+            _ => bail!(
+                "can't tell the archive format of `{}`; its name should end in `.zip` or `.tar`",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Information about an archive written by [`export_bundle`].
+#[derive(Clone, Debug)]
+pub struct ExportedBundle {
+    /// The digest of the bundle that the files came from.
+    pub digest: DigestData,
+
+    /// The number of files written, not counting the `SHA256SUM` file.
+    pub n_files: usize,
+
+    /// The requested files that the bundle couldn't provide.
+    pub missing: Vec<String>,
+}
+
+/// Export files from a bundle into an archive.
+///
+/// The archive format is determined from the extension of *path* using
+/// [`ArchiveFormat::from_path`]. The files named in *names* are read from the
+/// bundle and written to the archive in sorted order; names that the bundle
+/// doesn’t provide are skipped and reported in [`ExportedBundle::missing`].
+/// The bundle’s digest is stored in a `SHA256SUM` file, so that the archive
+/// can stand in for the bundle when it comes to caching format files.
+pub fn export_bundle<P: AsRef<Path>>(
+    bundle: &mut dyn Bundle,
+    names: &[String],
+    path: P,
+    status: &mut dyn StatusBackend,
+) -> Result<ExportedBundle> {
+    let path = path.as_ref();
+    let format = ArchiveFormat::from_path(path)?;
+    let digest = bundle.get_digest(status)?;
+
+    let names: BTreeSet<&str> = names
+        .iter()
+        .map(|n| n.as_str())
+        .filter(|n| *n != digest::DIGEST_NAME)
+        .collect();
+
+    let file = BufWriter::new(atry!(
+        File::create(path);
+        ["failed to create archive `{}`", path.display()]
+    ));
+
+    let mut writer = match format {
+        ArchiveFormat::Zip => ArchiveWriter::Zip(ZipWriter::new(file)),
+        ArchiveFormat::Tar => ArchiveWriter::Tar(TarWriter::new(file)),
+    };
+
+    let mut n_files = 0;
+    let mut missing = Vec::new();
+
+    for name in names {
+        let data = match bundle.input_open_name(name, status) {
+            OpenResult::Ok(mut ih) => {
+                let mut data = Vec::new();
+                atry!(
+                    ih.read_to_end(&mut data);
+                    ["failed to read `{}` from the bundle", name]
+                );
+                data
+            }
+
+            OpenResult::NotAvailable => {
+                missing.push(name.to_owned());
+                continue;
+            }
+
+            OpenResult::Err(e) => {
+                return Err(e);
+            }
+        };
+
+        writer.append(name, &data)?;
+        n_files += 1;
+    }
+
+    let digest_text = digest.to_string();
+    writer.append(digest::DIGEST_NAME, digest_text.as_bytes())?;
+    atry!(
+        writer.finish();
+        ["failed to finish writing archive `{}`", path.display()]
+    );
+
+    Ok(ExportedBundle {
+        digest,
+        n_files,
+        missing,
+    })
+}
+
+enum ArchiveWriter<W: Write + Seek> {
+    Zip(ZipWriter<W>),
+    Tar(TarWriter<W>),
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        match self {
+            ArchiveWriter::Zip(z) => {
+                let options =
+                    FileOptions::default().compression_method(CompressionMethod::Deflated);
+                z.start_file(name, options)?;
+                z.write_all(data)?;
+            }
+
+            ArchiveWriter::Tar(t) => {
+                t.append(name, data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            ArchiveWriter::Zip(mut z) => z.finish()?.flush()?,
+            ArchiveWriter::Tar(t) => t.finish()?.flush()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the files in an exported archive, calling *f* with the name and
+/// contents of each one in turn.
+///
+/// Besides ZIP and tar archives, *path* may also be a directory, such as an
+/// unpacked tar archive. Bundles are flat, so any directory components of the
+/// names of archive members are dropped, and subdirectories are ignored.
+pub fn read_archive<P, F>(path: P, mut f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str, Vec<u8>) -> Result<()>,
+{
+    let path = path.as_ref();
+
+    if path.is_dir() {
+        let mut entries = Vec::new();
+
+        for entry in atry!(fs::read_dir(path); ["failed to read directory `{}`", path.display()]) {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() {
+                if let Ok(name) = entry.file_name().into_string() {
+                    entries.push((name, entry.path()));
+                }
+            }
+        }
+
+        entries.sort();
+
+        for (name, entry_path) in entries {
+            let data = atry!(fs::read(&entry_path); ["failed to read `{}`", entry_path.display()]);
+            f(&name, data)?;
+        }
+
+        return Ok(());
+    }
+
+    let file = atry!(File::open(path); ["failed to open archive `{}`", path.display()]);
+
+    match ArchiveFormat::from_path(path)? {
+        ArchiveFormat::Zip => {
+            let mut zip = atry!(
+                ZipArchive::new(BufReader::new(file));
+                ["failed to read ZIP archive `{}`", path.display()]
+            );
+
+            for i in 0..zip.len() {
+                let mut item = zip.by_index(i)?;
+
+                if item.is_dir() {
+                    continue;
+                }
+
+                let name = base_name(item.name()).to_owned();
+                let mut data = Vec::with_capacity(item.size() as usize);
+                item.read_to_end(&mut data)?;
+                f(&name, data)?;
+            }
+        }
+
+        ArchiveFormat::Tar => {
+            atry!(
+                read_tar(BufReader::new(file), |name, data| f(base_name(name), data));
+                ["failed to read tar archive `{}`", path.display()]
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the last component of an archive member name.
+fn base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
@@ -13,17 +13,56 @@
 //! centered on HTTP byte-range requests. For each file contained in the backing
 //! resource, the index file merely contains a byte offset and length that are
 //! then used to construct an HTTP Range request to obtain the file as needed.
+//! When many files are wanted at once, as when prefetching, files that lie
+//! close together in the tar file are fetched with a single request, and
+//! several requests are made concurrently. If the bundle has mirrors and a
+//! file can't be fetched, the backend switches to the next mirror that serves
+//! the same bundle.
+//!
+//! Indexed tar files can also be used without a web server. The
+//! [`IndexedTarBundle`] struct reads the tar file and its index directly from
+//! the local filesystem, seeking to each file as it's needed, so that a bundle
+//! on a shared network volume can be used without unpacking it.
 
 use flate2::read::GzDecoder;
-use std::{convert::TryInto, io::Read, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
 use tectonic_errors::prelude::*;
-use tectonic_geturl::{DefaultBackend, DefaultRangeReader, GetUrlBackend, RangeReader};
-use tectonic_io_base::digest::{self, DigestData};
-use tectonic_status_base::{tt_note, tt_warning, StatusBackend};
+use tectonic_geturl::{config::default_config, DefaultBackend, GetUrlBackend, RangeReader};
+use tectonic_io_base::{
+    digest::{self, DigestData},
+    InputHandle, InputOrigin, IoProvider, OpenResult,
+};
+use tectonic_status_base::{progress::ProgressReader, tt_note, tt_warning, StatusBackend};
+
+use crate::{
+    cache::{BackendPullData, CacheBackend},
+    mirrors::MirrorList,
+    Bundle,
+};
 
-use crate::cache::{BackendPullData, CacheBackend};
+/// The maximum number of concurrent requests made when fetching many files.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
 
-const MAX_HTTP_ATTEMPTS: usize = 4;
+/// Files separated by at most this many bytes are fetched in a single request.
+///
+/// Consecutive files in a tar file are separated by a 512-byte header, plus
+/// padding, so this allows a few small unwanted files in between, too.
+const MAX_COALESCING_GAP: u64 = 4096;
+
+/// Requests are not coalesced beyond this length.
+const MAX_COALESCED_LENGTH: u64 = 4 * 1024 * 1024;
 
 /// The internal file-information struct used by the [`IndexedTarBackend`].
 #[derive(Clone, Copy, Debug)]
@@ -37,12 +76,185 @@ pub struct FileInfo {
 /// This type implements the [`CacheBackend`] trait and so can be used for
 /// web-based bundle access thorugh the [`crate::cache::CachingBundle`]
 /// framework.
+///
+/// The type parameter is the get-URL backend used to make requests. It only
+/// needs to be given in tests, which can use the in-memory
+/// [`tectonic_geturl::memory::MemoryBackend`].
 #[derive(Debug)]
-pub struct IndexedTarBackend {
-    reader: DefaultRangeReader,
+pub struct IndexedTarBackend<B: GetUrlBackend = DefaultBackend> {
+    url: String,
+    reader: B::RangeReader,
+
+    /// The location of the digest file and its contents, used to check that a
+    /// mirror serves the same bundle before switching to it.
+    digest: Option<(FileInfo, DigestData)>,
+
+    /// The mirrors serving the bundle, if any.
+    mirrors: MirrorList,
+
+    /// The mirror that [`Self::url`] was resolved from, if known.
+    mirror: Option<String>,
+
+    /// Mirrors that have failed while this backend was in use.
+    failed_mirrors: HashSet<String>,
 }
 
-impl CacheBackend for IndexedTarBackend {
+impl<B: GetUrlBackend> IndexedTarBackend<B> {
+    fn new(url: &str) -> Self {
+        IndexedTarBackend {
+            url: url.to_owned(),
+            reader: B::default().open_range_reader(url),
+            digest: None,
+            mirrors: MirrorList::default(),
+            mirror: None,
+            failed_mirrors: HashSet::new(),
+        }
+    }
+
+    /// Fetch a file from the current URL, retrying a few times in case of
+    /// transient network problems.
+    fn get_file_with_retries(
+        &mut self,
+        name: &str,
+        info: &FileInfo,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<u8>> {
+        // Historically, sometimes our web service would drop connections when
+        // fetching a bunch of resource files (i.e., on the first invocation).
+        // The error manifested itself in a way that has a not-so-nice user
+        // experience. Our solution: retry the request a few times in case it
+        // was a transient problem.
+
+        let n = info.length.try_into().unwrap();
+        let mut buf = Vec::with_capacity(n);
+        let mut overall_failed = true;
+        let mut any_failed = false;
+
+        // Our HTTP implementation actually has problems with zero-sized ranged
+        // reads (Azure gives us a 200 response, which we don't properly
+        // handle), but when the file is 0-sized we're all set anyway!
+        if n > 0 {
+            let config = default_config();
+
+            for attempt in 0..config.max_attempts.max(1) {
+                if attempt > 0 {
+                    thread::sleep(config.retry_delay(attempt));
+                }
+
+                buf.clear();
+
+                let stream = match self.reader.read_range(info.offset, n) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        tt_warning!(status, "failure requesting \"{}\" from network", name; e);
+                        any_failed = true;
+                        continue;
+                    }
+                };
+
+                let result = ProgressReader::new(stream, status).read_to_end(&mut buf);
+
+                if let Err(e) = result {
+                    tt_warning!(status, "failure downloading \"{}\" from network", name; e.into());
+                    any_failed = true;
+                    continue;
+                }
+
+                overall_failed = false;
+                break;
+            }
+
+            if overall_failed {
+                bail!(
+                    "failed to retrieve \"{}\" from the network; \
+                this most probably is not Tectonic's fault \
+                -- please check your network connection.",
+                    name
+                );
+            } else if any_failed {
+                tt_note!(status, "download succeeded after retry");
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Switch to the next mirror that serves the same bundle, returning false
+    /// if there are none left to try.
+    fn switch_mirror(&mut self, status: &mut dyn StatusBackend) -> bool {
+        if let Some(current) = self.mirror.take() {
+            self.mirrors.record_failure(&current);
+            self.failed_mirrors.insert(current);
+        }
+
+        let (digest_info, digest) = match self.digest {
+            Some(d) => d,
+            None => return false,
+        };
+
+        let mut geturl_backend = B::default();
+
+        for mirror in self.mirrors.ordered() {
+            if self.failed_mirrors.contains(&mirror) {
+                continue;
+            }
+
+            // Whatever happens, we won't want to try this one again.
+            self.failed_mirrors.insert(mirror.clone());
+
+            let resolved_url = match geturl_backend.resolve_url(&mirror, status) {
+                Ok(u) if u != self.url => u,
+                Ok(_) => continue,
+                Err(e) => {
+                    tt_warning!(status, "failed to reach bundle mirror `{}`", mirror; e);
+                    self.mirrors.record_failure(&mirror);
+                    continue;
+                }
+            };
+
+            let mut candidate = Self::new(&resolved_url);
+
+            let served_digest = candidate
+                .get_file_with_retries(digest::DIGEST_NAME, &digest_info, status)
+                .map(|d| {
+                    String::from_utf8(d)
+                        .ok()
+                        .and_then(|d| DigestData::from_str(d.trim()).ok())
+                });
+
+            match served_digest {
+                Ok(Some(d)) if d == digest => {}
+
+                Ok(_) => {
+                    tt_warning!(
+                        status,
+                        "bundle mirror `{}` doesn't serve the same bundle; ignoring it",
+                        mirror
+                    );
+                    self.mirrors.record_failure(&mirror);
+                    continue;
+                }
+
+                Err(e) => {
+                    tt_warning!(status, "failed to reach bundle mirror `{}`", mirror; e);
+                    self.mirrors.record_failure(&mirror);
+                    continue;
+                }
+            }
+
+            tt_note!(status, "switching to bundle mirror `{}`", mirror);
+            self.mirrors.record_success(&mirror);
+            self.url = candidate.url;
+            self.reader = candidate.reader;
+            self.mirror = Some(mirror);
+            return true;
+        }
+
+        false
+    }
+}
+
+impl<B: GetUrlBackend> CacheBackend for IndexedTarBackend<B> {
     type FileInfo = FileInfo;
 
     fn open_with_pull(
@@ -50,7 +262,7 @@ impl CacheBackend for IndexedTarBackend {
         status: &mut dyn StatusBackend,
     ) -> Result<(Self, BackendPullData)> {
         // Step 1: resolve URL
-        let mut geturl_backend = DefaultBackend::default();
+        let mut geturl_backend = B::default();
         let resolved_url = geturl_backend.resolve_url(start_url, status)?;
 
         // Step 2: fetch index
@@ -58,16 +270,21 @@ impl CacheBackend for IndexedTarBackend {
             let mut index = String::new();
             let index_url = format!("{}.index.gz", &resolved_url);
             tt_note!(status, "downloading index {}", index_url);
-            GzDecoder::new(geturl_backend.get_url(&index_url, status)?)
-                .read_to_string(&mut index)?;
+            let response = geturl_backend.get_url(&index_url, status)?;
+
+            // The size of the index isn't known in advance.
+            status.progress_start("downloading index", None);
+            let result =
+                GzDecoder::new(ProgressReader::new(response, status)).read_to_string(&mut index);
+            status.progress_finish();
+
+            result?;
             index
         };
 
         // Step 3: get digest, setting up instance as we go
 
-        let mut cache_backend = IndexedTarBackend {
-            reader: geturl_backend.open_range_reader(&resolved_url),
-        };
+        let mut cache_backend = Self::new(&resolved_url);
 
         let digest_info = {
             let mut digest_info = None;
@@ -90,7 +307,8 @@ impl CacheBackend for IndexedTarBackend {
         let digest_text =
             String::from_utf8(cache_backend.get_file(digest::DIGEST_NAME, &digest_info, status)?)
                 .map_err(|e| e.utf8_error())?;
-        let digest = DigestData::from_str(&digest_text)?;
+        let digest = DigestData::from_str(digest_text.trim())?;
+        cache_backend.digest = Some((digest_info, digest));
 
         // All done.
         Ok((
@@ -108,13 +326,12 @@ impl CacheBackend for IndexedTarBackend {
         digest_file_info: &Self::FileInfo,
         status: &mut dyn StatusBackend,
     ) -> Result<Option<(Self, DigestData)>> {
-        let mut cache_backend = IndexedTarBackend {
-            reader: DefaultBackend::default().open_range_reader(resolved_url),
-        };
+        let mut cache_backend = Self::new(resolved_url);
 
         if let Ok(d) = cache_backend.get_file(digest::DIGEST_NAME, digest_file_info, status) {
             if let Ok(d) = String::from_utf8(d) {
-                if let Ok(d) = DigestData::from_str(&d) {
+                if let Ok(d) = DigestData::from_str(d.trim()) {
+                    cache_backend.digest = Some((*digest_file_info, d));
                     return Ok(Some((cache_backend, d)));
                 }
             }
@@ -148,53 +365,576 @@ impl CacheBackend for IndexedTarBackend {
     ) -> Result<Vec<u8>> {
         tt_note!(status, "downloading {}", name);
 
-        // Historically, sometimes our web service would drop connections when
-        // fetching a bunch of resource files (i.e., on the first invocation).
-        // The error manifested itself in a way that has a not-so-nice user
-        // experience. Our solution: retry the request a few times in case it
-        // was a transient problem.
+        loop {
+            match self.get_file_with_retries(name, info, status) {
+                Ok(buf) => return Ok(buf),
+                Err(e) => {
+                    if !self.switch_mirror(status) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
 
-        let n = info.length.try_into().unwrap();
-        let mut buf = Vec::with_capacity(n);
-        let mut overall_failed = true;
-        let mut any_failed = false;
+    fn get_files(
+        &mut self,
+        files: &[(String, Self::FileInfo)],
+        status: &mut dyn StatusBackend,
+    ) -> Vec<Result<Vec<u8>>> {
+        // Group the files into byte ranges of the tar file, merging ranges
+        // that are close together.
 
-        // Our HTTP implementation actually has problems with zero-sized ranged
-        // reads (Azure gives us a 200 response, which we don't properly
-        // handle), but when the file is 0-sized we're all set anyway!
-        if n > 0 {
-            for _ in 0..MAX_HTTP_ATTEMPTS {
-                let mut stream = match self.reader.read_range(info.offset, n) {
-                    Ok(r) => r,
-                    Err(e) => {
-                        tt_warning!(status, "failure requesting \"{}\" from network", name; e);
-                        any_failed = true;
-                        continue;
-                    }
-                };
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        order.sort_by_key(|&i| files[i].1.offset);
 
-                if let Err(e) = stream.read_to_end(&mut buf) {
-                    tt_warning!(status, "failure downloading \"{}\" from network", name; e.into());
-                    any_failed = true;
+        let mut ranges: Vec<CoalescedRange> = Vec::new();
+
+        for i in order {
+            let info = &files[i].1;
+
+            if info.length == 0 {
+                continue;
+            }
+
+            let end = info.offset + info.length;
+
+            if let Some(r) = ranges.last_mut() {
+                let new_end = end.max(r.offset + r.length);
+
+                if info.offset <= r.offset + r.length + MAX_COALESCING_GAP
+                    && new_end - r.offset <= MAX_COALESCED_LENGTH
+                {
+                    r.length = new_end - r.offset;
+                    r.members.push(i);
                     continue;
                 }
+            }
 
-                overall_failed = false;
-                break;
+            ranges.push(CoalescedRange {
+                offset: info.offset,
+                length: info.length,
+                members: vec![i],
+            });
+        }
+
+        tt_note!(
+            status,
+            "downloading {} files in {} requests",
+            files.len(),
+            ranges.len()
+        );
+
+        // Fetch the ranges concurrently. Each thread gets its own range
+        // reader, and takes the next unclaimed range until there are none
+        // left. The threads report the sizes of the files that they've
+        // fetched, so that we can report progress.
+
+        let next_range = &AtomicUsize::new(0);
+        let n_threads = ranges.len().min(MAX_CONCURRENT_REQUESTS);
+        let url = &self.url;
+        let ranges_ref = &ranges;
+        let (progress_tx, progress_rx) = mpsc::channel();
+
+        let mut fetched: Vec<(usize, Result<Vec<u8>>)> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..n_threads)
+                .map(|_| {
+                    let progress_tx = progress_tx.clone();
+
+                    scope.spawn(move || {
+                        let mut reader = B::default().open_range_reader(url);
+                        let mut results = Vec::new();
+
+                        loop {
+                            let i = next_range.fetch_add(1, Ordering::SeqCst);
+
+                            let range = match ranges_ref.get(i) {
+                                Some(r) => r,
+                                None => break,
+                            };
+
+                            let result = read_range_with_retries(&mut reader, range);
+
+                            if result.is_ok() {
+                                let n: u64 = range.members.iter().map(|&m| files[m].1.length).sum();
+                                let _ = progress_tx.send(n);
+                            }
+
+                            results.push((i, result));
+                        }
+
+                        results
+                    })
+                })
+                .collect();
+
+            // This ends once all of the threads are done with their senders.
+            drop(progress_tx);
+            let mut done = 0;
+
+            for n in progress_rx {
+                done += n;
+                status.progress_update(done);
             }
 
-            if overall_failed {
-                bail!(
-                    "failed to retrieve \"{}\" from the network; \
-                this most probably is not Tectonic's fault \
-                -- please check your network connection.",
-                    name
-                );
-            } else if any_failed {
-                tt_note!(status, "download succeeded after retry");
+            threads
+                .into_iter()
+                .flat_map(|t| t.join().expect("download thread panicked"))
+                .collect()
+        });
+
+        fetched.sort_by_key(|(i, _)| *i);
+
+        // Split the fetched ranges back up into files.
+
+        let mut results: Vec<Option<Result<Vec<u8>>>> = files
+            .iter()
+            .map(|(_, info)| {
+                if info.length == 0 {
+                    Some(Ok(Vec::new()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for ((_, data), range) in fetched.into_iter().zip(&ranges) {
+            match data {
+                Ok(data) => {
+                    for &i in &range.members {
+                        let info = &files[i].1;
+                        let start = (info.offset - range.offset) as usize;
+                        let end = start + info.length as usize;
+                        results[i] = Some(Ok(data[start..end].to_vec()));
+                    }
+                }
+
+                Err(e) => {
+                    tt_warning!(status, "failure downloading {} files from the network", range.members.len(); e);
+
+                    for &i in &range.members {
+                        results[i] = Some(Err(anyhow!(
+                            "failed to retrieve \"{}\" from the network",
+                            files[i].0
+                        )));
+                    }
+                }
             }
         }
 
-        Ok(buf)
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(anyhow!("file was not downloaded"))))
+            .collect()
+    }
+
+    fn file_size(info: &Self::FileInfo) -> Option<u64> {
+        Some(info.length)
+    }
+
+    fn set_mirrors(&mut self, mirrors: &MirrorList, current: Option<&str>) {
+        self.mirrors = mirrors.clone();
+        self.mirror = current.map(|s| s.to_owned());
+    }
+}
+
+/// A byte range of the tar file covering one or more files.
+struct CoalescedRange {
+    offset: u64,
+    length: u64,
+
+    /// The indices of the files within the range.
+    members: Vec<usize>,
+}
+
+/// Read a range of the tar file, retrying a few times in case of transient
+/// network problems.
+fn read_range_with_retries<R: RangeReader>(
+    reader: &mut R,
+    range: &CoalescedRange,
+) -> Result<Vec<u8>> {
+    let length = range.length.try_into()?;
+    let config = default_config();
+    let mut last_error = None;
+
+    for attempt in 0..config.max_attempts.max(1) {
+        if attempt > 0 {
+            thread::sleep(config.retry_delay(attempt));
+        }
+
+        let mut buf = Vec::with_capacity(length);
+
+        let result = reader
+            .read_range(range.offset, length)
+            .and_then(|mut stream| Ok(stream.read_to_end(&mut buf)?));
+
+        match result {
+            Ok(n) if n == length => return Ok(buf),
+            Ok(n) => last_error = Some(anyhow!("expected {} bytes but got {}", length, n)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap())
+}
+
+/// An "indexed tar" bundle read directly from the local filesystem.
+///
+/// This uses the same files as [`IndexedTarBackend`], but rather than making
+/// HTTP requests, it seeks to each file in the tar file as it's needed. Since
+/// the data are already local, nothing is cached.
+pub struct IndexedTarBundle<R: Read + Seek> {
+    reader: R,
+    index: HashMap<String, FileInfo>,
+}
+
+impl<R: Read + Seek> IndexedTarBundle<R> {
+    /// Create a new indexed tar bundle for a generic readable and seekable
+    /// stream of tar data.
+    ///
+    /// The *index* is the uncompressed text of the bundle's index. Malformed
+    /// index lines are ignored.
+    pub fn new(reader: R, index: &str) -> IndexedTarBundle<R> {
+        let index = index
+            .lines()
+            .filter_map(|line| <IndexedTarBackend>::parse_index_line(line).ok())
+            .collect();
+
+        IndexedTarBundle { reader, index }
+    }
+}
+
+impl IndexedTarBundle<File> {
+    /// Open an indexed tar file on the filesystem as a bundle.
+    ///
+    /// The index is read from the file with the same path as the tar file, with
+    /// `.index.gz` appended, as written by [`crate::create::BundleCreator`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<IndexedTarBundle<File>> {
+        let path = path.as_ref();
+        let index_path = index_path(path);
+
+        let mut index = String::new();
+        let index_file = atry!(
+            File::open(&index_path);
+            ["failed to open bundle index `{}`", index_path.display()]
+        );
+        atry!(
+            GzDecoder::new(index_file).read_to_string(&mut index);
+            ["failed to read bundle index `{}`", index_path.display()]
+        );
+
+        let reader = atry!(
+            File::open(path);
+            ["failed to open `{}`", path.display()]
+        );
+
+        Ok(Self::new(reader, &index))
+    }
+
+    /// Check whether *path* looks like an indexed tar bundle; that is, whether
+    /// there is an index file next to it.
+    pub fn is_indexed_tar<P: AsRef<Path>>(path: P) -> bool {
+        let path = path.as_ref();
+        path.is_file() && index_path(path).is_file()
+    }
+}
+
+impl<R: Read + Seek> IoProvider for IndexedTarBundle<R> {
+    fn input_open_name(
+        &mut self,
+        name: &str,
+        _status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        let info = match self.index.get(name) {
+            Some(i) => *i,
+            None => return OpenResult::NotAvailable,
+        };
+
+        // Don't trust the index to describe a sensible range of the tar file
+        // before allocating space for the data.
+        let tar_length = match self.reader.seek(SeekFrom::End(0)) {
+            Ok(l) => l,
+            Err(e) => return OpenResult::Err(e.into()),
+        };
+
+        let in_bounds = matches!(
+            info.offset.checked_add(info.length),
+            Some(end) if end <= tar_length
+        );
+
+        if !in_bounds {
+            return OpenResult::Err(anyhow!(
+                "the index entry for \"{}\" points outside of the bundle; is the index corrupt?",
+                name
+            ));
+        }
+
+        let mut buf = vec![0; info.length as usize];
+
+        if let Err(e) = self
+            .reader
+            .seek(SeekFrom::Start(info.offset))
+            .and_then(|_| self.reader.read_exact(&mut buf))
+        {
+            return OpenResult::Err(e.into());
+        }
+
+        OpenResult::Ok(InputHandle::new_read_only(
+            name,
+            Cursor::new(buf),
+            InputOrigin::Other,
+        ))
+    }
+}
+
+impl<R: Read + Seek> Bundle for IndexedTarBundle<R> {
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        Ok(self.index.keys().cloned().collect())
+    }
+
+    fn all_files_with_sizes(
+        &mut self,
+        _status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        Ok(self
+            .index
+            .iter()
+            .map(|(name, info)| (name.clone(), Some(info.length)))
+            .collect())
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        Some(
+            names
+                .iter()
+                .filter(|name| self.index.contains_key(*name))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Get the path of the index of an indexed tar file.
+pub(crate) fn index_path(tar_path: &Path) -> PathBuf {
+    let mut path = tar_path.to_owned().into_os_string();
+    path.push(".index.gz");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use tectonic_geturl::memory::{MemoryBackend, MemoryServer, Request, RequestKind};
+    use tectonic_status_base::{collecting::CollectingStatusBackend, NoopStatusBackend};
+
+    use super::*;
+    use crate::cache::Cache;
+
+    pub(crate) type MemoryTarBackend = IndexedTarBackend<MemoryBackend>;
+
+    const DIGEST_TEXT: &[u8] =
+        b"0000000000000000000000000000000000000000000000000000000000000000\n";
+
+    /// Serve an indexed tar bundle of *files* at *path*. The "tar file" is just
+    /// the files' contents, concatenated.
+    pub(crate) fn serve_bundle(server: &mut MemoryServer, path: &str, files: &[(&str, &[u8])]) {
+        let mut tar = Vec::new();
+        let mut index = GzEncoder::new(Vec::new(), Compression::default());
+        let digest_file = (digest::DIGEST_NAME, DIGEST_TEXT);
+
+        for (name, data) in files.iter().chain(Some(&digest_file)) {
+            writeln!(index, "{} {} {}", name, tar.len(), data.len()).unwrap();
+            tar.extend_from_slice(data);
+        }
+
+        server.set_file(path, tar);
+        server.set_file(&format!("{path}.index.gz"), index.finish().unwrap());
+    }
+
+    pub(crate) fn read_file(
+        bundle: &mut dyn Bundle,
+        name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        match bundle.input_open_name(name, status) {
+            OpenResult::Ok(mut h) => h.read_to_end(&mut buf).unwrap(),
+            _ => panic!("failed to open {}", name),
+        };
+
+        buf
+    }
+
+    /// Count the byte-range requests for the data of *file*.
+    fn count_file_requests(requests: &[Request], path: &str, file: &[u8]) -> usize {
+        requests
+            .iter()
+            .filter(|r| {
+                r.path == path
+                    && matches!(r.kind, RequestKind::Range { length, .. } if length == file.len())
+            })
+            .count()
+    }
+
+    #[test]
+    fn files_are_downloaded_once() {
+        let mut server = MemoryServer::new();
+        serve_bundle(
+            &mut server,
+            "/b.tar",
+            &[("a.tex", b"hello"), ("b.tex", b"world!")],
+        );
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut status = NoopStatusBackend::default();
+        let url = server.url("/b.tar");
+
+        for _ in 0..2 {
+            let mut bundle = cache
+                .open::<MemoryTarBackend>(&url, false, &mut status)
+                .unwrap();
+            assert_eq!(read_file(&mut bundle, "a.tex", &mut status), b"hello");
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].kind, RequestKind::Resolve);
+        assert_eq!(requests[1].kind, RequestKind::Get);
+        assert_eq!(requests[1].path, "/b.tar.index.gz");
+        assert_eq!(count_file_requests(&requests, "/b.tar", b"hello"), 1);
+    }
+
+    #[test]
+    fn redirects_are_resolved() {
+        let mut server = MemoryServer::new();
+        serve_bundle(&mut server, "/b-v2.tar", &[("a.tex", b"hello")]);
+        let target = server.url("/b-v2.tar");
+        server.set_redirect("/latest", &target);
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut status = NoopStatusBackend::default();
+        let mut bundle = cache
+            .open::<MemoryTarBackend>(&server.url("/latest"), false, &mut status)
+            .unwrap();
+
+        assert_eq!(read_file(&mut bundle, "a.tex", &mut status), b"hello");
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.path == "/b-v2.tar.index.gz"));
+    }
+
+    #[test]
+    fn dropped_connections_are_retried() {
+        let mut server = MemoryServer::new();
+        serve_bundle(&mut server, "/b.tar", &[("a.tex", b"hello")]);
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut status = CollectingStatusBackend::new();
+        let mut bundle = cache
+            .open::<MemoryTarBackend>(&server.url("/b.tar"), false, &mut status)
+            .unwrap();
+
+        server.fail_next("/b.tar", 2);
+        assert_eq!(read_file(&mut bundle, "a.tex", &mut status), b"hello");
+        assert_eq!(
+            count_file_requests(&server.requests(), "/b.tar", b"hello"),
+            3
+        );
+        assert_eq!(status.warnings().count(), 2);
+        assert!(status
+            .notes()
+            .any(|m| m.text == "download succeeded after retry"));
+    }
+
+    #[test]
+    fn adjacent_files_are_prefetched_together() {
+        let mut server = MemoryServer::new();
+        serve_bundle(
+            &mut server,
+            "/b.tar",
+            &[("a.tex", b"aaa"), ("b.tex", b"bbbb"), ("c.tex", b"ccccc")],
+        );
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut status = NoopStatusBackend::default();
+        let mut bundle = cache
+            .open::<MemoryTarBackend>(&server.url("/b.tar"), false, &mut status)
+            .unwrap();
+
+        server.clear_requests();
+        let names = vec!["a.tex".to_owned(), "b.tex".to_owned(), "c.tex".to_owned()];
+        assert_eq!(bundle.prefetch(&names, &mut status).unwrap(), 3);
+        assert_eq!(
+            server.requests(),
+            vec![Request {
+                kind: RequestKind::Range {
+                    offset: 0,
+                    length: 12
+                },
+                path: "/b.tar".to_owned(),
+                headers: Vec::new(),
+            }]
+        );
+
+        assert_eq!(read_file(&mut bundle, "b.tex", &mut status), b"bbbb");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn local_index_is_checked() {
+        let tar = b"helloworld".to_vec();
+        let index = "hello 0 5\nworld 5 5\nhuge 5 18446744073709551615\npast 8 3\n";
+        let mut bundle = IndexedTarBundle::new(Cursor::new(tar), index);
+        let status = &mut NoopStatusBackend::default();
+
+        assert_eq!(read_file(&mut bundle, "world", status), b"world");
+
+        for name in ["huge", "past"] {
+            assert!(matches!(
+                bundle.input_open_name(name, status),
+                OpenResult::Err(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn mirrors_take_over_from_a_failed_server() {
+        let files: &[(&str, &[u8])] = &[("a.tex", b"hello"), ("b.tex", b"world!")];
+        let mut primary = MemoryServer::new();
+        serve_bundle(&mut primary, "/b.tar", files);
+        let mut mirror = MemoryServer::new();
+        serve_bundle(&mut mirror, "/b.tar", files);
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut status = CollectingStatusBackend::new();
+        let mirrors = vec![mirror.url("/b.tar")];
+        let mut bundle = cache
+            .open_with_mirrors::<MemoryTarBackend>(
+                &primary.url("/b.tar"),
+                &mirrors,
+                false,
+                &mut status,
+            )
+            .unwrap();
+
+        assert_eq!(read_file(&mut bundle, "a.tex", &mut status), b"hello");
+
+        primary.set_down(true);
+        assert_eq!(read_file(&mut bundle, "b.tex", &mut status), b"world!");
+        assert_eq!(
+            count_file_requests(&mirror.requests(), "/b.tar", b"world!"),
+            1
+        );
+        assert!(status
+            .notes()
+            .any(|m| m.text.starts_with("switching to bundle mirror")));
+        assert!(status.errors().next().is_none());
     }
 }
@@ -16,17 +16,39 @@
 //! - [`dir::DirBundle`] turns a directory full of files into a bundle; it is
 //!   useful for testing and lightweight usage.
 //! - [`zip::ZipBundle`] for a ZIP-format bundle.
+//! - [`itar::IndexedTarBundle`] for an "indexed tar" bundle on the local
+//!   filesystem.
+//! - [`overlay::OverlayBundle`] layers several bundles on top of one another.
+//!
+//! Web-based bundles accessed through the cache can be in the "indexed tar"
+//! format of [`itar::IndexedTarBackend`] or the compressed, content-addressed
+//! format of [`cas::ContentAddressedBackend`]. They may be served by several
+//! mirrors, as described in the [`mirrors`] module.
+//!
+//! The [`create`] module provides tooling to build new "indexed tar" bundles
+//! from trees of TeX support files, and the [`export`] module can write
+//! selected bundle files to portable archives for offline use.
 
 use std::{io::Read, str::FromStr};
 use tectonic_errors::{anyhow::bail, atry, Result};
-use tectonic_io_base::{digest, digest::DigestData, IoProvider, OpenResult};
+use tectonic_io_base::{
+    digest::{self, Digest, DigestComputer, DigestData},
+    IoProvider, OpenResult,
+};
 use tectonic_status_base::StatusBackend;
 
 pub mod cache;
+pub mod cas;
+pub mod create;
 pub mod dir;
+pub mod export;
 pub mod itar;
+pub mod mirrors;
+pub mod overlay;
 pub mod zip;
 
+mod tar;
+
 /// A trait for bundles of Tectonic support files.
 ///
 /// A "bundle" is an [`IoProvider`] with a few special properties. Bundles are
@@ -83,6 +105,68 @@ pub trait Bundle: IoProvider {
     /// might be fairly substantial (although we are talking megabytes, not
     /// gigabytes).
     fn all_files(&mut self, status: &mut dyn StatusBackend) -> Result<Vec<String>>;
+
+    /// Enumerate the files in this bundle, along with their sizes in bytes.
+    ///
+    /// Like [`Self::all_files`], this interface is intended to be used for
+    /// diagnostics. The size of a file is `None` if it can't be determined
+    /// without fetching the file. The default implementation doesn't report
+    /// any sizes.
+    fn all_files_with_sizes(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        Ok(self
+            .all_files(status)?
+            .into_iter()
+            .map(|name| (name, None))
+            .collect())
+    }
+
+    /// Get information about the local cache backing this bundle.
+    ///
+    /// Returns `None`, the default, if this bundle isn't backed by a
+    /// [`cache::Cache`].
+    fn cache_info(&self) -> Option<cache::CacheInfo> {
+        None
+    }
+
+    /// Check the integrity of this bundle’s locally cached files.
+    ///
+    /// Returns `None`, the default, if this bundle isn't backed by a
+    /// [`cache::Cache`].
+    fn verify_cache(
+        &mut self,
+        _status: &mut dyn StatusBackend,
+    ) -> Result<Option<cache::CacheVerification>> {
+        Ok(None)
+    }
+
+    /// Make a set of files available ahead of time.
+    ///
+    /// Bundles that obtain their files over the network can use this to
+    /// download the named files up front, and concurrently, rather than one at
+    /// a time as an engine asks for them. Names that the bundle doesn’t provide
+    /// are ignored. A file that can’t be prefetched will simply be fetched
+    /// again when it is needed, so that isn’t treated as an error. Returns the
+    /// number of files that were fetched.
+    ///
+    /// The default implementation does nothing.
+    fn prefetch(&mut self, _names: &[String], _status: &mut dyn StatusBackend) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Find which of a set of files this bundle provides, using only what it
+    /// knows already.
+    ///
+    /// Unlike [`Self::all_files`], this must never do anything slow, such as
+    /// accessing the network, so the result may leave out files that the
+    /// bundle would only find out about that way. [`overlay::OverlayBundle`]
+    /// uses it to work out which files don't need to be prefetched from lower
+    /// layers. Returns `None`, the default, if the bundle can't tell at all.
+    fn provided_names(&self, _names: &[String]) -> Option<Vec<String>> {
+        None
+    }
 }
 
 impl<B: Bundle + ?Sized> Bundle for Box<B> {
@@ -93,6 +177,45 @@ impl<B: Bundle + ?Sized> Bundle for Box<B> {
     fn all_files(&mut self, status: &mut dyn StatusBackend) -> Result<Vec<String>> {
         (**self).all_files(status)
     }
+
+    fn all_files_with_sizes(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        (**self).all_files_with_sizes(status)
+    }
+
+    fn cache_info(&self) -> Option<cache::CacheInfo> {
+        (**self).cache_info()
+    }
+
+    fn verify_cache(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<cache::CacheVerification>> {
+        (**self).verify_cache(status)
+    }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<usize> {
+        (**self).prefetch(names, status)
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        (**self).provided_names(names)
+    }
+}
+
+/// Add a file to a digest summarizing the contents of a bundle.
+///
+/// Content digests are computed by calling this function for each file in the
+/// bundle, in order of name, excluding the `SHA256SUM` file. Each file
+/// contributes its name, a NUL byte, the hex-encoded digest of its contents,
+/// and a newline.
+pub(crate) fn update_content_digest(dc: &mut DigestComputer, name: &str, file_digest: &DigestData) {
+    dc.update(name.as_bytes());
+    dc.update(b"\0");
+    dc.update(file_digest.to_string().as_bytes());
+    dc.update(b"\n");
 }
 
 /// Get the URL of the default bundle.
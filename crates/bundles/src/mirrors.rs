@@ -0,0 +1,127 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Lists of mirrors serving identical copies of a bundle.
+//!
+//! A web-based bundle may be available from several locations. A
+//! [`MirrorList`] holds them in order of preference, and remembers which ones
+//! have failed recently, so that a mirror that is down is only tried after the
+//! others — even by later Tectonic processes. These records are kept in a
+//! directory of the cache: each location that has failed has a file there whose
+//! modification time is the time of its most recent failure.
+//!
+//! All of the locations in a list must serve exactly the same bundle. The first
+//! one is the bundle’s primary location, which is used to identify it in the
+//! cache.
+
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tectonic_io_base::app_dirs;
+
+/// How long a failure counts against a location.
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// An ordered list of locations serving identical copies of a bundle.
+#[derive(Clone, Debug, Default)]
+pub struct MirrorList {
+    urls: Vec<String>,
+    health_dir: Option<PathBuf>,
+}
+
+impl MirrorList {
+    /// Create a list of mirrors from locations in order of preference.
+    ///
+    /// Duplicated locations are ignored. Until [`Self::set_health_dir`] is
+    /// called, failures are not remembered.
+    pub fn new<I, S>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut list = MirrorList::default();
+
+        for url in urls {
+            let url = url.into();
+
+            if !list.urls.contains(&url) {
+                list.urls.push(url);
+            }
+        }
+
+        list
+    }
+
+    /// Set the directory where failures are recorded, so that they are
+    /// remembered across runs.
+    pub fn set_health_dir<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.health_dir = Some(dir.into());
+        self
+    }
+
+    /// Get the locations in this list, in order of preference.
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// Get the locations in this list in the order in which they should be
+    /// tried.
+    ///
+    /// Locations that haven't failed recently come first, in order of
+    /// preference. They are followed by the ones that have, starting with the
+    /// one whose last failure was longest ago.
+    pub fn ordered(&self) -> Vec<String> {
+        let now = SystemTime::now();
+        let mut healthy = Vec::new();
+        let mut failing = Vec::new();
+
+        for url in &self.urls {
+            match self.last_failure(url) {
+                Some(t) if now.duration_since(t).unwrap_or_default() < FAILURE_MEMORY => {
+                    failing.push((t, url.clone()))
+                }
+                _ => healthy.push(url.clone()),
+            }
+        }
+
+        failing.sort();
+        healthy.extend(failing.into_iter().map(|(_, url)| url));
+        healthy
+    }
+
+    /// Record that a location couldn't be reached, or served bad data.
+    pub fn record_failure(&self, url: &str) {
+        // This is only bookkeeping, so problems are ignored, as with the
+        // records of bundle usage in the cache.
+        if let Some(path) = self.health_path(url) {
+            let _ = path
+                .parent()
+                .map(fs::create_dir_all)
+                .unwrap_or(Ok(()))
+                .and_then(|_| File::create(&path))
+                .and_then(|f| f.set_modified(SystemTime::now()));
+        }
+    }
+
+    /// Record that a location was used successfully, forgetting any previous
+    /// failure.
+    pub fn record_success(&self, url: &str) {
+        if let Some(path) = self.health_path(url) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Get the time at which a location last failed, if it has a record.
+    fn last_failure(&self, url: &str) -> Option<SystemTime> {
+        let path = self.health_path(url)?;
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn health_path(&self, url: &str) -> Option<PathBuf> {
+        self.health_dir
+            .as_ref()
+            .map(|d| d.join(app_dirs::app_dirs2::sanitized(url)))
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Stacking several bundles on top of one another.
+//!
+//! The [`OverlayBundle`] type makes it possible to augment or override the
+//! contents of a large base bundle, such as the default web bundle, with a
+//! few files of one’s own, without having to build an entirely new bundle.
+
+use std::{collections::HashSet, path::PathBuf};
+use tectonic_errors::prelude::*;
+use tectonic_io_base::{
+    digest::{self, Digest, DigestData},
+    InputHandle, IoProvider, OpenResult,
+};
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::{CacheInfo, CacheVerification},
+    Bundle,
+};
+
+/// A bundle composed of a stack of other bundles.
+///
+/// When a file is requested, the layers are searched in order, and the first
+/// one that provides the file wins. So, files in earlier layers shadow
+/// same-named files in later layers.
+///
+/// The digest of the overlay is composed from the digests of its layers, so
+/// that it changes if any of the layers change. An overlay with a single layer
+/// has the same digest as that layer.
+pub struct OverlayBundle {
+    layers: Vec<Box<dyn Bundle>>,
+}
+
+impl OverlayBundle {
+    /// Create a new overlay bundle.
+    ///
+    /// The *layers* are given in order of decreasing precedence: the base
+    /// bundle should come last.
+    pub fn new(layers: Vec<Box<dyn Bundle>>) -> OverlayBundle {
+        OverlayBundle { layers }
+    }
+}
+
+impl IoProvider for OverlayBundle {
+    fn input_open_name(
+        &mut self,
+        name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        for layer in &mut self.layers {
+            match layer.input_open_name(name, status) {
+                OpenResult::NotAvailable => continue,
+                r => return r,
+            }
+        }
+
+        OpenResult::NotAvailable
+    }
+
+    fn input_open_name_with_abspath(
+        &mut self,
+        name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        for layer in &mut self.layers {
+            match layer.input_open_name_with_abspath(name, status) {
+                OpenResult::NotAvailable => continue,
+                r => return r,
+            }
+        }
+
+        OpenResult::NotAvailable
+    }
+}
+
+impl Bundle for OverlayBundle {
+    fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        if self.layers.len() == 1 {
+            return self.layers[0].get_digest(status);
+        }
+
+        let mut dc = digest::create();
+
+        for layer in &mut self.layers {
+            dc.update(layer.get_digest(status)?.to_string().as_bytes());
+            dc.update(b"\n");
+        }
+
+        Ok(DigestData::from(dc))
+    }
+
+    fn all_files(&mut self, status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        Ok(self
+            .all_files_with_sizes(status)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    fn all_files_with_sizes(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+
+        for layer in &mut self.layers {
+            for (name, size) in layer.all_files_with_sizes(status)? {
+                if seen.insert(name.clone()) {
+                    files.push((name, size));
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn cache_info(&self) -> Option<CacheInfo> {
+        self.layers.iter().find_map(|layer| layer.cache_info())
+    }
+
+    fn verify_cache(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<CacheVerification>> {
+        let mut result: Option<CacheVerification> = None;
+
+        for layer in &mut self.layers {
+            if let Some(v) = layer.verify_cache(status)? {
+                let total = result.get_or_insert_with(Default::default);
+                total.n_checked += v.n_checked;
+                total.problems.extend(v.problems);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<usize> {
+        let mut remaining = names.to_vec();
+        let mut n = 0;
+        let n_layers = self.layers.len();
+
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            if remaining.is_empty() {
+                break;
+            }
+
+            n += layer.prefetch(&remaining, status)?;
+
+            // Files provided by this layer shadow those in later layers, so
+            // there's no need to fetch them from those. We only go by what the
+            // layer already knows: asking for its full list of files could
+            // mean a trip to the network on every build.
+            if i + 1 < n_layers {
+                if let Some(provided) = layer.provided_names(&remaining) {
+                    let provided: HashSet<_> = provided.into_iter().collect();
+                    remaining.retain(|name| !provided.contains(name));
+                }
+            }
+        }
+
+        Ok(n)
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        let mut provided = HashSet::new();
+
+        for layer in &self.layers {
+            provided.extend(layer.provided_names(names).into_iter().flatten());
+        }
+
+        Some(
+            names
+                .iter()
+                .filter(|name| provided.contains(*name))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tectonic_geturl::memory::{MemoryServer, RequestKind};
+    use tectonic_status_base::NoopStatusBackend;
+
+    use super::*;
+    use crate::{
+        cache::Cache,
+        dir::DirBundle,
+        itar::tests::{serve_bundle, MemoryTarBackend},
+    };
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn prefetch_skips_shadowed_files() {
+        let mut server = MemoryServer::new();
+        serve_bundle(
+            &mut server,
+            "/base.tar",
+            &[
+                ("a.tex", b"base a"),
+                ("b.tex", b"base b"),
+                ("c.tex", b"base c"),
+            ],
+        );
+
+        let local = tempfile::tempdir().unwrap();
+        fs::write(local.path().join("a.tex"), "local a").unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+        let mut status = NoopStatusBackend::default();
+        let wanted = names(&["a.tex", "b.tex", "missing.tex"]);
+        let url = server.url("/base.tar");
+
+        let open = |cache: &mut Cache, status: &mut NoopStatusBackend| {
+            let base = cache.open::<MemoryTarBackend>(&url, false, status).unwrap();
+            OverlayBundle::new(vec![Box::new(DirBundle::new(local.path())), Box::new(base)])
+        };
+
+        let mut overlay = open(&mut cache, &mut status);
+        assert_eq!(
+            overlay.provided_names(&wanted),
+            Some(names(&["a.tex", "b.tex"]))
+        );
+
+        server.clear_requests();
+        assert_eq!(overlay.prefetch(&wanted, &mut status).unwrap(), 1);
+
+        // Only `b.tex` is fetched, since the local `a.tex` shadows the base's.
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].kind,
+            RequestKind::Range {
+                offset: 6,
+                length: 6
+            }
+        );
+
+        // With everything cached, prefetching doesn't touch the network.
+        let mut overlay = open(&mut cache, &mut status);
+        server.clear_requests();
+        assert_eq!(overlay.prefetch(&wanted, &mut status).unwrap(), 0);
+        assert_eq!(server.requests(), []);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Minimal support for reading and writing tar files.
+//!
+//! Tectonic only needs to deal with simple archives of regular files, so this
+//! module doesn't attempt to support the full range of tar variants. It
+//! handles plain ustar headers and the GNU "long name" extension, which is
+//! what [`TarWriter`] produces.
+
+use std::io::{ErrorKind, Read, Write};
+use tectonic_errors::prelude::*;
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_NAME_LENGTH: usize = 100;
+
+/// A minimal writer for deterministic tar files.
+///
+/// Members are written with fixed permissions and timestamps so that the same
+/// inputs always yield the same bytes. Names that don't fit in the standard
+/// header are stored using the GNU "long name" extension.
+pub(crate) struct TarWriter<W: Write> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        TarWriter { inner, position: 0 }
+    }
+
+    /// Append a regular file, returning the offset of its data.
+    pub(crate) fn append(&mut self, name: &str, data: &[u8]) -> Result<u64> {
+        let name = name.as_bytes();
+
+        if name.len() > TAR_NAME_LENGTH {
+            let mut long_name = name.to_vec();
+            long_name.push(0);
+            self.write_header(b"././@LongLink", long_name.len() as u64, b'L')?;
+            self.write_data(&long_name)?;
+            self.write_header(&name[..TAR_NAME_LENGTH], data.len() as u64, b'0')?;
+        } else {
+            self.write_header(name, data.len() as u64, b'0')?;
+        }
+
+        let offset = self.position;
+        self.write_data(data)?;
+        Ok(offset)
+    }
+
+    fn write_header(&mut self, name: &[u8], size: u64, type_flag: u8) -> Result<()> {
+        // The size field holds 11 octal digits.
+        ensure!(size < 1 << 33, "file too large for a tar bundle");
+
+        let mut header = [0u8; TAR_BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name);
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], size);
+        write_octal(&mut header[136..148], 0);
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = type_flag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        write_octal(&mut header[148..155], checksum as u64);
+
+        self.inner.write_all(&header)?;
+        self.position += TAR_BLOCK_SIZE as u64;
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        self.inner.write_all(data)?;
+        let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        self.inner.write_all(&[0u8; TAR_BLOCK_SIZE][..padding])?;
+        self.position += (data.len() + padding) as u64;
+        Ok(())
+    }
+
+    /// Write the end-of-archive marker and return the underlying writer.
+    pub(crate) fn finish(mut self) -> Result<W> {
+        self.inner.write_all(&[0u8; 2 * TAR_BLOCK_SIZE])?;
+        Ok(self.inner)
+    }
+}
+
+/// Fill *field* with a zero-padded octal number followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/// Read the regular files in a tar archive, calling *f* with the name and
+/// contents of each one in turn.
+///
+/// Other kinds of members, such as directories and links, are skipped.
+pub(crate) fn read_tar<R, F>(mut reader: R, mut f: F) -> Result<()>
+where
+    R: Read,
+    F: FnMut(&str, Vec<u8>) -> Result<()>,
+{
+    let mut long_name: Option<String> = None;
+
+    loop {
+        let mut header = [0u8; TAR_BLOCK_SIZE];
+
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers omit the end-of-archive marker.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        if header.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+
+        let size = read_octal(&header[124..136])?;
+        let block = TAR_BLOCK_SIZE as u64;
+        let padded_size = size + (block - size % block) % block;
+        let mut data = Vec::with_capacity(padded_size as usize);
+        (&mut reader).take(padded_size).read_to_end(&mut data)?;
+        ensure!(
+            data.len() as u64 == padded_size,
+            "unexpected end of tar data"
+        );
+        data.truncate(size as usize);
+
+        match header[156] {
+            b'L' => {
+                long_name = Some(String::from_utf8_lossy(field_text(&data)).into_owned());
+            }
+
+            b'0' | 0 => {
+                let name = match long_name.take() {
+                    Some(n) => n,
+                    None => {
+                        let name = String::from_utf8_lossy(field_text(&header[..100]));
+                        let prefix = String::from_utf8_lossy(field_text(&header[345..500]));
+
+                        if prefix.is_empty() {
+                            name.into_owned()
+                        } else {
+                            format!("{prefix}/{name}")
+                        }
+                    }
+                };
+
+                f(&name, data)?;
+            }
+
+            _ => {
+                long_name = None;
+            }
+        }
+    }
+}
+
+/// Get the text of a NUL-terminated header field.
+fn field_text(field: &[u8]) -> &[u8] {
+    match field.iter().position(|b| *b == 0) {
+        Some(n) => &field[..n],
+        None => field,
+    }
+}
+
+/// Parse a (possibly space- or NUL-padded) octal number.
+fn read_octal(field: &[u8]) -> Result<u64> {
+    let text = String::from_utf8_lossy(field_text(field));
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Ok(0);
+    }
+
+    Ok(atry!(
+        u64::from_str_radix(text, 8);
+        ["invalid number `{}` in tar header", text]
+    ))
+}
@@ -4,6 +4,7 @@
 //! ZIP files as Tectonic bundles.
 
 use std::{
+    collections::HashSet,
     fs::File,
     io::{Cursor, Read, Seek},
     path::Path,
@@ -75,4 +76,29 @@ impl<R: Read + Seek> Bundle for ZipBundle<R> {
     fn all_files(&mut self, _status: &mut dyn StatusBackend) -> Result<Vec<String>> {
         Ok(self.zip.file_names().map(|s| s.to_owned()).collect())
     }
+
+    fn all_files_with_sizes(
+        &mut self,
+        _status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        let mut files = Vec::with_capacity(self.zip.len());
+
+        for i in 0..self.zip.len() {
+            let item = self.zip.by_index_raw(i)?;
+            files.push((item.name().to_owned(), Some(item.size())));
+        }
+
+        Ok(files)
+    }
+
+    fn provided_names(&self, names: &[String]) -> Option<Vec<String>> {
+        let files: HashSet<&str> = self.zip.file_names().collect();
+        Some(
+            names
+                .iter()
+                .filter(|name| files.contains(name.as_str()))
+                .cloned()
+                .collect(),
+        )
+    }
 }
@@ -0,0 +1,390 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Analysis of existing LaTeX projects.
+//!
+//! When a workspace is initialized in a directory that already contains a
+//! LaTeX document, we don't want to generate the usual
+//! `_preamble.tex`/`index.tex`/`_postamble.tex` split. Instead,
+//! [`analyze_directory`] looks for the document's main file and a few
+//! features that affect how it should be built, and
+//! [`ProjectAnalysis::to_template`] turns the result into a workspace template
+//! whose `Tectonic.toml` file points at the existing sources.
+
+use std::{
+    fmt::Write as FmtWrite,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use tectonic_errors::prelude::*;
+
+use crate::template::{toml_escape, WorkspaceTemplate};
+
+/// Packages that only work if TeX's shell-escape feature is enabled.
+const SHELL_ESCAPE_PACKAGES: &[&str] = &["gnuplottex", "minted", "shellesc", "svg"];
+
+/// How deep to look into subdirectories for TeX files.
+const MAX_DEPTH: usize = 3;
+
+/// The results of analyzing an existing LaTeX project.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProjectAnalysis {
+    /// The path of the document's main file, relative to the project
+    /// directory, using `/` as the separator.
+    pub main_file: String,
+
+    /// Other files that look like they might be main files. These are not
+    /// used, but the user might want to know about them.
+    pub other_candidates: Vec<String>,
+
+    /// The bibliography databases named by `\bibliography` or
+    /// `\addbibresource` commands.
+    pub bib_databases: Vec<String>,
+
+    /// The bibliography databases that could not be found, relative to the
+    /// directory of the main file.
+    pub missing_bib_databases: Vec<String>,
+
+    /// Whether the document uses `biblatex`, which needs the external `biber`
+    /// program rather than the BibTeX engine built into Tectonic.
+    pub uses_biblatex: bool,
+
+    /// The packages used by the document that require shell-escape.
+    pub shell_escape_packages: Vec<String>,
+}
+
+impl ProjectAnalysis {
+    /// The directory containing the main file, relative to the project
+    /// directory. This becomes the document's source directory, so that
+    /// relative paths in the sources work as they would with other TeX tools.
+    pub fn source_dir(&self) -> &str {
+        match self.main_file.rsplit_once('/') {
+            Some((dir, _)) => dir,
+            None => ".",
+        }
+    }
+
+    /// The name of the main file within [`Self::source_dir`].
+    pub fn main_file_name(&self) -> &str {
+        match self.main_file.rsplit_once('/') {
+            Some((_, name)) => name,
+            None => &self.main_file,
+        }
+    }
+
+    /// Create a workspace template that builds the existing document.
+    ///
+    /// The template consists only of a `Tectonic.toml` file.
+    pub fn to_template(&self) -> WorkspaceTemplate {
+        let mut toml = String::new();
+
+        // Writing to a String can't fail.
+        let _ = writeln!(
+            toml,
+            "[doc]\nname = \"@DOCUMENT_NAME@\"\nbundle = \"@BUNDLE_LOCATION@\"\nsource_dir = \"{}\"\n",
+            toml_escape(self.source_dir())
+        );
+        let _ = writeln!(
+            toml,
+            "[[output]]\nname = \"default\"\ntype = \"pdf\"\ninputs = \"{}\"",
+            toml_escape(self.main_file_name())
+        );
+
+        if !self.shell_escape_packages.is_empty() {
+            let _ = writeln!(
+                toml,
+                "# Needed by: {}\nshell_escape = true",
+                self.shell_escape_packages.join(", ")
+            );
+        }
+
+        let mut template = WorkspaceTemplate::default();
+        template.add_file("Tectonic.toml", toml);
+        template
+    }
+}
+
+/// Analyze a directory that might contain a LaTeX project.
+///
+/// The main file is a `.tex` file containing a `\documentclass` command. If
+/// there are several, files that also contain `\begin{document}` are
+/// preferred, then files named `main.tex` or after the directory, then files
+/// closer to the top of the directory tree. Returns `None` if no main file
+/// could be found.
+pub fn analyze_directory<P: AsRef<Path>>(root: P) -> Result<Option<ProjectAnalysis>> {
+    let root = root.as_ref();
+    let mut tex_files = Vec::new();
+    find_tex_files(root, Path::new(""), 0, &mut tex_files)?;
+    tex_files.sort();
+
+    let dir_name = root.canonicalize().ok().and_then(|p| {
+        p.file_stem()
+            .map(|s| format!("{}.tex", s.to_string_lossy()))
+    });
+
+    let mut candidates = Vec::new();
+    let mut sources = Vec::new();
+
+    for rel_path in tex_files {
+        let full_path = root.join(&rel_path);
+        let text = atry!(
+            fs::read(&full_path);
+            ["failed to read `{}`", full_path.display()]
+        );
+        let text = strip_comments(&String::from_utf8_lossy(&text));
+
+        if text.contains("\\documentclass") {
+            let has_begin = text.contains("\\begin{document}");
+            let file_name = rel_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned());
+            let name_rank = if file_name.as_deref() == Some("main.tex") {
+                0
+            } else if file_name.is_some() && file_name == dir_name {
+                1
+            } else {
+                2
+            };
+            let depth = rel_path.components().count();
+            candidates.push(((!has_begin, name_rank, depth), to_slash_path(&rel_path)));
+        }
+
+        sources.push(text);
+    }
+
+    candidates.sort();
+    let mut candidates = candidates.into_iter().map(|(_, p)| p);
+
+    let main_file = match candidates.next() {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+
+    let mut analysis = ProjectAnalysis {
+        main_file,
+        other_candidates: candidates.collect(),
+        ..Default::default()
+    };
+
+    for text in &sources {
+        for arg in command_args(text, "\\bibliography") {
+            for db in split_list(&arg) {
+                let db = if db.ends_with(".bib") {
+                    db
+                } else {
+                    format!("{db}.bib")
+                };
+                push_unique(&mut analysis.bib_databases, db);
+            }
+        }
+
+        for arg in command_args(text, "\\addbibresource") {
+            analysis.uses_biblatex = true;
+            push_unique(&mut analysis.bib_databases, arg.trim().to_owned());
+        }
+
+        for macro_name in ["\\usepackage", "\\RequirePackage"] {
+            for arg in command_args(text, macro_name) {
+                for package in split_list(&arg) {
+                    if package == "biblatex" {
+                        analysis.uses_biblatex = true;
+                    }
+
+                    if SHELL_ESCAPE_PACKAGES.contains(&package.as_str()) {
+                        push_unique(&mut analysis.shell_escape_packages, package);
+                    }
+                }
+            }
+        }
+    }
+
+    let source_dir = root.join(analysis.source_dir());
+    analysis.missing_bib_databases = analysis
+        .bib_databases
+        .iter()
+        .filter(|db| !source_dir.join(db).is_file())
+        .cloned()
+        .collect();
+
+    Ok(Some(analysis))
+}
+
+fn find_tex_files(root: &Path, rel: &Path, depth: usize, found: &mut Vec<PathBuf>) -> Result<()> {
+    let dir = root.join(rel);
+    let entries = atry!(
+        fs::read_dir(&dir);
+        ["failed to read directory `{}`", dir.display()]
+    );
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let rel_path = rel.join(&name);
+
+        if entry.file_type()?.is_dir() {
+            let name = name.to_string_lossy();
+
+            // Skip hidden directories and our own build products.
+            if depth < MAX_DEPTH && !name.starts_with('.') && name != "build" {
+                find_tex_files(root, &rel_path, depth + 1, found)?;
+            }
+        } else if rel_path.extension().map(|e| e == "tex").unwrap_or(false) {
+            found.push(rel_path);
+        }
+    }
+
+    Ok(())
+}
+
+fn to_slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Remove TeX comments: everything after an unescaped `%` on each line.
+fn strip_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        let mut escaped = false;
+
+        for c in line.chars() {
+            if c == '%' && !escaped {
+                break;
+            }
+
+            escaped = c == '\\' && !escaped;
+            result.push(c);
+        }
+
+        result.push('\n');
+    }
+
+    result
+}
+
+/// Find the mandatory arguments of every use of a command, skipping any
+/// optional arguments in square brackets.
+fn command_args(text: &str, command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = text;
+
+    while let Some(idx) = rest.find(command) {
+        rest = &rest[idx + command.len()..];
+
+        // Make sure that we haven't matched a prefix of a longer command name.
+        if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+
+        let mut after = rest.trim_start();
+
+        if let Some(opt) = after.strip_prefix('[') {
+            match opt.find(']') {
+                Some(end) => after = opt[end + 1..].trim_start(),
+                None => break,
+            }
+        }
+
+        if let Some(arg) = after.strip_prefix('{') {
+            if let Some(end) = arg.find('}') {
+                args.push(arg[..end].to_owned());
+            }
+        }
+    }
+
+    args
+}
+
+fn split_list(arg: &str) -> Vec<String> {
+    arg.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn push_unique(list: &mut Vec<String>, item: String) {
+    if !list.contains(&item) {
+        list.push(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn finds_main_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("chapters")).unwrap();
+        fs::write(
+            dir.path().join("thesis.tex"),
+            r"\documentclass{report}
+\usepackage[cache=false]{minted}
+\usepackage{amsmath, svg}
+% \usepackage{gnuplottex}
+\begin{document}
+\input{chapters/intro}
+\bibliography{refs,more}
+\end{document}
+",
+        )
+        .unwrap();
+        fs::write(dir.path().join("chapters/intro.tex"), "Hello.").unwrap();
+        fs::write(
+            dir.path().join("chapters/standalone.tex"),
+            r"\documentclass{standalone}",
+        )
+        .unwrap();
+        fs::write(dir.path().join("refs.bib"), "").unwrap();
+
+        let analysis = analyze_directory(dir.path()).unwrap().unwrap();
+        assert_eq!(analysis.main_file, "thesis.tex");
+        assert_eq!(analysis.other_candidates, vec!["chapters/standalone.tex"]);
+        assert_eq!(analysis.bib_databases, vec!["refs.bib", "more.bib"]);
+        assert_eq!(analysis.missing_bib_databases, vec!["more.bib"]);
+        assert!(!analysis.uses_biblatex);
+        assert_eq!(analysis.shell_escape_packages, vec!["minted", "svg"]);
+
+        let template = analysis.to_template();
+        let out = tempfile::tempdir().unwrap();
+        template.write_files(out.path(), "thesis", "na").unwrap();
+        let mut f = fs::File::open(out.path().join("Tectonic.toml")).unwrap();
+        let doc = Document::new_from_toml(out.path(), out.path().join("build"), &mut f).unwrap();
+        assert_eq!(doc.source_dir, ".");
+        let output = doc.outputs.get("default").unwrap();
+        assert!(output.shell_escape);
+    }
+
+    #[test]
+    fn main_file_in_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("paper")).unwrap();
+        fs::write(
+            dir.path().join("paper/main.tex"),
+            r"\documentclass{article}\usepackage{biblatex}\addbibresource{refs.bib}\begin{document}\end{document}",
+        )
+        .unwrap();
+
+        let analysis = analyze_directory(dir.path()).unwrap().unwrap();
+        assert_eq!(analysis.main_file, "paper/main.tex");
+        assert_eq!(analysis.source_dir(), "paper");
+        assert_eq!(analysis.main_file_name(), "main.tex");
+        assert!(analysis.uses_biblatex);
+        assert_eq!(analysis.missing_bib_databases, vec!["refs.bib"]);
+    }
+
+    #[test]
+    fn no_main_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.tex"), r"% \documentclass{article}").unwrap();
+        assert_eq!(analyze_directory(dir.path()).unwrap(), None);
+    }
+}
@@ -19,6 +19,7 @@ use std::{
 };
 use tectonic_errors::prelude::*;
 
+use crate::metadata::StandardMetadata;
 use crate::syntax;
 use crate::workspace::WorkspaceCreator;
 
@@ -28,6 +29,10 @@ use crate::workspace::WorkspaceCreator;
 /// `Tectonic.toml`.
 pub const DEFAULT_INPUTS: &[&str] = &["_preamble.tex", "index.tex", "_postamble.tex"];
 
+/// The default directory containing a document's TeX sources, relative to the
+/// directory containing its `Tectonic.toml` file.
+pub const DEFAULT_SOURCE_DIR: &str = "src";
+
 /// A Tectonic document.
 #[derive(Debug)]
 pub struct Document {
@@ -39,11 +44,17 @@ pub struct Document {
     build_dir: PathBuf,
 
     /// Arbitrary document metadata.
-    /// This has no effect on tectonic's build process.
-    /// Rather, allows users to add easily-accessible information to their documents,
-    /// which may be read by external tools.
+    /// Apart from the keys parsed into [`Self::standard_metadata`], this has no
+    /// effect on tectonic's build process. Rather, allows users to add
+    /// easily-accessible information to their documents, which may be read by
+    /// external tools.
     pub metadata: Option<toml::Value>,
 
+    /// The standard metadata keys extracted from [`Self::metadata`]. These are
+    /// propagated into the document outputs, e.g. the PDF document information
+    /// dictionary.
+    pub standard_metadata: StandardMetadata,
+
     /// The document name. This will be used to name build artifacts and the
     /// like, and so should be relatively filesystem-friendly. It does not
     /// need to be the same as the document title.
@@ -53,6 +64,21 @@ pub struct Document {
     /// Either a URL or a local path.
     pub bundle_loc: String,
 
+    /// The locations of additional bundles layered on top of the core bundle,
+    /// in order of decreasing precedence. Files in these bundles override
+    /// those in [`Self::bundle_loc`]. This is usually empty.
+    pub overlay_bundle_locs: Vec<String>,
+
+    /// Mirrors of web-based bundles, keyed by bundle location. Each list gives
+    /// other URLs serving the same bundle, in order of preference. This is
+    /// usually empty.
+    pub bundle_mirrors: HashMap<String, Vec<String>>,
+
+    /// The directory containing the document's TeX sources, relative to
+    /// [`Self::src_dir`]. Input files are resolved relative to this directory.
+    /// This is [`DEFAULT_SOURCE_DIR`] unless configured otherwise.
+    pub source_dir: String,
+
     /// The different outputs that are created from the document source. These
     /// may have different formats (e.g., PDF and HTML) or the same format but
     /// different settings (e.g., PDF with A4 paper and PDF with US Letter
@@ -93,12 +119,33 @@ impl Document {
             bail!("TOML specification must define at least one output");
         }
 
+        let standard_metadata = StandardMetadata::from_toml(doc.doc.metadata.as_ref())?;
+
+        let mut overlay_bundle_locs: Vec<String> = doc.doc.bundle.into();
+        let bundle_loc = match overlay_bundle_locs.pop() {
+            Some(loc) => loc,
+            None => bail!("TOML specification must list at least one bundle location"),
+        };
+
         Ok(Document {
             src_dir: src_dir.into(),
             build_dir: build_dir.into(),
             name: doc.doc.name,
-            bundle_loc: doc.doc.bundle,
+            bundle_loc,
+            overlay_bundle_locs,
+            bundle_mirrors: doc
+                .doc
+                .mirrors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(loc, mirrors)| (loc, mirrors.into()))
+                .collect(),
+            source_dir: doc
+                .doc
+                .source_dir
+                .unwrap_or_else(|| DEFAULT_SOURCE_DIR.to_owned()),
             metadata: doc.doc.metadata,
+            standard_metadata,
             outputs,
         })
     }
@@ -119,7 +166,23 @@ impl Document {
         let doc = syntax::TomlDocument {
             doc: syntax::TomlDocSection {
                 name: self.name.clone(),
-                bundle: self.bundle_loc.clone(),
+                bundle: if self.overlay_bundle_locs.is_empty() {
+                    syntax::StringOrStringVec::String(self.bundle_loc.clone())
+                } else {
+                    let mut locs = self.overlay_bundle_locs.clone();
+                    locs.push(self.bundle_loc.clone());
+                    syntax::StringOrStringVec::Vec(locs)
+                },
+                mirrors: (!self.bundle_mirrors.is_empty()).then(|| {
+                    self.bundle_mirrors
+                        .iter()
+                        .map(|(loc, mirrors)| {
+                            (loc.clone(), syntax::StringOrStringVec::Vec(mirrors.clone()))
+                        })
+                        .collect()
+                }),
+                source_dir: (self.source_dir != DEFAULT_SOURCE_DIR)
+                    .then(|| self.source_dir.clone()),
                 metadata: None,
             },
             outputs,
@@ -144,11 +207,48 @@ impl Document {
     /// Get this document's toplevel source directory.
     ///
     /// Note that this directory is the one containing the file `Tectonic.toml`.
-    /// The actual document source is contained in a subdirectory named `src`.
+    /// The actual document source is contained in the subdirectory given by
+    /// [`Self::source_dir`], which is usually named `src`. See [`Self::tex_dir`].
     pub fn src_dir(&self) -> &Path {
         &self.src_dir
     }
 
+    /// Get the directory containing this document's TeX sources.
+    ///
+    /// This is [`Self::source_dir`] resolved relative to [`Self::src_dir`].
+    pub fn tex_dir(&self) -> PathBuf {
+        self.src_dir.join(&self.source_dir)
+    }
+
+    /// Iterate over the locations of all of this document's bundles.
+    ///
+    /// The [`Self::overlay_bundle_locs`] come first, in order of decreasing
+    /// precedence, followed by the core [`Self::bundle_loc`].
+    pub fn bundle_locs(&self) -> impl Iterator<Item = &str> {
+        self.overlay_bundle_locs
+            .iter()
+            .chain(std::iter::once(&self.bundle_loc))
+            .map(|s| s.as_ref())
+    }
+
+    /// Get the mirrors of the bundle at *loc*, in order of preference.
+    ///
+    /// This is empty unless mirrors are configured in `[doc.mirrors]`.
+    pub fn mirrors_for(&self, loc: &str) -> &[String] {
+        self.bundle_mirrors
+            .get(loc)
+            .map(|m| m.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Get the path of this document's `Tectonic.lock` file.
+    ///
+    /// This file, which may not exist, is stored next to `Tectonic.toml` in
+    /// [`Self::src_dir`]. See [`crate::lock`].
+    pub fn lock_path(&self) -> PathBuf {
+        self.src_dir.join(crate::lock::LOCK_FILE_NAME)
+    }
+
     /// Get this document's build directory.
     ///
     /// This is the directory where persistent files associated with the
@@ -158,6 +258,18 @@ impl Document {
         &self.build_dir
     }
 
+    /// Get the path of the record of bundle files read while building the given
+    /// output profile.
+    ///
+    /// This file, which may not exist, lists the names of the support files
+    /// that the most recent build of the output read, one per line. It is
+    /// stored in [`Self::build_dir`] and is used to prefetch those files when
+    /// the output is next built.
+    pub fn prefetch_record_path(&self, profile_name: &str) -> PathBuf {
+        self.build_dir
+            .join(format!(".tectonic-files-{profile_name}.txt"))
+    }
+
     /// Iterate over the names of the output profiles defined for this document.
     ///
     /// These may have different formats (e.g., PDF and HTML) or the same format
@@ -221,6 +333,15 @@ pub struct OutputProfile {
     /// Directory is not managed and any files created in it will not be deleted.
     ///
     pub shell_escape_cwd: Option<String>,
+
+    /// Shell commands to run before this output is built.
+    ///
+    /// Like shell-escape, these hooks run arbitrary programs, so they should
+    /// only be executed for trusted documents.
+    pub pre_build: Vec<String>,
+
+    /// Shell commands to run after this output has been built successfully.
+    pub post_build: Vec<String>,
 }
 
 /// The output target type of a document build.
@@ -288,8 +409,12 @@ impl Document {
             build_dir,
             name,
             bundle_loc,
+            overlay_bundle_locs: Vec::new(),
+            bundle_mirrors: HashMap::new(),
+            source_dir: DEFAULT_SOURCE_DIR.to_owned(),
             outputs: crate::document::default_outputs(),
             metadata: None,
+            standard_metadata: StandardMetadata::default(),
         })
     }
 }
@@ -308,6 +433,8 @@ pub(crate) fn default_outputs() -> HashMap<String, OutputProfile> {
                 .collect(),
             shell_escape: false,
             shell_escape_cwd: None,
+            pre_build: Vec::new(),
+            post_build: Vec::new(),
         },
     );
     outputs
@@ -353,4 +480,78 @@ mod tests {
         let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
         assert!(doc.outputs.get("o").unwrap().shell_escape);
     }
+
+    #[test]
+    fn bundle_list() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = ["house", "extra", "na"]
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        assert_eq!(doc.bundle_loc, "na");
+        assert_eq!(doc.overlay_bundle_locs, vec!["house", "extra"]);
+
+        let mut c = Cursor::new(
+            TOML.replace(r#"["house", "extra", "na"]"#, "[]")
+                .into_bytes(),
+        );
+        assert!(Document::new_from_toml(".", ".", &mut c).is_err());
+    }
+
+    #[test]
+    fn bundle_mirrors() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = ["house", "https://example.com/b.tar"]
+
+        [doc.mirrors]
+        "https://example.com/b.tar" = ["https://a.example.org/b.tar", "https://b.example.org/b.tar"]
+        "house" = "https://example.com/house.tar"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        assert_eq!(
+            doc.mirrors_for("https://example.com/b.tar"),
+            ["https://a.example.org/b.tar", "https://b.example.org/b.tar"]
+        );
+        assert_eq!(doc.mirrors_for("house"), ["https://example.com/house.tar"]);
+        assert!(doc.mirrors_for("elsewhere").is_empty());
+    }
+
+    #[test]
+    fn build_hooks() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = "na"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        pre_build = ["echo one", "echo two"]
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        let o = doc.outputs.get("o").unwrap();
+        assert_eq!(o.pre_build, vec!["echo one", "echo two"]);
+        assert!(o.post_build.is_empty());
+
+        let t = syntax::TomlOutputProfile::from(o);
+        assert_eq!(t.pre_build.as_ref().unwrap().len(), 2);
+        assert!(t.post_build.is_none());
+    }
 }
@@ -18,6 +18,11 @@
 //! `Tectonic.toml` file. There is also [`workspace::WorkspaceCreator`] for
 //! creating new workspaces from scratch.
 
+pub mod analysis;
 pub mod document;
+pub mod lock;
+pub mod metadata;
 mod syntax;
+pub mod template;
+pub mod validation;
 pub mod workspace;
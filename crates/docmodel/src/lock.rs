@@ -0,0 +1,159 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! The `Tectonic.lock` file, which pins the bundles used by a document.
+//!
+//! A bundle location given in `Tectonic.toml` is usually a URL that may
+//! resolve to different content over time. The lock file records, for each
+//! bundle location, the URL that it resolved to and the digest of the bundle
+//! contents, so that later builds can detect (and avoid) changes to the
+//! bundle. It lives next to `Tectonic.toml` and is meant to be checked into
+//! version control.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+use tectonic_errors::prelude::*;
+
+/// The name of the lock file.
+pub const LOCK_FILE_NAME: &str = "Tectonic.lock";
+
+/// The version of the lock file format that this crate writes.
+const LOCK_FILE_VERSION: u32 = 1;
+
+const LOCK_FILE_HEADER: &str = "\
+# This file is generated by Tectonic. It pins the bundles used by this
+# document. Do not edit it by hand; use `tectonic -X bundle update` instead.
+";
+
+/// The contents of a `Tectonic.lock` file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BundleLock {
+    /// The locked bundles, in the order that they are listed in
+    /// `Tectonic.toml`.
+    pub bundles: Vec<LockedBundle>,
+}
+
+/// The locked state of one bundle.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedBundle {
+    /// The bundle location, exactly as given in `Tectonic.toml`.
+    pub location: String,
+
+    /// The URL that the location resolved to, if the bundle is accessed over
+    /// the network.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_url: Option<String>,
+
+    /// The hex-encoded digest of the bundle contents.
+    pub digest: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TomlBundleLock {
+    version: u32,
+
+    #[serde(default, rename = "bundle")]
+    bundles: Vec<LockedBundle>,
+}
+
+impl BundleLock {
+    /// Get the locked state of the bundle at *location*, if any.
+    pub fn get(&self, location: &str) -> Option<&LockedBundle> {
+        self.bundles.iter().find(|b| b.location == location)
+    }
+
+    /// Read a lock file.
+    ///
+    /// Returns `None` if the file does not exist.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let lock: TomlBundleLock =
+            atry!(toml::from_str(&text); ["failed to parse `{}`", path.display()]);
+
+        ensure!(
+            lock.version == LOCK_FILE_VERSION,
+            "unsupported version {} of lock file `{}`",
+            lock.version,
+            path.display()
+        );
+
+        Ok(Some(BundleLock {
+            bundles: lock.bundles,
+        }))
+    }
+
+    /// Write this lock to a file, replacing any existing contents.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let lock = TomlBundleLock {
+            version: LOCK_FILE_VERSION,
+            bundles: self.bundles.clone(),
+        };
+
+        let mut text = LOCK_FILE_HEADER.to_owned();
+        text.push('\n');
+        text.push_str(&toml::to_string_pretty(&lock)?);
+
+        let mut f = atry!(fs::File::create(path); ["couldn't create `{}`", path.display()]);
+        f.write_all(text.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        assert_eq!(BundleLock::read_from(&path).unwrap(), None);
+
+        let lock = BundleLock {
+            bundles: vec![
+                LockedBundle {
+                    location: "house".to_owned(),
+                    resolved_url: None,
+                    digest: "00".repeat(32),
+                },
+                LockedBundle {
+                    location: "https://example.com/bundle.tar".to_owned(),
+                    resolved_url: Some("https://example.com/bundle-v2.tar".to_owned()),
+                    digest: "ff".repeat(32),
+                },
+            ],
+        };
+
+        lock.write_to(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# This file is generated by Tectonic."));
+        assert_eq!(BundleLock::read_from(&path).unwrap(), Some(lock.clone()));
+        assert_eq!(
+            lock.get("house").unwrap().digest,
+            "0000000000000000000000000000000000000000000000000000000000000000"
+        );
+        assert!(lock.get("elsewhere").is_none());
+    }
+
+    #[test]
+    fn bad_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        fs::write(&path, "version = 99\n").unwrap();
+        assert!(BundleLock::read_from(&path).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Standard document metadata.
+//!
+//! The `[doc.metadata]` table of `Tectonic.toml` may contain arbitrary data,
+//! but a few keys have a defined meaning. Tectonic uses them to fill in the
+//! metadata of the outputs that it creates — for instance, the PDF document
+//! information dictionary — so that they only need to be specified once.
+
+use tectonic_errors::prelude::*;
+
+/// The metadata keys that have a defined meaning.
+pub const STANDARD_METADATA_KEYS: &[&str] =
+    &["title", "authors", "keywords", "language", "license"];
+
+/// The standard metadata of a document.
+///
+/// All fields are optional. Keys in `[doc.metadata]` that are not listed here
+/// are ignored by Tectonic, but may be read by external tools.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StandardMetadata {
+    /// The document title.
+    pub title: Option<String>,
+
+    /// The document authors. In `Tectonic.toml`, this may be a single string
+    /// or an array of strings.
+    pub authors: Vec<String>,
+
+    /// Keywords describing the document. In `Tectonic.toml`, this may be a
+    /// single string or an array of strings.
+    pub keywords: Vec<String>,
+
+    /// The primary language of the document, as a BCP 47 language tag such as
+    /// `en-US`.
+    pub language: Option<String>,
+
+    /// The license under which the document is distributed, such as an SPDX
+    /// identifier like `CC-BY-4.0`.
+    pub license: Option<String>,
+}
+
+impl StandardMetadata {
+    /// Extract the standard metadata from the contents of `[doc.metadata]`.
+    ///
+    /// This returns an error if any of the standard keys has a value of the
+    /// wrong type.
+    pub fn from_toml(metadata: Option<&toml::Value>) -> Result<Self> {
+        let mut md = StandardMetadata::default();
+
+        let table = match metadata {
+            None => return Ok(md),
+            Some(toml::Value::Table(t)) => t,
+            Some(_) => bail!("`doc.metadata` must be a table"),
+        };
+
+        md.title = optional_string(table, "title")?;
+        md.authors = string_or_strings(table, "authors")?;
+        md.keywords = string_or_strings(table, "keywords")?;
+        md.language = optional_string(table, "language")?;
+        md.license = optional_string(table, "license")?;
+        Ok(md)
+    }
+
+    /// Return true if none of the standard metadata are set.
+    pub fn is_empty(&self) -> bool {
+        *self == StandardMetadata::default()
+    }
+}
+
+fn optional_string(table: &toml::Table, key: &str) -> Result<Option<String>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => bail!("`doc.metadata.{}` must be a string", key),
+    }
+}
+
+fn string_or_strings(table: &toml::Table, key: &str) -> Result<Vec<String>> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::String(s)) => Ok(vec![s.clone()]),
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .map(|v| match v {
+                toml::Value::String(s) => Ok(s.clone()),
+                _ => Err(anyhow!("`doc.metadata.{}` must contain only strings", key)),
+            })
+            .collect(),
+        Some(_) => bail!(
+            "`doc.metadata.{}` must be a string or an array of strings",
+            key
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<StandardMetadata> {
+        let value: toml::Value = toml::from_str(text).unwrap();
+        StandardMetadata::from_toml(Some(&value))
+    }
+
+    #[test]
+    fn full() {
+        let md = parse(
+            r#"
+            title = "A Title"
+            authors = ["A. Author", "B. Author"]
+            keywords = "typesetting"
+            language = "en-US"
+            license = "CC-BY-4.0"
+            other = 1
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(md.title.as_deref(), Some("A Title"));
+        assert_eq!(md.authors, vec!["A. Author", "B. Author"]);
+        assert_eq!(md.keywords, vec!["typesetting"]);
+        assert_eq!(md.language.as_deref(), Some("en-US"));
+        assert_eq!(md.license.as_deref(), Some("CC-BY-4.0"));
+    }
+
+    #[test]
+    fn empty() {
+        assert!(StandardMetadata::from_toml(None).unwrap().is_empty());
+        assert!(parse("other = 1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_types() {
+        assert!(parse("title = 1").is_err());
+        assert!(parse("authors = [\"a\", 2]").is_err());
+        assert!(parse("keywords = { a = 1 }").is_err());
+    }
+}
@@ -7,7 +7,9 @@
 //! This module is only used by [`crate::document::Document`]
 
 use crate::document::{BuildTargetType, InputFile, OutputProfile};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
 
 // This file is an exercise in Rust type conversion.
 //
@@ -15,24 +17,87 @@ use serde::{Deserialize, Serialize, Serializer};
 // serializable version of a struct or enum in document.rs.
 // We convert between the two with ::from() and .into().
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A Tectonic document definition.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
+#[schemars(title = "Tectonic.toml")]
 pub struct TomlDocument {
+    /// Document-wide settings.
     pub doc: TomlDocSection,
 
+    /// The outputs that are created from the document source.
     #[serde(rename = "output")]
     pub outputs: Vec<TomlOutputProfile>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct TomlDocSection {
+    /// The document name, used to name build artifacts.
     pub name: String,
-    pub bundle: String,
+
+    /// The source of the TeX bundle: a URL or a filesystem path. This may
+    /// also be a list of locations, in which case the bundles are layered,
+    /// with earlier ones taking precedence over later ones.
+    pub bundle: StringOrStringVec,
+
+    /// Mirrors of web-based bundles. Each key is one of the bundle URLs, and
+    /// its value is another URL serving the same bundle, or a list of them in
+    /// order of preference. Mirrors are tried in turn if a bundle can't be
+    /// reached.
+    pub mirrors: Option<BTreeMap<String, StringOrStringVec>>,
+
+    /// The directory containing the TeX sources, relative to this file.
+    /// Defaults to `src`.
+    pub source_dir: Option<String>,
+
+    /// Document metadata. Some keys have a defined meaning; others may hold
+    /// arbitrary data.
+    #[schemars(with = "Option<TomlMetadataSchema>")]
     pub metadata: Option<toml::Value>,
 }
 
-#[derive(Debug, Deserialize)]
+/// The shape of `[doc.metadata]`, used only to generate the JSON Schema. The
+/// metadata are parsed by [`crate::metadata::StandardMetadata::from_toml`].
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct TomlMetadataSchema {
+    /// The document title.
+    title: Option<String>,
+
+    /// The document author or authors.
+    authors: Option<StringOrStringVec>,
+
+    /// Keywords describing the document.
+    keywords: Option<StringOrStringVec>,
+
+    /// The primary language of the document, as a BCP 47 tag like `en-US`.
+    language: Option<String>,
+
+    /// The license of the document, such as an SPDX identifier.
+    license: Option<String>,
+
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum StringOrStringVec {
+    String(String),
+    Vec(Vec<String>),
+}
+
+impl From<StringOrStringVec> for Vec<String> {
+    fn from(val: StringOrStringVec) -> Vec<String> {
+        match val {
+            StringOrStringVec::String(s) => vec![s],
+            StringOrStringVec::Vec(v) => v,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum StringOrInputVec {
     String(TomlInputFile),
@@ -59,7 +124,7 @@ impl Serialize for StringOrInputVec {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum TomlInputFile {
     Inline { inline: String },
@@ -86,28 +151,49 @@ impl From<TomlInputFile> for InputFile {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct TomlOutputProfile {
+    /// The name of this output.
     pub name: String,
+
+    /// The type of this output.
     #[serde(rename = "type")]
     pub target_type: TomlBuildTargetType,
+
+    /// The TeX format to use. Defaults to "latex".
     pub tex_format: Option<String>,
+
+    /// Whether the TeX shell-escape feature is allowed.
     pub shell_escape: Option<bool>,
+
+    /// The working directory for shell-escape commands.
     pub shell_escape_cwd: Option<String>,
 
+    /// Shell commands to run, in order, before this output is built.
+    pub pre_build: Option<Vec<String>>,
+
+    /// Shell commands to run, in order, after this output is built.
+    pub post_build: Option<Vec<String>>,
+
     // We cannot handle these two input variants with an enum.
     // The ideal solution requires #[serde(flatten)],
     // which is incompatible with deny_unknown_fields.
     // This will have to do for now.
+    /// The input files used to build this output, relative to `src`.
     pub inputs: Option<StringOrInputVec>,
 
     // Old-fashioned file inputs
     // we might want to deprecate these eventually, or at least provide a warning.
+    /// Deprecated: the preamble file to use. Use `inputs` instead.
     #[serde(rename = "preamble")]
     pub preamble_file: Option<String>,
+
+    /// Deprecated: the index file to use. Use `inputs` instead.
     #[serde(rename = "index")]
     pub index_file: Option<String>,
+
+    /// Deprecated: the postamble file to use. Use `inputs` instead.
     #[serde(rename = "postamble")]
     pub postamble_file: Option<String>,
 }
@@ -149,6 +235,8 @@ impl From<&TomlOutputProfile> for OutputProfile {
             inputs,
             shell_escape: val.shell_escape.unwrap_or(shell_escape_default),
             shell_escape_cwd: val.shell_escape_cwd.clone(),
+            pre_build: val.pre_build.clone().unwrap_or_default(),
+            post_build: val.post_build.clone().unwrap_or_default(),
         }
     }
 }
@@ -166,6 +254,8 @@ impl From<&OutputProfile> for TomlOutputProfile {
 
         let shell_escape = if !rt.shell_escape { None } else { Some(true) };
         let shell_escape_cwd = rt.shell_escape_cwd.clone();
+        let pre_build = (!rt.pre_build.is_empty()).then(|| rt.pre_build.clone());
+        let post_build = (!rt.post_build.is_empty()).then(|| rt.post_build.clone());
 
         TomlOutputProfile {
             name: rt.name.clone(),
@@ -174,6 +264,8 @@ impl From<&OutputProfile> for TomlOutputProfile {
             inputs: Some(inputs),
             shell_escape,
             shell_escape_cwd,
+            pre_build,
+            post_build,
             preamble_file: None,
             index_file: None,
             postamble_file: None,
@@ -181,7 +273,7 @@ impl From<&OutputProfile> for TomlOutputProfile {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum TomlBuildTargetType {
     #[serde(rename = "html")]
     Html,
@@ -0,0 +1,548 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Templates for new workspaces.
+//!
+//! A [`WorkspaceTemplate`] is a collection of files that are written out when
+//! a new workspace is created with [`crate::workspace::WorkspaceCreator`].
+//! Paths are relative to the workspace root, so TeX sources should generally
+//! live under `src/`.
+//!
+//! If a template contains a `Tectonic.toml` file, it is used as the
+//! workspace's document definition. Otherwise, a default one is generated,
+//! with inputs based on the TeX files in the template's `src` directory; see
+//! [`WorkspaceTemplate::default_inputs`].
+//!
+//! In every text file of the template, the placeholder
+//! [`DOCUMENT_NAME_PLACEHOLDER`] is replaced with the name of the new
+//! document, and [`BUNDLE_LOCATION_PLACEHOLDER`] with the location of its
+//! bundle. The replacements are escaped to suit the type of file: TOML strings
+//! in `Tectonic.toml`, TeX text in TeX files, and HTML text in HTML files,
+//! which are often Tera templates.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tectonic_errors::prelude::*;
+
+/// The placeholder that is replaced with the document name.
+pub const DOCUMENT_NAME_PLACEHOLDER: &str = "@DOCUMENT_NAME@";
+
+/// The placeholder that is replaced with the document’s bundle location.
+pub const BUNDLE_LOCATION_PLACEHOLDER: &str = "@BUNDLE_LOCATION@";
+
+/// The name of the template used if none is specified.
+pub const DEFAULT_TEMPLATE: &str = "article";
+
+/// The names of the built-in templates.
+pub const BUILTIN_TEMPLATES: &[&str] = &["article", "beamer", "book", "html-site"];
+
+/// The name of the document definition file within a template.
+const TOML_FILE: &str = "Tectonic.toml";
+
+/// A set of files used to populate a new workspace.
+#[derive(Clone, Debug, Default)]
+pub struct WorkspaceTemplate {
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl WorkspaceTemplate {
+    /// Get one of the built-in templates by name.
+    ///
+    /// Returns `None` if there is no built-in template with the given name.
+    /// The available names are listed in [`BUILTIN_TEMPLATES`].
+    pub fn builtin(name: &str) -> Option<Self> {
+        let files: &[(&str, &str)] = match name {
+            "article" => &[
+                ("src/_preamble.tex", ARTICLE_PREAMBLE),
+                ("src/index.tex", ARTICLE_INDEX),
+                ("src/_postamble.tex", LATEX_POSTAMBLE),
+            ],
+
+            "beamer" => &[
+                ("src/_preamble.tex", BEAMER_PREAMBLE),
+                ("src/index.tex", BEAMER_INDEX),
+                ("src/_postamble.tex", LATEX_POSTAMBLE),
+            ],
+
+            "book" => &[
+                ("src/_preamble.tex", BOOK_PREAMBLE),
+                ("src/index.tex", BOOK_INDEX),
+                ("src/_postamble.tex", LATEX_POSTAMBLE),
+            ],
+
+            "html-site" => &[
+                (TOML_FILE, HTML_SITE_TOML),
+                ("src/_preamble.tex", HTML_SITE_PREAMBLE),
+                ("src/_preamble_html.tex", HTML_SITE_PREAMBLE_HTML),
+                ("src/index.tex", HTML_SITE_INDEX),
+                ("src/_postamble.tex", LATEX_POSTAMBLE),
+                ("src/_postamble_html.tex", HTML_SITE_POSTAMBLE_HTML),
+                ("src/site.html", HTML_SITE_TEMPLATE),
+            ],
+
+            _ => return None,
+        };
+
+        let mut template = WorkspaceTemplate::default();
+
+        for (path, contents) in files {
+            template.add_file(path, contents.as_bytes());
+        }
+
+        Some(template)
+    }
+
+    /// Load a template from a directory.
+    ///
+    /// Every file in the directory, recursively, becomes part of the template.
+    pub fn from_directory<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        fn walk(template: &mut WorkspaceTemplate, root: &Path, rel: &Path) -> Result<()> {
+            let dir = root.join(rel);
+            let entries = atry!(
+                fs::read_dir(&dir);
+                ["failed to read template directory `{}`", dir.display()]
+            );
+
+            for entry in entries {
+                let entry = entry?;
+                let rel_path = rel.join(entry.file_name());
+
+                if entry.file_type()?.is_dir() {
+                    walk(template, root, &rel_path)?;
+                } else {
+                    let contents = atry!(
+                        fs::read(entry.path());
+                        ["failed to read template file `{}`", entry.path().display()]
+                    );
+                    template.add_file(rel_path, contents);
+                }
+            }
+
+            Ok(())
+        }
+
+        let dir = dir.as_ref();
+        let mut template = WorkspaceTemplate::default();
+        walk(&mut template, dir, Path::new(""))?;
+
+        ensure!(
+            !template.files.is_empty(),
+            "template directory `{}` is empty",
+            dir.display()
+        );
+        Ok(template)
+    }
+
+    /// Create a template from a single, complete TeX document.
+    ///
+    /// The document is stored as `src/index.tex`, and the generated
+    /// `Tectonic.toml` file uses it as the sole input of a PDF output.
+    pub fn from_single_file<C: Into<Vec<u8>>>(contents: C) -> Self {
+        let mut template = WorkspaceTemplate::default();
+        template.add_file(TOML_FILE, SINGLE_FILE_TOML.as_bytes());
+        template.add_file("src/index.tex", contents);
+        template
+    }
+
+    /// Add a file to the template.
+    ///
+    /// The path is relative to the workspace root. If the template already
+    /// contains a file with the same path, it is replaced.
+    pub fn add_file<P: Into<PathBuf>, C: Into<Vec<u8>>>(
+        &mut self,
+        path: P,
+        contents: C,
+    ) -> &mut Self {
+        let path = path.into();
+        self.files.retain(|(p, _)| *p != path);
+        self.files.push((path, contents.into()));
+        self
+    }
+
+    /// Return true if this template provides its own `Tectonic.toml` file.
+    pub fn has_toml(&self) -> bool {
+        self.files.iter().any(|(p, _)| p == Path::new(TOML_FILE))
+    }
+
+    /// Work out the inputs of the document, for templates that don't provide
+    /// their own `Tectonic.toml` file.
+    ///
+    /// The inputs are the TeX files directly within the template's `src`
+    /// directory. If there's an `index.tex`, it is used, along with
+    /// `_preamble.tex` and `_postamble.tex` if they exist, as in the default
+    /// document layout. Otherwise, there must be exactly one TeX file, which
+    /// is used on its own. Any other arrangement is an error, since there's no
+    /// way to tell which files are meant to be inputs.
+    pub fn default_inputs(&self) -> Result<Vec<String>> {
+        let tex_files: Vec<&str> = self
+            .files
+            .iter()
+            .filter_map(|(p, _)| {
+                let name = p.strip_prefix("src").ok()?.to_str()?;
+                let is_tex = Path::new(name).extension() == Some("tex".as_ref());
+                (is_tex && Path::new(name).components().count() == 1).then_some(name)
+            })
+            .collect();
+
+        if tex_files.contains(&"index.tex") {
+            return Ok(["_preamble.tex", "index.tex", "_postamble.tex"]
+                .iter()
+                .filter(|name| tex_files.contains(name))
+                .map(|name| name.to_string())
+                .collect());
+        }
+
+        match tex_files[..] {
+            [name] => Ok(vec![name.to_owned()]),
+            [] => bail!(
+                "the template has no `Tectonic.toml` file and no TeX files in `src`, \
+                 so its document can't be set up"
+            ),
+            _ => bail!(
+                "the template has no `Tectonic.toml` file and several TeX files in `src`, \
+                 so its inputs are unclear; add a `Tectonic.toml` file to the template"
+            ),
+        }
+    }
+
+    /// Check that none of the template's files already exist in a workspace
+    /// directory, so that writing them out won't clobber anything.
+    pub(crate) fn check_files(&self, root: &Path) -> Result<()> {
+        for (rel_path, _) in &self.files {
+            let path = root.join(rel_path);
+
+            ensure!(
+                fs::symlink_metadata(&path).is_err(),
+                "the file `{}` already exists; refusing to overwrite it",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write out the template files into a workspace directory.
+    ///
+    /// Placeholders in text files are replaced with the given document name
+    /// and bundle location, escaped to suit the type of each file. None of the
+    /// files may exist already; use [`Self::check_files`] first to avoid
+    /// writing some files and then failing on another.
+    pub(crate) fn write_files(&self, root: &Path, name: &str, bundle_loc: &str) -> Result<()> {
+        for (rel_path, contents) in &self.files {
+            let path = root.join(rel_path);
+
+            if let Some(parent) = path.parent() {
+                atry!(
+                    fs::create_dir_all(parent);
+                    ["couldn't create workspace directory `{}`", parent.display()]
+                );
+            }
+
+            let contents = match std::str::from_utf8(contents) {
+                Ok(text) => {
+                    let escape = escape_for(rel_path);
+                    substitute(text, &escape(name), &escape(bundle_loc)).into_bytes()
+                }
+                Err(_) => contents.clone(),
+            };
+
+            let mut f = atry!(
+                fs::OpenOptions::new().create_new(true).write(true).open(&path);
+                ["couldn\'t create new file `{}`", path.display()]
+            );
+
+            f.write_all(&contents)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn substitute(text: &str, name: &str, bundle_loc: &str) -> String {
+    text.replace(DOCUMENT_NAME_PLACEHOLDER, name)
+        .replace(BUNDLE_LOCATION_PLACEHOLDER, bundle_loc)
+}
+
+/// Get the function that escapes placeholder replacements in the template file
+/// at *path*, based on its type.
+fn escape_for(path: &Path) -> fn(&str) -> String {
+    if path == Path::new(TOML_FILE) {
+        return toml_escape;
+    }
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tex" | "ltx" | "sty" | "cls") => tex_escape,
+        Some("html" | "htm") => html_escape,
+        _ => |text| text.to_owned(),
+    }
+}
+
+/// Escape text so that TeX typesets it literally.
+fn tex_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '^' => escaped.push_str("\\^{}"),
+            '~' => escaped.push_str("\\~{}"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Escape text for use in HTML text or attribute values.
+///
+/// Braces are escaped too, so that the text can't be mistaken for Tera
+/// template syntax, and since double quotes are escaped, the text can be used
+/// inside Tera string literals, such as the arguments of filters.
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '{' => escaped.push_str("&#123;"),
+            '}' => escaped.push_str("&#125;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Escape text for use inside a TOML basic string.
+pub(crate) fn toml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+const ARTICLE_PREAMBLE: &str = r"\documentclass{article}
+\title{My Title}
+\begin{document}
+";
+
+const ARTICLE_INDEX: &str = r"Hello, world.
+";
+
+const LATEX_POSTAMBLE: &str = r"\end{document}
+";
+
+const BEAMER_PREAMBLE: &str = r"\documentclass{beamer}
+\title{@DOCUMENT_NAME@}
+\author{}
+\date{\today}
+\begin{document}
+";
+
+const BEAMER_INDEX: &str = r"\begin{frame}
+  \titlepage
+\end{frame}
+
+\begin{frame}{First Slide}
+  \begin{itemize}
+    \item Hello, world.
+  \end{itemize}
+\end{frame}
+";
+
+const BOOK_PREAMBLE: &str = r"\documentclass{book}
+\title{@DOCUMENT_NAME@}
+\author{}
+\begin{document}
+\frontmatter
+\maketitle
+\tableofcontents
+\mainmatter
+";
+
+const BOOK_INDEX: &str = r"\chapter{Introduction}
+
+Hello, world.
+";
+
+const HTML_SITE_TOML: &str = r#"[doc]
+name = "@DOCUMENT_NAME@"
+bundle = "@BUNDLE_LOCATION@"
+
+[doc.metadata]
+title = "@DOCUMENT_NAME@"
+
+[[output]]
+name = "html"
+type = "html"
+inputs = ["_preamble.tex", "_preamble_html.tex", "index.tex", "_postamble_html.tex", "_postamble.tex"]
+
+[[output]]
+name = "pdf"
+type = "pdf"
+inputs = ["_preamble.tex", "index.tex", "_postamble.tex"]
+"#;
+
+const HTML_SITE_PREAMBLE: &str = r"\documentclass{article}
+\title{@DOCUMENT_NAME@}
+";
+
+const HTML_SITE_PREAMBLE_HTML: &str = r"\special{tdux:addTemplate site.html}
+\special{tdux:setTemplate site.html}
+\special{tdux:setOutputPath index.html}
+";
+
+const HTML_SITE_INDEX: &str = r"\begin{document}
+\section{Welcome}
+
+Hello, world.
+";
+
+const HTML_SITE_POSTAMBLE_HTML: &str = r"\special{tdux:emit}
+";
+
+const HTML_SITE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="{{ tduxMetaLanguage | default(value="en") }}">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>{{ tduxMetaTitle | default(value="@DOCUMENT_NAME@") }}</title>
+</head>
+<body>
+{{ tduxContent | safe }}
+</body>
+</html>
+"#;
+
+const SINGLE_FILE_TOML: &str = r#"[doc]
+name = "@DOCUMENT_NAME@"
+bundle = "@BUNDLE_LOCATION@"
+
+[[output]]
+name = "default"
+type = "pdf"
+inputs = "index.tex"
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn builtins_exist() {
+        for name in BUILTIN_TEMPLATES {
+            assert!(WorkspaceTemplate::builtin(name).is_some(), "{}", name);
+        }
+
+        assert!(WorkspaceTemplate::builtin(DEFAULT_TEMPLATE).is_some());
+        assert!(WorkspaceTemplate::builtin("nonexistent").is_none());
+    }
+
+    #[test]
+    fn substitution() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = WorkspaceTemplate::builtin("html-site").unwrap();
+        assert!(template.has_toml());
+        template
+            .write_files(dir.path(), "my \"doc\"", "https://example.com/b.tar")
+            .unwrap();
+
+        let mut f = fs::File::open(dir.path().join(TOML_FILE)).unwrap();
+        let doc = Document::new_from_toml(dir.path(), dir.path().join("build"), &mut f).unwrap();
+        assert_eq!(doc.name, "my \"doc\"");
+        assert_eq!(doc.bundle_loc, "https://example.com/b.tar");
+        assert_eq!(doc.outputs.len(), 2);
+
+        let preamble = fs::read_to_string(dir.path().join("src/_preamble.tex")).unwrap();
+        assert!(preamble.contains(r#"\title{my "doc"}"#));
+    }
+
+    #[test]
+    fn directory() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("src")).unwrap();
+        fs::write(src.path().join("src/main.tex"), "@DOCUMENT_NAME@").unwrap();
+        fs::write(src.path().join("logo.bin"), [0xFFu8, 0xFE]).unwrap();
+
+        let template = WorkspaceTemplate::from_directory(src.path()).unwrap();
+        assert!(!template.has_toml());
+        assert_eq!(template.default_inputs().unwrap(), ["main.tex"]);
+
+        let dest = tempfile::tempdir().unwrap();
+        template.write_files(dest.path(), "doc", "").unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.path().join("src/main.tex")).unwrap(),
+            "doc"
+        );
+        assert_eq!(
+            fs::read(dest.path().join("logo.bin")).unwrap(),
+            [0xFF, 0xFE]
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = WorkspaceTemplate::builtin("html-site").unwrap();
+        template
+            .write_files(dir.path(), "my_thesis #1 {50% & \"more\"}", "")
+            .unwrap();
+
+        let preamble = fs::read_to_string(dir.path().join("src/_preamble.tex")).unwrap();
+        assert!(preamble.contains(r#"\title{my\_thesis \#1 \{50\% \& "more"\}}"#));
+
+        let html = fs::read_to_string(dir.path().join("src/site.html")).unwrap();
+        assert!(
+            html.contains("default(value=\"my_thesis #1 &#123;50% &amp; &quot;more&quot;&#125;\")")
+        );
+
+        assert_eq!(tex_escape(r"a\b^c~"), r"a\textbackslash{}b\^{}c\~{}");
+    }
+
+    #[test]
+    fn default_inputs() {
+        let mut template = WorkspaceTemplate::default();
+        assert!(template.default_inputs().is_err());
+
+        template.add_file("src/a.tex", "").add_file("src/b.tex", "");
+        assert!(template.default_inputs().is_err());
+
+        template
+            .add_file("src/index.tex", "")
+            .add_file("src/_postamble.tex", "");
+        assert_eq!(
+            template.default_inputs().unwrap(),
+            ["index.tex", "_postamble.tex"]
+        );
+
+        for name in ["article", "beamer", "book"] {
+            assert_eq!(
+                WorkspaceTemplate::builtin(name)
+                    .unwrap()
+                    .default_inputs()
+                    .unwrap(),
+                ["_preamble.tex", "index.tex", "_postamble.tex"]
+            );
+        }
+    }
+}
@@ -0,0 +1,927 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Validation of `Tectonic.toml` files.
+//!
+//! Loading a [`crate::document::Document`] stops at the first problem found in
+//! its `Tectonic.toml` file. The functions in this module instead examine the
+//! file and report every problem that they can find at once, with source
+//! locations where possible. They also check some things that parsing alone
+//! doesn't, such as whether the input files referenced by the outputs exist.
+//!
+//! This module also provides [`json_schema`], which describes the file format
+//! as a [JSON Schema](https://json-schema.org/) that editors can use for
+//! completion and inline checking.
+
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+use url::Url;
+
+use crate::{document::DEFAULT_SOURCE_DIR, syntax};
+
+/// The keys allowed in the `[doc]` section.
+const DOC_KEYS: &[&str] = &["name", "bundle", "mirrors", "source_dir", "metadata"];
+
+/// The keys allowed in an `[[output]]` section.
+const OUTPUT_KEYS: &[&str] = &[
+    "name",
+    "type",
+    "tex_format",
+    "shell_escape",
+    "shell_escape_cwd",
+    "pre_build",
+    "post_build",
+    "inputs",
+    "preamble",
+    "index",
+    "postamble",
+];
+
+/// The legacy input keys of an `[[output]]` section.
+const LEGACY_INPUT_KEYS: &[&str] = &["preamble", "index", "postamble"];
+
+/// The magic key that the `toml` crate uses to pass datetimes through serde.
+const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// A location in the text of a `Tectonic.toml` file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    /// The line number, starting at 1.
+    pub line: usize,
+
+    /// The column number, in characters, starting at 1.
+    pub column: usize,
+}
+
+impl SourceLocation {
+    /// Compute the location of a byte offset into some text.
+    fn from_offset(text: &str, offset: usize) -> Self {
+        let before = &text[..offset.min(text.len())];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        SourceLocation {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// A problem found while validating a `Tectonic.toml` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationIssue {
+    /// A description of the problem.
+    pub message: String,
+
+    /// The byte range of the source text that the problem pertains to, if it
+    /// can be attributed to a specific part of the file.
+    pub span: Option<Range<usize>>,
+
+    /// The start of [`Self::span`] expressed as a line and column.
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(loc) = &self.location {
+            write!(f, "{}:{}: ", loc.line, loc.column)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Validate the text of a `Tectonic.toml` file.
+///
+/// The *root_dir* argument gives the directory containing the file. It is used
+/// to check that the input files named by the outputs exist in its source
+/// directory (usually `src`), and to resolve relative filesystem paths given as the bundle
+/// location. The returned vector is empty if no problems were found.
+pub fn validate_toml(toml_text: &str, root_dir: &Path) -> Vec<ValidationIssue> {
+    let mut checker = Checker {
+        text: toml_text,
+        root_dir,
+        source_dir: root_dir.join(DEFAULT_SOURCE_DIR),
+        issues: Vec::new(),
+    };
+
+    match toml::from_str::<Node>(toml_text) {
+        Ok(root) => checker.check_root(&root),
+        Err(e) => checker.issue(e.span(), e.message()),
+    }
+
+    checker.issues
+}
+
+/// Get a JSON Schema describing the `Tectonic.toml` file format.
+///
+/// The schema is returned as pretty-printed JSON text.
+pub fn json_schema() -> String {
+    let schema = schemars::schema_for!(syntax::TomlDocument);
+    serde_json::to_string_pretty(&schema).expect("JSON schema serialization should not fail")
+}
+
+/// A TOML value, annotated with its location in the source text.
+#[derive(Debug)]
+struct Node {
+    span: Range<usize>,
+    value: NodeValue,
+}
+
+#[derive(Debug)]
+enum NodeValue {
+    String(String),
+    Integer,
+    Float,
+    Boolean,
+    Datetime,
+    Array(Vec<Node>),
+    Table(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn describe(&self) -> &'static str {
+        match self.value {
+            NodeValue::String(_) => "a string",
+            NodeValue::Integer => "an integer",
+            NodeValue::Float => "a float",
+            NodeValue::Boolean => "a boolean",
+            NodeValue::Datetime => "a datetime",
+            NodeValue::Array(_) => "an array",
+            NodeValue::Table(_) => "a table",
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            NodeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_table(&self) -> Option<&[(String, Node)]> {
+        match &self.value {
+            NodeValue::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Node> {
+        self.as_table()?
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let spanned = Spanned::<NodeValue>::deserialize(deserializer)?;
+
+        Ok(Node {
+            span: spanned.span(),
+            value: spanned.into_inner(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeValueVisitor)
+    }
+}
+
+struct NodeValueVisitor;
+
+impl<'de> Visitor<'de> for NodeValueVisitor {
+    type Value = NodeValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TOML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _v: bool) -> Result<NodeValue, E> {
+        Ok(NodeValue::Boolean)
+    }
+
+    fn visit_i64<E: de::Error>(self, _v: i64) -> Result<NodeValue, E> {
+        Ok(NodeValue::Integer)
+    }
+
+    fn visit_u64<E: de::Error>(self, _v: u64) -> Result<NodeValue, E> {
+        Ok(NodeValue::Integer)
+    }
+
+    fn visit_f64<E: de::Error>(self, _v: f64) -> Result<NodeValue, E> {
+        Ok(NodeValue::Float)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<NodeValue, E> {
+        Ok(NodeValue::String(v.to_owned()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<NodeValue, A::Error> {
+        let mut items = Vec::new();
+
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+
+        Ok(NodeValue::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<NodeValue, A::Error> {
+        let mut entries = Vec::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            // The `toml` crate hands datetimes to us as a magic one-item map.
+            if key == TOML_DATETIME_FIELD {
+                map.next_value::<de::IgnoredAny>()?;
+                return Ok(NodeValue::Datetime);
+            }
+
+            let value: Node = map.next_value()?;
+            entries.push((key, value));
+        }
+
+        Ok(NodeValue::Table(entries))
+    }
+}
+
+/// Accumulated state for a validation pass.
+struct Checker<'a> {
+    text: &'a str,
+    root_dir: &'a Path,
+    source_dir: PathBuf,
+    issues: Vec<ValidationIssue>,
+}
+
+impl<'a> Checker<'a> {
+    fn issue<S: Into<String>>(&mut self, span: Option<Range<usize>>, message: S) {
+        let location = span
+            .as_ref()
+            .map(|s| SourceLocation::from_offset(self.text, s.start));
+
+        self.issues.push(ValidationIssue {
+            message: message.into(),
+            span,
+            location,
+        });
+    }
+
+    fn check_root(&mut self, root: &Node) {
+        let table = match root.as_table() {
+            Some(t) => t,
+            None => {
+                self.issue(Some(root.span.clone()), "expected a TOML table");
+                return;
+            }
+        };
+
+        // The source directory affects the checks of the outputs, which might
+        // come before the `[doc]` section.
+        if let Some(source_dir) = root
+            .get("doc")
+            .and_then(|d| d.get("source_dir"))
+            .and_then(Node::as_str)
+        {
+            self.source_dir = self.root_dir.join(source_dir);
+        }
+
+        let mut saw_doc = false;
+        let mut saw_output = false;
+
+        for (key, value) in table {
+            match key.as_str() {
+                "doc" => {
+                    saw_doc = true;
+                    self.check_doc(value);
+                }
+
+                "output" => {
+                    saw_output = true;
+                    self.check_outputs(value);
+                }
+
+                other => self.issue(
+                    Some(value.span.clone()),
+                    format!("unknown top-level key `{other}`; expected `doc` or `output`"),
+                ),
+            }
+        }
+
+        if !saw_doc {
+            self.issue(None, "missing the required `[doc]` section");
+        }
+
+        if !saw_output {
+            self.issue(None, "at least one `[[output]]` section must be defined");
+        }
+    }
+
+    fn check_doc(&mut self, doc: &Node) {
+        let table = match doc.as_table() {
+            Some(t) => t,
+            None => {
+                self.issue(
+                    Some(doc.span.clone()),
+                    format!("`doc` must be a table, not {}", doc.describe()),
+                );
+                return;
+            }
+        };
+
+        self.check_keys(table, DOC_KEYS, "[doc]");
+
+        if let Some(name) = self.require_string(doc, "name", "[doc]") {
+            if name.as_str().map(str::is_empty) == Some(true) {
+                self.issue(
+                    Some(name.span.clone()),
+                    "the document name must not be empty",
+                );
+            }
+        }
+
+        match doc.get("bundle") {
+            Some(bundle) => match &bundle.value {
+                NodeValue::String(loc) => self.check_bundle(loc, bundle.span.clone()),
+
+                NodeValue::Array(items) if items.is_empty() => {
+                    self.issue(
+                        Some(bundle.span.clone()),
+                        "`bundle` must list at least one bundle location",
+                    );
+                }
+
+                NodeValue::Array(items) => {
+                    for item in items {
+                        match item.as_str() {
+                            Some(loc) => self.check_bundle(loc, item.span.clone()),
+                            None => self.issue(
+                                Some(item.span.clone()),
+                                format!(
+                                    "bundle locations must be strings, not {}",
+                                    item.describe()
+                                ),
+                            ),
+                        }
+                    }
+                }
+
+                _ => self.issue(
+                    Some(bundle.span.clone()),
+                    format!(
+                        "`bundle` must be a string or an array of strings, not {}",
+                        bundle.describe()
+                    ),
+                ),
+            },
+
+            None => self.issue(
+                Some(doc.span.clone()),
+                "missing required key `bundle` in `[doc]`",
+            ),
+        }
+
+        if let Some(mirrors) = doc.get("mirrors") {
+            self.check_mirrors(doc, mirrors);
+        }
+
+        self.check_optional_type(doc, "source_dir", "a string", |n| n.as_str().is_some());
+
+        if let Some(source_dir) = doc.get("source_dir") {
+            if source_dir.as_str().is_some() && !self.source_dir.is_dir() {
+                self.issue(
+                    Some(source_dir.span.clone()),
+                    format!(
+                        "source directory `{}` does not exist",
+                        self.source_dir.display()
+                    ),
+                );
+            }
+        }
+
+        if let Some(metadata) = doc.get("metadata") {
+            if metadata.as_table().is_none() {
+                self.issue(
+                    Some(metadata.span.clone()),
+                    format!("`metadata` must be a table, not {}", metadata.describe()),
+                );
+            } else {
+                self.check_metadata(metadata);
+            }
+        }
+    }
+
+    /// Check the types of the standard metadata keys. Other keys may have any
+    /// value.
+    fn check_metadata(&mut self, metadata: &Node) {
+        for key in ["title", "language", "license"] {
+            self.check_optional_type(metadata, key, "a string", |n| n.as_str().is_some());
+        }
+
+        for key in ["authors", "keywords"] {
+            self.check_optional_type(
+                metadata,
+                key,
+                "a string or an array of strings",
+                |n| match &n.value {
+                    NodeValue::String(_) => true,
+                    NodeValue::Array(items) => items.iter().all(|i| i.as_str().is_some()),
+                    _ => false,
+                },
+            );
+        }
+    }
+
+    /// Check `[doc.mirrors]`. Each key should be one of the document's bundle
+    /// locations, and its mirrors should be web URLs.
+    fn check_mirrors(&mut self, doc: &Node, mirrors: &Node) {
+        let table = match mirrors.as_table() {
+            Some(t) => t,
+            None => {
+                self.issue(
+                    Some(mirrors.span.clone()),
+                    format!("`mirrors` must be a table, not {}", mirrors.describe()),
+                );
+                return;
+            }
+        };
+
+        let locs: Vec<&str> = match doc.get("bundle").map(|b| &b.value) {
+            Some(NodeValue::String(loc)) => vec![loc.as_str()],
+            Some(NodeValue::Array(items)) => items.iter().filter_map(|i| i.as_str()).collect(),
+            _ => Vec::new(),
+        };
+
+        for (loc, node) in table {
+            if !locs.contains(&loc.as_str()) {
+                self.issue(
+                    Some(node.span.clone()),
+                    format!("`{loc}` in `[doc.mirrors]` is not one of the document's bundles"),
+                );
+            }
+
+            match &node.value {
+                NodeValue::String(url) => self.check_mirror(url, node.span.clone()),
+
+                NodeValue::Array(items) => {
+                    for item in items {
+                        match item.as_str() {
+                            Some(url) => self.check_mirror(url, item.span.clone()),
+                            None => self.issue(
+                                Some(item.span.clone()),
+                                format!("bundle mirrors must be strings, not {}", item.describe()),
+                            ),
+                        }
+                    }
+                }
+
+                _ => self.issue(
+                    Some(node.span.clone()),
+                    format!(
+                        "the mirrors of `{loc}` must be a string or an array of strings, not {}",
+                        node.describe()
+                    ),
+                ),
+            }
+        }
+    }
+
+    fn check_mirror(&mut self, url: &str, span: Range<usize>) {
+        match Url::parse(url) {
+            Ok(u) if u.scheme() == "http" || u.scheme() == "https" => {}
+            _ => self.issue(
+                Some(span),
+                format!("bundle mirror `{url}` must be an HTTP or HTTPS URL"),
+            ),
+        }
+    }
+
+    fn check_bundle(&mut self, loc: &str, span: Range<usize>) {
+        // Single-letter "schemes" are Windows drive letters, not URLs.
+        let url = Url::parse(loc).ok().filter(|u| u.scheme().len() > 1);
+
+        let path = match url {
+            Some(url) => match url.scheme() {
+                "http" | "https" | "test-bundle" => return,
+
+                "file" => match url.to_file_path() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        self.issue(
+                            Some(span),
+                            format!("bundle URL `{loc}` does not refer to a local path"),
+                        );
+                        return;
+                    }
+                },
+
+                other => {
+                    self.issue(
+                        Some(span),
+                        format!("unsupported bundle URL scheme `{other}` in `{loc}`"),
+                    );
+                    return;
+                }
+            },
+
+            None => self.root_dir.join(loc),
+        };
+
+        if !path.exists() {
+            self.issue(
+                Some(span),
+                format!("bundle path `{}` does not exist", path.display()),
+            );
+        }
+    }
+
+    fn check_outputs(&mut self, outputs: &Node) {
+        let items = match &outputs.value {
+            NodeValue::Array(items) => items,
+            _ => {
+                self.issue(
+                    Some(outputs.span.clone()),
+                    format!(
+                        "`output` must be an array of tables (`[[output]]`), not {}",
+                        outputs.describe()
+                    ),
+                );
+                return;
+            }
+        };
+
+        if items.is_empty() {
+            self.issue(
+                Some(outputs.span.clone()),
+                "at least one `[[output]]` section must be defined",
+            );
+        }
+
+        let mut names: HashMap<String, Range<usize>> = HashMap::new();
+
+        for output in items {
+            let table = match output.as_table() {
+                Some(t) => t,
+                None => {
+                    self.issue(
+                        Some(output.span.clone()),
+                        format!("each output must be a table, not {}", output.describe()),
+                    );
+                    continue;
+                }
+            };
+
+            self.check_keys(table, OUTPUT_KEYS, "[[output]]");
+
+            if let Some(name) = self.require_string(output, "name", "[[output]]") {
+                if let Some(s) = name.as_str() {
+                    if let Some(prev) = names.get(s) {
+                        let prev = SourceLocation::from_offset(self.text, prev.start);
+                        self.issue(
+                            Some(name.span.clone()),
+                            format!(
+                                "duplicated output name `{s}` (first defined on line {})",
+                                prev.line
+                            ),
+                        );
+                    } else {
+                        names.insert(s.to_owned(), name.span.clone());
+                    }
+                }
+            }
+
+            if let Some(ty) = self.require_string(output, "type", "[[output]]") {
+                if let Some(s) = ty.as_str() {
+                    if s != "pdf" && s != "html" {
+                        self.issue(
+                            Some(ty.span.clone()),
+                            format!("unknown output type `{s}`; expected `pdf` or `html`"),
+                        );
+                    }
+                }
+            }
+
+            self.check_optional_type(output, "tex_format", "a string", |n| n.as_str().is_some());
+            self.check_optional_type(output, "shell_escape", "a boolean", |n| {
+                matches!(n.value, NodeValue::Boolean)
+            });
+            self.check_optional_type(output, "shell_escape_cwd", "a string", |n| {
+                n.as_str().is_some()
+            });
+
+            for key in ["pre_build", "post_build"] {
+                self.check_optional_type(output, key, "an array of strings", |n| match &n.value {
+                    NodeValue::Array(items) => items.iter().all(|i| i.as_str().is_some()),
+                    _ => false,
+                });
+            }
+
+            self.check_output_inputs(output);
+        }
+    }
+
+    fn check_output_inputs(&mut self, output: &Node) {
+        let legacy: Vec<&str> = LEGACY_INPUT_KEYS
+            .iter()
+            .copied()
+            .filter(|k| output.get(k).is_some())
+            .collect();
+
+        if let Some(inputs) = output.get("inputs") {
+            if !legacy.is_empty() {
+                self.issue(
+                    Some(inputs.span.clone()),
+                    format!(
+                        "`inputs` may not be combined with the deprecated `{}` setting",
+                        legacy.join("`/`")
+                    ),
+                );
+            }
+
+            match &inputs.value {
+                NodeValue::Array(items) => {
+                    for item in items {
+                        self.check_input(item);
+                    }
+                }
+
+                _ => self.check_input(inputs),
+            }
+        } else if legacy.is_empty() {
+            self.issue(
+                Some(output.span.clone()),
+                "output does not specify any inputs; add an `inputs` setting",
+            );
+        } else {
+            for key in legacy {
+                let node = output.get(key).unwrap();
+
+                match node.as_str() {
+                    Some(path) => self.check_input_path(path, node.span.clone()),
+                    None => self.issue(
+                        Some(node.span.clone()),
+                        format!("`{key}` must be a string, not {}", node.describe()),
+                    ),
+                }
+            }
+        }
+    }
+
+    fn check_input(&mut self, input: &Node) {
+        match &input.value {
+            NodeValue::String(path) => self.check_input_path(path, input.span.clone()),
+
+            NodeValue::Table(entries) => {
+                let ok = entries.len() == 1
+                    && entries[0].0 == "inline"
+                    && entries[0].1.as_str().is_some();
+
+                if !ok {
+                    self.issue(
+                        Some(input.span.clone()),
+                        "an inline input must have the form `{ inline = \"...\" }`",
+                    );
+                }
+            }
+
+            _ => self.issue(
+                Some(input.span.clone()),
+                format!(
+                    "an input must be a file path or an inline table, not {}",
+                    input.describe()
+                ),
+            ),
+        }
+    }
+
+    fn check_input_path(&mut self, path: &str, span: Range<usize>) {
+        let mut full_path = self.source_dir.clone();
+        full_path.push(path);
+
+        if !full_path.is_file() {
+            self.issue(
+                Some(span),
+                format!("input file `{}` does not exist", full_path.display()),
+            );
+        }
+    }
+
+    /// Check for a string-valued key that must be present.
+    ///
+    /// Returns the node if it is present, whether or not it is actually a
+    /// string, so that callers can do more checks.
+    fn require_string<'n>(
+        &mut self,
+        table: &'n Node,
+        key: &str,
+        section: &str,
+    ) -> Option<&'n Node> {
+        match table.get(key) {
+            Some(node) => {
+                if node.as_str().is_none() {
+                    self.issue(
+                        Some(node.span.clone()),
+                        format!("`{key}` must be a string, not {}", node.describe()),
+                    );
+                }
+
+                Some(node)
+            }
+
+            None => {
+                self.issue(
+                    Some(table.span.clone()),
+                    format!("missing required key `{key}` in `{section}`"),
+                );
+                None
+            }
+        }
+    }
+
+    fn check_optional_type<F: Fn(&Node) -> bool>(
+        &mut self,
+        table: &Node,
+        key: &str,
+        expected: &str,
+        is_ok: F,
+    ) {
+        if let Some(node) = table.get(key) {
+            if !is_ok(node) {
+                self.issue(
+                    Some(node.span.clone()),
+                    format!("`{key}` must be {expected}, not {}", node.describe()),
+                );
+            }
+        }
+    }
+
+    fn check_keys(&mut self, table: &[(String, Node)], allowed: &[&str], section: &str) {
+        for (key, value) in table {
+            if !allowed.contains(&key.as_str()) {
+                self.issue(
+                    Some(value.span.clone()),
+                    format!(
+                        "unknown key `{}` in `{section}`; expected one of `{}`",
+                        key,
+                        allowed.join("`, `")
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn setup(files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+
+        for f in files {
+            fs::write(dir.path().join("src").join(f), "").unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn valid_document() {
+        const TOML: &str = r#"
+[doc]
+name = "test"
+bundle = "https://example.com/bundle.tar"
+
+[doc.metadata]
+date = 2023-01-01
+title = "Test"
+authors = ["A. Author", "B. Author"]
+keywords = "testing"
+
+[[output]]
+name = "o"
+type = "pdf"
+inputs = ["main.tex", { inline = "\\end" }]
+pre_build = ["echo pre"]
+post_build = []
+"#;
+
+        let dir = setup(&["main.tex"]);
+        assert_eq!(validate_toml(TOML, dir.path()), vec![]);
+    }
+
+    #[test]
+    fn syntax_error_has_location() {
+        let dir = setup(&[]);
+        let issues = validate_toml("[doc]\nname = \n", dir.path());
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].location.unwrap().line, 2);
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        const TOML: &str = r#"[doc]
+name = "test"
+bundle = "ftp://example.com/bundle.tar"
+nmae = "oops"
+
+[[output]]
+name = "o"
+type = "docx"
+inputs = "missing.tex"
+
+[[output]]
+name = "o"
+type = "pdf"
+inputs = "main.tex"
+index = "main.tex"
+
+[doc.metadata]
+authors = ["A. Author", 2]
+"#;
+
+        let dir = setup(&["main.tex"]);
+        let issues = validate_toml(TOML, dir.path());
+        let lines: Vec<usize> = issues.iter().map(|i| i.location.unwrap().line).collect();
+        assert_eq!(lines, vec![4, 3, 18, 8, 9, 12, 14]);
+    }
+
+    #[test]
+    fn bundle_lists() {
+        const TOML: &str = r#"[doc]
+name = "test"
+bundle = ["house", "missing", "https://example.com/bundle.tar", 1]
+
+[[output]]
+name = "o"
+type = "pdf"
+inputs = "main.tex"
+"#;
+
+        let dir = setup(&["main.tex"]);
+        fs::create_dir(dir.path().join("house")).unwrap();
+        let issues = validate_toml(TOML, dir.path());
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].message.contains("missing"));
+        assert_eq!(
+            issues[1].message,
+            "bundle locations must be strings, not an integer"
+        );
+
+        let issues = validate_toml(&TOML.replace(r#", "missing""#, ""), dir.path());
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn bundle_mirrors() {
+        const TOML: &str = r#"[doc]
+name = "test"
+bundle = "https://example.com/bundle.tar"
+
+[doc.mirrors]
+"https://example.com/bundle.tar" = ["https://mirror.example.org/bundle.tar", "../bundle"]
+"https://example.com/other.tar" = "https://mirror.example.org/other.tar"
+
+[[output]]
+name = "o"
+type = "pdf"
+inputs = "main.tex"
+"#;
+
+        let dir = setup(&["main.tex"]);
+        let issues = validate_toml(TOML, dir.path());
+        let lines: Vec<usize> = issues.iter().map(|i| i.location.unwrap().line).collect();
+        assert_eq!(lines, vec![6, 7]);
+        assert!(issues[0].message.contains("HTTP"));
+        assert!(issues[1]
+            .message
+            .contains("not one of the document's bundles"));
+    }
+
+    #[test]
+    fn missing_sections() {
+        let dir = setup(&[]);
+        let issues = validate_toml("", dir.path());
+        assert_eq!(issues.len(), 2);
+        assert!(issues.iter().all(|i| i.location.is_none()));
+    }
+
+    #[test]
+    fn schema_mentions_outputs() {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema()).unwrap();
+        assert!(schema["properties"]["output"].is_object());
+        assert!(schema["properties"]["doc"].is_object());
+    }
+}
@@ -7,16 +7,13 @@
 //! `Document` instance. This API exists to future-proof a bit for a potential
 //! world where one workspace can contain multiple documents.
 
-use std::{
-    env,
-    error::Error,
-    fmt, fs,
-    io::{self, Write},
-    path::PathBuf,
-};
+use std::{env, error::Error, fmt, fs, io, path::PathBuf};
 use tectonic_errors::prelude::*;
 
-use crate::document::Document;
+use crate::{
+    document::{Document, InputFile},
+    template::{WorkspaceTemplate, DEFAULT_TEMPLATE},
+};
 
 /// A Tectonic workspace.
 ///
@@ -61,6 +58,25 @@ impl Workspace {
     /// no such file is found, an error downcastable into
     /// [`NoWorkspaceFoundError`] is returned.
     pub fn open_from_environment() -> Result<Self> {
+        let mut root_dir = Self::locate_toml_from_environment()?;
+        let mut doc_file = fs::File::open(&root_dir)?;
+
+        root_dir.pop();
+        let mut doc_build_dir = root_dir.clone();
+        doc_build_dir.push("build");
+        let doc = Document::new_from_toml(root_dir.clone(), doc_build_dir, &mut doc_file)?;
+
+        Ok(Workspace { root_dir, doc })
+    }
+
+    /// Find the `Tectonic.toml` file of the workspace containing the current
+    /// directory.
+    ///
+    /// This performs the same search as [`Self::open_from_environment`], but
+    /// only returns the path of the file that was found, without attempting to
+    /// parse it. This is useful for tools that want to examine files that might
+    /// not be valid.
+    pub fn locate_toml_from_environment() -> Result<PathBuf> {
         let initial_dir = env::current_dir()?;
 
         let mut root_dir = initial_dir.clone();
@@ -69,21 +85,14 @@ impl Workspace {
         while root_dir.pop() {
             root_dir.push("Tectonic.toml");
 
-            let mut doc_file = match fs::File::open(&root_dir) {
-                Ok(f) => f,
+            match fs::metadata(&root_dir) {
+                Ok(_) => return Ok(root_dir),
                 Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
                     root_dir.pop(); // remove "Tectonic.toml"
                     continue; // this will pop up one directory and try again
                 }
                 Err(e) => return Err(e.into()),
-            };
-
-            root_dir.pop();
-            let mut doc_build_dir = root_dir.clone();
-            doc_build_dir.push("build");
-            let doc = Document::new_from_toml(root_dir.clone(), doc_build_dir, &mut doc_file)?;
-
-            return Ok(Workspace { root_dir, doc });
+            }
         }
 
         Err(NoWorkspaceFoundError { initial_dir }.into())
@@ -114,6 +123,9 @@ impl Error for NoWorkspaceFoundError {}
 pub struct WorkspaceCreator {
     /// The root directory of the workspace to be created.
     pub(crate) root_dir: PathBuf,
+
+    /// The template used to populate the workspace.
+    template: Option<WorkspaceTemplate>,
 }
 
 impl WorkspaceCreator {
@@ -121,55 +133,53 @@ impl WorkspaceCreator {
     pub fn new<P: Into<PathBuf>>(root_dir: P) -> Self {
         WorkspaceCreator {
             root_dir: root_dir.into(),
+            template: None,
         }
     }
 
+    /// Specify the template used to populate the new workspace.
+    ///
+    /// If this is not called, the built-in [`DEFAULT_TEMPLATE`] is used.
+    pub fn template(&mut self, template: WorkspaceTemplate) -> &mut Self {
+        self.template = Some(template);
+        self
+    }
+
     /// Consume this object and attempt to create the new workspace.
     pub fn create(self, bundle_loc: String) -> Result<Workspace> {
-        let doc = Document::create_for(&self, bundle_loc)?;
-
-        let mut tex_dir = self.root_dir.clone();
-        tex_dir.push("src");
-
-        atry!(
-            fs::create_dir_all(&tex_dir);
-            ["couldn\'t create workspace directory `{}`", tex_dir.display()]
-        );
-
-        doc.create_toml()?;
-
-        // Stub out the TeX.
-
-        {
-            tex_dir.push("_preamble.tex");
-            let mut f = fs::File::create(&tex_dir)?;
-            f.write_all(
-                br"\documentclass{article}
-\title{My Title}
-\begin{document}
-",
-            )?;
-            tex_dir.pop();
-        }
-
-        {
-            tex_dir.push("index.tex");
-            let mut f = fs::File::create(&tex_dir)?;
-            f.write_all(
-                br"Hello, world.
-",
-            )?;
-            tex_dir.pop();
-        }
-
-        {
-            tex_dir.push("_postamble.tex");
-            let mut f = fs::File::create(&tex_dir)?;
-            f.write_all(
-                br"\end{document}
-",
-            )?;
-            tex_dir.pop();
+        let mut doc = Document::create_for(&self, bundle_loc)?;
+
+        let template = match self.template {
+            Some(t) => t,
+            None => WorkspaceTemplate::builtin(DEFAULT_TEMPLATE).unwrap(),
+        };
+
+        // Don't touch anything if it would mean clobbering existing files.
+        template.check_files(&self.root_dir)?;
+
+        if template.has_toml() {
+            template.write_files(&self.root_dir, &doc.name, &doc.bundle_loc)?;
+
+            // Load up the document as the template defined it.
+            let mut toml_path = self.root_dir.clone();
+            toml_path.push("Tectonic.toml");
+            let mut toml_file = fs::File::open(&toml_path)?;
+            doc = atry!(
+                Document::new_from_toml(self.root_dir.clone(), doc.build_dir(), &mut toml_file);
+                ["the template's `Tectonic.toml` file is invalid"]
+            );
+
+            create_tex_dir(&doc)?;
+        } else {
+            let inputs = template.default_inputs()?;
+
+            if let Some(profile) = doc.outputs.get_mut("default") {
+                profile.inputs = inputs.into_iter().map(InputFile::File).collect();
+            }
+
+            create_tex_dir(&doc)?;
+            doc.create_toml()?;
+            template.write_files(&self.root_dir, &doc.name, &doc.bundle_loc)?;
         }
 
         // All done.
@@ -180,3 +190,55 @@ impl WorkspaceCreator {
         })
     }
 }
+
+fn create_tex_dir(doc: &Document) -> Result<()> {
+    let tex_dir = doc.tex_dir();
+
+    atry!(
+        fs::create_dir_all(&tex_dir);
+        ["couldn\'t create workspace directory `{}`", tex_dir.display()]
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_clobbering() {
+        let mut with_toml = WorkspaceTemplate::default();
+        with_toml
+            .add_file("Tectonic.toml", "[doc]\nname = 'x'\nbundle = 'y'\n")
+            .add_file("src/index.tex", "template text");
+
+        let mut without_toml = WorkspaceTemplate::default();
+        without_toml.add_file("src/index.tex", "template text");
+
+        let templates = [
+            None,
+            Some(with_toml),
+            Some(without_toml),
+            Some(WorkspaceTemplate::builtin("beamer").unwrap()),
+        ];
+
+        for template in templates {
+            let dir = tempfile::tempdir().unwrap();
+            let index = dir.path().join("src/index.tex");
+            fs::create_dir(dir.path().join("src")).unwrap();
+            fs::write(&index, "my work").unwrap();
+
+            let mut wc = WorkspaceCreator::new(dir.path());
+
+            if let Some(t) = template {
+                wc.template(t);
+            }
+
+            assert!(wc.create("bundle".to_owned()).is_err());
+            assert_eq!(fs::read_to_string(&index).unwrap(), "my work");
+            assert!(!dir.path().join("Tectonic.toml").exists());
+            assert!(!dir.path().join("src/_preamble.tex").exists());
+        }
+    }
+}
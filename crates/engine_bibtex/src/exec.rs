@@ -10,8 +10,8 @@ use crate::{
     log::{
         brace_lvl_one_letters_complaint, braces_unbalanced_complaint,
         bst_1print_string_size_exceeded, bst_2print_string_size_exceeded,
-        bst_cant_mess_with_entries_print, output_bbl_line, print_a_pool_str, print_confusion,
-        rs_print_fn_class, write_logs,
+        bst_cant_mess_with_entries_print, output_bbl_line, pool_str_lossy, print_a_pool_str,
+        print_confusion, rs_print_fn_class, set_message_location, write_logs,
     },
     pool::{add_buf_pool, add_out_pool, StringPool},
     scan::{
@@ -218,6 +218,8 @@ pub(crate) fn bst_ln_num_print(
     glbl_ctx: &Bibtex<'_, '_>,
     pool: &StringPool,
 ) -> Result<(), BibtexError> {
+    let name = pool_str_lossy(pool, glbl_ctx.bst_str)? + ".bst";
+    set_message_location(name, glbl_ctx.bst_line_num as i32);
     write_logs(&format!("--line {} of file ", glbl_ctx.bst_line_num));
     print_bst_name(glbl_ctx, pool)
 }
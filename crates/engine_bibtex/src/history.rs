@@ -1,3 +1,4 @@
+use crate::log::finish_message;
 use std::cell::Cell;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -25,6 +26,8 @@ pub(crate) fn set_history(hist: History) {
 }
 
 pub(crate) fn mark_warning() {
+    finish_message(false);
+
     match get_history() {
         History::WarningIssued(cur) => set_history(History::WarningIssued(cur + 1)),
         History::Spotless => set_history(History::WarningIssued(1)),
@@ -33,6 +36,8 @@ pub(crate) fn mark_warning() {
 }
 
 pub(crate) fn mark_error() {
+    finish_message(true);
+
     match get_history() {
         History::Spotless | History::WarningIssued(_) => set_history(History::ErrorIssued(1)),
         History::ErrorIssued(cur) => set_history(History::ErrorIssued(cur + 1)),
@@ -41,5 +46,6 @@ pub(crate) fn mark_error() {
 }
 
 pub fn mark_fatal() {
+    finish_message(true);
     set_history(History::FatalError);
 }
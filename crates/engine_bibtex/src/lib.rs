@@ -145,6 +145,7 @@ impl BibtexEngine {
         launcher.with_global_lock(|state| {
             let mut ctx = Bibtex::new(state, self.config.clone());
             let hist = bibtex_main(&mut ctx, &caux);
+            log::report_diagnostics(ctx.engine);
 
             match hist {
                 History::Spotless => Ok(BibtexOutcome::Spotless),
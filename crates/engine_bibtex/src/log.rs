@@ -14,7 +14,16 @@ use crate::{
     ttbc_output_close, ttbc_output_open, ttbc_output_open_stdout, ASCIICode, Bibtex, BibtexError,
     CiteNumber, FieldLoc, HashPointer, StrNumber,
 };
-use std::{cell::Cell, ffi::CStr, io::Write, slice};
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{CStr, CString},
+    io::Write,
+    mem, slice,
+};
+use tectonic_bridge_core::{
+    ttbc_diag_append, ttbc_diag_begin_error, ttbc_diag_begin_warning, ttbc_diag_finish,
+    ttbc_diag_set_location, CoreBridgeState,
+};
 use tectonic_io_base::OutputHandle;
 
 pub trait AsBytes {
@@ -39,14 +48,148 @@ impl AsBytes for [u8] {
     }
 }
 
+/// A warning or error, waiting to be reported to the status backend.
+struct PendingDiagnostic {
+    is_error: bool,
+    message: String,
+    location: Option<(String, i32)>,
+}
+
+/// Keeps track of the messages being printed, so that they can be reported
+/// to the status backend along with the files and lines they're about.
+///
+/// BibTeX prints each message on one line, and then prints its location,
+/// either on the same line or on the next one. So the text of the message is
+/// taken from the line before the location.
+#[derive(Default)]
+struct MessageCapture {
+    /// The line of output being printed.
+    current: Vec<u8>,
+    /// The last non-blank line of output that was completed.
+    previous: Vec<u8>,
+    /// The text and location of the message being printed, once its location
+    /// is known.
+    located: Option<(String, String, i32)>,
+    pending: Vec<PendingDiagnostic>,
+}
+
+impl MessageCapture {
+    fn message(&self) -> String {
+        let current = trim_message(&self.current);
+
+        // Errors while executing style functions are printed as "<message>",
+        // then "while executing", then the location.
+        if current.is_empty() || current == "while executing" {
+            trim_message(&self.previous)
+        } else {
+            current
+        }
+    }
+}
+
+fn trim_message(line: &[u8]) -> String {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+
+    // The status backend will say that warnings are warnings.
+    line.strip_prefix("Warning--")
+        .unwrap_or(line)
+        .trim_end_matches('-')
+        .trim_end()
+        .to_owned()
+}
+
 thread_local! {
     static STANDARD_OUTPUT: Cell<Option<&'static mut OutputHandle>> = Cell::new(None);
     static LOG_FILE: Cell<Option<&'static mut OutputHandle>> = Cell::new(None);
+    static MESSAGES: RefCell<MessageCapture> = RefCell::new(MessageCapture::default());
 }
 
 pub(crate) fn reset() {
     STANDARD_OUTPUT.with(|cell| cell.set(None));
     LOG_FILE.with(|cell| cell.set(None));
+    MESSAGES.with(|cell| *cell.borrow_mut() = MessageCapture::default());
+}
+
+fn capture_message_text(bytes: &[u8]) {
+    MESSAGES.with(|cell| {
+        let mut capture = cell.borrow_mut();
+
+        for &b in bytes {
+            if b == b'\n' {
+                let line = mem::take(&mut capture.current);
+
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    capture.previous = line;
+                }
+            } else {
+                capture.current.push(b);
+            }
+        }
+    })
+}
+
+/// Record that the message being printed is about *line* of *file*. This
+/// should be called just before the location is printed. A line number of 0
+/// means that the line is unknown.
+pub(crate) fn set_message_location(file: String, line: i32) {
+    MESSAGES.with(|cell| {
+        let mut capture = cell.borrow_mut();
+        let message = capture.message();
+        capture.located = Some((message, file, line));
+    })
+}
+
+/// Finish the message being printed, queueing it to be reported to the status
+/// backend as a warning or error.
+pub(crate) fn finish_message(is_error: bool) {
+    MESSAGES.with(|cell| {
+        let mut capture = cell.borrow_mut();
+
+        let (message, location) = match capture.located.take() {
+            Some((message, file, line)) => (message, Some((file, line))),
+            None => (capture.message(), None),
+        };
+
+        capture.current.clear();
+        capture.previous.clear();
+
+        if !message.is_empty() {
+            capture.pending.push(PendingDiagnostic {
+                is_error,
+                message,
+                location,
+            });
+        }
+    })
+}
+
+/// Report the queued warnings and errors to the status backend.
+pub(crate) fn report_diagnostics(engine: &mut CoreBridgeState<'_>) {
+    let pending = MESSAGES.with(|cell| mem::take(&mut cell.borrow_mut().pending));
+
+    for diag in pending {
+        let handle = if diag.is_error {
+            ttbc_diag_begin_error()
+        } else {
+            ttbc_diag_begin_warning()
+        };
+
+        let message = CString::new(diag.message.replace('\0', "")).unwrap();
+
+        // SAFETY: The diagnostic pointer is freshly allocated and is freed by
+        // ttbc_diag_finish, and the strings are valid for each call.
+        unsafe {
+            ttbc_diag_append(&mut *handle, message.as_ptr());
+
+            if let Some((file, line)) = diag.location {
+                let file = CString::new(file.replace('\0', "")).unwrap();
+                ttbc_diag_set_location(&mut *handle, file.as_ptr(), line);
+            }
+
+            ttbc_diag_finish(engine, handle);
+        }
+    }
 }
 
 fn with_stdout<T>(f: impl FnOnce(&mut OutputHandle) -> T) -> T {
@@ -70,6 +213,7 @@ fn with_log<T>(f: impl FnOnce(&mut OutputHandle) -> T) -> T {
 pub(crate) fn write_logs<B: ?Sized + AsBytes>(str: &B) {
     let _ = with_log(|log| log.write_all(str.as_bytes()));
     let _ = with_stdout(|out| out.write_all(str.as_bytes()));
+    capture_message_text(str.as_bytes());
 }
 
 pub(crate) fn write_log_file<B: ?Sized + AsBytes>(str: &B) {
@@ -136,6 +280,10 @@ pub(crate) fn out_token(handle: &mut OutputHandle, buffers: &GlobalBuffer) {
 pub(crate) fn print_a_token(buffers: &GlobalBuffer) {
     with_stdout(|stdout| out_token(stdout, buffers));
     with_log(|log| out_token(log, buffers));
+
+    let start = buffers.offset(BufTy::Base, 1);
+    let end = buffers.offset(BufTy::Base, 2);
+    capture_message_text(&buffers.buffer(BufTy::Base)[start..end]);
 }
 
 pub(crate) fn print_bad_input_line(buffers: &GlobalBuffer) {
@@ -200,6 +348,18 @@ pub(crate) fn out_pool_str(
     }
 }
 
+/// Get a string from the pool, for reporting in a diagnostic.
+pub(crate) fn pool_str_lossy(pool: &StringPool, s: StrNumber) -> Result<String, BibtexError> {
+    match pool.try_get_str(s) {
+        Ok(str) => Ok(String::from_utf8_lossy(str).into_owned()),
+        Err(_) => {
+            write_logs(&format!("Illegal string number: {}", s));
+            print_confusion();
+            Err(BibtexError::Fatal)
+        }
+    }
+}
+
 pub(crate) fn print_a_pool_str(s: StrNumber, pool: &StringPool) -> Result<(), BibtexError> {
     let str = pool.try_get_str(s);
     if let Ok(str) = str {
@@ -242,6 +402,10 @@ pub(crate) fn aux_err_print(
     aux: &AuxData,
     pool: &StringPool,
 ) -> Result<(), BibtexError> {
+    set_message_location(
+        pool_str_lossy(pool, aux.top_file().name)?,
+        aux.top_file().line,
+    );
     write_logs(&format!("---line {} of file ", aux.top_file().line));
     print_aux_name(pool, aux.top_file().name)?;
     print_bad_input_line(buffers);
@@ -282,6 +446,7 @@ pub fn aux_end1_err_print() {
 }
 
 pub(crate) fn aux_end2_err_print(pool: &StringPool, name: StrNumber) -> Result<(), BibtexError> {
+    set_message_location(pool_str_lossy(pool, name)?, 0);
     write_logs("---while reading file ");
     print_aux_name(pool, name)?;
     mark_error();
@@ -378,6 +543,11 @@ pub fn bst_right_brace_print() {
 }
 
 pub(crate) fn bib_ln_num_print(pool: &StringPool, bibs: &BibData) -> Result<(), BibtexError> {
+    let mut name = pool_str_lossy(pool, bibs.cur_bib())?;
+    if !name.ends_with(".bib") {
+        name.push_str(".bib");
+    }
+    set_message_location(name, bibs.line_num());
     write_logs(&format!("--line {} of file ", bibs.line_num()));
     print_bib_name(pool, bibs)
 }
@@ -706,3 +876,146 @@ pub(crate) fn brace_lvl_one_letters_complaint(
     bst_ex_warn_print(ctx, pool, cites)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Diagnostic = (bool, String, Option<(String, i32)>);
+
+    /// Take the messages queued so far.
+    fn take_pending() -> Vec<Diagnostic> {
+        MESSAGES.with(|cell| {
+            mem::take(&mut cell.borrow_mut().pending)
+                .into_iter()
+                .map(|d| (d.is_error, d.message, d.location))
+                .collect()
+        })
+    }
+
+    fn located(is_error: bool, message: &str, file: &str, line: i32) -> Diagnostic {
+        (is_error, message.to_owned(), Some((file.to_owned(), line)))
+    }
+
+    #[test]
+    fn test_location_on_next_line() {
+        reset();
+
+        // As from a warning followed by `bib_ln_num_print`.
+        capture_message_text(b"Warning--I'm ignoring foo's extra \"year\" field\n");
+        set_message_location("refs.bib".to_owned(), 12);
+        capture_message_text(b"--line 12 of file refs.bib\n");
+        finish_message(false);
+
+        assert_eq!(
+            take_pending(),
+            [located(
+                false,
+                "I'm ignoring foo's extra \"year\" field",
+                "refs.bib",
+                12
+            )]
+        );
+    }
+
+    #[test]
+    fn test_location_on_same_line() {
+        reset();
+
+        // As from `bib_err_print`.
+        capture_message_text(b"I was expecting a `,' or a `}'");
+        set_message_location("refs.bib".to_owned(), 5);
+        capture_message_text(b"---line 5 of file refs.bib\n : @article{foo\n");
+        capture_message_text(b"I'm skipping whatever remains of this entry\n");
+        finish_message(true);
+
+        assert_eq!(
+            take_pending(),
+            [located(
+                true,
+                "I was expecting a `,' or a `}'",
+                "refs.bib",
+                5
+            )]
+        );
+    }
+
+    #[test]
+    fn test_while_executing() {
+        reset();
+
+        // As from `bst_ex_warn_print` and `bst_ln_num_print`.
+        capture_message_text(b"empty author in smith2020\n");
+        capture_message_text(b"\n");
+        capture_message_text(b"while executing-");
+        set_message_location("plain.bst".to_owned(), 1043);
+        capture_message_text(b"--line 1043 of file plain.bst\n");
+        finish_message(false);
+
+        assert_eq!(
+            take_pending(),
+            [located(
+                false,
+                "empty author in smith2020",
+                "plain.bst",
+                1043
+            )]
+        );
+    }
+
+    #[test]
+    fn test_aux_errors() {
+        reset();
+
+        // As from `aux_err_print`.
+        capture_message_text(b"Illegal, another \\bibdata command");
+        set_message_location("main.aux".to_owned(), 3);
+        capture_message_text(b"---line 3 of file main.aux\n : \\bibdata{refs}\n");
+        capture_message_text(b"I'm skipping whatever remains of this command\n");
+        finish_message(true);
+
+        // As from `aux_end2_err_print`, where the line is unknown.
+        capture_message_text(b"I found no \\citation commands");
+        set_message_location("main.aux".to_owned(), 0);
+        capture_message_text(b"---while reading file main.aux\n");
+        finish_message(true);
+
+        assert_eq!(
+            take_pending(),
+            [
+                located(true, "Illegal, another \\bibdata command", "main.aux", 3),
+                located(true, "I found no \\citation commands", "main.aux", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_without_location() {
+        reset();
+
+        capture_message_text(b"Warning--I didn't find a database entry for \"foo\"\n");
+        finish_message(false);
+
+        // Nothing was printed for this one, so there's nothing to report.
+        finish_message(true);
+
+        capture_message_text(b"Sorry---you've exceeded BibTeX's hash size 100000");
+        finish_message(true);
+
+        assert_eq!(
+            take_pending(),
+            [
+                (
+                    false,
+                    "I didn't find a database entry for \"foo\"".to_owned(),
+                    None
+                ),
+                (
+                    true,
+                    "Sorry---you've exceeded BibTeX's hash size 100000".to_owned(),
+                    None
+                ),
+            ]
+        );
+    }
+}
@@ -49,6 +49,15 @@ impl Default for InitializationState {
 }
 
 impl InitializationState {
+    /// Create a new state with some template variables predefined. Variables
+    /// set by the document itself will override these.
+    pub(crate) fn new_with_variables(variables: HashMap<String, String>) -> Self {
+        InitializationState {
+            variables,
+            ..Default::default()
+        }
+    }
+
     /// Return true if we're in not in the midst of a multi-step construct like
     /// startDefineFontFamily. In such situations, if we see an event that is
     /// associated with the beginning of the actual content, we should end the
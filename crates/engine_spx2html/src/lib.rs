@@ -8,7 +8,10 @@
 //! SPX is essentially the same thing as XDV, but we identify it differently to
 //! mark that the semantics of the content wil be set up for HTML output.
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 use tectonic_bridge_core::DriverHooks;
 use tectonic_errors::prelude::*;
 use tectonic_status_base::StatusBackend;
@@ -36,6 +39,7 @@ pub struct Spx2HtmlEngine {
     precomputed_assets: Option<AssetSpecification>,
     assets_spec_path: Option<String>,
     do_not_emit_assets: bool,
+    template_variables: HashMap<String, String>,
 }
 
 #[derive(Debug, Default)]
@@ -113,6 +117,17 @@ impl Spx2HtmlEngine {
         self
     }
 
+    /// Predefine a variable that will be available to the HTML templates.
+    ///
+    /// Documents can set template variables themselves with the
+    /// `tdux:setTemplateVariable` special. If a document sets a variable with
+    /// the same name as one defined here, the document's value wins.
+    pub fn template_variable<N: ToString, V: ToString>(&mut self, name: N, value: V) -> &mut Self {
+        self.template_variables
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
     /// Specify the root path for output files.
     ///
     /// Because this driver will, in the generic case, produce a tree of HTML
@@ -145,7 +160,13 @@ impl Spx2HtmlEngine {
         };
 
         {
-            let state = EngineState::new(hooks, status, out_base, self.precomputed_assets.as_ref());
+            let state = EngineState::new(
+                hooks,
+                status,
+                out_base,
+                self.precomputed_assets.as_ref(),
+                self.template_variables.clone(),
+            );
             let state = XdvParser::process_with_seeks(&mut input, state)?;
             let (fonts, assets, mut common) = state.finished()?;
 
@@ -186,6 +207,7 @@ impl<'a> EngineState<'a> {
         status: &'a mut dyn StatusBackend,
         out_base: Option<&'a Path>,
         precomputed_assets: Option<&'a AssetSpecification>,
+        template_variables: HashMap<String, String>,
     ) -> Self {
         Self {
             common: Common {
@@ -194,7 +216,7 @@ impl<'a> EngineState<'a> {
                 out_base,
                 precomputed_assets,
             },
-            state: State::Initializing(InitializationState::default()),
+            state: State::Initializing(InitializationState::new_with_variables(template_variables)),
         }
     }
 }
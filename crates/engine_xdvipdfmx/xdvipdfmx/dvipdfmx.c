@@ -361,6 +361,7 @@ dvipdfmx_main (
   pdf_dev_reset_global_state();
   pdf_obj_reset_global_state();
   pdf_font_reset_unique_tag_state();
+  dpx_error_reset_global_state();
 
   if (quiet) {
     shut_up(2);
@@ -58,6 +58,7 @@ tt_engine_xetex_main(
     int rv;
 
     if (setjmp(*ttbc_global_engine_enter(api))) {
+        finish_diagnostics();
         ttbc_global_engine_exit();
         return HISTORY_FATAL_ERROR;
     }
@@ -65,6 +66,7 @@ tt_engine_xetex_main(
     /* See ttstub_input_get_mtime() in tectonic_bridge_core about bridging time_t
     * over FFI. */
     rv = tt_run_engine(dump_name, input_file_name, (time_t) build_date);
+    finish_diagnostics();
     ttbc_global_engine_exit();
     return rv;
 }
@@ -56,7 +56,10 @@ error(void)
         history = HISTORY_ERROR_ISSUED;
 
     print_char('.');
+    diagnostic_begin_context();
     show_context();
+    capture_to_diagnostic(NULL);
+
     if (halt_on_error_p) {
         history = HISTORY_FATAL_ERROR;
         post_error_message(0);
@@ -9,21 +9,62 @@
 #include "tectonic_bridge_core.h"
 
 static ttbc_diagnostic_t *current_diagnostic = 0;
+static bool current_is_error = false;
+
+// An error whose message has been captured, but which is waiting for error()
+// to show its context.
+static ttbc_diagnostic_t *pending_error = 0;
+static bool capturing_context = false;
+
+static void
+finish_pending_error(void)
+{
+    if (pending_error) {
+        ttstub_diag_finish(pending_error);
+        pending_error = 0;
+    }
+
+    capturing_context = false;
+}
 
 void
 capture_to_diagnostic(ttbc_diagnostic_t *diagnostic)
 {
+    // Errors are reported once error() has added their context, so that
+    // they're reported in order, any older pending error goes first.
+    finish_pending_error();
+
     if (current_diagnostic) {
-        ttstub_diag_finish(current_diagnostic);
+        if (current_is_error && !diagnostic)
+            pending_error = current_diagnostic;
+        else
+            ttstub_diag_finish(current_diagnostic);
     }
 
     current_diagnostic = diagnostic;
+    current_is_error = false;
+}
+
+void
+diagnostic_begin_context(void)
+{
+    if (current_diagnostic && current_is_error)
+        capture_to_diagnostic(NULL);
+
+    capturing_context = (pending_error != 0);
+}
+
+void
+finish_diagnostics(void)
+{
+    capture_to_diagnostic(NULL);
+    finish_pending_error();
 }
 
 static void
-diagnostic_print_file_line(ttbc_diagnostic_t *diagnostic)
+diagnostic_set_file_line(ttbc_diagnostic_t *diagnostic)
 {
-    // Add file/line number information
+    // Attach file/line number information
     // This duplicates logic from print_file_line
 
     int32_t level = in_open;
@@ -39,7 +80,7 @@ diagnostic_print_file_line(ttbc_diagnostic_t *diagnostic)
         }
 
         char* filename = gettexstring(full_source_filename_stack[level]);
-        ttstub_diag_printf(diagnostic, "%s:%d: ", filename, source_line);
+        ttbc_diag_set_location(diagnostic, filename, source_line);
         free(filename);
     }
 }
@@ -48,7 +89,7 @@ ttbc_diagnostic_t *
 diagnostic_begin_capture_warning_here(void)
 {
     ttbc_diagnostic_t *warning = ttbc_diag_begin_warning();
-    diagnostic_print_file_line(warning);
+    diagnostic_set_file_line(warning);
     capture_to_diagnostic(warning);
     return warning;
 }
@@ -58,7 +99,7 @@ ttbc_diagnostic_t *
 error_here_with_diagnostic(const char* message)
 {
     ttbc_diagnostic_t *error = ttbc_diag_begin_error();
-    diagnostic_print_file_line(error);
+    diagnostic_set_file_line(error);
     ttstub_diag_printf(error, "%s", message);
 
     if (file_line_error_style_p)
@@ -68,6 +109,7 @@ error_here_with_diagnostic(const char* message)
     print_cstr(message);
 
     capture_to_diagnostic(error);
+    current_is_error = true;
 
     return error;
 }
@@ -75,10 +117,12 @@ error_here_with_diagnostic(const char* message)
 static void
 warn_char(int c)
 {
-    if (current_diagnostic) {
-        char bytes[2] = { c, 0 };
+    char bytes[2] = { c, 0 };
+
+    if (current_diagnostic)
         ttbc_diag_append(current_diagnostic, bytes);
-    }
+    else if (capturing_context)
+        ttbc_diag_append_context(pending_error, bytes);
 }
 
 void
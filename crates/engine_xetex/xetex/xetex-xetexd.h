@@ -1064,8 +1064,8 @@ void flush_math(void);
 //
 //     capture_to_diagnostic(NULL);
 //
-// The current file and line number information are prefixed to the captured
-// output.
+// The current file and line number information are attached to the diagnostic
+// as its source location.
 //
 // NOTE: the only reason there isn't also an _error_ version of this function is
 // that we haven't yet wired up anything that uses it.
@@ -1076,8 +1076,21 @@ ttbc_diagnostic_t *diagnostic_begin_capture_warning_here(void);
 // this function -- however, the other functions in this API generally do this
 // for you. Complete capture by passing NULL. Either way, if a capture is in
 // progress when this function is called, it will be completed and reported.
+//
+// Errors are an exception: when their capture is completed, they're held back
+// until error() has captured the TeX context that it shows, using
+// diagnostic_begin_context(), and are reported when the next capture begins or
+// ends.
 void capture_to_diagnostic(ttbc_diagnostic_t *diagnostic);
 
+// Capture the TeX context of the most recent error, which is printed next,
+// into its diagnostic.
+void diagnostic_begin_context(void);
+
+// Report any diagnostics still being captured or held back. This is used when
+// the engine finishes or aborts.
+void finish_diagnostics(void);
+
 // A replacement for xetex print_file_line+print_nl_ctr blocks. e.g. Replace
 //
 //     if (file_line_error_style_p)
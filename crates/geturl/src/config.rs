@@ -0,0 +1,217 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Configuration of the HTTP clients used by the backends.
+//!
+//! A [`GetUrlConfig`] is passed to [`crate::GetUrlBackend::new_with_config`].
+//! Backends created with `Default::default()` use a process-wide default
+//! configuration instead, which can be changed with [`set_default_config`] —
+//! this is how applications apply their settings to backends that are created
+//! deep inside other crates.
+
+use std::{path::PathBuf, sync::RwLock, time::Duration};
+
+/// The longest that [`GetUrlConfig::retry_delay`] will ask callers to wait.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+static DEFAULT_CONFIG: RwLock<Option<GetUrlConfig>> = RwLock::new(None);
+
+/// Settings for the HTTP clients used by the get-URL backends.
+///
+/// The [`Default`] settings match the backends' historical behavior: the
+/// proxy and CA certificates are taken from the environment in the usual way,
+/// no extra headers are sent, and requests are retried a few times in quick
+/// succession.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GetUrlConfig {
+    /// The URL of a proxy to use for all requests.
+    pub proxy: Option<String>,
+
+    /// A file of PEM-encoded certificates to trust in addition to the system's
+    /// root certificates.
+    pub ca_file: Option<PathBuf>,
+
+    /// Extra headers to send to particular hosts.
+    pub headers: Vec<HostHeader>,
+
+    /// The time allowed for establishing a connection.
+    pub connect_timeout: Option<Duration>,
+
+    /// The time allowed for a response to arrive, after which a stalled
+    /// transfer is abandoned.
+    pub read_timeout: Option<Duration>,
+
+    /// How many times callers should try a request before giving up.
+    pub max_attempts: usize,
+
+    /// How long callers should wait before retrying a failed request. The
+    /// delay doubles with each further attempt.
+    pub retry_backoff: Duration,
+}
+
+impl Default for GetUrlConfig {
+    fn default() -> Self {
+        GetUrlConfig {
+            proxy: None,
+            ca_file: None,
+            headers: Vec::new(),
+            connect_timeout: None,
+            read_timeout: None,
+            max_attempts: 4,
+            retry_backoff: Duration::from_millis(250),
+        }
+    }
+}
+
+impl GetUrlConfig {
+    /// Get the extra headers to send with a request for *url*, as pairs of
+    /// names and values.
+    pub fn headers_for(&self, url: &str) -> Vec<(&str, &str)> {
+        let host = match url_host(url) {
+            Some(h) => h,
+            None => return Vec::new(),
+        };
+
+        self.headers
+            .iter()
+            .filter(|h| h.host.eq_ignore_ascii_case(host))
+            .map(|h| (h.name.as_str(), h.value.as_str()))
+            .collect()
+    }
+
+    /// Get how long to wait before making another attempt at a request, after
+    /// *n_failures* attempts have failed.
+    pub fn retry_delay(&self, n_failures: usize) -> Duration {
+        let factor = 1u32 << n_failures.saturating_sub(1).min(16);
+        self.retry_backoff
+            .saturating_mul(factor)
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+/// A header to send with every request to a particular host.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostHeader {
+    /// The host name, without a port number.
+    pub host: String,
+
+    /// The header name, such as `Authorization`.
+    pub name: String,
+
+    /// The header value.
+    pub value: String,
+}
+
+/// Set the configuration used by backends created with `Default::default()`.
+pub fn set_default_config(config: GetUrlConfig) {
+    *DEFAULT_CONFIG.write().unwrap() = Some(config);
+}
+
+/// Get the configuration used by backends created with `Default::default()`.
+pub fn default_config() -> GetUrlConfig {
+    DEFAULT_CONFIG.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Extract the host name from a URL, without pulling in a URL parser.
+fn url_host(url: &str) -> Option<&str> {
+    let rest = &url[url.find("://")? + 3..];
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit('@').next()?;
+
+    if let Some(v6) = host_port.strip_prefix('[') {
+        return v6.split(']').next();
+    }
+
+    host_port.split(':').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(host: &str, name: &str, value: &str) -> HostHeader {
+        HostHeader {
+            host: host.to_owned(),
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(url_host("https://example.com"), Some("example.com"));
+        assert_eq!(url_host("https://example.com/a/b"), Some("example.com"));
+        assert_eq!(url_host("https://example.com?x=1"), Some("example.com"));
+        assert_eq!(url_host("https://example.com#frag"), Some("example.com"));
+        assert_eq!(url_host("http://example.com:8080/x"), Some("example.com"));
+        assert_eq!(
+            url_host("https://user:pw@example.com/x"),
+            Some("example.com")
+        );
+        assert_eq!(
+            url_host("https://user@evil.com@example.com:443/x"),
+            Some("example.com")
+        );
+        assert_eq!(url_host("http://[::1]:8080/x"), Some("::1"));
+        assert_eq!(url_host("http://[2001:db8::1]/x"), Some("2001:db8::1"));
+        assert_eq!(url_host("https://Example.COM/x"), Some("Example.COM"));
+        assert_eq!(url_host("example.com/x"), None);
+    }
+
+    #[test]
+    fn headers() {
+        let config = GetUrlConfig {
+            headers: vec![
+                header("gitlab.example.com", "Private-Token", "secret"),
+                header("gitlab.example.com", "X-Other", "1"),
+                header("::1", "Authorization", "Bearer local"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.headers_for("https://GitLab.Example.com:8443/bundle.tar"),
+            vec![("Private-Token", "secret"), ("X-Other", "1")]
+        );
+        assert_eq!(
+            config.headers_for("https://someone@gitlab.example.com/bundle.tar"),
+            vec![("Private-Token", "secret"), ("X-Other", "1")]
+        );
+        assert_eq!(
+            config.headers_for("http://[::1]:8000/bundle.tar"),
+            vec![("Authorization", "Bearer local")]
+        );
+        assert!(config
+            .headers_for("https://cdn.example.com/bundle.tar")
+            .is_empty());
+        assert!(config
+            .headers_for("https://gitlab.example.com.evil.net/bundle.tar")
+            .is_empty());
+        assert!(config
+            .headers_for("https://gitlab.example.com@evil.net/bundle.tar")
+            .is_empty());
+        assert!(config.headers_for("not a url").is_empty());
+    }
+
+    #[test]
+    fn retry_delays() {
+        let config = GetUrlConfig {
+            retry_backoff: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        assert_eq!(config.retry_delay(0), Duration::from_millis(100));
+        assert_eq!(config.retry_delay(1), Duration::from_millis(100));
+        assert_eq!(config.retry_delay(2), Duration::from_millis(200));
+        assert_eq!(config.retry_delay(3), Duration::from_millis(400));
+        assert_eq!(config.retry_delay(8), MAX_RETRY_DELAY);
+        assert_eq!(config.retry_delay(usize::MAX), MAX_RETRY_DELAY);
+
+        let config = GetUrlConfig {
+            retry_backoff: Duration::ZERO,
+            ..Default::default()
+        };
+
+        assert_eq!(config.retry_delay(5), Duration::ZERO);
+    }
+}
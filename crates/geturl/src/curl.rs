@@ -3,63 +3,109 @@
 
 //! A URL-get backend based on the `curl` crate.
 
-use curl::easy::Easy;
+use curl::easy::{Easy, List};
 use std::io::Cursor;
 use tectonic_errors::{anyhow::bail, Result};
 use tectonic_status_base::StatusBackend;
 
-use crate::{GetUrlBackend, RangeReader};
+use crate::{config::default_config, GetUrlBackend, GetUrlConfig, RangeReader};
 
 const MAX_HTTP_REDIRECTS_ALLOWED: u32 = 10;
 
 fn get_url_generic(
     handle: &mut Easy,
+    config: &GetUrlConfig,
     url: &str,
     range: Option<(u64, usize)>,
 ) -> Result<Cursor<Vec<u8>>> {
-    handle.url(url)?;
-    handle.follow_location(true)?;
-    handle.max_redirections(MAX_HTTP_REDIRECTS_ALLOWED)?;
+    // We follow redirects ourselves, rather than letting curl do it, so that
+    // each hop only gets the extra headers configured for its own host.
+    // Otherwise a token meant for one server would be handed to whatever
+    // server it redirects us to.
+    handle.follow_location(false)?;
+
+    if let Some(proxy) = &config.proxy {
+        handle.proxy(proxy)?;
+    }
+
+    if let Some(ca_file) = &config.ca_file {
+        handle.cainfo(ca_file)?;
+    }
+
+    if let Some(t) = config.connect_timeout {
+        handle.connect_timeout(t)?;
+    }
+
+    if let Some(t) = config.read_timeout {
+        // Abandon the transfer if it stalls for this long.
+        handle.low_speed_limit(1)?;
+        handle.low_speed_time(t)?;
+    }
 
     if let Some((start, length)) = range {
         let end = start + length as u64 - 1;
         handle.range(&format!("{start}-{end}"))?;
     }
 
-    let mut buf = Vec::new();
-    {
-        let mut transfer = handle.transfer();
-        transfer.write_function(|data| {
-            buf.extend_from_slice(data);
-            Ok(data.len())
-        })?;
-        transfer.perform()?;
-    }
+    let mut current = url.to_owned();
+    let mut n_redirects = 0;
 
-    let code = handle.response_code()?;
+    loop {
+        handle.url(&current)?;
 
-    if !(200..300).contains(&code) {
-        bail!(
-            "unsuccessful HTTP GET status code {} for url `{}`",
-            code,
-            url
-        );
-    }
+        let mut headers = List::new();
+        for (name, value) in config.headers_for(&current) {
+            headers.append(&format!("{name}: {value}"))?;
+        }
+        handle.http_headers(headers)?;
+
+        let mut buf = Vec::new();
+        {
+            let mut transfer = handle.transfer();
+            transfer.write_function(|data| {
+                buf.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
+
+        let code = handle.response_code()?;
+
+        if (300..400).contains(&code) {
+            if let Some(next) = handle.redirect_url()? {
+                n_redirects += 1;
+
+                if n_redirects > MAX_HTTP_REDIRECTS_ALLOWED {
+                    bail!("too many redirections for url `{}`", url);
+                }
 
-    Ok(Cursor::new(buf))
+                current = next.to_owned();
+                continue;
+            }
+        }
+
+        if !(200..300).contains(&code) {
+            bail!(
+                "unsuccessful HTTP GET status code {} for url `{}`",
+                code,
+                url
+            );
+        }
+
+        return Ok(Cursor::new(buf));
+    }
 }
 
 /// URL-get backend implemented using the `curl` crate.
 #[derive(Debug)]
 pub struct CurlBackend {
     handle: Easy,
+    config: GetUrlConfig,
 }
 
 impl Default for CurlBackend {
     fn default() -> Self {
-        CurlBackend {
-            handle: Easy::new(),
-        }
+        Self::new_with_config(default_config())
     }
 }
 
@@ -67,8 +113,15 @@ impl GetUrlBackend for CurlBackend {
     type Response = Cursor<Vec<u8>>;
     type RangeReader = CurlRangeReader;
 
+    fn new_with_config(config: GetUrlConfig) -> Self {
+        CurlBackend {
+            handle: Easy::new(),
+            config,
+        }
+    }
+
     fn get_url(&mut self, url: &str, _status: &mut dyn StatusBackend) -> Result<Self::Response> {
-        get_url_generic(&mut self.handle, url, None)
+        get_url_generic(&mut self.handle, &self.config, url, None)
     }
 
     fn resolve_url(&mut self, url: &str, _status: &mut dyn StatusBackend) -> Result<String> {
@@ -76,7 +129,7 @@ impl GetUrlBackend for CurlBackend {
     }
 
     fn open_range_reader(&self, url: &str) -> Self::RangeReader {
-        CurlRangeReader::new(url)
+        CurlRangeReader::new(url, self.config.clone())
     }
 }
 
@@ -85,13 +138,15 @@ impl GetUrlBackend for CurlBackend {
 pub struct CurlRangeReader {
     url: String,
     handle: Easy,
+    config: GetUrlConfig,
 }
 
 impl CurlRangeReader {
-    fn new(url: &str) -> CurlRangeReader {
+    fn new(url: &str, config: GetUrlConfig) -> CurlRangeReader {
         CurlRangeReader {
             url: url.to_owned(),
             handle: Easy::new(),
+            config,
         }
     }
 }
@@ -100,6 +155,11 @@ impl RangeReader for CurlRangeReader {
     type Response = Cursor<Vec<u8>>;
 
     fn read_range(&mut self, offset: u64, length: usize) -> Result<Self::Response> {
-        get_url_generic(&mut self.handle, &self.url, Some((offset, length)))
+        get_url_generic(
+            &mut self.handle,
+            &self.config,
+            &self.url,
+            Some((offset, length)),
+        )
     }
 }
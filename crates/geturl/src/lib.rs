@@ -5,11 +5,14 @@
 
 //! A simple, pluggable interface for HTTP GETs and range requests.
 //!
-//! At the moment, there are three backends that might be available:
+//! At the moment, there are four backends that might be available:
 //! 1. A "null" backend that is always available, but does nothing and always
 //!    fails.
-//! 2. A backend based on [reqwest](https://docs.rs/reqwest/).
-//! 3. A backend based on [curl](https://docs.rs/curl/).
+//! 2. An in-memory backend that is always available, and serves files from
+//!    [`memory::MemoryServer`]s. It is intended for testing, and is never the
+//!    default.
+//! 3. A backend based on [reqwest](https://docs.rs/reqwest/).
+//! 4. A backend based on [curl](https://docs.rs/curl/).
 //!
 //! The actual backend availability is determined at build time using [Cargo
 //! features][features]. See the crate README for information about the features
@@ -20,7 +23,8 @@
 //! The default backend is intentionally exposed as a concrete type, so that
 //! crates relying on this one need not use a lot of dyns and impl Traits. It is
 //! intended that the choice of HTTP backend is a build-time one, not a runtime
-//! one.
+//! one. Settings such as proxies and timeouts are given with a
+//! [`GetUrlConfig`]; see the [`config`] module.
 
 use cfg_if::cfg_if;
 use std::io::Read;
@@ -37,6 +41,9 @@ pub trait RangeReader {
 }
 
 /// A trait for simple HTTP operations needed by the Tectonic backends.
+///
+/// Backends created with `Default::default()` use the process-wide
+/// configuration returned by [`config::default_config`].
 pub trait GetUrlBackend: Default {
     /// The readable type returned by URL get requests.
     type Response: Read;
@@ -44,6 +51,11 @@ pub trait GetUrlBackend: Default {
     /// The range-reader type for URLs that will undergo byte-range reads.
     type RangeReader: RangeReader;
 
+    /// Create a backend with the specified configuration.
+    ///
+    /// Range readers opened by the backend use the same configuration.
+    fn new_with_config(config: GetUrlConfig) -> Self;
+
     /// Starting with an input URL, follow redirections to get a final URL.
     ///
     /// But we attempt to detect redirects into CDNs/S3/etc and *stop* following
@@ -57,8 +69,12 @@ pub trait GetUrlBackend: Default {
     fn open_range_reader(&self, url: &str) -> Self::RangeReader;
 }
 
+pub mod config;
+pub mod memory;
 pub mod null;
 
+pub use config::GetUrlConfig;
+
 #[cfg(feature = "curl")]
 pub mod curl;
 
@@ -0,0 +1,526 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An in-memory URL-get backend, for testing code that uses the network.
+//!
+//! A [`MemoryServer`] holds a set of files, which are served at `memory://`
+//! URLs to every [`MemoryBackend`] — including ones created with
+//! `Default::default()` deep inside other crates. Servers can simulate
+//! redirects, failing requests, outages and slow responses, and they log every
+//! request made of them, so that tests can check exactly what was fetched.
+//!
+//! Each server has its own unique host name, so that tests running in parallel
+//! don't interfere with each other. A server stops serving its files when it is
+//! dropped.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::Duration,
+};
+use tectonic_errors::{anyhow::bail, Result};
+use tectonic_status_base::StatusBackend;
+
+use crate::{config::default_config, GetUrlBackend, GetUrlConfig, RangeReader};
+
+const MAX_HTTP_REDIRECTS_ALLOWED: usize = 10;
+
+const URL_SCHEME: &str = "memory://";
+
+static NEXT_SERVER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The servers that currently exist, keyed by host name.
+static SERVERS: Mutex<Vec<(String, Weak<Mutex<ServerState>>)>> = Mutex::new(Vec::new());
+
+/// A kind of request made of a [`MemoryServer`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequestKind {
+    /// A request for a whole file.
+    Get,
+
+    /// A request to resolve a URL by following its redirects.
+    Resolve,
+
+    /// A request for a byte range of a file.
+    Range {
+        /// The offset of the start of the range.
+        offset: u64,
+
+        /// The length of the range.
+        length: usize,
+    },
+}
+
+/// A request made of a [`MemoryServer`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Request {
+    /// The kind of request.
+    pub kind: RequestKind,
+
+    /// The path that was requested, starting with a slash.
+    pub path: String,
+
+    /// The extra headers sent with the request, as configured with
+    /// [`GetUrlConfig::headers`].
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    files: HashMap<String, Arc<Vec<u8>>>,
+    redirects: HashMap<String, String>,
+    failures: HashMap<String, usize>,
+    down: bool,
+    latency: Duration,
+    requests: Vec<Request>,
+}
+
+/// What a server did with a request.
+enum Reply {
+    Data(Arc<Vec<u8>>),
+    Redirect(String),
+}
+
+/// A set of files served to [`MemoryBackend`]s.
+#[derive(Debug)]
+pub struct MemoryServer {
+    host: String,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl Default for MemoryServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryServer {
+    /// Create a new server, with no files.
+    pub fn new() -> Self {
+        let host = format!("server-{}", NEXT_SERVER_ID.fetch_add(1, Ordering::SeqCst));
+        let state = Arc::new(Mutex::new(ServerState::default()));
+
+        let mut servers = SERVERS.lock().unwrap();
+        servers.retain(|(_, s)| s.strong_count() > 0);
+        servers.push((host.clone(), Arc::downgrade(&state)));
+
+        MemoryServer { host, state }
+    }
+
+    /// Get the URL of a path on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}{}", URL_SCHEME, self.host, normalize_path(path))
+    }
+
+    /// Serve a file at *path*, replacing any existing file or redirect.
+    pub fn set_file<D: Into<Vec<u8>>>(&mut self, path: &str, data: D) -> &mut Self {
+        let path = normalize_path(path);
+        let mut state = self.state.lock().unwrap();
+        state.redirects.remove(&path);
+        state.files.insert(path, Arc::new(data.into()));
+        drop(state);
+        self
+    }
+
+    /// Stop serving anything at *path*, so that requests for it fail as if
+    /// with a 404 error.
+    pub fn remove_file(&mut self, path: &str) -> &mut Self {
+        let path = normalize_path(path);
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(&path);
+        state.redirects.remove(&path);
+        drop(state);
+        self
+    }
+
+    /// Redirect requests for *path* to *target*, which is a complete URL.
+    pub fn set_redirect(&mut self, path: &str, target: &str) -> &mut Self {
+        let path = normalize_path(path);
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(&path);
+        state.redirects.insert(path, target.to_owned());
+        drop(state);
+        self
+    }
+
+    /// Make the next *n* requests for *path* fail, as if the connection had
+    /// been dropped.
+    pub fn fail_next(&mut self, path: &str, n: usize) -> &mut Self {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .insert(normalize_path(path), n);
+        self
+    }
+
+    /// Set whether the server is down, in which case all requests fail.
+    pub fn set_down(&mut self, down: bool) -> &mut Self {
+        self.state.lock().unwrap().down = down;
+        self
+    }
+
+    /// Set how long the server takes to answer each request.
+    pub fn set_latency(&mut self, latency: Duration) -> &mut Self {
+        self.state.lock().unwrap().latency = latency;
+        self
+    }
+
+    /// Get the requests that have been made of this server, in order.
+    ///
+    /// Requests are logged even if they fail.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Forget the requests that have been made of this server.
+    pub fn clear_requests(&mut self) -> &mut Self {
+        self.state.lock().unwrap().requests.clear();
+        self
+    }
+}
+
+impl Drop for MemoryServer {
+    fn drop(&mut self) {
+        if let Ok(mut servers) = SERVERS.lock() {
+            servers.retain(|(h, _)| h != &self.host);
+        }
+    }
+}
+
+fn normalize_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Have the appropriate server handle a request, without following redirects.
+fn handle(url: &str, kind: RequestKind, config: &GetUrlConfig) -> Result<Reply> {
+    let rest = match url.strip_prefix(URL_SCHEME) {
+        Some(r) => r,
+        None => bail!("the in-memory backend can't fetch URL {}", url),
+    };
+
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let state = SERVERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(h, _)| h == host)
+        .and_then(|(_, s)| s.upgrade());
+
+    let state = match state {
+        Some(s) => s,
+        None => bail!("failed to connect to {}: no such in-memory server", url),
+    };
+
+    let latency = {
+        let mut state = state.lock().unwrap();
+
+        state.requests.push(Request {
+            kind,
+            path: path.to_owned(),
+            headers: config
+                .headers_for(url)
+                .into_iter()
+                .map(|(n, v)| (n.to_owned(), v.to_owned()))
+                .collect(),
+        });
+
+        state.latency
+    };
+
+    // Don't hold the lock while waiting, so that concurrent requests are
+    // answered concurrently.
+    if !latency.is_zero() {
+        thread::sleep(latency);
+    }
+
+    let mut state = state.lock().unwrap();
+
+    if state.down {
+        bail!("failed to connect to {}: simulated outage", url);
+    }
+
+    if let Some(n) = state.failures.get_mut(path) {
+        if *n > 0 {
+            *n -= 1;
+            bail!(
+                "connection dropped while requesting {}: simulated failure",
+                url
+            );
+        }
+    }
+
+    if let Some(target) = state.redirects.get(path) {
+        return Ok(Reply::Redirect(target.clone()));
+    }
+
+    match state.files.get(path) {
+        Some(data) => Ok(Reply::Data(data.clone())),
+        None => bail!(
+            "unexpected HTTP response code 404 Not Found for URL {}",
+            url
+        ),
+    }
+}
+
+/// Handle a request, following redirects, and returning the final URL along
+/// with the data.
+fn handle_following(
+    url: &str,
+    kind: RequestKind,
+    config: &GetUrlConfig,
+) -> Result<(String, Arc<Vec<u8>>)> {
+    let mut url = url.to_owned();
+
+    for _ in 0..=MAX_HTTP_REDIRECTS_ALLOWED {
+        match handle(&url, kind, config)? {
+            Reply::Data(data) => return Ok((url, data)),
+            Reply::Redirect(target) => url = target,
+        }
+    }
+
+    bail!("too many redirections for URL {}", url);
+}
+
+/// URL-get backend that fetches files from [`MemoryServer`]s.
+#[derive(Debug)]
+pub struct MemoryBackend {
+    config: GetUrlConfig,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new_with_config(default_config())
+    }
+}
+
+impl GetUrlBackend for MemoryBackend {
+    type Response = Cursor<Vec<u8>>;
+    type RangeReader = MemoryRangeReader;
+
+    fn new_with_config(config: GetUrlConfig) -> Self {
+        MemoryBackend { config }
+    }
+
+    fn get_url(&mut self, url: &str, _status: &mut dyn StatusBackend) -> Result<Self::Response> {
+        let (_, data) = handle_following(url, RequestKind::Get, &self.config)?;
+        Ok(Cursor::new(data.to_vec()))
+    }
+
+    fn resolve_url(&mut self, url: &str, _status: &mut dyn StatusBackend) -> Result<String> {
+        let (final_url, _) = handle_following(url, RequestKind::Resolve, &self.config)?;
+        Ok(final_url)
+    }
+
+    fn open_range_reader(&self, url: &str) -> Self::RangeReader {
+        MemoryRangeReader {
+            url: url.to_owned(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// Byte-range reader for files on [`MemoryServer`]s.
+#[derive(Debug)]
+pub struct MemoryRangeReader {
+    url: String,
+    config: GetUrlConfig,
+}
+
+impl RangeReader for MemoryRangeReader {
+    type Response = Cursor<Vec<u8>>;
+
+    fn read_range(&mut self, offset: u64, length: usize) -> Result<Self::Response> {
+        let kind = RequestKind::Range { offset, length };
+        let (_, data) = handle_following(&self.url, kind, &self.config)?;
+
+        let start: Option<usize> = offset.try_into().ok();
+        let end = start.and_then(|s| s.checked_add(length));
+
+        match (start, end) {
+            (Some(start), Some(end)) if end <= data.len() => {
+                Ok(Cursor::new(data[start..end].to_vec()))
+            }
+
+            _ => bail!(
+                "unexpected HTTP response code 416 Range Not Satisfiable for URL {}",
+                self.url
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, time::Instant};
+    use tectonic_status_base::NoopStatusBackend;
+
+    use super::*;
+
+    fn get(url: &str) -> Result<Vec<u8>> {
+        let mut status = NoopStatusBackend::default();
+        let mut buf = Vec::new();
+        MemoryBackend::default()
+            .get_url(url, &mut status)?
+            .read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_range(url: &str, offset: u64, length: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        MemoryBackend::default()
+            .open_range_reader(url)
+            .read_range(offset, length)?
+            .read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn ranges() {
+        let mut server = MemoryServer::new();
+        server.set_file("data", &b"0123456789"[..]);
+        let url = server.url("/data");
+
+        assert_eq!(read_range(&url, 2, 3).unwrap(), b"234");
+        assert_eq!(read_range(&url, 0, 10).unwrap(), b"0123456789");
+        assert_eq!(read_range(&url, 4, 0).unwrap(), b"");
+        assert_eq!(read_range(&url, 10, 0).unwrap(), b"");
+
+        assert!(read_range(&url, 8, 3).is_err());
+        assert!(read_range(&url, 11, 0).is_err());
+        assert!(read_range(&url, u64::MAX, 1).is_err());
+        assert!(read_range(&url, 1, usize::MAX).is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 8);
+        assert_eq!(requests[0].path, "/data");
+        assert_eq!(
+            requests[0].kind,
+            RequestKind::Range {
+                offset: 2,
+                length: 3
+            }
+        );
+    }
+
+    #[test]
+    fn redirects() {
+        let mut server = MemoryServer::new();
+        let end = server.url("/end");
+        let middle = server.url("/middle");
+        server
+            .set_file("/end", "done")
+            .set_redirect("/middle", &end)
+            .set_redirect("/start", &middle);
+
+        let mut status = NoopStatusBackend::default();
+        let resolved = MemoryBackend::default()
+            .resolve_url(&server.url("/start"), &mut status)
+            .unwrap();
+        assert_eq!(resolved, end);
+        assert_eq!(get(&server.url("/start")).unwrap(), b"done");
+        assert_eq!(read_range(&server.url("/start"), 1, 2).unwrap(), b"on");
+
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths.len(), 9);
+        assert_eq!(paths[..3], ["/start", "/middle", "/end"]);
+
+        // Replacing a redirect with a file, or vice versa, replaces it.
+        server.set_file("/middle", "here");
+        assert_eq!(get(&server.url("/start")).unwrap(), b"here");
+
+        // Loops are given up on.
+        let a = server.url("/a");
+        let b = server.url("/b");
+        server.set_redirect("/a", &b).set_redirect("/b", &a);
+        server.clear_requests();
+        let err = get(&a).unwrap_err().to_string();
+        assert!(err.contains("too many redirections"), "{}", err);
+        assert_eq!(server.requests().len(), MAX_HTTP_REDIRECTS_ALLOWED + 1);
+    }
+
+    #[test]
+    fn failures() {
+        let mut server = MemoryServer::new();
+        server.set_file("/f", "data").fail_next("/f", 2);
+        let url = server.url("/f");
+
+        assert!(get(&url).is_err());
+        assert!(read_range(&url, 0, 1).is_err());
+        assert_eq!(get(&url).unwrap(), b"data");
+        assert_eq!(get(&url).unwrap(), b"data");
+
+        // Failures are counted per path, and logged.
+        server.fail_next("/f", 1).set_file("/g", "other");
+        assert_eq!(get(&server.url("/g")).unwrap(), b"other");
+        assert!(get(&url).is_err());
+        assert_eq!(get(&url).unwrap(), b"data");
+        assert_eq!(server.requests().len(), 7);
+
+        server.remove_file("/f");
+        assert!(get(&url).is_err());
+    }
+
+    #[test]
+    fn outages() {
+        let mut server = MemoryServer::new();
+        server.set_file("/f", "data").set_down(true);
+        let url = server.url("/f");
+
+        let err = get(&url).unwrap_err().to_string();
+        assert!(err.contains("simulated outage"), "{}", err);
+        assert!(read_range(&url, 0, 1).is_err());
+        assert_eq!(server.requests().len(), 2);
+
+        server.set_down(false);
+        assert_eq!(get(&url).unwrap(), b"data");
+
+        // Once the server is gone, so are its files.
+        drop(server);
+        assert!(get(&url).is_err());
+    }
+
+    #[test]
+    fn latency() {
+        let mut server = MemoryServer::new();
+        server
+            .set_file("/f", "data")
+            .set_latency(Duration::from_millis(200));
+        let url = server.url("/f");
+
+        let start = Instant::now();
+        assert_eq!(get(&url).unwrap(), b"data");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // Requests are answered concurrently.
+        let start = Instant::now();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let url = url.clone();
+                thread::spawn(move || get(&url).unwrap())
+            })
+            .collect();
+
+        for t in threads {
+            assert_eq!(t.join().unwrap(), b"data");
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(800), "{:?}", elapsed);
+    }
+}
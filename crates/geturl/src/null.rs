@@ -12,7 +12,7 @@ use std::{
 use tectonic_errors::Result;
 use tectonic_status_base::StatusBackend;
 
-use crate::{GetUrlBackend, RangeReader};
+use crate::{GetUrlBackend, GetUrlConfig, RangeReader};
 
 /// The error type for the always-failing geturl backend.
 #[derive(Debug)]
@@ -34,6 +34,10 @@ impl GetUrlBackend for NullBackend {
     type Response = Empty;
     type RangeReader = NullRangeReader;
 
+    fn new_with_config(_config: GetUrlConfig) -> Self {
+        NullBackend {}
+    }
+
     fn get_url(&mut self, _url: &str, _status: &mut dyn StatusBackend) -> Result<Empty> {
         Err((NoGetUrlBackendError {}).into())
     }
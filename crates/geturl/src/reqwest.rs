@@ -4,28 +4,52 @@
 //! A URL-get backend based on the `reqwest` crate.
 
 use reqwest::{
-    blocking::{Client, Response},
-    header::{HeaderMap, RANGE},
+    blocking::{Client, ClientBuilder, Response},
+    header::{HeaderMap, HeaderName, HeaderValue, LOCATION, RANGE},
     redirect::Policy,
-    StatusCode, Url,
+    Certificate, Method, Proxy, StatusCode, Url,
+};
+use std::fs;
+use tectonic_errors::{
+    anyhow::{bail, Context},
+    Result,
 };
-use tectonic_errors::{anyhow::bail, Result};
 use tectonic_status_base::{tt_note, StatusBackend};
 
-use crate::{GetUrlBackend, RangeReader};
+use crate::{config::default_config, GetUrlBackend, GetUrlConfig, RangeReader};
 
 const MAX_HTTP_REDIRECTS_ALLOWED: usize = 10;
 
 /// URL-get backend implemented using the `reqwest` crate.
-#[derive(Debug, Default)]
-pub struct ReqwestBackend {}
+#[derive(Debug)]
+pub struct ReqwestBackend {
+    config: GetUrlConfig,
+}
+
+impl Default for ReqwestBackend {
+    fn default() -> Self {
+        Self::new_with_config(default_config())
+    }
+}
 
 impl GetUrlBackend for ReqwestBackend {
     type Response = Response;
     type RangeReader = ReqwestRangeReader;
 
+    fn new_with_config(config: GetUrlConfig) -> Self {
+        ReqwestBackend { config }
+    }
+
     fn get_url(&mut self, url: &str, _status: &mut dyn StatusBackend) -> Result<Response> {
-        let res = Client::new().get(url).send()?;
+        let client = client_builder(&self.config)?.build()?;
+        let res = send(
+            &client,
+            &self.config,
+            Method::GET,
+            url,
+            HeaderMap::new(),
+            |_| true,
+        )?;
         if !res.status().is_success() {
             bail!(
                 "unexpected HTTP response code {} for URL {}",
@@ -50,39 +74,31 @@ impl GetUrlBackend for ReqwestBackend {
         // If it's redirected, we update our URL to follow the redirects. If
         // we didn't do this separately, the index file would have to be the
         // one with the redirect setup, which would be confusing and annoying.
-        let redirect_policy = Policy::custom(move |attempt| {
-            // In the process of resolving the file URL it might be necessary to
-            // stop at a certain level of redirection. This might be required
-            // because some hosts might redirect to a version of the URL where
-            // it isn't possible to select the index file by appending
-            // `.index.gz`. (This mostly happens because CDNs redirect to a file
-            // hash.) However, the original default Tectonic URL doesn't contain
-            // a `.` in the filename, and an October 2021 change to archive.org
-            // added a redirect through a secondary URL with a similar path
-            // structure. So we also keep resolving if the new filename seems to
-            // match the original filename.
-            if attempt.previous().len() >= MAX_HTTP_REDIRECTS_ALLOWED {
-                attempt.error("too many redirections")
-            } else if let Some(segments) = attempt.url().clone().path_segments() {
-                let follow = segments
-                    .last()
+        //
+        // In the process of resolving the file URL it might be necessary to
+        // stop at a certain level of redirection. This might be required
+        // because some hosts might redirect to a version of the URL where it
+        // isn't possible to select the index file by appending `.index.gz`.
+        // (This mostly happens because CDNs redirect to a file hash.)
+        // However, the original default Tectonic URL doesn't contain a `.` in
+        // the filename, and an October 2021 change to archive.org added a
+        // redirect through a secondary URL with a similar path structure. So
+        // we also keep resolving if the new filename seems to match the
+        // original filename.
+        let client = client_builder(&self.config)?.build()?;
+        let res = send(
+            &client,
+            &self.config,
+            Method::HEAD,
+            url,
+            HeaderMap::new(),
+            |next| {
+                next.path_segments()
+                    .and_then(|mut s| s.next_back())
                     .map(|file| file.contains('.') || file == original_filename)
-                    .unwrap_or(true);
-                if follow {
-                    attempt.follow()
-                } else {
-                    attempt.stop()
-                }
-            } else {
-                attempt.follow()
-            }
-        });
-
-        let res = Client::builder()
-            .redirect(redirect_policy)
-            .build()?
-            .head(url)
-            .send()?;
+                    .unwrap_or(true)
+            },
+        )?;
 
         if !(res.status().is_success()
             || res.status() == StatusCode::FOUND
@@ -104,7 +120,111 @@ impl GetUrlBackend for ReqwestBackend {
     }
 
     fn open_range_reader(&self, url: &str) -> Self::RangeReader {
-        ReqwestRangeReader::new(url)
+        ReqwestRangeReader::new(url, self.config.clone())
+    }
+}
+
+/// Start building a client with the settings from a [`GetUrlConfig`].
+///
+/// The client doesn't follow redirects itself; see [`send`].
+fn client_builder(config: &GetUrlConfig) -> Result<ClientBuilder> {
+    let mut builder = Client::builder().redirect(Policy::none());
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder
+            .proxy(Proxy::all(proxy).with_context(|| format!("invalid proxy URL `{proxy}`"))?);
+    }
+
+    if let Some(ca_file) = &config.ca_file {
+        let pem = fs::read(ca_file)
+            .with_context(|| format!("failed to read CA certificates `{}`", ca_file.display()))?;
+
+        for cert in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("invalid CA certificates in `{}`", ca_file.display()))?
+        {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if let Some(t) = config.connect_timeout {
+        builder = builder.connect_timeout(t);
+    }
+
+    if let Some(t) = config.read_timeout {
+        builder = builder.timeout(t);
+    }
+
+    Ok(builder)
+}
+
+/// Get the extra headers that should be sent with a request for *url*.
+fn headers_for(config: &GetUrlConfig, url: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in config.headers_for(url) {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid HTTP header name `{name}`"))?,
+            HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value for HTTP header `{name}`"))?,
+        );
+    }
+
+    Ok(headers)
+}
+
+/// Send a request, following redirects as long as *follow* approves of the
+/// next URL.
+///
+/// We follow redirects ourselves, rather than letting reqwest do it, so that
+/// each hop only gets the extra headers configured for its own host. Otherwise
+/// a token meant for one server would be handed to whatever server it
+/// redirects us to. The *extra* headers are sent with every hop.
+fn send(
+    client: &Client,
+    config: &GetUrlConfig,
+    method: Method,
+    url: &str,
+    extra: HeaderMap,
+    mut follow: impl FnMut(&Url) -> bool,
+) -> Result<Response> {
+    let mut current = Url::parse(url)?;
+    let mut n_redirects = 0;
+
+    loop {
+        let mut headers = headers_for(config, current.as_str())?;
+        headers.extend(extra.clone());
+
+        let res = client
+            .request(method.clone(), current.clone())
+            .headers(headers)
+            .send()?;
+
+        if !res.status().is_redirection() {
+            return Ok(res);
+        }
+
+        let next = match res
+            .headers()
+            .get(LOCATION)
+            .and_then(|loc| loc.to_str().ok())
+            .and_then(|loc| current.join(loc).ok())
+        {
+            Some(u) => u,
+            None => return Ok(res),
+        };
+
+        if !follow(&next) {
+            return Ok(res);
+        }
+
+        n_redirects += 1;
+
+        if n_redirects > MAX_HTTP_REDIRECTS_ALLOWED {
+            bail!("too many redirections for URL {}", url);
+        }
+
+        current = next;
     }
 }
 
@@ -114,14 +234,18 @@ impl GetUrlBackend for ReqwestBackend {
 #[derive(Debug)]
 pub struct ReqwestRangeReader {
     url: String,
-    client: Client,
+    config: GetUrlConfig,
+
+    /// The client, which is created when the first range is read.
+    client: Option<Client>,
 }
 
 impl ReqwestRangeReader {
-    fn new(url: &str) -> ReqwestRangeReader {
+    fn new(url: &str, config: GetUrlConfig) -> ReqwestRangeReader {
         ReqwestRangeReader {
             url: url.to_owned(),
-            client: Client::new(),
+            config,
+            client: None,
         }
     }
 }
@@ -133,10 +257,17 @@ impl RangeReader for ReqwestRangeReader {
         let end_inclusive = offset + length as u64 - 1;
         let header_val = format!("bytes={offset}-{end_inclusive}").parse()?;
 
-        let mut headers = HeaderMap::new();
-        headers.insert(RANGE, header_val);
+        let mut range = HeaderMap::new();
+        range.insert(RANGE, header_val);
 
-        let res = self.client.get(&self.url).headers(headers).send()?;
+        let client = match &self.client {
+            Some(c) => c,
+            None => self.client.insert(client_builder(&self.config)?.build()?),
+        };
+
+        let res = send(client, &self.config, Method::GET, &self.url, range, |_| {
+            true
+        })?;
 
         if res.status() != StatusCode::PARTIAL_CONTENT {
             bail!(
@@ -149,3 +280,83 @@ impl RangeReader for ReqwestRangeReader {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostHeader;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+    use tectonic_status_base::NoopStatusBackend;
+
+    /// Serve one request with *response*, returning the request's header
+    /// lines.
+    fn serve_one(listener: TcpListener, response: String) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = Vec::new();
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_owned();
+
+                if line.is_empty() {
+                    break;
+                }
+
+                lines.push(line.to_ascii_lowercase());
+            }
+
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            lines
+        })
+    }
+
+    #[test]
+    fn host_headers_stay_on_their_host() {
+        let target = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target_url = format!(
+            "http://127.0.0.1:{}/file",
+            target.local_addr().unwrap().port()
+        );
+        let origin = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_port = origin.local_addr().unwrap().port();
+
+        let origin = serve_one(
+            origin,
+            format!("HTTP/1.1 302 Found\r\nLocation: {target_url}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+        );
+        let target = serve_one(
+            target,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_owned(),
+        );
+
+        let mut backend = ReqwestBackend::new_with_config(GetUrlConfig {
+            headers: vec![HostHeader {
+                host: "localhost".to_owned(),
+                name: "Private-Token".to_owned(),
+                value: "secret".to_owned(),
+            }],
+            ..Default::default()
+        });
+
+        let res = backend
+            .get_url(
+                &format!("http://localhost:{origin_port}/file"),
+                &mut NoopStatusBackend::default(),
+            )
+            .unwrap();
+        assert_eq!(res.text().unwrap(), "ok");
+
+        let origin = origin.join().unwrap();
+        assert!(origin.iter().any(|l| l == "private-token: secret"));
+
+        let target = target.join().unwrap();
+        assert!(!target.iter().any(|l| l.starts_with("private-token")));
+    }
+}
@@ -74,7 +74,7 @@ pub fn create() -> DigestComputer {
 
 /// A wrapper for a fixed-size byte array representing a digest computed with
 /// the default implementation.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct DigestData([u8; N_BYTES]);
 
 impl DigestData {
@@ -24,8 +24,10 @@
 
 #include <stdarg.h>
 #include <stdio.h>
+#include <string.h>
 
 #include "tectonic_bridge_core.h"
+#include "dpx-mem.h"
 
 typedef enum _message_type {
     DPX_MESG_INFO,
@@ -42,6 +44,78 @@ shut_up (int quietness)
 }
 
 
+/* The files being read, innermost last, so that warnings can say where they
+ * come from. Files nested more deeply than the stack allows are counted but
+ * not recorded. */
+#define DPX_INPUT_STACK_SIZE 16
+
+static struct {
+    char *name;
+    int line;
+} _dpx_input_stack[DPX_INPUT_STACK_SIZE];
+static int _dpx_input_depth = 0;
+
+void
+dpx_push_input (const char *name)
+{
+    if (_dpx_input_depth < DPX_INPUT_STACK_SIZE) {
+        _dpx_input_stack[_dpx_input_depth].name = NEW(strlen(name) + 1, char);
+        strcpy(_dpx_input_stack[_dpx_input_depth].name, name);
+        _dpx_input_stack[_dpx_input_depth].line = 0;
+    }
+
+    _dpx_input_depth++;
+}
+
+void
+dpx_set_input_line (int line)
+{
+    if (_dpx_input_depth > 0 && _dpx_input_depth <= DPX_INPUT_STACK_SIZE)
+        _dpx_input_stack[_dpx_input_depth - 1].line = line;
+}
+
+void
+dpx_pop_input (void)
+{
+    if (_dpx_input_depth == 0)
+        return;
+
+    _dpx_input_depth--;
+
+    if (_dpx_input_depth < DPX_INPUT_STACK_SIZE)
+        _dpx_input_stack[_dpx_input_depth].name = mfree(_dpx_input_stack[_dpx_input_depth].name);
+}
+
+
+void
+dpx_error_reset_global_state (void)
+{
+    while (_dpx_input_depth > 0)
+        dpx_pop_input();
+}
+
+
+static void
+_dpx_issue_warning (const char *message)
+{
+    ttbc_diagnostic_t *diag;
+    int level = _dpx_input_depth;
+
+    if (level > DPX_INPUT_STACK_SIZE)
+        level = DPX_INPUT_STACK_SIZE;
+
+    if (level == 0) {
+        ttstub_issue_warning("%s", message);
+        return;
+    }
+
+    diag = ttbc_diag_begin_warning();
+    ttbc_diag_append(diag, message);
+    ttbc_diag_set_location(diag, _dpx_input_stack[level - 1].name, _dpx_input_stack[level - 1].line);
+    ttstub_diag_finish(diag);
+}
+
+
 static rust_output_handle_t _dpx_message_handle = NULL;
 static char _dpx_message_buf[1024];
 
@@ -73,7 +147,7 @@ _dpx_print_to_stdout (const char *fmt, va_list argp, int warn)
     }
 
     if (warn)
-        ttstub_issue_warning("%s", _dpx_message_buf);
+        _dpx_issue_warning(_dpx_message_buf);
 
     ttstub_output_write(_dpx_ensure_output_handle(), _dpx_message_buf, n);
 }
@@ -35,4 +35,11 @@ void shut_up (int quietness);
 PRINTF_FUNC(1,2) void dpx_message (const char *fmt, ...);
 PRINTF_FUNC(1,2) void dpx_warning (const char *fmt, ...);
 
+/* Track the files being read, so that warnings can be attributed to them. A
+ * line number of 0 means that the position within the file is unknown. */
+void dpx_push_input (const char *name);
+void dpx_set_input_line (int line);
+void dpx_pop_input (void);
+void dpx_error_reset_global_state (void); /* Tectonic */
+
 #endif /* _ERROR_H_ */
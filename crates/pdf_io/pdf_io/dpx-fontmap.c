@@ -967,10 +967,13 @@ pdf_load_fontmap_file (const char *filename, int mode)
         return  -1;
     }
 
+    dpx_push_input(filename);
+
     while (!error && (p = tt_readline(work_buffer, WORK_BUFFER_SIZE, handle)) != NULL) {
         int m;
 
         lpos++;
+        dpx_set_input_line(lpos);
         llen  = strlen(work_buffer);
         endptr = p + llen;
 
@@ -1016,6 +1019,7 @@ pdf_load_fontmap_file (const char *filename, int mode)
     }
 
     ttstub_input_close(handle);
+    dpx_pop_input();
 
     if (dpx_conf.verbose_level)
         dpx_message(">");
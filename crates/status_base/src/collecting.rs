@@ -0,0 +1,420 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status-reporting backend that records messages, so that programs can
+//! inspect them.
+//!
+//! This is meant for programs that use Tectonic as a library, and for tests,
+//! which often want to know whether processing produced any warnings, without
+//! showing them to anyone.
+
+use std::fmt::{Arguments, Display, Formatter, Result as FmtResult};
+use tectonic_errors::Error;
+
+use super::{MessageKind, SourceLocation, StatusBackend};
+
+/// A message recorded by a [`CollectingStatusBackend`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    /// The kind of message.
+    pub kind: MessageKind,
+
+    /// The text of the message.
+    pub text: String,
+
+    /// The place in a source file that the message is about, if any.
+    pub location: Option<SourceLocation>,
+
+    /// The chain of errors that caused the message, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl Display for Message {
+    /// Format the message like the plain status backend does.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let prefix = match self.kind {
+            MessageKind::Note => "note:",
+            MessageKind::Warning => "warning:",
+            MessageKind::Error => "error:",
+        };
+
+        write!(f, "{prefix} ")?;
+
+        if let Some(loc) = &self.location {
+            write!(f, "{loc}: ")?;
+        }
+
+        write!(f, "{}", self.text)?;
+
+        for cause in &self.causes {
+            write!(f, "\ncaused by: {cause}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A status-reporting backend that records the messages sent to it.
+///
+/// All messages are recorded, regardless of chatter level. The backend can
+/// also forward everything to another backend, so that the messages are shown
+/// as usual, too.
+#[derive(Default)]
+pub struct CollectingStatusBackend {
+    messages: Vec<Message>,
+    error_logs: Vec<Vec<u8>>,
+    forward: Option<Box<dyn StatusBackend>>,
+}
+
+impl CollectingStatusBackend {
+    /// Create a new backend that records messages without forwarding them.
+    ///
+    /// You can also use [`Self::default`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forward all messages, as well as progress reports, to *backend*.
+    pub fn forward_to(&mut self, backend: Box<dyn StatusBackend>) -> &mut Self {
+        self.forward = Some(backend);
+        self
+    }
+
+    /// Get all of the messages recorded so far, in order.
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Get the recorded messages of a particular kind, in order.
+    pub fn messages_of_kind(&self, kind: MessageKind) -> impl Iterator<Item = &Message> {
+        self.messages.iter().filter(move |m| m.kind == kind)
+    }
+
+    /// Get the recorded notes, in order.
+    pub fn notes(&self) -> impl Iterator<Item = &Message> {
+        self.messages_of_kind(MessageKind::Note)
+    }
+
+    /// Get the recorded warnings, in order.
+    pub fn warnings(&self) -> impl Iterator<Item = &Message> {
+        self.messages_of_kind(MessageKind::Warning)
+    }
+
+    /// Get the recorded errors, in order.
+    pub fn errors(&self) -> impl Iterator<Item = &Message> {
+        self.messages_of_kind(MessageKind::Error)
+    }
+
+    /// Get the engine logs that were dumped after errors, in order.
+    pub fn error_logs(&self) -> &[Vec<u8>] {
+        &self.error_logs
+    }
+
+    /// Forget all of the messages and logs recorded so far.
+    pub fn clear(&mut self) -> &mut Self {
+        self.messages.clear();
+        self.error_logs.clear();
+        self
+    }
+}
+
+fn error_causes(err: Option<&Error>) -> Vec<String> {
+    err.into_iter()
+        .flat_map(|e| e.chain())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+impl StatusBackend for CollectingStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        self.messages.push(Message {
+            kind,
+            text: args.to_string(),
+            location: None,
+            causes: error_causes(err),
+        });
+
+        if let Some(b) = &mut self.forward {
+            b.report(kind, args, err);
+        }
+    }
+
+    fn report_at(
+        &mut self,
+        kind: MessageKind,
+        location: &SourceLocation,
+        args: Arguments,
+        err: Option<&Error>,
+    ) {
+        self.messages.push(Message {
+            kind,
+            text: args.to_string(),
+            location: Some(location.clone()),
+            causes: error_causes(err),
+        });
+
+        if let Some(b) = &mut self.forward {
+            b.report_at(kind, location, args, err);
+        }
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut chain = err.chain().map(|item| item.to_string());
+
+        self.messages.push(Message {
+            kind: MessageKind::Error,
+            text: chain.next().unwrap_or_default(),
+            location: None,
+            causes: chain.collect(),
+        });
+
+        if let Some(b) = &mut self.forward {
+            b.report_error(err);
+        }
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.messages.push(Message {
+            kind: MessageKind::Note,
+            text: format!("{before}{highlighted}{after}"),
+            location: None,
+            causes: Vec::new(),
+        });
+
+        if let Some(b) = &mut self.forward {
+            b.note_highlighted(before, highlighted, after);
+        }
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.error_logs.push(output.to_owned());
+
+        if let Some(b) = &mut self.forward {
+            b.dump_error_logs(output);
+        }
+    }
+
+    fn progress_start(&mut self, label: &str, total: Option<u64>) {
+        if let Some(b) = &mut self.forward {
+            b.progress_start(label, total);
+        }
+    }
+
+    fn progress_update(&mut self, done: u64) {
+        if let Some(b) = &mut self.forward {
+            b.progress_update(done);
+        }
+    }
+
+    fn progress_finish(&mut self) {
+        if let Some(b) = &mut self.forward {
+            b.progress_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+    use tectonic_errors::anyhow::anyhow;
+
+    use super::*;
+    use crate::{tt_error, tt_note, tt_warning};
+
+    /// A backend that records which of its methods were called.
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl StatusBackend for Recorder {
+        fn report(&mut self, kind: MessageKind, args: Arguments, _err: Option<&Error>) {
+            self.0.borrow_mut().push(format!("report {kind:?} {args}"));
+        }
+
+        fn report_at(
+            &mut self,
+            kind: MessageKind,
+            location: &SourceLocation,
+            args: Arguments,
+            _err: Option<&Error>,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(format!("report_at {kind:?} {location} {args}"));
+        }
+
+        fn report_error(&mut self, err: &Error) {
+            self.0.borrow_mut().push(format!("report_error {err}"));
+        }
+
+        fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+            self.0
+                .borrow_mut()
+                .push(format!("note_highlighted {before}|{highlighted}|{after}"));
+        }
+
+        fn dump_error_logs(&mut self, output: &[u8]) {
+            self.0.borrow_mut().push(format!(
+                "dump_error_logs {}",
+                String::from_utf8_lossy(output)
+            ));
+        }
+
+        fn progress_start(&mut self, label: &str, total: Option<u64>) {
+            self.0
+                .borrow_mut()
+                .push(format!("progress_start {label} {total:?}"));
+        }
+
+        fn progress_update(&mut self, done: u64) {
+            self.0.borrow_mut().push(format!("progress_update {done}"));
+        }
+
+        fn progress_finish(&mut self) {
+            self.0.borrow_mut().push("progress_finish".to_owned());
+        }
+    }
+
+    #[test]
+    fn records_messages() {
+        let mut status = CollectingStatusBackend::new();
+        tt_note!(status, "one");
+        tt_warning!(status, "two"; anyhow!("inner").context("outer"));
+        status.note_highlighted("a ", "b", " c");
+        status.report_error(&anyhow!("root").context("top"));
+
+        let texts: Vec<_> = status.messages().iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["one", "two", "a b c", "top"]);
+        assert_eq!(status.notes().count(), 2);
+        assert_eq!(status.warnings().count(), 1);
+        assert_eq!(status.messages()[1].causes, ["outer", "inner"]);
+        assert_eq!(status.messages()[3].kind, MessageKind::Error);
+        assert_eq!(status.messages()[3].causes, ["root"]);
+    }
+
+    #[test]
+    fn locations() {
+        let mut location = SourceLocation::new("chap1.tex", Some(7));
+        location.context = vec!["l.7 \\foo".to_owned()];
+
+        let mut status = CollectingStatusBackend::new();
+        status.report_at(
+            MessageKind::Error,
+            &location,
+            format_args!("Undefined control sequence"),
+            None,
+        );
+        status.report_at(
+            MessageKind::Warning,
+            &SourceLocation::new("chap2.tex", None),
+            format_args!("Overfull \\hbox"),
+            None,
+        );
+
+        let errors: Vec<_> = status.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location.as_ref(), Some(&location));
+
+        let warning = status.warnings().next().unwrap();
+        let warning_location = warning.location.as_ref().unwrap();
+        assert_eq!(warning_location.file, "chap2.tex");
+        assert_eq!(warning_location.line, None);
+        assert!(warning_location.context.is_empty());
+
+        tt_note!(status, "no location");
+        assert_eq!(status.notes().next().unwrap().location, None);
+    }
+
+    #[test]
+    fn display() {
+        let mut status = CollectingStatusBackend::new();
+        tt_note!(status, "plain");
+        tt_error!(status, "failed"; anyhow!("no space").context("couldn't write"));
+        status.report_at(
+            MessageKind::Warning,
+            &SourceLocation::new("main.tex", Some(3)),
+            format_args!("Overfull \\hbox"),
+            None,
+        );
+        status.report_at(
+            MessageKind::Note,
+            &SourceLocation::new("main.aux", None),
+            format_args!("rerun"),
+            None,
+        );
+
+        let shown: Vec<_> = status.messages().iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            shown,
+            [
+                "note: plain",
+                "error: failed\ncaused by: couldn't write\ncaused by: no space",
+                "warning: main.tex:3: Overfull \\hbox",
+                "note: main.aux: rerun",
+            ]
+        );
+    }
+
+    #[test]
+    fn error_logs_and_clear() {
+        let mut status = CollectingStatusBackend::new();
+        tt_warning!(status, "w");
+        status.dump_error_logs(b"first log");
+        status.dump_error_logs(b"second\xfflog");
+
+        assert_eq!(
+            status.error_logs(),
+            [b"first log".to_vec(), b"second\xfflog".to_vec()]
+        );
+
+        status.clear();
+        assert!(status.messages().is_empty());
+        assert!(status.error_logs().is_empty());
+
+        tt_note!(status, "after");
+        assert_eq!(status.messages().len(), 1);
+        assert!(status.error_logs().is_empty());
+    }
+
+    #[test]
+    fn forwarding() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut status = CollectingStatusBackend::new();
+        status.forward_to(Box::new(Recorder(calls.clone())));
+
+        tt_note!(status, "hello");
+        status.report_at(
+            MessageKind::Warning,
+            &SourceLocation::new("a.tex", Some(2)),
+            format_args!("careful"),
+            None,
+        );
+        status.report_error(&anyhow!("bad"));
+        status.note_highlighted("x", "y", "z");
+        status.dump_error_logs(b"log");
+        status.progress_start("downloading", Some(10));
+        status.progress_update(5);
+        status.progress_finish();
+
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "report Note hello",
+                "report_at Warning a.tex:2 careful",
+                "report_error bad",
+                "note_highlighted x|y|z",
+                "dump_error_logs log",
+                "progress_start downloading Some(10)",
+                "progress_update 5",
+                "progress_finish",
+            ]
+        );
+
+        // Forwarding doesn't stop the messages from being recorded, and
+        // clearing the record doesn't affect the forwarding.
+        assert_eq!(status.messages().len(), 4);
+        assert_eq!(status.error_logs().len(), 1);
+        status.clear();
+        tt_note!(status, "again");
+        assert_eq!(calls.borrow().len(), 9);
+        assert_eq!(status.messages().len(), 1);
+    }
+}
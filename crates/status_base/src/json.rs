@@ -0,0 +1,377 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status-reporting backend that prints messages as JSON, for other programs
+//! to parse.
+//!
+//! Each message is printed as a JSON object on a line of its own. Every object
+//! has these fields:
+//!
+//! - `timestamp`: the time of the message, in seconds since the Unix epoch.
+//! - `kind`: `"note"`, `"warning"` or `"error"` for ordinary messages, or
+//!   `"log"` for the logs printed by [`StatusBackend::dump_error_logs`].
+//! - `message`: the text of the message, or of the log.
+//! - `causes`: the chain of errors that caused the message, outermost first,
+//!   which may be empty.
+//!
+//! Messages from [`StatusBackend::note_highlighted`] also have a `highlighted`
+//! field giving the highlighted part of the message, and messages from
+//! [`StatusBackend::report_at`] have a `file` field, along with a `line` field
+//! if the line number is known, a `path` field if the file's place on the
+//! filesystem is known, and a `context` field if TeX showed the context of an
+//! error.
+
+use std::{
+    fmt::{Arguments, Write as FmtWrite},
+    io::{self, Stderr, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tectonic_errors::Error;
+
+use super::{ChatterLevel, MessageKind, SourceLocation, StatusBackend};
+
+/// A status-reporting backend that prints messages as lines of JSON.
+///
+/// By default, messages are printed to the standard error stream, so that they
+/// aren't mixed up with any other output.
+#[derive(Debug)]
+pub struct JsonStatusBackend<W: Write = Stderr> {
+    chatter: ChatterLevel,
+    writer: W,
+}
+
+impl JsonStatusBackend {
+    /// Create a new backend with the specified chatter level, printing to the
+    /// standard error stream.
+    pub fn new(chatter: ChatterLevel) -> Self {
+        Self::new_with_writer(chatter, io::stderr())
+    }
+}
+
+impl<W: Write> JsonStatusBackend<W> {
+    /// Create a new backend with the specified chatter level, printing to
+    /// *writer*.
+    pub fn new_with_writer(chatter: ChatterLevel, writer: W) -> Self {
+        JsonStatusBackend { chatter, writer }
+    }
+
+    /// Get back the writer that messages were printed to.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn emit<I>(
+        &mut self,
+        kind: &str,
+        message: &str,
+        causes: I,
+        highlighted: Option<&str>,
+        location: Option<&SourceLocation>,
+    ) where
+        I: IntoIterator<Item = String>,
+    {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let mut line = format!("{{\"timestamp\":{timestamp:.3},\"kind\":");
+        push_json_string(&mut line, kind);
+        line.push_str(",\"message\":");
+        push_json_string(&mut line, message);
+        line.push_str(",\"causes\":[");
+
+        for (i, cause) in causes.into_iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+
+            push_json_string(&mut line, &cause);
+        }
+
+        line.push(']');
+
+        if let Some(h) = highlighted {
+            line.push_str(",\"highlighted\":");
+            push_json_string(&mut line, h);
+        }
+
+        if let Some(loc) = location {
+            line.push_str(",\"file\":");
+            push_json_string(&mut line, &loc.file);
+
+            if let Some(n) = loc.line {
+                let _ = write!(line, ",\"line\":{n}");
+            }
+
+            if let Some(p) = &loc.path {
+                line.push_str(",\"path\":");
+                push_json_string(&mut line, &p.to_string_lossy());
+            }
+
+            if !loc.context.is_empty() {
+                line.push_str(",\"context\":[");
+
+                for (i, text) in loc.context.iter().enumerate() {
+                    if i > 0 {
+                        line.push(',');
+                    }
+
+                    push_json_string(&mut line, text);
+                }
+
+                line.push(']');
+            }
+        }
+
+        line.push_str("}\n");
+
+        // As with the other backends, there's nothing sensible to do if the
+        // messages can't be printed.
+        self.writer
+            .write_all(line.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .expect("failed to write status message");
+    }
+}
+
+fn kind_name(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Note => "note",
+        MessageKind::Warning => "warning",
+        MessageKind::Error => "error",
+    }
+}
+
+/// Append *text* to *buf* as a JSON string literal.
+fn push_json_string(buf: &mut String, text: &str) {
+    buf.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+
+    buf.push('"');
+}
+
+impl<W: Write> StatusBackend for JsonStatusBackend<W> {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        if self.chatter.suppress_message(kind) {
+            return;
+        }
+
+        let causes = err
+            .into_iter()
+            .flat_map(|e| e.chain())
+            .map(|item| item.to_string());
+
+        self.emit(kind_name(kind), &args.to_string(), causes, None, None);
+    }
+
+    fn report_at(
+        &mut self,
+        kind: MessageKind,
+        location: &SourceLocation,
+        args: Arguments,
+        err: Option<&Error>,
+    ) {
+        if self.chatter.suppress_message(kind) {
+            return;
+        }
+
+        let causes = err
+            .into_iter()
+            .flat_map(|e| e.chain())
+            .map(|item| item.to_string());
+
+        self.emit(
+            kind_name(kind),
+            &args.to_string(),
+            causes,
+            None,
+            Some(location),
+        );
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut chain = err.chain().map(|item| item.to_string());
+        let message = chain.next().unwrap_or_default();
+        self.emit(kind_name(MessageKind::Error), &message, chain, None, None);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        if self.chatter.suppress_message(MessageKind::Note) {
+            return;
+        }
+
+        let message = format!("{before}{highlighted}{after}");
+        self.emit(
+            kind_name(MessageKind::Note),
+            &message,
+            None,
+            Some(highlighted),
+            None,
+        );
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.emit("log", &String::from_utf8_lossy(output), None, None, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tectonic_errors::anyhow::anyhow;
+
+    use super::*;
+    use crate::{tt_error, tt_note, tt_warning};
+
+    /// Run *f* against a backend and parse each line that it prints.
+    fn messages<F: FnOnce(&mut JsonStatusBackend<Vec<u8>>)>(
+        chatter: ChatterLevel,
+        f: F,
+    ) -> Vec<Value> {
+        let mut backend = JsonStatusBackend::new_with_writer(chatter, Vec::new());
+        f(&mut backend);
+        let output = String::from_utf8(backend.into_inner()).unwrap();
+        assert!(output.is_empty() || output.ends_with('\n'));
+
+        output
+            .lines()
+            .map(|line| {
+                let v: Value = serde_json::from_str(line)
+                    .unwrap_or_else(|e| panic!("invalid JSON `{}`: {}", line, e));
+                assert!(v["timestamp"].as_f64().unwrap() > 0.);
+                v
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "say \"hi\" \\ back\nthen\ttab\r\u{1}\u{1f} and \u{7f}";
+        let unicode = "naïve café — 日本語 🦀";
+
+        let msgs = messages(ChatterLevel::Normal, |b| {
+            tt_note!(b, "{}", text);
+            tt_warning!(b, "{}", unicode);
+        });
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0]["kind"], "note");
+        assert_eq!(msgs[0]["message"], text);
+        assert_eq!(msgs[0]["causes"], serde_json::json!([]));
+        assert_eq!(msgs[1]["kind"], "warning");
+        assert_eq!(msgs[1]["message"], unicode);
+    }
+
+    #[test]
+    fn error_chains() {
+        let err = anyhow!("disk on \"fire\"")
+            .context("couldn't write `a\\b`")
+            .context("build failed");
+
+        let msgs = messages(ChatterLevel::Normal, |b| {
+            tt_error!(b, "the build stopped"; err);
+            b.report_error(&err);
+        });
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0]["kind"], "error");
+        assert_eq!(msgs[0]["message"], "the build stopped");
+        assert_eq!(
+            msgs[0]["causes"],
+            serde_json::json!(["build failed", "couldn't write `a\\b`", "disk on \"fire\""])
+        );
+        assert_eq!(msgs[1]["kind"], "error");
+        assert_eq!(msgs[1]["message"], "build failed");
+        assert_eq!(
+            msgs[1]["causes"],
+            serde_json::json!(["couldn't write `a\\b`", "disk on \"fire\""])
+        );
+    }
+
+    #[test]
+    fn locations() {
+        let bare = SourceLocation::new("chapters/\"one\".tex", None);
+        let mut full = SourceLocation::new("main.tex", Some(12));
+        full.context = vec![
+            "l.12 Hello \\foo".to_owned(),
+            "               bar".to_owned(),
+        ];
+        full.path = Some("/home/me/doc/src/main.tex".into());
+
+        let msgs = messages(ChatterLevel::Normal, |b| {
+            b.report_at(MessageKind::Warning, &bare, format_args!("overfull"), None);
+            b.report_at(
+                MessageKind::Error,
+                &full,
+                format_args!("Undefined control sequence"),
+                None,
+            );
+        });
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0]["kind"], "warning");
+        assert_eq!(msgs[0]["file"], "chapters/\"one\".tex");
+        assert!(msgs[0].get("line").is_none());
+        assert!(msgs[0].get("path").is_none());
+        assert!(msgs[0].get("context").is_none());
+
+        assert_eq!(msgs[1]["kind"], "error");
+        assert_eq!(msgs[1]["message"], "Undefined control sequence");
+        assert_eq!(msgs[1]["file"], "main.tex");
+        assert_eq!(msgs[1]["line"], 12);
+        assert_eq!(msgs[1]["path"], "/home/me/doc/src/main.tex");
+        assert_eq!(
+            msgs[1]["context"],
+            serde_json::json!(["l.12 Hello \\foo", "               bar"])
+        );
+        assert!(msgs[1].get("highlighted").is_none());
+    }
+
+    #[test]
+    fn highlighted_and_logs() {
+        let msgs = messages(ChatterLevel::Normal, |b| {
+            b.note_highlighted("Running ", "`xdvipdfmx`", " ...");
+            b.dump_error_logs(b"! Missing $ inserted.\n\xff\n");
+        });
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0]["kind"], "note");
+        assert_eq!(msgs[0]["message"], "Running `xdvipdfmx` ...");
+        assert_eq!(msgs[0]["highlighted"], "`xdvipdfmx`");
+        assert_eq!(msgs[1]["kind"], "log");
+        assert_eq!(msgs[1]["message"], "! Missing $ inserted.\n\u{fffd}\n");
+        assert!(msgs[1].get("highlighted").is_none());
+    }
+
+    #[test]
+    fn minimal_chatter() {
+        let msgs = messages(ChatterLevel::Minimal, |b| {
+            tt_note!(b, "quiet");
+            b.note_highlighted("a", "b", "c");
+            b.report_at(
+                MessageKind::Note,
+                &SourceLocation::new("x.tex", Some(1)),
+                format_args!("quiet too"),
+                None,
+            );
+            tt_warning!(b, "loud");
+        });
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["message"], "loud");
+    }
+}
@@ -9,10 +9,19 @@
 //! frameworks, but we do have some extra hooks to help support a nice user
 //! experience for the Tectonic CLI tool.
 
-use std::{cmp::Ordering, fmt::Arguments, result::Result as StdResult, str::FromStr};
+use std::{
+    cmp::Ordering,
+    fmt::{Arguments, Display, Formatter, Result as FmtResult},
+    path::PathBuf,
+    result::Result as StdResult,
+    str::FromStr,
+};
 use tectonic_errors::Error;
 
+pub mod collecting;
+pub mod json;
 pub mod plain;
+pub mod progress;
 
 /// A kind of status message.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -86,6 +95,54 @@ impl Ord for ChatterLevel {
     }
 }
 
+/// A place in a source file that a message is about.
+///
+/// The TeX engines attach these to the warnings and errors that they report,
+/// based on the file that they were reading at the time.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    /// The name of the file, as the engine knows it.
+    pub file: String,
+
+    /// The line number within the file, starting at 1, if known.
+    pub line: Option<u32>,
+
+    /// The lines of context that TeX prints with its errors, showing how far
+    /// it had read into its inputs. This is often empty.
+    ///
+    /// The last two lines usually show the current line of the file, split at
+    /// the point that TeX had reached, as in `l.12 Hello \foo` followed by
+    /// `               bar`.
+    pub context: Vec<String>,
+
+    /// Where the file is on the filesystem, if it was read from there. The
+    /// engine's name for the file is relative to wherever the engine found
+    /// it, which needn't be the current directory.
+    pub path: Option<PathBuf>,
+}
+
+impl SourceLocation {
+    /// Create a new location in *file*, optionally at a particular line.
+    pub fn new<S: Into<String>>(file: S, line: Option<u32>) -> Self {
+        SourceLocation {
+            file: file.into(),
+            line,
+            context: Vec::new(),
+            path: None,
+        }
+    }
+}
+
+impl Display for SourceLocation {
+    /// Format the location in the usual `file:line` style.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file, line),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
 /// A trait for accepting status messages.
 pub trait StatusBackend {
     /// Report a message to the status backend.
@@ -95,6 +152,22 @@ pub trait StatusBackend {
     /// appropriate fashion.
     fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>);
 
+    /// Report a message about a particular place in a source file.
+    ///
+    /// This is used for the warnings and errors that the engines find in the
+    /// documents that they process. The default implementation delegates to
+    /// `report()`, starting the message with the location, as in
+    /// `file.tex:12: message`.
+    fn report_at(
+        &mut self,
+        kind: MessageKind,
+        location: &SourceLocation,
+        args: Arguments,
+        err: Option<&Error>,
+    ) {
+        self.report(kind, format_args!("{location}: {args}"), err)
+    }
+
     /// Report an error to the status backend.
     ///
     /// Unlike the basic `report` function, in this case there is no additional
@@ -125,6 +198,24 @@ pub trait StatusBackend {
     /// should print the provided output, which may span many lines, with some
     /// clear delineation.
     fn dump_error_logs(&mut self, output: &[u8]);
+
+    /// Report that a task with measurable progress, such as a download, is
+    /// starting.
+    ///
+    /// The *label* describes the task, and *total* is the number of bytes that
+    /// it will process, if known. Only one task is in progress at a time:
+    /// starting a new one replaces the current one. Progress reports are
+    /// optional, and the default implementation ignores them.
+    fn progress_start(&mut self, _label: &str, _total: Option<u64>) {}
+
+    /// Report how many bytes of the current task have been processed.
+    ///
+    /// The count may go down, if part of the task is retried. Updates are
+    /// ignored if no task is in progress.
+    fn progress_update(&mut self, _done: u64) {}
+
+    /// Report that the current task has finished, successfully or not.
+    fn progress_finish(&mut self) {}
 }
 
 /// Report a formatted informational message to the user.
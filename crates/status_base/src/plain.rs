@@ -5,17 +5,36 @@
 
 use std::{
     fmt::Arguments,
-    io::{self, Write},
+    io::{self, Stderr, Write},
+    time::{Duration, Instant},
 };
 use tectonic_errors::Error;
 
-use super::{ChatterLevel, MessageKind, StatusBackend};
+use super::{progress::Progress, ChatterLevel, MessageKind, StatusBackend};
+
+/// How often a line is printed about a task in progress. Tasks that finish
+/// sooner than this aren't mentioned at all.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
 
 /// A basic status-reporting backend that prints messages via stdio.
-#[derive(Clone, Debug, Default)]
-pub struct PlainStatusBackend {
+///
+/// The progress of long-running tasks is reported with a line every few
+/// seconds.
+///
+/// By default, notes are printed to the standard output stream and other
+/// messages to the standard error stream. A backend created with
+/// [`Self::new_with_writer`] prints everything to its writer instead.
+#[derive(Debug)]
+pub struct PlainStatusBackend<W: Write = Stderr> {
     chatter: ChatterLevel,
     always_stderr: bool,
+    progress: Option<Progress>,
+
+    /// When a line about the current task was last printed, if ever.
+    progress_printed: Option<Instant>,
+
+    /// Where to print messages, if not to the standard streams.
+    writer: Option<W>,
 }
 
 impl PlainStatusBackend {
@@ -26,9 +45,49 @@ impl PlainStatusBackend {
         PlainStatusBackend {
             chatter,
             always_stderr: false,
+            progress: None,
+            progress_printed: None,
+            writer: None,
+        }
+    }
+}
+
+impl Default for PlainStatusBackend {
+    fn default() -> Self {
+        Self::new(ChatterLevel::default())
+    }
+}
+
+impl Clone for PlainStatusBackend {
+    fn clone(&self) -> Self {
+        PlainStatusBackend {
+            chatter: self.chatter,
+            always_stderr: self.always_stderr,
+            progress: self.progress.clone(),
+            progress_printed: self.progress_printed,
+            writer: self.writer.as_ref().map(|_| io::stderr()),
+        }
+    }
+}
+
+impl<W: Write> PlainStatusBackend<W> {
+    /// Create a new backend with the specified chatter level, printing all
+    /// messages to *writer*.
+    pub fn new_with_writer(chatter: ChatterLevel, writer: W) -> Self {
+        PlainStatusBackend {
+            chatter,
+            always_stderr: false,
+            progress: None,
+            progress_printed: None,
+            writer: Some(writer),
         }
     }
 
+    /// Get back the writer that messages were printed to, if there is one.
+    pub fn into_inner(self) -> Option<W> {
+        self.writer
+    }
+
     /// Configure this backend to always print to the standard error stream.
     ///
     /// This setting can be useful if you have a program that is printing output
@@ -39,9 +98,32 @@ impl PlainStatusBackend {
         self.always_stderr = setting;
         self
     }
+
+    fn print_progress(&mut self) {
+        let text = match &self.progress {
+            Some(p) => match p.fraction() {
+                Some(f) => format!("{}: {} ({:.0}%)", p.label(), p.describe_amount(), f * 100.),
+                None => format!("{}: {}", p.label(), p.describe_amount()),
+            },
+            None => return,
+        };
+
+        self.report(MessageKind::Note, format_args!("{text}"), None);
+        self.progress_printed = Some(Instant::now());
+    }
+
+    /// Print a line, to standard output if *to_stdout* is true and we're
+    /// using the standard streams.
+    fn print_line(&mut self, to_stdout: bool, args: Arguments) {
+        match &mut self.writer {
+            Some(w) => writeln!(w, "{args}").expect("write to status stream failed"),
+            None if to_stdout => println!("{args}"),
+            None => eprintln!("{args}"),
+        }
+    }
 }
 
-impl StatusBackend for PlainStatusBackend {
+impl<W: Write> StatusBackend for PlainStatusBackend<W> {
     fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
         if self.chatter.suppress_message(kind) {
             return;
@@ -53,15 +135,12 @@ impl StatusBackend for PlainStatusBackend {
             MessageKind::Error => "error:",
         };
 
-        if kind == MessageKind::Note && !self.always_stderr {
-            println!("{prefix} {args}");
-        } else {
-            eprintln!("{prefix} {args}");
-        }
+        let to_stdout = kind == MessageKind::Note && !self.always_stderr;
+        self.print_line(to_stdout, format_args!("{prefix} {args}"));
 
         if let Some(e) = err {
             for item in e.chain() {
-                eprintln!("caused by: {item}");
+                self.print_line(false, format_args!("caused by: {item}"));
             }
         }
     }
@@ -70,7 +149,7 @@ impl StatusBackend for PlainStatusBackend {
         let mut prefix = "error";
 
         for item in err.chain() {
-            eprintln!("{prefix}: {item}");
+            self.print_line(false, format_args!("{prefix}: {item}"));
             prefix = "caused by";
         }
     }
@@ -84,16 +163,116 @@ impl StatusBackend for PlainStatusBackend {
     }
 
     fn dump_error_logs(&mut self, output: &[u8]) {
-        eprintln!(
-            "==============================================================================="
+        const RULE: &str =
+            "===============================================================================";
+
+        self.print_line(false, format_args!("{RULE}"));
+
+        match &mut self.writer {
+            Some(w) => w.write_all(output).expect("write to status stream failed"),
+            None => io::stderr()
+                .write_all(output)
+                .expect("write to stderr failed"),
+        }
+
+        self.print_line(false, format_args!("{RULE}"));
+    }
+
+    fn progress_start(&mut self, label: &str, total: Option<u64>) {
+        self.progress = Some(Progress::new(label, total));
+        self.progress_printed = None;
+    }
+
+    fn progress_update(&mut self, done: u64) {
+        let progress = match &mut self.progress {
+            Some(p) => p,
+            None => return,
+        };
+
+        progress.set_done(done);
+
+        let since = match self.progress_printed {
+            Some(t) => t.elapsed(),
+            None => progress.elapsed(),
+        };
+
+        if since >= PROGRESS_INTERVAL {
+            self.print_progress();
+        }
+    }
+
+    fn progress_finish(&mut self) {
+        // Only give a final report if we've been reporting all along.
+        if self.progress_printed.is_some() {
+            self.print_progress();
+        }
+
+        self.progress = None;
+        self.progress_printed = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(backend: &mut PlainStatusBackend<Vec<u8>>) -> String {
+        String::from_utf8(std::mem::take(backend.writer.as_mut().unwrap())).unwrap()
+    }
+
+    #[test]
+    fn messages() {
+        let mut backend = PlainStatusBackend::new_with_writer(ChatterLevel::Normal, Vec::new());
+        let err = tectonic_errors::anyhow::anyhow!("inner");
+        backend.report(MessageKind::Warning, format_args!("careful"), Some(&err));
+        backend.note_highlighted("see ", "this", "!");
+        backend.dump_error_logs(b"log\n");
+
+        let text = output(&mut backend);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[..3],
+            ["warning: careful", "caused by: inner", "note: see this!"]
+        );
+        assert_eq!(lines[4], "log");
+
+        let mut backend = PlainStatusBackend::new_with_writer(ChatterLevel::Minimal, Vec::new());
+        backend.report(MessageKind::Note, format_args!("quiet"), None);
+        backend.report(MessageKind::Error, format_args!("loud"), None);
+        assert_eq!(backend.into_inner().unwrap(), b"error: loud\n");
+    }
+
+    #[test]
+    fn progress_lines_are_throttled() {
+        let mut backend = PlainStatusBackend::new_with_writer(ChatterLevel::Normal, Vec::new());
+
+        // Tasks that finish quickly aren't mentioned.
+        backend.progress_start("Quick", Some(10));
+        backend.progress_update(10);
+        backend.progress_finish();
+        assert_eq!(output(&mut backend), "");
+
+        backend.progress_start("Downloading", Some(2048));
+        backend.progress_update(512);
+        assert_eq!(output(&mut backend), "");
+
+        // Pretend that the last line was printed long enough ago.
+        backend.progress_printed = Some(Instant::now() - PROGRESS_INTERVAL);
+        backend.progress_update(1024);
+        assert_eq!(
+            output(&mut backend),
+            "note: Downloading: 1.0 KiB of 2.0 KiB (50%)\n"
         );
 
-        io::stderr()
-            .write_all(output)
-            .expect("write to stderr failed");
+        backend.progress_update(1536);
+        assert_eq!(output(&mut backend), "");
 
-        eprintln!(
-            "==============================================================================="
+        // Having reported the task, we report its end, too.
+        backend.progress_update(2048);
+        backend.progress_finish();
+        assert_eq!(
+            output(&mut backend),
+            "note: Downloading: 2.0 KiB of 2.0 KiB (100%)\n"
         );
     }
 }
@@ -0,0 +1,179 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Helpers for reporting the progress of long-running tasks, such as downloads.
+//!
+//! Tasks are reported with [`StatusBackend::progress_start`],
+//! [`StatusBackend::progress_update`] and [`StatusBackend::progress_finish`].
+//! The [`Progress`] type helps backends keep track of the current task, and
+//! [`ProgressReader`] helps tasks report how much data they've read.
+
+use std::{
+    io::{Read, Result as IoResult},
+    time::{Duration, Instant},
+};
+
+use super::StatusBackend;
+
+/// The state of a task whose progress is being reported.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    started: Instant,
+}
+
+impl Progress {
+    /// Start tracking a task, described by *label*, that has *total* bytes to
+    /// do, if that's known.
+    pub fn new(label: &str, total: Option<u64>) -> Self {
+        Progress {
+            label: label.to_owned(),
+            total,
+            done: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Get the description of the task.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Get the total number of bytes to do, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Get the number of bytes done so far.
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+
+    /// Set the number of bytes done so far.
+    ///
+    /// This can go down, as when a download is retried.
+    pub fn set_done(&mut self, done: u64) {
+        self.done = done;
+    }
+
+    /// Get the time since the task started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Get the fraction of the task that's done, between 0 and 1, if the total
+    /// is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.),
+            Some(t) => Some((self.done as f64 / t as f64).min(1.)),
+            None => None,
+        }
+    }
+
+    /// Describe how much of the task is done, as in `"1.2 MiB of 3.0 MiB"`.
+    pub fn describe_amount(&self) -> String {
+        match self.total {
+            Some(t) => format!("{} of {}", format_bytes(self.done), format_bytes(t)),
+            None => format_bytes(self.done),
+        }
+    }
+}
+
+/// Format a number of bytes for people to read, as in `"1.2 MiB"`.
+pub fn format_bytes(n: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if n < 1024 {
+        return format!("{n} B");
+    }
+
+    let mut value = n as f64 / 1024.;
+    let mut unit = UNITS[0];
+
+    for u in &UNITS[1..] {
+        if value < 1024. {
+            break;
+        }
+
+        value /= 1024.;
+        unit = u;
+    }
+
+    format!("{value:.1} {unit}")
+}
+
+/// A reader that reports the number of bytes read through it as the progress
+/// of the current task.
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    status: &'a mut dyn StatusBackend,
+    done: u64,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    /// Wrap a reader, reporting progress to *status*.
+    pub fn new(inner: R, status: &'a mut dyn StatusBackend) -> Self {
+        ProgressReader {
+            inner,
+            status,
+            done: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = self.inner.read(buf)?;
+
+        if n > 0 {
+            self.done += n as u64;
+            self.status.progress_update(self.done);
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractions() {
+        let mut p = Progress::new("task", Some(200));
+        assert_eq!(p.fraction(), Some(0.));
+        p.set_done(50);
+        assert_eq!(p.fraction(), Some(0.25));
+        p.set_done(300);
+        assert_eq!(p.fraction(), Some(1.));
+
+        assert_eq!(Progress::new("empty", Some(0)).fraction(), Some(1.));
+        assert_eq!(Progress::new("unknown", None).fraction(), None);
+    }
+
+    #[test]
+    fn descriptions() {
+        let mut p = Progress::new("task", Some(3 * 1024 * 1024));
+        p.set_done(1258291);
+        assert_eq!(p.label(), "task");
+        assert_eq!(p.describe_amount(), "1.2 MiB of 3.0 MiB");
+
+        let mut p = Progress::new("task", None);
+        p.set_done(100);
+        assert_eq!(p.describe_amount(), "100 B");
+    }
+
+    #[test]
+    fn byte_formatting() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(1024 * 1024 - 1), "1024.0 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_bytes(u64::MAX), "16777216.0 TiB");
+    }
+}
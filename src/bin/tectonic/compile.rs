@@ -6,11 +6,15 @@
 //! `compile` subcommand of the "V2" / "cargo-like" interface.
 
 use clap::Parser;
-use std::path::{Path, PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use tectonic_bridge_core::{SecuritySettings, SecurityStance};
 
 use tectonic::{
     config::PersistentConfig,
+    ctry,
     driver::{OutputFormat, PassSetting, ProcessingSession, ProcessingSessionBuilder},
     errmsg,
     errors::{ErrorKind, Result},
@@ -29,7 +33,7 @@ pub struct CompileOptions {
     #[arg(long, short, name = "path", default_value = "latex")]
     format: String,
 
-    /// Use this directory or Zip-format bundle file to find resource files instead of the default
+    /// Use this directory, Zip file or indexed tar file to find resource files instead of the default
     #[arg(long, short, name = "file_path")]
     bundle: Option<PathBuf>,
 
@@ -37,6 +41,10 @@ pub struct CompileOptions {
     #[arg(short = 'C', long)]
     only_cached: bool,
 
+    /// Download the resource files listed in <manifest_path> ahead of time
+    #[arg(long, name = "manifest_path")]
+    prefetch: Option<PathBuf>,
+
     /// The kind of output to generate
     #[arg(long, name = "format", default_value = "pdf")]
     outfmt: OutputFormat,
@@ -196,17 +204,68 @@ impl CompileOptions {
         } else {
             sess_builder.bundle(config.default_bundle(only_cached, status)?);
         }
+        if let Some(p) = self.prefetch {
+            sess_builder.prefetch(read_prefetch_manifest(p)?);
+        }
         sess_builder.build_date_from_env(deterministic_mode);
 
         run_and_report(sess_builder, status).map(|_| 0)
     }
 }
 
+/// Read a list of files to prefetch from a bundle.
+///
+/// The manifest lists one file name per line. Blank lines are ignored, as are
+/// lines starting with `#`.
+pub(crate) fn read_prefetch_manifest<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let text = ctry!(
+        fs::read_to_string(path);
+        "failed to read prefetch manifest \"{}\"", path.display()
+    );
+
+    Ok(text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+/// Write a list of files to prefetch from a bundle, in the format read by
+/// [`read_prefetch_manifest`].
+pub(crate) fn write_prefetch_manifest<P: AsRef<Path>>(path: P, names: &[String]) -> Result<()> {
+    let path = path.as_ref();
+    let mut text = String::new();
+
+    for name in names {
+        text.push_str(name);
+        text.push('\n');
+    }
+
+    ctry!(
+        fs::write(path, text);
+        "failed to write prefetch manifest \"{}\"", path.display()
+    );
+    Ok(())
+}
+
 pub(crate) fn run_and_report(
     sess_builder: ProcessingSessionBuilder,
     status: &mut dyn StatusBackend,
 ) -> Result<ProcessingSession> {
     let mut sess = sess_builder.create(status)?;
+    run_session_and_report(&mut sess, status)?;
+    Ok(sess)
+}
+
+/// Run an already-created session, reporting engine failures like
+/// [`run_and_report`]. Unlike that function, this leaves the session in the
+/// caller's hands even if processing fails.
+pub(crate) fn run_session_and_report(
+    sess: &mut ProcessingSession,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
     let result = sess.run(status);
 
     if let Err(e) = &result {
@@ -230,5 +289,5 @@ pub(crate) fn run_and_report(
         }
     }
 
-    result.map(|_| sess)
+    result
 }
@@ -4,7 +4,7 @@
 
 use clap::{Parser, ValueEnum};
 use std::{env, io::IsTerminal, process};
-use tectonic_status_base::plain::PlainStatusBackend;
+use tectonic_status_base::{json::JsonStatusBackend, plain::PlainStatusBackend};
 
 use tectonic::{
     config::PersistentConfig,
@@ -50,6 +50,10 @@ struct CliOptions {
     #[arg(long = "color", name = "when", default_value = "auto")]
     cli_color: CliColor,
 
+    /// How to format status messages
+    #[arg(long = "status-format", value_name = "format", default_value = "human")]
+    status_format: StatusFormat,
+
     #[command(flatten)]
     compile: compile::CompileOptions,
 }
@@ -76,6 +80,17 @@ impl CliColor {
     }
 }
 
+#[derive(ValueEnum, Clone, Debug)]
+pub enum StatusFormat {
+    /// Messages for people to read
+    #[value(name = "human")]
+    Human,
+
+    /// One JSON object per line, for other programs to parse
+    #[value(name = "json")]
+    Json,
+}
+
 #[derive(Parser)]
 struct PeekUnstableOptions {
     #[arg(name = "option", short = 'Z')]
@@ -163,15 +178,29 @@ fn main() {
         }
     };
 
+    match config.geturl_config() {
+        Ok(c) => tectonic_geturl::config::set_default_config(c),
+        Err(ref e) => {
+            e.dump_uncolorized();
+            process::exit(1);
+        }
+    }
+
     // Set up colorized output. This comes after the config because you could
     // imagine wanting to be able to configure the colorization (which is
     // something I'd be relatively OK with since it'd only affect the progam
     // UI, not the processing results).
 
-    let mut status = if args.cli_color.should_enable() {
-        Box::new(TermcolorStatusBackend::new(args.chatter_level)) as Box<dyn StatusBackend>
-    } else {
-        Box::new(PlainStatusBackend::new(args.chatter_level)) as Box<dyn StatusBackend>
+    let mut status = match args.status_format {
+        StatusFormat::Json => {
+            Box::new(JsonStatusBackend::new(args.chatter_level)) as Box<dyn StatusBackend>
+        }
+        StatusFormat::Human if args.cli_color.should_enable() => {
+            Box::new(TermcolorStatusBackend::new(args.chatter_level)) as Box<dyn StatusBackend>
+        }
+        StatusFormat::Human => {
+            Box::new(PlainStatusBackend::new(args.chatter_level)) as Box<dyn StatusBackend>
+        }
     };
 
     // Now that we've got colorized output, pass off to the inner function ...
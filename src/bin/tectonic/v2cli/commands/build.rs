@@ -1,16 +1,21 @@
 use clap::Args;
+use std::path::PathBuf;
 use tectonic::{
     config::is_config_test_mode_activated,
     config::PersistentConfig,
     docmodel::{DocumentExt, DocumentSetupOptions},
-    errors::Result,
-    tt_error, tt_note,
+    driver::ProcessingSessionBuilder,
+    errors::{Result, SyncError},
+    tt_error, tt_note, tt_warning,
 };
 use tectonic_bridge_core::{SecuritySettings, SecurityStance};
 use tectonic_docmodel::workspace::Workspace;
 use tectonic_status_base::StatusBackend;
 
-use crate::v2cli::{CommandCustomizations, TectonicCommand};
+use crate::{
+    compile::{read_prefetch_manifest, write_prefetch_manifest},
+    v2cli::{CommandCustomizations, TectonicCommand},
+};
 
 /// `build`: Build a document
 #[derive(Debug, Eq, PartialEq, Args, Clone)]
@@ -23,6 +28,14 @@ pub struct BuildCommand {
     #[arg(short = 'C', long)]
     only_cached: bool,
 
+    /// Download the resource files listed in <manifest_path> ahead of time
+    #[arg(long, name = "manifest_path")]
+    prefetch: Option<PathBuf>,
+
+    /// Fail if the bundles don't match the document's Tectonic.lock file
+    #[arg(long)]
+    locked: bool,
+
     /// Keep the intermediate files generated during processing
     #[arg(short, long)]
     keep_intermediates: bool,
@@ -73,7 +86,10 @@ impl TectonicCommand for BuildCommand {
 
         let mut setup_options =
             DocumentSetupOptions::new_with_security(SecuritySettings::new(stance));
-        setup_options.only_cached(self.only_cached);
+        setup_options
+            .only_cached(self.only_cached)
+            .locked(self.locked)
+            .bundle_mirrors(config.bundle_mirrors());
 
         for output_name in doc.output_names() {
             if let Some(out) = self.target.as_ref() {
@@ -82,15 +98,44 @@ impl TectonicCommand for BuildCommand {
                 }
             }
 
-            let mut builder = doc.setup_session(output_name, &setup_options, status)?;
+            let format_cache_path = config.format_cache_path()?;
+
+            // Prefetch the files that the previous build of this output read,
+            // unless we've been told exactly what to prefetch.
+            let record_path = doc.prefetch_record_path(output_name);
+
+            let prefetch_names = if let Some(p) = self.prefetch.as_ref() {
+                read_prefetch_manifest(p)?
+            } else if record_path.exists() {
+                read_prefetch_manifest(&record_path).unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+
+            let mut run = |mut builder: ProcessingSessionBuilder,
+                           status: &mut dyn StatusBackend|
+             -> Result<()> {
+                builder
+                    .format_cache_path(&format_cache_path)
+                    .keep_intermediates(self.keep_intermediates)
+                    .keep_logs(self.keep_logs)
+                    .print_stdout(self.print_stdout)
+                    .prefetch(prefetch_names.clone());
+
+                let sess = crate::compile::run_and_report(builder, status)?;
+
+                if let Err(e) = write_prefetch_manifest(&record_path, &sess.read_input_names()) {
+                    tt_warning!(
+                        status,
+                        "failed to record the files read by this build";
+                        SyncError::new(e).into()
+                    );
+                }
 
-            builder
-                .format_cache_path(config.format_cache_path()?)
-                .keep_intermediates(self.keep_intermediates)
-                .keep_logs(self.keep_logs)
-                .print_stdout(self.print_stdout);
+                Ok(())
+            };
 
-            crate::compile::run_and_report(builder, status)?;
+            doc.build(output_name, &setup_options, status, &mut run)?;
 
             if self.open {
                 let out_file = doc.output_main_file(output_name);
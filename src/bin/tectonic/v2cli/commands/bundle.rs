@@ -1,29 +1,45 @@
 use clap::{Parser, Subcommand};
+use std::{collections::BTreeSet, path::PathBuf};
 use tectonic::{
     config::PersistentConfig,
+    ctry,
     docmodel::{DocumentExt, DocumentSetupOptions},
+    driver::ProcessingSessionBuilder,
+    errmsg,
     errors::Result,
-    tt_note,
+    tt_error, tt_note, tt_warning,
+};
+use tectonic_bundles::{
+    cache::Cache,
+    cas::convert_bundle,
+    create::BundleCreator,
+    export::{export_bundle, ArchiveFormat},
+    Bundle,
 };
-use tectonic_bundles::Bundle;
 use tectonic_docmodel::workspace::Workspace;
-use tectonic_status_base::StatusBackend;
+use tectonic_status_base::{progress::format_bytes, StatusBackend};
 
 use crate::v2cli::{CommandCustomizations, TectonicCommand};
 
+/// Get the bundle of the current document, or the default bundle, along with
+/// a description of its location.
 fn get_a_bundle(
-    _config: PersistentConfig,
+    config: PersistentConfig,
     only_cached: bool,
     status: &mut dyn StatusBackend,
-) -> Result<Box<dyn Bundle>> {
+) -> Result<(Box<dyn Bundle>, String)> {
     use tectonic_docmodel::workspace::NoWorkspaceFoundError;
 
     match Workspace::open_from_environment() {
         Ok(ws) => {
             let doc = ws.first_document();
             let mut options: DocumentSetupOptions = Default::default();
-            options.only_cached(only_cached);
-            doc.bundle(&options, status)
+            options
+                .only_cached(only_cached)
+                .read_only_lock(true)
+                .bundle_mirrors(config.bundle_mirrors());
+            let location = doc.bundle_locs().collect::<Vec<_>>().join(", ");
+            Ok((doc.bundle(&options, status)?, location))
         }
 
         Err(e) => {
@@ -34,11 +50,14 @@ fn get_a_bundle(
                     status,
                     "not in a document workspace; using the built-in default bundle"
                 );
-                Ok(Box::new(tectonic_bundles::get_fallback_bundle(
+                let bundle = tectonic_bundles::get_fallback_bundle(
                     tectonic_engine_xetex::FORMAT_SERIAL,
                     only_cached,
                     status,
-                )?))
+                )?;
+                let location =
+                    tectonic_bundles::get_fallback_bundle_url(tectonic_engine_xetex::FORMAT_SERIAL);
+                Ok((Box::new(bundle), location))
             }
         }
     }
@@ -57,23 +76,71 @@ enum BundleCommands {
     /// Dump the contents of a file in the bundle
     Cat(BundleCatCommand),
 
+    #[command(name = "convert")]
+    /// Convert a bundle to the compressed, content-addressed format
+    Convert(BundleConvertCommand),
+
+    #[command(name = "create")]
+    /// Create a new indexed tar bundle from directories of support files
+    Create(BundleCreateCommand),
+
+    #[command(name = "export")]
+    /// Export the files used by the document, or the whole bundle, to an archive
+    Export(BundleExportCommand),
+
+    #[command(name = "import")]
+    /// Seed the local bundle cache from an exported archive
+    Import(BundleImportCommand),
+
+    #[command(name = "info")]
+    /// Print information about the bundle and its local cache
+    Info(BundleInfoCommand),
+
+    #[command(name = "list")]
+    /// List the files contained in the bundle, with their sizes
+    List(BundleListCommand),
+
     #[command(name = "search")]
     /// Filter the list of filenames contained in the bundle
     Search(BundleSearchCommand),
+
+    #[command(name = "update")]
+    /// Re-resolve the document's bundles and update its Tectonic.lock file
+    Update(BundleUpdateCommand),
+
+    #[command(name = "verify")]
+    /// Check the locally cached bundle files for corruption
+    Verify(BundleVerifyCommand),
 }
 
 impl TectonicCommand for BundleCommand {
     fn customize(&self, cc: &mut CommandCustomizations) {
         match &self.command {
             BundleCommands::Cat(c) => c.customize(cc),
+            BundleCommands::Convert(c) => c.customize(cc),
+            BundleCommands::Create(c) => c.customize(cc),
+            BundleCommands::Export(c) => c.customize(cc),
+            BundleCommands::Import(c) => c.customize(cc),
+            BundleCommands::Info(c) => c.customize(cc),
+            BundleCommands::List(c) => c.customize(cc),
             BundleCommands::Search(c) => c.customize(cc),
+            BundleCommands::Update(c) => c.customize(cc),
+            BundleCommands::Verify(c) => c.customize(cc),
         }
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         match self.command {
             BundleCommands::Cat(c) => c.execute(config, status),
+            BundleCommands::Convert(c) => c.execute(config, status),
+            BundleCommands::Create(c) => c.execute(config, status),
+            BundleCommands::Export(c) => c.execute(config, status),
+            BundleCommands::Import(c) => c.execute(config, status),
+            BundleCommands::Info(c) => c.execute(config, status),
+            BundleCommands::List(c) => c.execute(config, status),
             BundleCommands::Search(c) => c.execute(config, status),
+            BundleCommands::Update(c) => c.execute(config, status),
+            BundleCommands::Verify(c) => c.execute(config, status),
         }
     }
 }
@@ -94,7 +161,7 @@ impl BundleCatCommand {
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
-        let mut bundle = get_a_bundle(config, self.only_cached, status)?;
+        let (mut bundle, _) = get_a_bundle(config, self.only_cached, status)?;
         let mut ih = bundle
             .input_open_name(&self.filename, status)
             .must_exist()?;
@@ -103,6 +170,284 @@ impl BundleCatCommand {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleConvertCommand {
+    /// The directory to write the converted bundle to; blobs already there are reused
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// The name of the index file to write, without its `.cabundle` extension
+    /// [default: the name of the bundle being converted]
+    #[arg(long)]
+    name: Option<String>,
+
+    /// The bundle to convert: an indexed tar file, a Zip file, or a directory
+    bundle: PathBuf,
+}
+
+impl BundleConvertCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let name = match self.name {
+            Some(n) => n,
+            None => match self.bundle.file_stem().and_then(|s| s.to_str()) {
+                Some(s) => s.to_owned(),
+                None => {
+                    return Err(errmsg!(
+                        "can't derive an index name from `{}`; use `--name`",
+                        self.bundle.display()
+                    ))
+                }
+            },
+        };
+
+        let mut bundle = config.make_local_file_provider(self.bundle, status)?;
+
+        ctry!(
+            std::fs::create_dir_all(&self.output);
+            "failed to create the output directory `{}`", self.output.display()
+        );
+
+        let converted = convert_bundle(bundle.as_mut(), &self.output, &name, status)?;
+
+        tt_note!(
+            status,
+            "wrote {} new blob(s), {} compressed from {} in total",
+            converted.n_new_blobs,
+            format_bytes(converted.new_blobs_size),
+            format_bytes(converted.size)
+        );
+        tt_note!(status, "bundle digest: {}", converted.digest.to_string());
+        tt_note!(status, "index: {}", converted.index_path.display());
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleCreateCommand {
+    /// The path of the tar file to create; the index is written alongside it
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// A file listing patterns of files to include or (with a `!` prefix) exclude
+    #[arg(long)]
+    spec: Option<PathBuf>,
+
+    /// A pattern of files to include (may be repeated)
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// A pattern of files to exclude (may be repeated)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Directories of support files; earlier ones take precedence
+    #[arg(required = true)]
+    sources: Vec<PathBuf>,
+}
+
+impl BundleCreateCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let mut creator = BundleCreator::new();
+
+        for source in self.sources {
+            creator.source(source);
+        }
+
+        if let Some(spec_path) = &self.spec {
+            let spec = ctry!(
+                std::fs::read_to_string(spec_path);
+                "failed to read the selection specification `{}`", spec_path.display()
+            );
+            creator.selection_spec(&spec)?;
+        }
+
+        for pattern in &self.include {
+            creator.include(pattern)?;
+        }
+
+        for pattern in &self.exclude {
+            creator.exclude(pattern)?;
+        }
+
+        let created = creator.create(&self.output, status)?;
+
+        if created.n_duplicates > 0 {
+            tt_note!(
+                status,
+                "skipped {} duplicated file name(s), {} with differing contents",
+                created.n_duplicates,
+                created.n_conflicts
+            );
+        }
+
+        tt_note!(status, "bundle digest: {}", created.digest.to_string());
+        tt_note!(status, "index: {}", created.index_path.display());
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleExportCommand {
+    /// Use only resource files cached locally
+    #[arg(short = 'C', long)]
+    only_cached: bool,
+
+    /// Export every file in the bundle, not just the ones that the document uses
+    #[arg(long)]
+    all: bool,
+
+    /// Only export the files used to build this output of the document
+    #[arg(long, conflicts_with = "all")]
+    target: Option<String>,
+
+    /// The archive to create; its name must end in `.zip` or `.tar`
+    path: PathBuf,
+}
+
+impl BundleExportCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        // Check this before doing all the work of building the document.
+        ArchiveFormat::from_path(&self.path)?;
+
+        let (mut bundle, names) = if self.all {
+            let (mut bundle, _) = get_a_bundle(config, self.only_cached, status)?;
+            let names = bundle.all_files(status)?;
+            (bundle, names)
+        } else {
+            self.used_files(&config, status)?
+        };
+
+        let exported = export_bundle(bundle.as_mut(), &names, &self.path, status)?;
+
+        // When exporting the files used by a document, some of the names will
+        // refer to things that didn't come from the bundle, so there's nothing
+        // to say about them.
+        if self.all && !exported.missing.is_empty() {
+            tt_warning!(
+                status,
+                "{} file(s) could not be exported; are they cached locally?",
+                exported.missing.len()
+            );
+        }
+
+        tt_note!(
+            status,
+            "wrote {} files to `{}`",
+            exported.n_files,
+            self.path.display()
+        );
+        tt_note!(status, "bundle digest: {}", exported.digest.to_string());
+        Ok(0)
+    }
+
+    /// Build the document and find out which bundle files it used.
+    fn used_files(
+        &self,
+        config: &PersistentConfig,
+        status: &mut dyn StatusBackend,
+    ) -> Result<(Box<dyn Bundle>, Vec<String>)> {
+        let ws = Workspace::open_from_environment()?;
+        let doc = ws.first_document();
+        let mut setup_options: DocumentSetupOptions = Default::default();
+        setup_options
+            .only_cached(self.only_cached)
+            .read_only_lock(true)
+            .bundle_mirrors(config.bundle_mirrors());
+
+        // Build with an empty format cache, so that the files needed to
+        // generate the format are recorded too.
+        let format_cache = ctry!(
+            tempfile::tempdir();
+            "failed to create a temporary directory"
+        );
+
+        let outputs: Vec<&str> = match &self.target {
+            Some(t) => vec![t.as_str()],
+            None => doc.output_names().collect(),
+        };
+
+        let mut bundle_slot = None;
+        let mut names = BTreeSet::new();
+
+        for output_name in outputs {
+            let bundle = match bundle_slot.take() {
+                Some(b) => b,
+                None => doc.bundle(&setup_options, status)?,
+            };
+
+            let mut run = |mut builder: ProcessingSessionBuilder,
+                           status: &mut dyn StatusBackend|
+             -> Result<()> {
+                builder.format_cache_path(format_cache.path());
+
+                let mut sess = builder.create(status)?;
+                crate::compile::run_session_and_report(&mut sess, status)?;
+                names.extend(sess.read_input_names());
+                bundle_slot = Some(sess.into_bundle());
+                Ok(())
+            };
+
+            doc.build_with_bundle(output_name, &setup_options, bundle, status, &mut run)?;
+        }
+
+        let bundle = match bundle_slot {
+            Some(b) => b,
+            None => doc.bundle(&setup_options, status)?,
+        };
+
+        Ok((bundle, names.into_iter().collect()))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleImportCommand {
+    /// The URL of the bundle that the archive was exported from [default: the
+    /// document's bundle, or the built-in default bundle]
+    #[arg(long)]
+    url: Option<String>,
+
+    /// The archive to import: a ZIP or tar file, or a directory
+    archive: PathBuf,
+}
+
+impl BundleImportCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        use tectonic_docmodel::workspace::NoWorkspaceFoundError;
+
+        let url = match self.url {
+            Some(u) => u,
+            None => match Workspace::open_from_environment() {
+                Ok(ws) => ws.first_document().bundle_loc.clone(),
+                Err(e) if e.downcast_ref::<NoWorkspaceFoundError>().is_some() => {
+                    tectonic_bundles::get_fallback_bundle_url(tectonic_engine_xetex::FORMAT_SERIAL)
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        let mut cache = Cache::get_user_default()?;
+        let imported = cache.import_archive(&url, &self.archive, status)?;
+
+        tt_note!(
+            status,
+            "imported {} files ({} new) for bundle `{}`",
+            imported.n_files,
+            imported.n_new,
+            url
+        );
+        tt_note!(status, "bundle digest: {}", imported.digest.to_string());
+        Ok(0)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Parser)]
 struct BundleSearchCommand {
     /// Use only resource files cached locally
@@ -119,7 +464,7 @@ impl BundleSearchCommand {
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
-        let mut bundle = get_a_bundle(config, self.only_cached, status)?;
+        let (mut bundle, _) = get_a_bundle(config, self.only_cached, status)?;
         let files = bundle.all_files(status)?;
 
         // Is there a better way to do this?
@@ -138,3 +483,162 @@ impl BundleSearchCommand {
         Ok(0)
     }
 }
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleInfoCommand {
+    /// Use only resource files cached locally
+    #[arg(short = 'C', long)]
+    only_cached: bool,
+}
+
+impl BundleInfoCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let (mut bundle, location) = get_a_bundle(config, self.only_cached, status)?;
+        let digest = bundle.get_digest(status)?;
+        let n_files = bundle.all_files(status)?.len();
+
+        println!("location:        {location}");
+        println!("digest:          {}", digest.to_string());
+        println!("format version:  {}", tectonic_engine_xetex::FORMAT_SERIAL);
+        println!("files:           {n_files}");
+
+        if let Some(info) = bundle.cache_info() {
+            println!("resolved URL:    {}", info.resolved_url);
+            println!("cache directory: {}", info.root.display());
+            println!(
+                "cached files:    {} ({})",
+                info.n_cached_files,
+                format_bytes(info.cached_size)
+            );
+        }
+
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleListCommand {
+    /// Use only resource files cached locally
+    #[arg(short = 'C', long)]
+    only_cached: bool,
+}
+
+impl BundleListCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let (mut bundle, _) = get_a_bundle(config, self.only_cached, status)?;
+        let mut files = bundle.all_files_with_sizes(status)?;
+        files.sort();
+
+        for (filename, size) in &files {
+            match size {
+                Some(n) => println!("{n:>12}  {filename}"),
+                None => println!("{:>12}  {filename}", "?"),
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleUpdateCommand {}
+
+impl BundleUpdateCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let ws = Workspace::open_from_environment()?;
+        let doc = ws.first_document();
+        let mut setup_options: DocumentSetupOptions = Default::default();
+        setup_options.bundle_mirrors(config.bundle_mirrors());
+        let (old_lock, new_lock) = doc.update_bundle_lock(&setup_options, status)?;
+
+        for entry in &new_lock.bundles {
+            match old_lock.as_ref().and_then(|l| l.get(&entry.location)) {
+                Some(old) if old.digest == entry.digest => {
+                    tt_note!(status, "bundle `{}` is unchanged", entry.location);
+                }
+
+                Some(old) => {
+                    tt_note!(
+                        status,
+                        "bundle `{}` updated: {} -> {}",
+                        entry.location,
+                        old.digest,
+                        entry.digest
+                    );
+                }
+
+                None => {
+                    tt_note!(
+                        status,
+                        "bundle `{}` locked at {}",
+                        entry.location,
+                        entry.digest
+                    );
+                }
+            }
+        }
+
+        tt_note!(status, "wrote `{}`", doc.lock_path().display());
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct BundleVerifyCommand {}
+
+impl BundleVerifyCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        // Verification only looks at the cache, so there's no need to go to
+        // the network.
+        let (mut bundle, location) = get_a_bundle(config, true, status)?;
+
+        let verification = match bundle.verify_cache(status)? {
+            Some(v) => v,
+            None => {
+                tt_note!(
+                    status,
+                    "bundle `{}` is not cached locally; there is nothing to verify",
+                    location
+                );
+                return Ok(0);
+            }
+        };
+
+        for problem in &verification.problems {
+            tt_error!(status, "{}", problem);
+        }
+
+        if verification.problems.is_empty() {
+            tt_note!(
+                status,
+                "all {} cached files of bundle `{}` are intact",
+                verification.n_checked,
+                location
+            );
+            Ok(0)
+        } else {
+            tt_error!(
+                status,
+                "found {} problem(s) among {} cached files of bundle `{}`",
+                verification.problems.len(),
+                verification.n_checked,
+                location
+            );
+            Ok(1)
+        }
+    }
+}
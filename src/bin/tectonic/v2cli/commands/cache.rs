@@ -0,0 +1,272 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+use clap::{Parser, Subcommand};
+use std::{
+    collections::HashSet,
+    fs,
+    time::{Duration, SystemTime},
+};
+use tectonic::{
+    config::{parse_size, PersistentConfig},
+    errors::Result,
+    io::format_cache::{CachedFormat, FormatCache},
+    tt_note, tt_warning,
+};
+use tectonic_bundles::cache::{Cache, GcOptions, GcReport};
+use tectonic_status_base::{progress::format_bytes, StatusBackend};
+
+use crate::v2cli::{CommandCustomizations, TectonicCommand};
+
+/// How long cached files may go unused before `cache gc` removes them, if
+/// neither the command line nor the configuration file says otherwise.
+const DEFAULT_MAX_AGE_DAYS: u64 = 90;
+
+/// `cache`: Commands relating to the local cache of bundle files and formats
+#[derive(Debug, Eq, PartialEq, Parser)]
+pub struct CacheCommand {
+    #[command(subcommand)]
+    command: CacheCommands,
+}
+
+#[derive(Debug, Eq, PartialEq, Subcommand)]
+enum CacheCommands {
+    #[command(name = "gc")]
+    /// Remove cached bundles and formats that are no longer in use
+    Gc(CacheGcCommand),
+
+    #[command(name = "info")]
+    /// Print information about the contents of the cache
+    Info(CacheInfoCommand),
+
+    #[command(name = "purge")]
+    /// Remove everything from the cache
+    Purge(CachePurgeCommand),
+}
+
+impl TectonicCommand for CacheCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        match &self.command {
+            CacheCommands::Gc(c) => c.customize(cc),
+            CacheCommands::Info(c) => c.customize(cc),
+            CacheCommands::Purge(c) => c.customize(cc),
+        }
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        match self.command {
+            CacheCommands::Gc(c) => c.execute(config, status),
+            CacheCommands::Info(c) => c.execute(config, status),
+            CacheCommands::Purge(c) => c.execute(config, status),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct CacheGcCommand {
+    /// Remove bundles and formats that haven't been used for this many days
+    /// [default: from the configuration file, or 90]
+    #[arg(long, name = "days")]
+    max_age: Option<u64>,
+
+    /// Then remove the least recently used bundles until the cache is no
+    /// larger than this, e.g. "2 GiB" [default: from the configuration file]
+    #[arg(long, name = "size")]
+    max_size: Option<String>,
+}
+
+impl CacheGcCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let max_age = match self.max_age {
+            // Huge ages just mean "never expire", so don't overflow on them.
+            Some(days) => Duration::from_secs(days.saturating_mul(24 * 60 * 60)),
+            None => config
+                .cache_max_age()
+                .unwrap_or_else(|| Duration::from_secs(DEFAULT_MAX_AGE_DAYS * 24 * 60 * 60)),
+        };
+
+        let max_size = match self.max_size {
+            Some(text) => Some(parse_size(&text)?),
+            None => config.cache_max_size()?,
+        };
+
+        // Formats are cheap to regenerate, so they're expired by age alone.
+        // Whatever room remains under the size cap goes to the bundles. We
+        // don't remove anything until the bundle cache has been locked, though.
+
+        let now = SystemTime::now();
+        let (expired, kept): (Vec<_>, Vec<_>) =
+            FormatCache::list_formats(config.format_cache_path()?)?
+                .into_iter()
+                .partition(|f| match f.last_used {
+                    Some(t) => now.duration_since(t).unwrap_or_default() >= max_age,
+                    None => true,
+                });
+
+        let kept_size: u64 = kept.iter().map(|f| f.size).sum();
+
+        let options = GcOptions {
+            max_age: Some(max_age),
+            max_size: max_size.map(|s| s.saturating_sub(kept_size)),
+        };
+
+        let mut cache = Cache::get_user_default()?;
+        let report = cache.gc(&options, status)?;
+
+        let removed: HashSet<_> = report.removed_bundles.iter().collect();
+        let mut n_formats_removed = 0;
+        let mut bytes_freed = 0;
+
+        for f in expired
+            .iter()
+            .chain(kept.iter().filter(|f| removed.contains(&f.bundle_digest)))
+        {
+            if remove_format(f, status) {
+                n_formats_removed += 1;
+                bytes_freed += f.size;
+            }
+        }
+
+        report_removal(&report, n_formats_removed, bytes_freed, status);
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct CacheInfoCommand {}
+
+impl CacheInfoCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let cache = Cache::get_user_default()?;
+        let summary = cache.summarize()?;
+        let formats_path = config.format_cache_path()?;
+        let formats = FormatCache::list_formats(&formats_path)?;
+        let now = SystemTime::now();
+
+        println!("cache directory:  {}", cache.root().display());
+        println!("format directory: {}", formats_path.display());
+        println!(
+            "cached files:     {} ({})",
+            summary.n_data_files,
+            format_bytes(summary.data_size)
+        );
+        println!(
+            "cached formats:   {} ({})",
+            formats.len(),
+            format_bytes(formats.iter().map(|f| f.size).sum())
+        );
+
+        match config.cache_max_age() {
+            Some(age) => println!("maximum age:      {} days", age.as_secs() / (24 * 60 * 60)),
+            None => println!("maximum age:      {DEFAULT_MAX_AGE_DAYS} days (default)"),
+        }
+
+        match config.cache_max_size()? {
+            Some(size) => println!("maximum size:     {}", format_bytes(size)),
+            None => println!("maximum size:     none"),
+        }
+
+        println!("bundles:          {}", summary.bundles.len());
+
+        for bundle in &summary.bundles {
+            let n_formats = formats
+                .iter()
+                .filter(|f| f.bundle_digest == bundle.digest)
+                .count();
+
+            println!();
+            println!("  digest:    {}", bundle.digest.to_string());
+
+            if let Some(url) = &bundle.resolved_url {
+                println!("  URL:       {url}");
+            }
+
+            println!("  last used: {}", format_age(now, bundle.last_used));
+            println!(
+                "  files:     {} ({})",
+                bundle.n_files,
+                format_bytes(bundle.size)
+            );
+            println!("  formats:   {n_formats}");
+        }
+
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct CachePurgeCommand {}
+
+impl CachePurgeCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let mut cache = Cache::get_user_default()?;
+        let report = cache.purge(status)?;
+
+        let mut n_formats_removed = 0;
+        let mut bytes_freed = 0;
+
+        for f in FormatCache::list_formats(config.format_cache_path()?)? {
+            if remove_format(&f, status) {
+                n_formats_removed += 1;
+                bytes_freed += f.size;
+            }
+        }
+
+        report_removal(&report, n_formats_removed, bytes_freed, status);
+        Ok(0)
+    }
+}
+
+/// Remove a cached format file, warning and returning false if that fails.
+fn remove_format(format: &CachedFormat, status: &mut dyn StatusBackend) -> bool {
+    match fs::remove_file(&format.path) {
+        Ok(()) => true,
+        Err(e) => {
+            tt_warning!(
+                status,
+                "failed to remove cached format `{}`",
+                format.path.display();
+                e.into()
+            );
+            false
+        }
+    }
+}
+
+fn report_removal(
+    report: &GcReport,
+    n_formats_removed: usize,
+    format_bytes_freed: u64,
+    status: &mut dyn StatusBackend,
+) {
+    tt_note!(
+        status,
+        "removed {} bundle(s), {} cached file(s) and {} format(s), freeing {}",
+        report.removed_bundles.len(),
+        report.n_files_removed,
+        n_formats_removed,
+        format_bytes(report.bytes_freed + format_bytes_freed)
+    );
+}
+
+/// Describe how long ago something was used.
+fn format_age(now: SystemTime, t: Option<SystemTime>) -> String {
+    let days = match t {
+        Some(t) => now.duration_since(t).unwrap_or_default().as_secs() / (24 * 60 * 60),
+        None => return "unknown".to_owned(),
+    };
+
+    match days {
+        0 => "today".to_owned(),
+        1 => "1 day ago".to_owned(),
+        n => format!("{n} days ago"),
+    }
+}
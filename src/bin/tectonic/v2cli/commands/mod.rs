@@ -1,6 +1,9 @@
 pub mod build;
 pub mod bundle;
+pub mod cache;
 pub mod dump;
 pub mod new;
+pub mod serve;
 pub mod show;
+pub mod validate;
 pub mod watch;
@@ -2,9 +2,13 @@ use clap::Parser;
 use std::env;
 use std::path::PathBuf;
 use tectonic::{
-    config::PersistentConfig, ctry, docmodel::WorkspaceCreatorExt, errors::Result, tt_note,
+    config::PersistentConfig,
+    ctry,
+    docmodel::{load_workspace_template, WorkspaceCreatorExt},
+    errors::Result,
+    tt_note, tt_warning,
 };
-use tectonic_docmodel::workspace::WorkspaceCreator;
+use tectonic_docmodel::{analysis::analyze_directory, workspace::WorkspaceCreator};
 use tectonic_status_base::StatusBackend;
 
 use crate::v2cli::{CommandCustomizations, TectonicCommand};
@@ -28,7 +32,55 @@ impl TectonicCommand for InitCommand {
             path.display()
         );
 
-        let wc = WorkspaceCreator::new(path);
+        let analysis = ctry!(
+            analyze_directory(&path);
+            "failed to analyze the existing files in `{}`", path.display()
+        );
+
+        let mut wc = WorkspaceCreator::new(path);
+
+        if let Some(analysis) = analysis {
+            tt_note!(
+                status,
+                "using the existing file `{}` as the main input",
+                analysis.main_file
+            );
+
+            if !analysis.other_candidates.is_empty() {
+                tt_note!(
+                    status,
+                    "other possible main files: {} (edit `Tectonic.toml` to use one of them)",
+                    analysis.other_candidates.join(", ")
+                );
+            }
+
+            if !analysis.shell_escape_packages.is_empty() {
+                tt_note!(
+                    status,
+                    "enabling shell-escape, which is needed by: {}",
+                    analysis.shell_escape_packages.join(", ")
+                );
+            }
+
+            if analysis.uses_biblatex {
+                tt_note!(
+                    status,
+                    "the document uses biblatex; building it requires the external `biber` program"
+                );
+            } else if !analysis.bib_databases.is_empty() {
+                tt_note!(
+                    status,
+                    "the document uses BibTeX, which Tectonic will run automatically"
+                );
+            }
+
+            for db in &analysis.missing_bib_databases {
+                tt_warning!(status, "the bibliography database `{}` was not found", db);
+            }
+
+            wc.template(analysis.to_template());
+        }
+
         ctry!(
             wc.create_defaulted(config, status, self.web_bundle);
             "failed to create the new Tectonic workspace"
@@ -44,6 +96,11 @@ pub struct NewCommand {
     #[arg(default_value = ".")]
     path: PathBuf,
 
+    /// The template to use: a built-in or user template name, a directory
+    /// path, or `bundle:<path>`
+    #[arg(long, short)]
+    template: Option<String>,
+
     /// Use this URL to find resource files instead of the default
     #[arg(long, short, name = "url", overrides_with = "url", global(true))]
     web_bundle: Option<String>,
@@ -59,7 +116,13 @@ impl TectonicCommand for NewCommand {
             self.path.display()
         );
 
-        let wc = WorkspaceCreator::new(self.path);
+        let mut wc = WorkspaceCreator::new(self.path);
+
+        if let Some(spec) = self.template.as_ref() {
+            let template = load_workspace_template(spec, &config, self.web_bundle.clone(), status)?;
+            wc.template(template);
+        }
+
         ctry!(
             wc.create_defaulted(config, status, self.web_bundle);
             "failed to create the new Tectonic workspace"
@@ -0,0 +1,528 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! The `serve` command: a live-reloading preview server.
+//!
+//! This builds the document in-process, just like `watch`, and serves the
+//! output directory over HTTP on the local machine. Browsers are notified of
+//! successful rebuilds with [server-sent events][sse]. HTML files have a small
+//! script injected into them to listen for these events. PDF outputs are shown
+//! with a viewer page that reloads the PDF in place. The page uses the
+//! browser's built-in PDF viewer, or a copy of PDF.js from the user's
+//! configuration directory if there is one, so that nothing is loaded from
+//! elsewhere.
+//!
+//! This is a development convenience, not a general-purpose web server, so it
+//! only uses the standard library and a thread per connection.
+//!
+//! [sse]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use clap::Parser;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tectonic::{
+    config::{is_config_test_mode_activated, PersistentConfig},
+    ctry, errmsg,
+    errors::Result,
+    tt_error, tt_note,
+};
+use tectonic_docmodel::{document::BuildTargetType, workspace::Workspace};
+use tectonic_status_base::StatusBackend;
+
+use super::watch::InProcessWatch;
+use crate::v2cli::{CommandCustomizations, TectonicCommand};
+
+/// The path of the server-sent event stream that announces rebuilds.
+const EVENTS_PATH: &str = "/_tectonic/events";
+
+/// The path prefix under which the files of PDF.js are served.
+const PDFJS_PATH: &str = "/_tectonic/pdfjs/";
+
+/// The PDF.js files that the viewer page needs.
+const PDFJS_FILES: &[&str] = &["pdf.min.js", "pdf.worker.min.js"];
+
+/// How long to wait for a browser to accept a rebuild event before giving up
+/// on it, so that one stalled browser can't hold up the others.
+const EVENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a browser to send its request, so that idle
+/// connections don't tie up threads forever.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The script injected into served HTML files to reload them after rebuilds.
+const RELOAD_SCRIPT: &str = "<script>new EventSource(\"/_tectonic/events\").addEventListener(\"reload\", function () { location.reload(); });</script>\n";
+
+/// The page used to view PDF outputs. It uses PDF.js if it's installed, and
+/// otherwise the browser's built-in PDF viewer, which it reloads in place so
+/// that the position in the document is kept.
+const PDF_VIEWER_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>@PDF_NAME@ - Tectonic preview</title>
+<style>
+body { margin: 0; background: #525659; }
+#pages { display: flex; flex-direction: column; align-items: center; gap: 1em; padding: 1em 0; }
+#pages canvas { background: white; box-shadow: 0 0 8px rgba(0, 0, 0, 0.5); }
+#fallback { position: fixed; top: 0; left: 0; width: 100%; height: 100%; border: none; }
+</style>
+<script src="/_tectonic/pdfjs/pdf.min.js"></script>
+</head>
+<body>
+<div id="pages"></div>
+<script>
+(function () {
+  var pdfUrl = "/@PDF_NAME@";
+  var pages = document.getElementById("pages");
+
+  // The page that the built-in viewer was last known to be showing. The
+  // viewers follow `#page=N` in the URL, and some of them keep it up to date
+  // as the document is scrolled.
+  var lastHash = sessionStorage.getItem(pdfUrl) || "";
+
+  function currentHash(frame) {
+    try {
+      return frame.contentWindow.location.hash;
+    } catch (e) {
+      return "";
+    }
+  }
+
+  function fallback() {
+    var frame = document.getElementById("fallback");
+
+    if (!frame) {
+      frame = document.createElement("iframe");
+      frame.id = "fallback";
+      frame.src = pdfUrl + lastHash;
+      document.body.appendChild(frame);
+      return;
+    }
+
+    lastHash = currentHash(frame) || lastHash;
+    sessionStorage.setItem(pdfUrl, lastHash);
+
+    // Reloading the same document in place lets the browser restore the
+    // scroll position. If the frame can't be reached, load it again at the
+    // last known page.
+    try {
+      frame.contentWindow.location.reload();
+    } catch (e) {
+      frame.src = pdfUrl + "?t=" + Date.now() + lastHash;
+    }
+  }
+
+  async function render() {
+    var pdf = await pdfjsLib.getDocument(pdfUrl + "?t=" + Date.now()).promise;
+    var ratio = window.devicePixelRatio || 1;
+    var rendered = document.createDocumentFragment();
+
+    for (var i = 1; i <= pdf.numPages; i++) {
+      var page = await pdf.getPage(i);
+      var viewport = page.getViewport({ scale: 1.25 * ratio });
+      var canvas = document.createElement("canvas");
+      canvas.width = viewport.width;
+      canvas.height = viewport.height;
+      canvas.style.width = viewport.width / ratio + "px";
+      await page.render({ canvasContext: canvas.getContext("2d"), viewport: viewport }).promise;
+      rendered.appendChild(canvas);
+    }
+
+    // Swap in all of the new pages at once, so that the scroll position
+    // survives.
+    var x = window.scrollX, y = window.scrollY;
+    pages.replaceChildren(rendered);
+    window.scrollTo(x, y);
+  }
+
+  function reload() {
+    if (window.pdfjsLib) {
+      render().catch(function (e) { console.error(e); });
+    } else {
+      fallback();
+    }
+  }
+
+  if (window.pdfjsLib) {
+    pdfjsLib.GlobalWorkerOptions.workerSrc = "/_tectonic/pdfjs/pdf.worker.min.js";
+  }
+
+  reload();
+  new EventSource("/_tectonic/events").addEventListener("reload", reload);
+})();
+</script>
+</body>
+</html>
+"#;
+
+/// `serve`: Build the document, serve it locally, and reload it on changes
+#[derive(Debug, Eq, PartialEq, Parser)]
+pub struct ServeCommand {
+    /// The output to build and serve [default: the only one, or `default`]
+    #[arg(long)]
+    target: Option<String>,
+
+    /// The port to listen on, or 0 to use any free port
+    #[arg(long, short, default_value_t = 8000)]
+    port: u16,
+
+    /// Open the preview in a web browser
+    #[arg(long)]
+    open: bool,
+}
+
+impl TectonicCommand for ServeCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let ws = Workspace::open_from_environment()?;
+        let doc = ws.first_document();
+
+        let output_name = match self.target {
+            Some(ref t) => {
+                if !doc.outputs.contains_key(t) {
+                    return Err(errmsg!("unrecognized output profile name \"{}\"", t));
+                }
+
+                t.clone()
+            }
+
+            None => {
+                if doc.outputs.len() == 1 {
+                    doc.output_names().next().unwrap().to_owned()
+                } else if doc.outputs.contains_key("default") {
+                    "default".to_owned()
+                } else {
+                    return Err(errmsg!(
+                        "the document has several outputs; choose one with `--target`"
+                    ));
+                }
+            }
+        };
+
+        let pdf_name = match doc.outputs[&output_name].target_type {
+            BuildTargetType::Html => None,
+            BuildTargetType::Pdf => doc
+                .output_main_file(&output_name)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned()),
+        };
+
+        let pdfjs_dir = config.user_pdfjs_dir()?;
+
+        if pdf_name.is_some() && !PDFJS_FILES.iter().all(|f| pdfjs_dir.join(f).is_file()) {
+            tt_note!(
+                status,
+                "to view the PDF with PDF.js rather than the browser's built-in viewer, \
+                 put the files {} from the `pdfjs-dist` package into `{}`",
+                PDFJS_FILES.join(" and "),
+                pdfjs_dir.display()
+            );
+        }
+
+        let server = Arc::new(PreviewServer {
+            root: doc.build_dir().join(&output_name),
+            pdf_name,
+            pdfjs_dir,
+            clients: Mutex::new(Vec::new()),
+        });
+
+        let listener = ctry!(
+            TcpListener::bind(("127.0.0.1", self.port));
+            "failed to listen on port {}", self.port
+        );
+        let url = format!("http://{}/", listener.local_addr()?);
+
+        {
+            let server = server.clone();
+            thread::spawn(move || server.listen(listener));
+        }
+
+        tt_note!(status, "serving output `{}` at {}", output_name, url);
+
+        let mut watch = InProcessWatch::new(&config)?;
+        watch.only_output(&output_name);
+
+        let mut need_open = self.open;
+
+        watch.run(status, &mut |success, status| {
+            if success {
+                server.notify_reload();
+            }
+
+            if need_open {
+                need_open = false;
+
+                if is_config_test_mode_activated() {
+                    tt_note!(status, "not opening `{}` -- test mode", url);
+                } else if let Err(e) = open::that(&url) {
+                    tt_error!(status, "failed to open `{}` with system handler", url; e.into());
+                }
+            }
+        })
+    }
+}
+
+/// The HTTP server for previews.
+struct PreviewServer {
+    /// The output directory.
+    root: PathBuf,
+
+    /// If the output is a PDF, the name of the PDF file. In this case, the
+    /// root URL serves a viewer page.
+    pdf_name: Option<String>,
+
+    /// The directory containing PDF.js, for the viewer page.
+    pdfjs_dir: PathBuf,
+
+    /// The connections listening for rebuild events.
+    clients: Mutex<Vec<TcpStream>>,
+}
+
+impl PreviewServer {
+    fn listen(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming().flatten() {
+            let server = self.clone();
+
+            thread::spawn(move || {
+                // Errors here are generally just browsers going away.
+                let _ = server.handle(stream);
+            });
+        }
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // We don't care about any of the headers.
+        loop {
+            let mut line = String::new();
+
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut pieces = request_line.split_whitespace();
+        let method = pieces.next().unwrap_or("");
+        let target = pieces.next().unwrap_or("/");
+        let path = target.split(['?', '#']).next().unwrap_or("/");
+        let include_body = method != "HEAD";
+
+        if method != "GET" && method != "HEAD" {
+            return respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"method not allowed\n",
+                include_body,
+            );
+        }
+
+        if path == EVENTS_PATH {
+            stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n: connected\n\n",
+            )?;
+            stream.flush()?;
+            stream.set_write_timeout(Some(EVENT_WRITE_TIMEOUT))?;
+            self.clients.lock().unwrap().push(stream);
+            return Ok(());
+        }
+
+        if path == "/" {
+            if let Some(ref pdf_name) = self.pdf_name {
+                let page = PDF_VIEWER_PAGE.replace("@PDF_NAME@", &percent_encode(pdf_name));
+                return respond(
+                    &mut stream,
+                    "200 OK",
+                    "text/html; charset=utf-8",
+                    page.as_bytes(),
+                    include_body,
+                );
+            }
+        }
+
+        let resolved = match path.strip_prefix(PDFJS_PATH) {
+            Some(name) if PDFJS_FILES.contains(&name) => Some(self.pdfjs_dir.join(name)),
+            Some(_) => None,
+            None => self.resolve(path),
+        };
+
+        let file_path = match resolved {
+            Some(p) => p,
+            None => {
+                return respond(
+                    &mut stream,
+                    "404 Not Found",
+                    "text/plain",
+                    b"not found\n",
+                    include_body,
+                )
+            }
+        };
+
+        let content_type = content_type(&file_path);
+
+        let mut data = match fs::read(&file_path) {
+            Ok(d) => d,
+            Err(_) => {
+                return respond(
+                    &mut stream,
+                    "404 Not Found",
+                    "text/plain",
+                    b"not found\n",
+                    include_body,
+                )
+            }
+        };
+
+        if content_type.starts_with("text/html") {
+            data = inject_reload_script(data);
+        }
+
+        respond(&mut stream, "200 OK", content_type, &data, include_body)
+    }
+
+    /// Map a URL path to a file in the output directory, refusing to leave
+    /// it.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(url_path)?;
+        let rel = Path::new(decoded.trim_start_matches('/'));
+
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return None;
+        }
+
+        let mut full = self.root.join(rel);
+
+        if full.is_dir() {
+            full.push("index.html");
+        }
+
+        Some(full)
+    }
+
+    /// Tell all of the listening browsers to reload.
+    ///
+    /// The clients are taken out of the list while the events are sent, so
+    /// that new browsers can connect in the meantime. Clients that can't be
+    /// sent the event are dropped.
+    fn notify_reload(&self) {
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+
+        let live: Vec<TcpStream> = clients
+            .into_iter()
+            .filter(|mut client| {
+                client
+                    .write_all(b"event: reload\ndata: reload\n\n")
+                    .and_then(|_| client.flush())
+                    .is_ok()
+            })
+            .collect();
+
+        self.clients.lock().unwrap().extend(live);
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status_line: &str,
+    content_type: &str,
+    body: &[u8],
+    include_body: bool,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status_line,
+        content_type,
+        body.len()
+    )?;
+
+    if include_body {
+        stream.write_all(body)?;
+    }
+
+    stream.flush()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "pdf" => "application/pdf",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "otf" => "font/otf",
+        "ttf" => "font/ttf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Insert [`RELOAD_SCRIPT`] just before the closing `</body>` tag, or at the
+/// end if there isn't one.
+fn inject_reload_script(mut html: Vec<u8>) -> Vec<u8> {
+    let lower = html.to_ascii_lowercase();
+    let needle = b"</body>";
+    let pos = lower
+        .windows(needle.len())
+        .rposition(|w| w == needle)
+        .unwrap_or(html.len());
+    html.splice(pos..pos, RELOAD_SCRIPT.bytes());
+    html
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+
+    encoded
+}
@@ -13,6 +13,10 @@ pub struct ShowCommand {
 
 #[derive(Debug, Eq, PartialEq, Parser)]
 enum ShowCommands {
+    #[command(name = "toml-schema")]
+    /// Print a JSON Schema describing the `Tectonic.toml` file format
+    TomlSchema(ShowTomlSchemaCommand),
+
     #[command(name = "user-cache-dir")]
     /// Print the location of the default per-user cache directory
     UserCacheDir(ShowUserCacheDirCommand),
@@ -21,17 +25,33 @@ enum ShowCommands {
 impl TectonicCommand for ShowCommand {
     fn customize(&self, cc: &mut CommandCustomizations) {
         match &self.command {
+            ShowCommands::TomlSchema(c) => c.customize(cc),
             ShowCommands::UserCacheDir(c) => c.customize(cc),
         }
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         match self.command {
+            ShowCommands::TomlSchema(c) => c.execute(config, status),
             ShowCommands::UserCacheDir(c) => c.execute(config, status),
         }
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Parser)]
+struct ShowTomlSchemaCommand {}
+
+impl ShowTomlSchemaCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, _config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        println!("{}", tectonic_docmodel::validation::json_schema());
+        Ok(0)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Parser)]
 struct ShowUserCacheDirCommand {}
 
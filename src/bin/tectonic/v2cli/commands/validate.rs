@@ -0,0 +1,54 @@
+use clap::Parser;
+use std::fs;
+use tectonic::{config::PersistentConfig, ctry, errors::Result, tt_error, tt_note};
+use tectonic_docmodel::{validation::validate_toml, workspace::Workspace};
+use tectonic_status_base::StatusBackend;
+
+use crate::v2cli::{CommandCustomizations, TectonicCommand};
+
+/// `validate`: Check the workspace's `Tectonic.toml` file for problems
+#[derive(Debug, Eq, PartialEq, Parser)]
+pub struct ValidateCommand {}
+
+impl TectonicCommand for ValidateCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let toml_path = Workspace::locate_toml_from_environment()?;
+        let toml_text = ctry!(
+            fs::read_to_string(&toml_path);
+            "couldn\'t read `{}`", toml_path.display()
+        );
+        let root_dir = toml_path.parent().unwrap();
+
+        let issues = validate_toml(&toml_text, root_dir);
+
+        if issues.is_empty() {
+            tt_note!(status, "no problems found in `{}`", toml_path.display());
+            return Ok(0);
+        }
+
+        for issue in &issues {
+            match issue.location {
+                Some(loc) => tt_error!(
+                    status,
+                    "{}:{}:{}: {}",
+                    toml_path.display(),
+                    loc.line,
+                    loc.column,
+                    issue.message
+                ),
+                None => tt_error!(status, "{}: {}", toml_path.display(), issue.message),
+            }
+        }
+
+        tt_error!(
+            status,
+            "found {} problem{} in `{}`",
+            issues.len(),
+            if issues.len() == 1 { "" } else { "s" },
+            toml_path.display()
+        );
+        Ok(1)
+    }
+}
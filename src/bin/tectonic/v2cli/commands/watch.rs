@@ -1,6 +1,23 @@
 use clap::Parser;
-use std::{env, path::PathBuf, sync::Arc};
-use tectonic::{config::PersistentConfig, errors::Result, tt_error};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    env,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+use tectonic::{
+    config::PersistentConfig,
+    ctry,
+    docmodel::{DocumentExt, DocumentSetupOptions},
+    driver::ProcessingSessionBuilder,
+    errors::{Result, SyncError},
+    tt_error, tt_note, tt_warning,
+};
+use tectonic_bridge_core::{SecuritySettings, SecurityStance};
+use tectonic_bundles::Bundle;
+use tectonic_docmodel::workspace::Workspace;
 use tectonic_status_base::StatusBackend;
 use tokio::runtime;
 use watchexec::command::Program;
@@ -31,16 +48,288 @@ fn get_trimmed_exe_name() -> PathBuf {
     exe_name
 }
 
+/// How long to wait for further changes after one is detected, so that
+/// editors that save files in several steps only trigger one rebuild.
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Normalize a path so that it can be compared to the paths in file-change
+/// events.
+///
+/// Event paths are based on the watched directories, which we canonicalize.
+/// The file itself may not exist, e.g. if it has just been deleted, so we
+/// can't canonicalize the full path.
+fn normalize_path(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => match dir.canonicalize() {
+            Ok(dir) => dir.join(name),
+            Err(_) => path.to_owned(),
+        },
+        _ => path.to_owned(),
+    }
+}
+
+/// The state of an in-process watch session.
+///
+/// This is also used by the `serve` command.
+pub(crate) struct InProcessWatch {
+    setup_options: DocumentSetupOptions,
+    format_cache_path: PathBuf,
+    toml_path: PathBuf,
+    workspace: Option<Workspace>,
+
+    /// If set, only this output is built.
+    only_output: Option<String>,
+
+    /// The bundle used by the last build, kept open so that subsequent
+    /// builds don't need to set it up again.
+    bundle: Option<Box<dyn Bundle>>,
+
+    /// The files read from the filesystem by the recent builds.
+    inputs: HashSet<PathBuf>,
+
+    /// The directories that we're currently asking to be notified about.
+    watched_dirs: HashSet<PathBuf>,
+}
+
+impl InProcessWatch {
+    pub(crate) fn new(config: &PersistentConfig) -> Result<Self> {
+        let toml_path = normalize_path(&Workspace::locate_toml_from_environment()?);
+        let workspace = Workspace::open_from_environment()?;
+
+        // Like `build`, default to allowing insecure features.
+        let mut setup_options = DocumentSetupOptions::new_with_security(SecuritySettings::new(
+            SecurityStance::MaybeAllowInsecures,
+        ));
+        setup_options.bundle_mirrors(config.bundle_mirrors());
+
+        Ok(InProcessWatch {
+            setup_options,
+            format_cache_path: config.format_cache_path()?,
+            toml_path,
+            workspace: Some(workspace),
+            only_output: None,
+            bundle: None,
+            inputs: HashSet::new(),
+            watched_dirs: HashSet::new(),
+        })
+    }
+
+    /// Only build the named output, rather than all of them.
+    pub(crate) fn only_output<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.only_output = Some(name.to_string());
+        self
+    }
+
+    /// Reload the workspace after its `Tectonic.toml` file has changed.
+    fn reload_workspace(&mut self, status: &mut dyn StatusBackend) {
+        // The bundle location might have changed.
+        self.bundle = None;
+
+        match Workspace::open_from_environment() {
+            Ok(ws) => {
+                self.workspace = Some(ws);
+            }
+
+            Err(e) => {
+                tt_error!(status, "failed to load `{}`", self.toml_path.display(); e);
+                self.workspace = None;
+            }
+        }
+    }
+
+    /// Build all of the document's outputs, recording the files that they
+    /// read. Returns whether all of the builds succeeded.
+    fn build_all(&mut self, status: &mut dyn StatusBackend) -> bool {
+        let ws = match self.workspace {
+            Some(ref ws) => ws,
+            None => return false,
+        };
+
+        let doc = ws.first_document();
+        let setup_options = &self.setup_options;
+        let format_cache_path = &self.format_cache_path;
+        let bundle_slot = &mut self.bundle;
+        let mut inputs = HashSet::new();
+        let mut success = true;
+
+        for output_name in doc.output_names() {
+            if let Some(ref only) = self.only_output {
+                if only != output_name {
+                    continue;
+                }
+            }
+
+            let bundle = match bundle_slot.take() {
+                Some(b) => b,
+                None => match doc.bundle(setup_options, status) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        status.report_error(&SyncError::new(e).into());
+                        success = false;
+                        break;
+                    }
+                },
+            };
+
+            let mut run = |mut builder: ProcessingSessionBuilder,
+                           status: &mut dyn StatusBackend|
+             -> Result<()> {
+                builder.format_cache_path(format_cache_path);
+
+                let mut sess = builder.create(status)?;
+                let result = crate::compile::run_session_and_report(&mut sess, status);
+
+                // Even if the build failed, the files that it read are worth
+                // watching, and the bundle is worth keeping.
+                inputs.extend(
+                    sess.filesystem_input_paths()
+                        .iter()
+                        .map(|p| normalize_path(p)),
+                );
+                *bundle_slot = Some(sess.into_bundle());
+                result
+            };
+
+            if let Err(e) =
+                doc.build_with_bundle(output_name, setup_options, bundle, status, &mut run)
+            {
+                status.report_error(&SyncError::new(e).into());
+                success = false;
+            }
+        }
+
+        // If a build failed, it might not have gotten as far as reading all of
+        // its inputs, so keep watching the ones that we already knew about.
+        if success {
+            self.inputs = inputs;
+        } else {
+            self.inputs.extend(inputs);
+        }
+
+        self.inputs.insert(self.toml_path.clone());
+        success
+    }
+
+    /// Update the set of watched directories to match the current inputs.
+    fn update_watches(&mut self, watcher: &mut dyn Watcher, status: &mut dyn StatusBackend) {
+        let dirs: HashSet<PathBuf> = self
+            .inputs
+            .iter()
+            .filter_map(|p| p.parent().map(|d| d.to_owned()))
+            .collect();
+
+        for dir in self.watched_dirs.difference(&dirs) {
+            // This may fail if the directory has been deleted, which is fine.
+            let _ = watcher.unwatch(dir);
+        }
+
+        for dir in dirs.difference(&self.watched_dirs) {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                tt_warning!(status, "unable to watch directory `{}` for changes", dir.display(); e.into());
+            }
+        }
+
+        self.watched_dirs = dirs;
+    }
+
+    /// Returns whether a file-change event affects the build, and whether it
+    /// affects the `Tectonic.toml` file specifically.
+    fn classify_event(&self, event: &notify::Event) -> (bool, bool) {
+        if let EventKind::Access(_) = event.kind {
+            return (false, false);
+        }
+
+        let relevant = event.paths.iter().any(|p| self.inputs.contains(p));
+        let toml = event.paths.iter().any(|p| *p == self.toml_path);
+        (relevant, toml)
+    }
+
+    /// Build and rebuild until the process is terminated.
+    ///
+    /// The *after_build* callback is called after each round of builds, with
+    /// an argument indicating whether they all succeeded.
+    pub(crate) fn run(
+        mut self,
+        status: &mut dyn StatusBackend,
+        after_build: &mut dyn FnMut(bool, &mut dyn StatusBackend),
+    ) -> Result<i32> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = ctry!(notify::recommended_watcher(tx); "failed to set up file watching");
+
+        loop {
+            let success = self.build_all(status);
+
+            if success {
+                tt_note!(status, "build succeeded");
+            } else {
+                tt_error!(status, "build failed");
+            }
+
+            after_build(success, status);
+
+            self.update_watches(&mut watcher, status);
+
+            // Discard the events caused by the build itself, such as outputs
+            // written next to the inputs or inputs regenerated by a
+            // `pre_build` hook. Otherwise, each build would trigger another
+            // one, forever. The events may be delivered a little late, so
+            // wait for things to settle down.
+            while rx.recv_timeout(DEBOUNCE_INTERVAL).is_ok() {}
+
+            tt_note!(
+                status,
+                "waiting for changes to {} input files",
+                self.inputs.len()
+            );
+
+            // Wait for a change that matters, then for things to settle down.
+
+            let mut toml_changed = false;
+
+            loop {
+                let event = match rx.recv() {
+                    Ok(Ok(event)) => event,
+                    Ok(Err(e)) => {
+                        tt_warning!(status, "error while watching for changes"; e.into());
+                        continue;
+                    }
+                    Err(_) => return Ok(0),
+                };
+
+                let (relevant, toml) = self.classify_event(&event);
+                toml_changed |= toml;
+
+                if relevant {
+                    break;
+                }
+            }
+
+            while let Ok(event) = rx.recv_timeout(DEBOUNCE_INTERVAL) {
+                if let Ok(event) = event {
+                    toml_changed |= self.classify_event(&event).1;
+                }
+            }
+
+            if toml_changed {
+                tt_note!(status, "`{}` changed; reloading", self.toml_path.display());
+                self.reload_workspace(status);
+            }
+        }
+    }
+}
+
 /// `watch`: Watch input files and execute commands on change
 #[derive(Debug, Eq, PartialEq, Parser)]
 pub struct WatchCommand {
-    /// Tectonic commands to execute on build [default: build]
+    /// Tectonic commands to execute on build, in separate processes [default: build in-process]
     #[arg(long = "exec", short = 'x')]
     execute: Vec<String>,
 }
 
 impl WatchCommand {
-    async fn execute_inner(self, status: &mut dyn StatusBackend) -> Result<i32> {
+    /// Run the `--exec` commands with watchexec whenever anything in the
+    /// current directory changes.
+    async fn execute_external(self, status: &mut dyn StatusBackend) -> Result<i32> {
         let exe_name = get_trimmed_exe_name()
             .into_os_string()
             .into_string()
@@ -67,17 +356,6 @@ impl WatchCommand {
             }
         }
 
-        if cmds.is_empty() {
-            let cmd = Command {
-                program: Program::Exec {
-                    prog: exe_name.into(),
-                    args: vec!["-X".to_string(), "build".to_string()],
-                },
-                options: Default::default(),
-            };
-            cmds.push((Id::default(), Arc::new(cmd)));
-        }
-
         let current_dir = env::current_dir()?;
 
         let filter = GlobsetFilterer::new(
@@ -180,11 +458,15 @@ impl WatchCommand {
 impl TectonicCommand for WatchCommand {
     fn customize(&self, _cc: &mut CommandCustomizations) {}
 
-    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        if self.execute.iter().all(|x| x.trim().is_empty()) {
+            return InProcessWatch::new(&config)?.run(status, &mut |_, _| {});
+        }
+
         let rt = runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(self.execute_inner(status))
+        rt.block_on(self.execute_external(status))
     }
 }
@@ -13,14 +13,17 @@ use tectonic::{
     tt_note,
 };
 use tectonic_errors::prelude::anyhow;
-use tectonic_status_base::plain::PlainStatusBackend;
+use tectonic_status_base::{json::JsonStatusBackend, plain::PlainStatusBackend};
 
 use self::commands::{
     build::BuildCommand,
     bundle::BundleCommand,
+    cache::CacheCommand,
     dump::DumpCommand,
     new::{InitCommand, NewCommand},
+    serve::ServeCommand,
     show::ShowCommand,
+    validate::ValidateCommand,
     watch::WatchCommand,
 };
 
@@ -43,6 +46,10 @@ struct V2CliOptions {
     #[arg(long = "color", name = "when", default_value = "auto")]
     cli_color: crate::CliColor,
 
+    /// How to format status messages
+    #[arg(long = "status-format", value_name = "format", default_value = "human")]
+    status_format: crate::StatusFormat,
+
     /// Use this URL to find resource files instead of the default
     // TODO add URL validation
     #[arg(
@@ -87,6 +94,14 @@ pub fn v2_main(effective_args: &[OsString]) {
         }
     };
 
+    match config.geturl_config() {
+        Ok(c) => tectonic_geturl::config::set_default_config(c),
+        Err(ref e) => {
+            e.dump_uncolorized();
+            process::exit(1);
+        }
+    }
+
     // Parse args -- this will exit if there are problems.
 
     let args = V2CliOptions::parse_from(effective_args);
@@ -99,11 +114,14 @@ pub fn v2_main(effective_args: &[OsString]) {
     match &args.command {
         Commands::Build(o) => o.customize(&mut customizations),
         Commands::Bundle(o) => o.customize(&mut customizations),
+        Commands::Cache(o) => o.customize(&mut customizations),
         Commands::Compile(_) => {} // avoid namespacing/etc issues
         Commands::Dump(o) => o.customize(&mut customizations),
         Commands::New(o) => o.customize(&mut customizations),
         Commands::Init(o) => o.customize(&mut customizations),
+        Commands::Serve(o) => o.customize(&mut customizations),
         Commands::Show(o) => o.customize(&mut customizations),
+        Commands::Validate(o) => o.customize(&mut customizations),
         Commands::Watch(o) => o.customize(&mut customizations),
         Commands::External(_) => {}
     }
@@ -115,7 +133,10 @@ pub fn v2_main(effective_args: &[OsString]) {
         args.chatter_level
     };
 
-    let mut status = if args.cli_color.should_enable() {
+    // JSON messages always go to stderr, so there's nothing to customize.
+    let mut status = if let crate::StatusFormat::Json = args.status_format {
+        Box::new(JsonStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
+    } else if args.cli_color.should_enable() {
         let mut sb = TermcolorStatusBackend::new(chatter_level);
         sb.always_stderr(customizations.always_stderr);
         Box::new(sb) as Box<dyn StatusBackend>
@@ -137,11 +158,14 @@ pub fn v2_main(effective_args: &[OsString]) {
     let r = match args.command {
         Commands::Build(o) => o.execute(config, &mut *status),
         Commands::Bundle(o) => o.execute(config, &mut *status),
+        Commands::Cache(o) => o.execute(config, &mut *status),
         Commands::Compile(o) => o.execute(config, &mut *status),
         Commands::Dump(o) => o.execute(config, &mut *status),
         Commands::New(o) => o.execute(config, &mut *status),
         Commands::Init(o) => o.execute(config, &mut *status),
+        Commands::Serve(o) => o.execute(config, &mut *status),
         Commands::Show(o) => o.execute(config, &mut *status),
+        Commands::Validate(o) => o.execute(config, &mut *status),
         Commands::Watch(o) => o.execute(config, &mut *status),
         Commands::External(all_args) => do_external(all_args),
     };
@@ -171,6 +195,10 @@ enum Commands {
     /// Commands relating to this document’s TeX file bundle
     Bundle(BundleCommand),
 
+    #[command(name = "cache")]
+    /// Commands relating to the local cache of bundle files and formats
+    Cache(CacheCommand),
+
     #[command(name = "compile")]
     /// Run a standalone (La)TeX compilation
     Compile(crate::compile::CompileOptions),
@@ -187,10 +215,18 @@ enum Commands {
     /// Initializes a new document in the current directory
     Init(InitCommand),
 
+    #[command(name = "serve")]
+    /// Build the document, serve it locally, and reload it on changes
+    Serve(ServeCommand),
+
     #[command(name = "show")]
     /// Display various useful pieces of information
     Show(ShowCommand),
 
+    #[command(name = "validate")]
+    /// Check the document's `Tectonic.toml` file for problems
+    Validate(ValidateCommand),
+
     #[command(name = "watch")]
     /// Watch input files and execute commands on change
     Watch(WatchCommand),
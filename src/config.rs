@@ -12,12 +12,21 @@
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
+    env,
     path::{Path, PathBuf},
     sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 use tectonic_bundles::{
-    cache::Cache, dir::DirBundle, itar::IndexedTarBackend, zip::ZipBundle, Bundle,
+    cache::Cache,
+    cas::{self, ContentAddressedBackend},
+    dir::DirBundle,
+    itar::{IndexedTarBackend, IndexedTarBundle},
+    zip::ZipBundle,
+    Bundle,
 };
+use tectonic_geturl::{config::HostHeader, GetUrlConfig};
 use tectonic_io_base::app_dirs;
 use url::Url;
 
@@ -66,11 +75,114 @@ pub fn maybe_return_test_bundle(web_bundle: Option<String>) -> Result<Box<dyn Bu
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PersistentConfig {
     default_bundles: Vec<BundleInfo>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "CacheConfig::is_empty")
+    )]
+    cache: CacheConfig,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "NetworkConfig::is_empty")
+    )]
+    network: NetworkConfig,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct BundleInfo {
     url: String,
+
+    /// Other URLs serving the same bundle, tried in turn if it can't be
+    /// reached.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    mirrors: Vec<String>,
+}
+
+/// Limits on the size of the local cache, used by `tectonic -X cache gc`.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Default)]
+pub struct CacheConfig {
+    /// Remove cached bundles and formats that haven't been used for this many
+    /// days.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    max_age_days: Option<u64>,
+
+    /// Keep the cache no larger than this, as a string like `"2 GiB"`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    max_size: Option<String>,
+}
+
+impl CacheConfig {
+    fn is_empty(&self) -> bool {
+        self.max_age_days.is_none() && self.max_size.is_none()
+    }
+}
+
+/// Settings for the HTTP client used to download bundles.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Default)]
+pub struct NetworkConfig {
+    /// The URL of a proxy to use for all requests. Overridden by the
+    /// `TECTONIC_PROXY` environment variable.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    proxy: Option<String>,
+
+    /// A file of extra PEM-encoded CA certificates to trust. Overridden by the
+    /// `TECTONIC_CA_FILE` environment variable.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    ca_file: Option<PathBuf>,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    connect_timeout_secs: Option<u64>,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    read_timeout_secs: Option<u64>,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    max_attempts: Option<usize>,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    retry_backoff_ms: Option<u64>,
+
+    /// Extra headers to send to particular hosts.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    headers: Vec<HeaderConfig>,
+}
+
+impl NetworkConfig {
+    fn is_empty(&self) -> bool {
+        self.proxy.is_none()
+            && self.ca_file.is_none()
+            && self.connect_timeout_secs.is_none()
+            && self.read_timeout_secs.is_none()
+            && self.max_attempts.is_none()
+            && self.retry_backoff_ms.is_none()
+            && self.headers.is_empty()
+    }
+}
+
+/// A header to send with every request to a particular host.
+///
+/// Exactly one of `value` and `value_env` should be given. The latter names an
+/// environment variable holding the value, so that secrets such as access
+/// tokens needn't be stored in the configuration file.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct HeaderConfig {
+    host: String,
+    name: String,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    value: Option<String>,
+
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    value_env: Option<String>,
 }
 
 impl PersistentConfig {
@@ -145,13 +257,35 @@ impl PersistentConfig {
             return Ok(test_bundle);
         }
 
+        // Local bundles don't need to go through the cache.
+        if let Some(file_path) = Url::parse(url)
+            .ok()
+            .filter(|u| u.scheme() == "file")
+            .and_then(|u| u.to_file_path().ok())
+        {
+            return self.make_local_file_provider(file_path, status);
+        }
+
         let mut cache = if let Some(root) = custom_cache_root {
             Cache::get_for_custom_directory(root)
         } else {
             Cache::get_user_default()?
         };
 
-        let bundle = cache.open::<IndexedTarBackend>(url, only_cached, status)?;
+        let mirrors = self.mirrors_for(url);
+
+        if cas::is_content_addressed_url(url) {
+            let bundle = cache.open_with_mirrors::<ContentAddressedBackend>(
+                url,
+                mirrors,
+                only_cached,
+                status,
+            )?;
+            return Ok(Box::new(bundle) as _);
+        }
+
+        let bundle =
+            cache.open_with_mirrors::<IndexedTarBackend>(url, mirrors, only_cached, status)?;
         Ok(Box::new(bundle) as _)
     }
 
@@ -162,6 +296,8 @@ impl PersistentConfig {
     ) -> Result<Box<dyn Bundle>> {
         let bundle: Box<dyn Bundle> = if file_path.is_dir() {
             Box::new(DirBundle::new(file_path))
+        } else if IndexedTarBundle::is_indexed_tar(&file_path) {
+            Box::new(IndexedTarBundle::open(file_path)?)
         } else {
             Box::new(ZipBundle::open(file_path)?)
         };
@@ -172,6 +308,27 @@ impl PersistentConfig {
         &self.default_bundles[0].url
     }
 
+    /// Get the configured mirrors of web-based bundles, keyed by bundle URL.
+    ///
+    /// Each list gives other URLs serving the same bundle, in order of
+    /// preference.
+    pub fn bundle_mirrors(&self) -> HashMap<String, Vec<String>> {
+        self.default_bundles
+            .iter()
+            .filter(|b| !b.mirrors.is_empty())
+            .map(|b| (b.url.clone(), b.mirrors.clone()))
+            .collect()
+    }
+
+    /// Get the configured mirrors of the bundle at *url*.
+    fn mirrors_for(&self, url: &str) -> &[String] {
+        self.default_bundles
+            .iter()
+            .find(|b| b.url == url)
+            .map(|b| b.mirrors.as_slice())
+            .unwrap_or_default()
+    }
+
     pub fn default_bundle(
         &self,
         only_cached: bool,
@@ -204,6 +361,110 @@ impl PersistentConfig {
         Ok(Box::new(bundle) as _)
     }
 
+    /// Get the directory containing the user's templates for new workspaces.
+    ///
+    /// Each subdirectory is a template that can be selected by name with
+    /// `tectonic -X new --template`. The directory need not exist.
+    pub fn user_templates_dir(&self) -> Result<PathBuf> {
+        if is_config_test_mode_activated() {
+            Ok(crate::test_util::test_path(&["templates"]))
+        } else {
+            let mut path = app_dirs::get_user_config()?;
+            path.push("templates");
+            Ok(path)
+        }
+    }
+
+    /// Get the directory in which `tectonic -X serve` looks for a copy of
+    /// PDF.js, which its PDF viewer uses. The directory need not exist.
+    pub fn user_pdfjs_dir(&self) -> Result<PathBuf> {
+        if is_config_test_mode_activated() {
+            Ok(crate::test_util::test_path(&["pdfjs"]))
+        } else {
+            let mut path = app_dirs::get_user_config()?;
+            path.push("pdfjs");
+            Ok(path)
+        }
+    }
+
+    /// Get the configured maximum age of unused files in the cache, if any.
+    pub fn cache_max_age(&self) -> Option<Duration> {
+        self.cache
+            .max_age_days
+            .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+    }
+
+    /// Get the configured maximum size of the cache in bytes, if any.
+    pub fn cache_max_size(&self) -> Result<Option<u64>> {
+        match &self.cache.max_size {
+            Some(text) => Ok(Some(parse_size(text)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the settings for the HTTP client used to download bundles.
+    ///
+    /// These come from the `[network]` section of the configuration file, but
+    /// the proxy and CA file can be overridden with the `TECTONIC_PROXY` and
+    /// `TECTONIC_CA_FILE` environment variables. A header whose value is taken
+    /// from an environment variable that isn't set is skipped.
+    pub fn geturl_config(&self) -> Result<GetUrlConfig> {
+        let network = &self.network;
+        let defaults = GetUrlConfig::default();
+
+        let max_attempts = match network.max_attempts {
+            Some(0) => {
+                return Err(ErrorKind::Msg(
+                    "the `network.max_attempts` setting must be at least 1".to_owned(),
+                )
+                .into())
+            }
+            Some(n) => n,
+            None => defaults.max_attempts,
+        };
+
+        let mut headers = Vec::new();
+
+        for h in &network.headers {
+            let value = match (&h.value, &h.value_env) {
+                (Some(v), None) => v.clone(),
+                (None, Some(var)) => match env::var(var) {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                },
+                _ => {
+                    return Err(ErrorKind::Msg(format!(
+                        "the `{}` header for host `{}` must have exactly one of \
+                         `value` and `value_env`",
+                        h.name, h.host
+                    ))
+                    .into())
+                }
+            };
+
+            headers.push(HostHeader {
+                host: h.host.clone(),
+                name: h.name.clone(),
+                value,
+            });
+        }
+
+        Ok(GetUrlConfig {
+            proxy: nonempty_env_var("TECTONIC_PROXY").or_else(|| network.proxy.clone()),
+            ca_file: nonempty_env_var("TECTONIC_CA_FILE")
+                .map(PathBuf::from)
+                .or_else(|| network.ca_file.clone()),
+            headers,
+            connect_timeout: network.connect_timeout_secs.map(Duration::from_secs),
+            read_timeout: network.read_timeout_secs.map(Duration::from_secs),
+            max_attempts,
+            retry_backoff: network
+                .retry_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.retry_backoff),
+        })
+    }
+
     pub fn format_cache_path(&self) -> Result<PathBuf> {
         if is_config_test_mode_activated() {
             Ok(crate::test_util::test_path(&[]))
@@ -218,7 +479,27 @@ impl Default for PersistentConfig {
         let url = tectonic_bundles::get_fallback_bundle_url(tectonic_engine_xetex::FORMAT_SERIAL);
 
         PersistentConfig {
-            default_bundles: vec![BundleInfo { url }],
+            default_bundles: vec![BundleInfo {
+                url,
+                mirrors: Vec::new(),
+            }],
+            cache: CacheConfig::default(),
+            network: NetworkConfig::default(),
         }
     }
 }
+
+/// Get the value of an environment variable, treating an empty value like an
+/// unset one.
+fn nonempty_env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Parse a size in bytes, with an optional unit, such as `"500 MB"` or
+/// `"2 GiB"`.
+pub fn parse_size(text: &str) -> Result<u64> {
+    match byte_unit::Byte::parse_str(text, true) {
+        Ok(b) => Ok(b.as_u64()),
+        Err(e) => Err(ErrorKind::Msg(format!("invalid size \"{text}\": {e}")).into()),
+    }
+}
@@ -8,16 +8,28 @@
 //! provided by the processing engines.
 
 use std::{
+    collections::HashMap,
     fmt::Write as FmtWrite,
-    fs, io,
+    fs,
+    io::{self, Read},
     path::{Path, PathBuf},
+    process::Command,
 };
 use tectonic_bridge_core::SecuritySettings;
 use tectonic_bundles::{
-    cache::Cache, dir::DirBundle, itar::IndexedTarBackend, zip::ZipBundle, Bundle,
+    cache::{Cache, CacheBackend},
+    cas::{self, ContentAddressedBackend},
+    dir::DirBundle,
+    itar::{IndexedTarBackend, IndexedTarBundle},
+    overlay::OverlayBundle,
+    zip::ZipBundle,
+    Bundle,
 };
 use tectonic_docmodel::{
-    document::{BuildTargetType, Document, InputFile},
+    document::{BuildTargetType, Document, InputFile, OutputProfile},
+    lock::{BundleLock, LockedBundle},
+    metadata::StandardMetadata,
+    template::{WorkspaceTemplate, BUILTIN_TEMPLATES},
     workspace::{Workspace, WorkspaceCreator},
 };
 use tectonic_geturl::{DefaultBackend, GetUrlBackend};
@@ -26,12 +38,145 @@ use url::Url;
 use crate::{
     config, ctry,
     driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
+    errmsg,
     errors::{ErrorKind, Result},
     status::StatusBackend,
-    tt_note,
+    tt_note, tt_warning,
     unstable_opts::UnstableOptions,
 };
 
+/// Open the bundle at *loc*, which may be a URL or a filesystem path.
+/// Relative paths are resolved relative to *base_dir*. Network-based bundles
+/// may also be served by *mirrors*. If *refresh* is true, network-based
+/// bundles are resolved afresh rather than from the cache.
+fn open_bundle_loc(
+    loc: &str,
+    mirrors: &[String],
+    base_dir: &Path,
+    setup_options: &DocumentSetupOptions,
+    refresh: bool,
+    status: &mut dyn StatusBackend,
+) -> Result<Box<dyn Bundle>> {
+    fn bundle_from_path(p: PathBuf) -> Result<Box<dyn Bundle>> {
+        if p.is_dir() {
+            Ok(Box::new(DirBundle::new(p)))
+        } else if IndexedTarBundle::is_indexed_tar(&p) {
+            Ok(Box::new(IndexedTarBundle::open(p)?))
+        } else {
+            Ok(Box::new(ZipBundle::open(p)?))
+        }
+    }
+
+    fn bundle_from_cache<CB: CacheBackend + 'static>(
+        loc: &str,
+        mirrors: &[String],
+        setup_options: &DocumentSetupOptions,
+        refresh: bool,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Box<dyn Bundle>> {
+        let mut cache = Cache::get_user_default()?;
+        let bundle = if refresh {
+            cache.open_refreshed_with_mirrors::<CB>(loc, mirrors, status)?
+        } else {
+            cache.open_with_mirrors::<CB>(loc, mirrors, setup_options.only_cached, status)?
+        };
+        Ok(Box::new(bundle))
+    }
+
+    // Single-letter "schemes" are Windows drive letters, not URLs.
+    match Url::parse(loc).ok().filter(|u| u.scheme().len() > 1) {
+        Some(url) if url.scheme() != "file" => {
+            if cas::is_content_addressed_url(loc) {
+                bundle_from_cache::<ContentAddressedBackend>(
+                    loc,
+                    mirrors,
+                    setup_options,
+                    refresh,
+                    status,
+                )
+            } else {
+                bundle_from_cache::<IndexedTarBackend>(loc, mirrors, setup_options, refresh, status)
+            }
+        }
+
+        Some(url) => {
+            let file_path = url.to_file_path().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
+            })?;
+            bundle_from_path(file_path)
+        }
+
+        None => bundle_from_path(base_dir.join(loc)),
+    }
+}
+
+/// Summarize the current state of a bundle for the lock file.
+fn lock_entry(
+    loc: &str,
+    bundle: &mut Box<dyn Bundle>,
+    status: &mut dyn StatusBackend,
+) -> Result<LockedBundle> {
+    Ok(LockedBundle {
+        location: loc.to_owned(),
+        resolved_url: bundle.cache_info().map(|info| info.resolved_url),
+        digest: bundle.get_digest(status)?.to_string(),
+    })
+}
+
+/// Deal with a bundle whose contents don’t match the lock file.
+///
+/// If the lock file records a resolved URL that still provides the locked
+/// contents, the bundle at that URL is returned. Otherwise, this is an error
+/// if the lock must be honored, and a warning if not.
+fn use_pinned_bundle(
+    current: LockedBundle,
+    locked: &LockedBundle,
+    bundle: Box<dyn Bundle>,
+    setup_options: &DocumentSetupOptions,
+    status: &mut dyn StatusBackend,
+) -> Result<Box<dyn Bundle>> {
+    if let Some(url) = locked.resolved_url.as_ref() {
+        if current.resolved_url.as_ref() != Some(url) {
+            let pinned = open_bundle_loc(url, &[], Path::new(""), setup_options, false, status)
+                .and_then(|mut b| Ok((lock_entry(url, &mut b, status)?, b)));
+
+            if let Ok((entry, pinned)) = pinned {
+                if entry.digest == locked.digest {
+                    tt_note!(
+                        status,
+                        "bundle `{}` has changed; using the version pinned in the lock file",
+                        locked.location
+                    );
+                    return Ok(pinned);
+                }
+            }
+        }
+    }
+
+    if setup_options.locked {
+        return Err(errmsg!(
+            "the contents of bundle `{}` (digest {}) do not match the lock file (digest {}); \
+             run `tectonic -X bundle update` to accept the change",
+            locked.location,
+            current.digest,
+            locked.digest
+        ));
+    }
+
+    tt_warning!(
+        status,
+        "the contents of bundle `{}` (digest {}) do not match the lock file (digest {})",
+        locked.location,
+        current.digest,
+        locked.digest
+    );
+    tt_note!(
+        status,
+        "run `tectonic -X bundle update` to accept the change, or use `--locked` to refuse it"
+    );
+    Ok(bundle)
+}
+
 /// Options for setting up [`Document`] instances with the driver
 #[derive(Clone, Debug, Default)]
 pub struct DocumentSetupOptions {
@@ -44,6 +189,16 @@ pub struct DocumentSetupOptions {
 
     /// Ensure a deterministic build environment.
     deterministic_mode: bool,
+
+    /// Refuse to use bundles that don't match the document's lock file.
+    locked: bool,
+
+    /// Never write the document's lock file.
+    read_only_lock: bool,
+
+    /// Mirrors of web-based bundles, keyed by bundle URL, in addition to those
+    /// given in the document.
+    bundle_mirrors: HashMap<String, Vec<String>>,
 }
 
 impl DocumentSetupOptions {
@@ -53,6 +208,9 @@ impl DocumentSetupOptions {
         DocumentSetupOptions {
             only_cached: false,
             deterministic_mode: false,
+            locked: false,
+            read_only_lock: false,
+            bundle_mirrors: HashMap::new(),
             security,
         }
     }
@@ -72,19 +230,81 @@ impl DocumentSetupOptions {
         self.deterministic_mode = s;
         self
     }
+
+    /// Specify whether the document’s bundles must match its `Tectonic.lock`
+    /// file exactly.
+    ///
+    /// If true, opening the document’s bundle fails if the lock file is
+    /// missing or out of date, or if a bundle’s contents don’t match the
+    /// locked digest. Otherwise, a missing or incomplete lock file is written
+    /// out, and mismatches only cause warnings.
+    pub fn locked(&mut self, s: bool) -> &mut Self {
+        self.locked = s;
+        self
+    }
+
+    /// Specify whether the document’s `Tectonic.lock` file may be written.
+    ///
+    /// By default, a missing or incomplete lock file is written out when the
+    /// document’s bundles are opened. Commands that only inspect the bundles
+    /// should set this to true, so that they don’t modify the workspace.
+    pub fn read_only_lock(&mut self, s: bool) -> &mut Self {
+        self.read_only_lock = s;
+        self
+    }
+
+    /// Specify mirrors of web-based bundles, keyed by bundle URL, such as
+    /// those from the user's configuration file.
+    ///
+    /// These are tried after any mirrors given in the document itself.
+    pub fn bundle_mirrors(&mut self, m: HashMap<String, Vec<String>>) -> &mut Self {
+        self.bundle_mirrors = m;
+        self
+    }
+
+    /// Get all of the mirrors of the bundle at *loc*.
+    fn mirrors_for(&self, doc: &Document, loc: &str) -> Vec<String> {
+        let configured = self.bundle_mirrors.get(loc).map(|m| m.as_slice());
+        doc.mirrors_for(loc)
+            .iter()
+            .chain(configured.unwrap_or_default())
+            .cloned()
+            .collect()
+    }
 }
 
 pub trait DocumentExt {
     /// Get the bundle used by this document.
     ///
     /// This parses [`Document::bundle_loc`] and turns it into the appropriate
-    /// bundle backend.
+    /// bundle backend. If [`Document::overlay_bundle_locs`] is non-empty, those
+    /// bundles are opened too and stacked on top of it with an
+    /// [`OverlayBundle`].
+    ///
+    /// The bundles are checked against the document’s `Tectonic.lock` file,
+    /// which is created if it doesn’t exist, unless
+    /// [`DocumentSetupOptions::read_only_lock`] is set. If a bundle location now resolves
+    /// to different contents than the lock file records, the pinned URL from
+    /// the lock file is used instead, if possible. See
+    /// [`DocumentSetupOptions::locked`] for what happens otherwise.
     fn bundle(
         &self,
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
     ) -> Result<Box<dyn Bundle>>;
 
+    /// Re-resolve this document’s bundles and rewrite its `Tectonic.lock`.
+    ///
+    /// Network-based bundles are contacted afresh, ignoring any cached
+    /// information about what their URLs resolve to. Of the *setup_options*,
+    /// only the bundle mirrors apply. Returns the previous
+    /// lock, if there was one, and the new one.
+    fn update_bundle_lock(
+        &self,
+        setup_options: &DocumentSetupOptions,
+        status: &mut dyn StatusBackend,
+    ) -> Result<(Option<BundleLock>, BundleLock)>;
+
     /// Set up a [`ProcessingSessionBuilder`] for one of the outputs.
     ///
     /// The *output_profile* argument gives the name of the document’s output profile to
@@ -95,6 +315,47 @@ pub trait DocumentExt {
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
     ) -> Result<ProcessingSessionBuilder>;
+
+    /// Set up a [`ProcessingSessionBuilder`] for one of the outputs, using an
+    /// already-opened bundle.
+    ///
+    /// This is like [`Self::setup_session`], but allows a bundle obtained
+    /// from [`Self::bundle`] to be reused across sessions.
+    fn setup_session_with_bundle(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        bundle: Box<dyn Bundle>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<ProcessingSessionBuilder>;
+
+    /// Build one of the outputs, running its pre- and post-build hooks.
+    ///
+    /// This sets up a session with [`Self::setup_session`] and hands it to the
+    /// *run* callback, which may customize and must execute it. The output
+    /// profile's `pre_build` commands are run beforehand and its `post_build`
+    /// commands afterwards, unless the callback fails. The hooks are only run
+    /// if the security settings allow it, just like shell-escape.
+    fn build(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        status: &mut dyn StatusBackend,
+        run: &mut dyn FnMut(ProcessingSessionBuilder, &mut dyn StatusBackend) -> Result<()>,
+    ) -> Result<()>;
+
+    /// Build one of the outputs using an already-opened bundle.
+    ///
+    /// This is like [`Self::build`], but sets up the session with
+    /// [`Self::setup_session_with_bundle`].
+    fn build_with_bundle(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        bundle: Box<dyn Bundle>,
+        status: &mut dyn StatusBackend,
+        run: &mut dyn FnMut(ProcessingSessionBuilder, &mut dyn StatusBackend) -> Result<()>,
+    ) -> Result<()>;
 }
 
 impl DocumentExt for Document {
@@ -103,41 +364,117 @@ impl DocumentExt for Document {
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
     ) -> Result<Box<dyn Bundle>> {
-        fn bundle_from_path(p: PathBuf) -> Result<Box<dyn Bundle>> {
-            if p.is_dir() {
-                Ok(Box::new(DirBundle::new(p)))
-            } else {
-                Ok(Box::new(ZipBundle::open(p)?))
-            }
+        let lock_path = self.lock_path();
+        let lock = BundleLock::read_from(&lock_path)?;
+        let mut new_lock = BundleLock::default();
+        let mut layers = Vec::new();
+
+        for (i, loc) in self.bundle_locs().enumerate() {
+            let mut bundle = match config::maybe_return_test_bundle(None) {
+                Ok(test_bundle) if i == self.overlay_bundle_locs.len() => test_bundle,
+                _ => open_bundle_loc(
+                    loc,
+                    &setup_options.mirrors_for(self, loc),
+                    self.src_dir(),
+                    setup_options,
+                    false,
+                    status,
+                )?,
+            };
+
+            let entry = match lock.as_ref().and_then(|l| l.get(loc)) {
+                Some(locked) => {
+                    let current = lock_entry(loc, &mut bundle, status)?;
+
+                    if current.digest != locked.digest {
+                        bundle = use_pinned_bundle(current, locked, bundle, setup_options, status)?;
+                    }
+
+                    locked.clone()
+                }
+
+                None => lock_entry(loc, &mut bundle, status)?,
+            };
+
+            new_lock.bundles.push(entry);
+            layers.push(bundle);
         }
 
-        if let Ok(test_bundle) = config::maybe_return_test_bundle(None) {
-            Ok(test_bundle)
-        } else if let Ok(url) = Url::parse(&self.bundle_loc) {
-            if url.scheme() != "file" {
-                let mut cache = Cache::get_user_default()?;
-                let bundle = cache.open::<IndexedTarBackend>(
-                    &self.bundle_loc,
-                    setup_options.only_cached,
+        if lock.as_ref() != Some(&new_lock) {
+            if setup_options.locked {
+                return Err(errmsg!(
+                    "`{}` is missing or out of date, and the bundles may not be \
+                     re-locked; run `tectonic -X bundle update` to update it",
+                    lock_path.display()
+                ));
+            }
+
+            if !setup_options.read_only_lock {
+                ctry!(new_lock.write_to(&lock_path); "failed to write `{}`", lock_path.display());
+                tt_note!(
                     status,
-                )?;
-                Ok(Box::new(bundle))
-            } else {
-                let file_path = url.to_file_path().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
-                })?;
-                bundle_from_path(file_path)
+                    "recorded the document’s bundles in `{}`",
+                    lock_path.display()
+                );
             }
+        }
+
+        if layers.len() == 1 {
+            Ok(layers.pop().unwrap())
         } else {
-            bundle_from_path(Path::new(&self.bundle_loc).to_owned())
+            Ok(Box::new(OverlayBundle::new(layers)))
         }
     }
 
+    fn update_bundle_lock(
+        &self,
+        setup_options: &DocumentSetupOptions,
+        status: &mut dyn StatusBackend,
+    ) -> Result<(Option<BundleLock>, BundleLock)> {
+        let lock_path = self.lock_path();
+        let old_lock = BundleLock::read_from(&lock_path)?;
+        let mut new_lock = BundleLock::default();
+
+        for (i, loc) in self.bundle_locs().enumerate() {
+            let mut bundle = match config::maybe_return_test_bundle(None) {
+                Ok(test_bundle) if i == self.overlay_bundle_locs.len() => test_bundle,
+                _ => open_bundle_loc(
+                    loc,
+                    &setup_options.mirrors_for(self, loc),
+                    self.src_dir(),
+                    setup_options,
+                    true,
+                    status,
+                )?,
+            };
+
+            new_lock.bundles.push(lock_entry(loc, &mut bundle, status)?);
+        }
+
+        ctry!(new_lock.write_to(&lock_path); "failed to write `{}`", lock_path.display());
+        Ok((old_lock, new_lock))
+    }
+
     fn setup_session(
         &self,
         output_profile: &str,
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
+    ) -> Result<ProcessingSessionBuilder> {
+        if setup_options.only_cached {
+            tt_note!(status, "using only cached resource files");
+        }
+
+        let bundle = self.bundle(setup_options, status)?;
+        self.setup_session_with_bundle(output_profile, setup_options, bundle, status)
+    }
+
+    fn setup_session_with_bundle(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        bundle: Box<dyn Bundle>,
+        _status: &mut dyn StatusBackend,
     ) -> Result<ProcessingSessionBuilder> {
         let profile = self.outputs.get(output_profile).ok_or_else(|| {
             ErrorKind::Msg(format!(
@@ -152,6 +489,10 @@ impl DocumentExt for Document {
 
         let mut input_buffer = String::new();
 
+        if profile.target_type == BuildTargetType::Pdf {
+            write_pdf_metadata_specials(&mut input_buffer, &self.standard_metadata)?;
+        }
+
         for input in &profile.inputs {
             match input {
                 InputFile::Inline(s) => {
@@ -178,6 +519,12 @@ impl DocumentExt for Document {
             .primary_input_buffer(input_buffer.as_bytes())
             .tex_input_name(output_profile);
 
+        if profile.target_type == BuildTargetType::Html {
+            for (name, value) in html_metadata_variables(&self.standard_metadata) {
+                sess_builder.html_template_variable(name, value);
+            }
+        }
+
         if profile.shell_escape {
             // For now, this is the only option we allow.
             if let Some(cwd) = &profile.shell_escape_cwd {
@@ -187,14 +534,9 @@ impl DocumentExt for Document {
             }
         }
 
-        if setup_options.only_cached {
-            tt_note!(status, "using only cached resource files");
-        }
-        sess_builder.bundle(self.bundle(setup_options, status)?);
+        sess_builder.bundle(bundle);
 
-        let mut tex_dir = self.src_dir().to_owned();
-        tex_dir.push("src");
-        sess_builder.filesystem_root(&tex_dir);
+        sess_builder.filesystem_root(self.tex_dir());
 
         let mut output_dir = self.build_dir().to_owned();
         output_dir.push(output_profile);
@@ -206,6 +548,342 @@ impl DocumentExt for Document {
 
         Ok(sess_builder)
     }
+
+    fn build(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        status: &mut dyn StatusBackend,
+        run: &mut dyn FnMut(ProcessingSessionBuilder, &mut dyn StatusBackend) -> Result<()>,
+    ) -> Result<()> {
+        let sess_builder = self.setup_session(output_profile, setup_options, status)?;
+        run_build(
+            self,
+            output_profile,
+            setup_options,
+            sess_builder,
+            status,
+            run,
+        )
+    }
+
+    fn build_with_bundle(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+        bundle: Box<dyn Bundle>,
+        status: &mut dyn StatusBackend,
+        run: &mut dyn FnMut(ProcessingSessionBuilder, &mut dyn StatusBackend) -> Result<()>,
+    ) -> Result<()> {
+        let sess_builder =
+            self.setup_session_with_bundle(output_profile, setup_options, bundle, status)?;
+        run_build(
+            self,
+            output_profile,
+            setup_options,
+            sess_builder,
+            status,
+            run,
+        )
+    }
+}
+
+/// The shared implementation of [`DocumentExt::build`] and
+/// [`DocumentExt::build_with_bundle`].
+fn run_build(
+    doc: &Document,
+    output_profile: &str,
+    setup_options: &DocumentSetupOptions,
+    sess_builder: ProcessingSessionBuilder,
+    status: &mut dyn StatusBackend,
+    run: &mut dyn FnMut(ProcessingSessionBuilder, &mut dyn StatusBackend) -> Result<()>,
+) -> Result<()> {
+    // The session setup has validated the profile name.
+    let profile = &doc.outputs[output_profile];
+
+    let hooks_allowed = setup_options.security.allow_build_hooks();
+
+    if !hooks_allowed && !(profile.pre_build.is_empty() && profile.post_build.is_empty()) {
+        tt_warning!(
+            status,
+            "not running the build hooks of output `{}` since insecure features are disabled",
+            output_profile
+        );
+    }
+
+    if hooks_allowed {
+        run_build_hooks(doc, profile, "pre-build", &profile.pre_build, status)?;
+    }
+
+    run(sess_builder, status)?;
+
+    if hooks_allowed {
+        run_build_hooks(doc, profile, "post-build", &profile.post_build, status)?;
+    }
+
+    Ok(())
+}
+
+/// Run a list of build-hook shell commands for an output.
+///
+/// The commands are run in order, in the document's source directory, with
+/// environment variables describing the output. The first failing command
+/// aborts the build.
+fn run_build_hooks(
+    doc: &Document,
+    profile: &OutputProfile,
+    stage: &str,
+    commands: &[String],
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    #[cfg(unix)]
+    const SHELL: &[&str] = &["sh", "-c"];
+
+    #[cfg(windows)]
+    const SHELL: &[&str] = &["cmd.exe", "/c"];
+
+    let target_type = match profile.target_type {
+        BuildTargetType::Html => "html",
+        BuildTargetType::Pdf => "pdf",
+    };
+
+    let output_dir = doc.build_dir().join(&profile.name);
+
+    for command in commands {
+        tt_note!(status, "running {} command: `{}`", stage, command);
+
+        let exit_status = ctry!(
+            Command::new(SHELL[0])
+                .args(&SHELL[1..])
+                .arg(command)
+                .current_dir(doc.src_dir())
+                .env("TECTONIC_DOCUMENT_NAME", &doc.name)
+                .env("TECTONIC_DOCUMENT_DIR", doc.src_dir())
+                .env("TECTONIC_OUTPUT_NAME", &profile.name)
+                .env("TECTONIC_OUTPUT_TYPE", target_type)
+                .env("TECTONIC_OUTPUT_DIR", &output_dir)
+                .env("TECTONIC_OUTPUT_PATH", doc.output_main_file(&profile.name))
+                .status();
+            "failed to launch the {} command `{}`", stage, command
+        );
+
+        if !exit_status.success() {
+            return Err(errmsg!(
+                "the {} command `{}` failed ({})",
+                stage,
+                command,
+                exit_status
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write TeX code that records the document metadata in the PDF output.
+///
+/// The metadata are passed to `xdvipdfmx` through its `pdf:docinfo`,
+/// `pdf:stream` and `pdf:put` specials, which fill in the document information
+/// dictionary, an XMP metadata packet, and the catalog’s `/Lang`. All strings
+/// are written as PDF hex strings, so that they need no escaping at either the
+/// TeX or the PDF level.
+///
+/// This code comes before the document’s own inputs, so in LaTeX documents the
+/// specials are deferred with `\AtBeginDocument`, to keep them out of the
+/// preamble.
+fn write_pdf_metadata_specials(buf: &mut String, md: &StandardMetadata) -> Result<()> {
+    let mut info = String::new();
+
+    if let Some(title) = &md.title {
+        write!(info, "/Title{}", pdf_text_string(title))?;
+    }
+
+    if !md.authors.is_empty() {
+        write!(info, "/Author{}", pdf_text_string(&md.authors.join(", ")))?;
+    }
+
+    if !md.keywords.is_empty() {
+        write!(
+            info,
+            "/Keywords{}",
+            pdf_text_string(&md.keywords.join(", "))
+        )?;
+    }
+
+    if let Some(license) = &md.license {
+        write!(info, "/License{}", pdf_text_string(license))?;
+    }
+
+    let mut specials = String::new();
+
+    if !info.is_empty() {
+        write!(specials, "\\special{{pdf:docinfo<<{info}>>}}")?;
+    }
+
+    if let Some(xmp) = xmp_packet(md) {
+        write!(
+            specials,
+            "\\special{{pdf:stream @tectonicxmp {} <</Type/Metadata/Subtype/XML>>}}\
+             \\special{{pdf:put @catalog<</Metadata @tectonicxmp>>}}",
+            pdf_hex_string(xmp.as_bytes())
+        )?;
+    }
+
+    if let Some(lang) = &md.language {
+        write!(
+            specials,
+            "\\special{{pdf:put @catalog<</Lang{}>>}}",
+            pdf_text_string(lang)
+        )?;
+    }
+
+    if !specials.is_empty() {
+        writeln!(buf, "\\def\\tectonicpdfmetadata{{{specials}}}")?;
+        writeln!(
+            buf,
+            "\\ifdefined\\AtBeginDocument\\AtBeginDocument{{\\tectonicpdfmetadata}}\
+             \\else\\tectonicpdfmetadata\\fi"
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Create an XMP metadata packet describing the document, using the Dublin
+/// Core schema. Returns None if there are no metadata to describe.
+fn xmp_packet(md: &StandardMetadata) -> Option<String> {
+    if md.is_empty() {
+        return None;
+    }
+
+    let mut props = String::new();
+
+    // Writing to a String can't fail.
+    let mut container = |name: &str, kind: &str, items: &[String], lang: bool| {
+        let _ = write!(props, "<{name}><rdf:{kind}>");
+
+        for item in items {
+            if lang {
+                let _ = write!(props, "<rdf:li xml:lang=\"x-default\">");
+            } else {
+                props.push_str("<rdf:li>");
+            }
+
+            props.push_str(&xml_escape(item));
+            props.push_str("</rdf:li>");
+        }
+
+        let _ = write!(props, "</rdf:{kind}></{name}>");
+    };
+
+    if let Some(title) = &md.title {
+        container("dc:title", "Alt", std::slice::from_ref(title), true);
+    }
+
+    if !md.authors.is_empty() {
+        container("dc:creator", "Seq", &md.authors, false);
+    }
+
+    if !md.keywords.is_empty() {
+        container("dc:subject", "Bag", &md.keywords, false);
+    }
+
+    if let Some(lang) = &md.language {
+        container("dc:language", "Bag", std::slice::from_ref(lang), false);
+    }
+
+    if let Some(license) = &md.license {
+        container("dc:rights", "Alt", std::slice::from_ref(license), true);
+    }
+
+    if !md.keywords.is_empty() {
+        let _ = write!(
+            props,
+            "<pdf:Keywords>{}</pdf:Keywords>",
+            xml_escape(&md.keywords.join(", "))
+        );
+    }
+
+    Some(format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+         <rdf:Description rdf:about=\"\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\
+         {props}</rdf:Description></rdf:RDF></x:xmpmeta>\
+         <?xpacket end=\"w\"?>"
+    ))
+}
+
+/// Escape text for inclusion in XML character data or attribute values.
+fn xml_escape(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            c => s.push(c),
+        }
+    }
+
+    s
+}
+
+/// Encode bytes as a PDF hex string.
+fn pdf_hex_string(bytes: &[u8]) -> String {
+    let mut s = String::from("<");
+
+    for b in bytes {
+        // Writing to a String can't fail.
+        let _ = write!(s, "{b:02X}");
+    }
+
+    s.push('>');
+    s
+}
+
+/// Encode a string as a PDF hex string in UTF-16BE with a byte-order mark.
+fn pdf_text_string(text: &str) -> String {
+    let mut s = String::from("<FEFF");
+
+    for unit in text.encode_utf16() {
+        // Writing to a String can't fail.
+        let _ = write!(s, "{unit:04X}");
+    }
+
+    s.push('>');
+    s
+}
+
+/// Get the HTML template variables that expose the document metadata.
+fn html_metadata_variables(md: &StandardMetadata) -> Vec<(&'static str, String)> {
+    let mut vars = Vec::new();
+
+    if let Some(title) = &md.title {
+        vars.push(("tduxMetaTitle", title.clone()));
+    }
+
+    if !md.authors.is_empty() {
+        vars.push(("tduxMetaAuthors", md.authors.join(", ")));
+    }
+
+    if !md.keywords.is_empty() {
+        vars.push(("tduxMetaKeywords", md.keywords.join(", ")));
+    }
+
+    if let Some(lang) = &md.language {
+        vars.push(("tduxMetaLanguage", lang.clone()));
+    }
+
+    if let Some(license) = &md.license {
+        vars.push(("tduxMetaLicense", license.clone()));
+    }
+
+    vars
 }
 
 /// Extension methods for [`WorkspaceCreator`].
@@ -241,3 +919,155 @@ impl WorkspaceCreatorExt for WorkspaceCreator {
         Ok(self.create(bundle_loc)?)
     }
 }
+
+/// Load the template used to populate a new workspace.
+///
+/// The *spec* may be:
+///
+/// - `bundle:<path>`, to use a complete TeX file from the bundle as the
+///   document's sole input;
+/// - a path to a directory containing template files, if it contains a path
+///   separator or starts with a period;
+/// - the name of a subdirectory of the user's template directory, as given by
+///   [`config::PersistentConfig::user_templates_dir`]; or
+/// - the name of one of the built-in templates, listed in [`BUILTIN_TEMPLATES`].
+///
+/// User templates take precedence over built-in templates of the same name.
+pub fn load_workspace_template(
+    spec: &str,
+    config: &config::PersistentConfig,
+    web_bundle: Option<String>,
+    status: &mut dyn StatusBackend,
+) -> Result<WorkspaceTemplate> {
+    if let Some(path) = spec.strip_prefix("bundle:") {
+        let mut bundle = if let Ok(b) = config::maybe_return_test_bundle(web_bundle.clone()) {
+            b
+        } else if let Some(url) = web_bundle {
+            config.make_cached_url_provider(&url, false, None, status)?
+        } else {
+            config.default_bundle(false, status)?
+        };
+
+        let mut ih = ctry!(
+            bundle.input_open_name(path, status).must_exist();
+            "failed to open template file `{}` in the bundle", path
+        );
+
+        let mut contents = Vec::new();
+        ctry!(
+            ih.read_to_end(&mut contents);
+            "failed to read template file `{}` from the bundle", path
+        );
+
+        return Ok(WorkspaceTemplate::from_single_file(contents));
+    }
+
+    let spec_path = Path::new(spec);
+
+    if spec_path.components().count() > 1 || spec.starts_with('.') {
+        return Ok(ctry!(
+            WorkspaceTemplate::from_directory(spec_path);
+            "failed to load template directory `{}`", spec
+        ));
+    }
+
+    let user_dir = config.user_templates_dir()?.join(spec);
+
+    if user_dir.is_dir() {
+        return Ok(ctry!(
+            WorkspaceTemplate::from_directory(&user_dir);
+            "failed to load template directory `{}`", user_dir.display()
+        ));
+    }
+
+    WorkspaceTemplate::builtin(spec).ok_or_else(|| {
+        errmsg!(
+            "no template named `{}`; the built-in templates are: {}",
+            spec,
+            BUILTIN_TEMPLATES.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> StandardMetadata {
+        StandardMetadata {
+            title: Some("Fish & <Chips>".to_owned()),
+            authors: vec!["A. Author".to_owned(), "B. Author".to_owned()],
+            keywords: vec!["food".to_owned()],
+            language: Some("en-GB".to_owned()),
+            license: None,
+        }
+    }
+
+    #[test]
+    fn no_pdf_metadata() {
+        let mut buf = String::new();
+        write_pdf_metadata_specials(&mut buf, &StandardMetadata::default()).unwrap();
+        assert_eq!(buf, "");
+        assert!(xmp_packet(&StandardMetadata::default()).is_none());
+    }
+
+    #[test]
+    fn pdf_metadata_specials() {
+        let mut buf = String::new();
+        write_pdf_metadata_specials(&mut buf, &metadata()).unwrap();
+
+        let xmp = pdf_hex_string(xmp_packet(&metadata()).unwrap().as_bytes());
+        let expected = format!(
+            "\\def\\tectonicpdfmetadata{{\
+             \\special{{pdf:docinfo<</Title{}/Author{}/Keywords{}>>}}\
+             \\special{{pdf:stream @tectonicxmp {xmp} <</Type/Metadata/Subtype/XML>>}}\
+             \\special{{pdf:put @catalog<</Metadata @tectonicxmp>>}}\
+             \\special{{pdf:put @catalog<</Lang<FEFF0065006E002D00470042>>>}}}}\n\
+             \\ifdefined\\AtBeginDocument\\AtBeginDocument{{\\tectonicpdfmetadata}}\
+             \\else\\tectonicpdfmetadata\\fi\n",
+            pdf_text_string("Fish & <Chips>"),
+            pdf_text_string("A. Author, B. Author"),
+            pdf_text_string("food"),
+        );
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn xmp_metadata() {
+        let xmp = xmp_packet(&metadata()).unwrap();
+        assert!(xmp.starts_with("<?xpacket begin=\"\u{feff}\""));
+        assert!(xmp.ends_with("<?xpacket end=\"w\"?>"));
+        assert!(xmp.contains(
+            "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">\
+             Fish &amp; &lt;Chips&gt;</rdf:li></rdf:Alt></dc:title>"
+        ));
+        assert!(xmp.contains(
+            "<dc:creator><rdf:Seq><rdf:li>A. Author</rdf:li>\
+             <rdf:li>B. Author</rdf:li></rdf:Seq></dc:creator>"
+        ));
+        assert!(xmp.contains("<dc:language><rdf:Bag><rdf:li>en-GB</rdf:li></rdf:Bag>"));
+        assert!(xmp.contains("<pdf:Keywords>food</pdf:Keywords>"));
+        assert!(!xmp.contains("dc:rights"));
+    }
+
+    #[test]
+    fn pdf_strings() {
+        assert_eq!(pdf_text_string("Aé"), "<FEFF004100E9>");
+        assert_eq!(pdf_hex_string(b"<a>"), "<3C613E>");
+    }
+
+    #[test]
+    fn html_metadata() {
+        let vars = html_metadata_variables(&metadata());
+        assert_eq!(
+            vars,
+            vec![
+                ("tduxMetaTitle", "Fish & <Chips>".to_owned()),
+                ("tduxMetaAuthors", "A. Author, B. Author".to_owned()),
+                ("tduxMetaKeywords", "food".to_owned()),
+                ("tduxMetaLanguage", "en-GB".to_owned()),
+            ]
+        );
+        assert!(html_metadata_variables(&StandardMetadata::default()).is_empty());
+    }
+}
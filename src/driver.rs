@@ -100,6 +100,10 @@ struct FileSummary {
     /// written.
     pub write_digest: Option<DigestData>,
 
+    /// If this file was read from the filesystem, this is the path that it
+    /// was read from.
+    pub input_path: Option<PathBuf>,
+
     got_written_to_disk: bool,
 }
 
@@ -110,6 +114,7 @@ impl FileSummary {
             input_origin,
             read_digest: None,
             write_digest: None,
+            input_path: None,
             got_written_to_disk: false,
         }
     }
@@ -552,17 +557,20 @@ impl IoProvider for BridgeState {
         })();
 
         match r {
-            OpenResult::Ok((ref ih, ref _path)) => {
+            OpenResult::Ok((ref ih, ref path)) => {
                 if let Some(summ) = self.events.get_mut(name) {
                     summ.access_pattern = match summ.access_pattern {
                         AccessPattern::Written => AccessPattern::WrittenThenRead,
                         c => c, // identity mapping makes sense for remaining options
                     };
                 } else {
-                    self.events.insert(
-                        name.to_owned(),
-                        FileSummary::new(AccessPattern::Read, ih.origin()),
-                    );
+                    let mut summ = FileSummary::new(AccessPattern::Read, ih.origin());
+
+                    if ih.origin() == InputOrigin::Filesystem {
+                        summ.input_path = path.clone();
+                    }
+
+                    self.events.insert(name.to_owned(), summ);
                 }
             }
 
@@ -811,6 +819,7 @@ pub struct ProcessingSessionBuilder {
     reruns: Option<usize>,
     print_stdout: bool,
     bundle: Option<Box<dyn Bundle>>,
+    prefetch_names: Vec<String>,
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
@@ -821,6 +830,7 @@ pub struct ProcessingSessionBuilder {
     html_precomputed_assets: Option<AssetSpecification>,
     html_do_not_emit_files: bool,
     html_do_not_emit_assets: bool,
+    html_template_variables: HashMap<String, String>,
 }
 
 impl ProcessingSessionBuilder {
@@ -960,6 +970,19 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Sets the names of bundle files to fetch ahead of time.
+    ///
+    /// When the session is created, these files will be requested from the
+    /// bundle all at once, so that bundles backed by the network can download
+    /// them concurrently rather than one at a time as the engines ask for
+    /// them. See [`Bundle::prefetch`]. A typical source of names is the list
+    /// of files that a previous build read, as returned by
+    /// [`ProcessingSession::read_input_names`].
+    pub fn prefetch(&mut self, names: Vec<String>) -> &mut Self {
+        self.prefetch_names = names;
+        self
+    }
+
     /// If set to `true`, various intermediate files will be written out to the filesystem.
     pub fn keep_intermediates(&mut self, k: bool) -> &mut Self {
         self.keep_intermediates = k;
@@ -1107,6 +1130,20 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Predefine a variable for the HTML templates used during HTML processing.
+    ///
+    /// If the document sets a template variable of the same name itself, the
+    /// document's value takes precedence.
+    pub fn html_template_variable<N: ToString, V: ToString>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> &mut Self {
+        self.html_template_variables
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
     /// Creates a `ProcessingSession`.
     pub fn create(self, status: &mut dyn StatusBackend) -> Result<ProcessingSession> {
         // First, work on the "bridge state", which gathers the subset of our
@@ -1115,6 +1152,12 @@ impl ProcessingSessionBuilder {
 
         let mut bundle = self.bundle.expect("a bundle must be specified");
 
+        if !self.prefetch_names.is_empty() {
+            if let Err(e) = bundle.prefetch(&self.prefetch_names, status) {
+                tt_warning!(status, "failed to prefetch bundle files"; e);
+            }
+        }
+
         let mut filesystem_root = self.filesystem_root.unwrap_or_default();
 
         let (pio, primary_input_path, default_output_path) = match self.primary_input {
@@ -1265,6 +1308,7 @@ impl ProcessingSessionBuilder {
             html_precomputed_assets: self.html_precomputed_assets,
             html_emit_files: !self.html_do_not_emit_files,
             html_emit_assets: !self.html_do_not_emit_assets,
+            html_template_variables: self.html_template_variables,
         })
     }
 }
@@ -1336,6 +1380,7 @@ pub struct ProcessingSession {
     html_precomputed_assets: Option<AssetSpecification>,
     html_emit_files: bool,
     html_emit_assets: bool,
+    html_template_variables: HashMap<String, String>,
 }
 
 const DEFAULT_MAX_TEX_PASSES: usize = 6;
@@ -2000,6 +2045,10 @@ impl ProcessingSession {
                 engine.precomputed_assets(a.clone());
             }
 
+            for (name, value) in &self.html_template_variables {
+                engine.template_variable(name, value);
+            }
+
             status.note_highlighted("Running ", "spx2html", " ...");
             engine.process_to_filesystem(&mut self.bs, status, &self.tex_xdv_path)?;
         }
@@ -2029,6 +2078,61 @@ impl ProcessingSession {
             .into_inner()
     }
 
+    /// Get the paths of the files on the filesystem that were read during
+    /// processing.
+    ///
+    /// If the primary input is a file, it is included. Files that the session
+    /// itself wrote back to disk are excluded, like they are in the Makefile
+    /// rules. These are the files that need to be monitored if one wants to
+    /// know when the processing results might change.
+    pub fn filesystem_input_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.primary_input_path.iter().cloned().collect();
+
+        for info in self.bs.events.values() {
+            if info.input_origin != InputOrigin::Filesystem || info.got_written_to_disk {
+                continue;
+            }
+
+            if let Some(ref p) = info.input_path {
+                paths.push(p.clone());
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Get the names of the input files that the processing read.
+    ///
+    /// Files that the processing wrote, or tried to read but didn't find, are
+    /// excluded. The inputs may have come from the bundle, the filesystem, or
+    /// elsewhere; callers interested in the bundle’s files, for instance,
+    /// should check which of the names the bundle actually provides.
+    pub fn read_input_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .bs
+            .events
+            .iter()
+            .filter(|(_, info)| {
+                info.access_pattern == AccessPattern::Read
+                    && info.input_origin != InputOrigin::NotInput
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Consume the session and return its backing bundle.
+    ///
+    /// This makes it possible to reuse an already-initialized bundle, with
+    /// whatever state it has accumulated, in a subsequent session.
+    pub fn into_bundle(self) -> Box<dyn Bundle> {
+        self.bs.bundle
+    }
+
     /// See if we need to run `biber`, and parse the `.run.xml` file from the
     /// `loqreq` package to figure out what files `biber` needs. This
     /// functionality should probably become more generic, but I don't have a
@@ -5,15 +5,23 @@
 
 //! Code for locally caching compiled format files.
 
+use fs2::FileExt;
 use std::{
-    io::{BufReader, Write},
-    path::PathBuf,
+    fs,
+    io::{BufReader, ErrorKind as IoErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
 };
 use tectonic_errors::{anyhow::bail, Result};
 
 use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
 use crate::{digest::DigestData, status::StatusBackend};
 
+/// The name of the file in the format cache directory that is locked while a
+/// format is being written.
+const LOCK_FILE_NAME: &str = "formats.lock";
+
 /// A local cache for compiled format files.
 ///
 /// The format cache takes care of saving compiled format files. It uses the
@@ -61,6 +69,75 @@ impl FormatCache {
         ));
         Ok(p)
     }
+
+    /// List the format files saved in a format cache directory.
+    ///
+    /// Files in the directory that don't look like cached formats are ignored.
+    /// If the directory doesn't exist, the list is empty.
+    pub fn list_formats<P: AsRef<Path>>(formats_base: P) -> Result<Vec<CachedFormat>> {
+        let formats_base = formats_base.as_ref();
+
+        let entries = match fs::read_dir(formats_base) {
+            Ok(e) => e,
+            Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut formats = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+
+            let name = match entry.file_name().into_string() {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+
+            // See `path_for_format()`.
+            let bundle_digest = match name
+                .strip_suffix(".fmt")
+                .and_then(|n| n.split_once('-'))
+                .and_then(|(d, _)| DigestData::from_str(d).ok())
+            {
+                Some(d) => d,
+                None => continue,
+            };
+
+            let md = entry.metadata()?;
+
+            if !md.is_file() {
+                continue;
+            }
+
+            formats.push(CachedFormat {
+                path: entry.path(),
+                bundle_digest,
+                size: md.len(),
+                last_used: md.modified().ok(),
+            });
+        }
+
+        Ok(formats)
+    }
+}
+
+/// Information about a format file saved by a [`FormatCache`].
+#[derive(Clone, Debug)]
+pub struct CachedFormat {
+    /// The path of the format file.
+    pub path: PathBuf,
+
+    /// The digest of the bundle that the format was generated from.
+    pub bundle_digest: DigestData,
+
+    /// The size of the format file, in bytes.
+    pub size: u64,
+
+    /// When the format file was last used.
+    ///
+    /// This is the modification time of the file, which is updated whenever
+    /// the format is loaded.
+    pub last_used: Option<SystemTime>,
 }
 
 impl IoProvider for FormatCache {
@@ -80,6 +157,11 @@ impl IoProvider for FormatCache {
             OpenResult::Err(e) => return OpenResult::Err(e),
         };
 
+        // Record that the format was used, so that it isn't garbage-collected.
+        // This might not be possible on all platforms, but it's not important
+        // enough to complain about.
+        let _ = f.set_modified(SystemTime::now());
+
         OpenResult::Ok(InputHandle::new_read_only(
             name,
             BufReader::new(f),
@@ -94,6 +176,18 @@ impl IoProvider for FormatCache {
         _status: &mut dyn StatusBackend,
     ) -> Result<()> {
         let final_path = self.path_for_format(name)?;
+
+        // Other processes sharing the cache might be writing the same format
+        // at the same time, so take turns. The lock is released when the file
+        // is closed.
+        let lock_path = self.formats_base.join(LOCK_FILE_NAME);
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+        lock.lock_exclusive()?;
+
         let mut temp_dest = tempfile::Builder::new()
             .prefix("format_")
             .rand_bytes(6)
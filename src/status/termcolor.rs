@@ -6,12 +6,26 @@
 // link with termcolor
 
 use std::fmt::Arguments;
-use std::io::Write;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::time::{Duration, Instant};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 use tectonic_errors::Error;
+use tectonic_status_base::progress::Progress;
 
-use super::{ChatterLevel, MessageKind, StatusBackend};
+use super::{ChatterLevel, MessageKind, SourceLocation, StatusBackend};
+
+/// Progress bars are only drawn for tasks that take longer than this, to avoid
+/// flickering.
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
+
+/// The minimum time between redraws of a progress bar.
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+const PROGRESS_LABEL_WIDTH: usize = 30;
+const PROGRESS_BAR_WIDTH: usize = 25;
 
 pub struct TermcolorStatusBackend {
     chatter: ChatterLevel,
@@ -22,6 +36,24 @@ pub struct TermcolorStatusBackend {
     highlight_spec: ColorSpec,
     warning_spec: ColorSpec,
     error_spec: ColorSpec,
+
+    /// The task in progress, if any.
+    progress: Option<Progress>,
+
+    /// Whether to draw progress bars, which we only do on a terminal.
+    progress_bars: bool,
+
+    /// When the progress bar was last drawn, and its width, if it's showing.
+    progress_drawn: Option<(Instant, usize)>,
+
+    /// How many TeX errors have been shown along with their source since the
+    /// engine logs were last dumped. If these are all of the errors in the
+    /// logs, the logs aren't needed.
+    errors_with_source: usize,
+
+    /// How many errors about source files have been reported without showing
+    /// their source since the engine logs were last dumped.
+    errors_without_source: usize,
 }
 
 impl TermcolorStatusBackend {
@@ -47,6 +79,11 @@ impl TermcolorStatusBackend {
             highlight_spec,
             warning_spec,
             error_spec,
+            progress: None,
+            progress_bars: chatter > ChatterLevel::Minimal && io::stderr().is_terminal(),
+            progress_drawn: None,
+            errors_with_source: 0,
+            errors_without_source: 0,
         }
     }
 
@@ -63,6 +100,8 @@ impl TermcolorStatusBackend {
             return;
         }
 
+        self.clear_progress();
+
         let (spec, stream) = match kind {
             MessageKind::Note => {
                 if self.always_stderr {
@@ -88,6 +127,8 @@ impl TermcolorStatusBackend {
             return;
         }
 
+        self.clear_progress();
+
         let stream = match kind {
             MessageKind::Note => {
                 if self.always_stderr {
@@ -127,6 +168,8 @@ impl TermcolorStatusBackend {
 
     pub fn note_styled(&mut self, args: Arguments) {
         if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
+
             if self.always_stderr {
                 writeln!(self.stderr, "{args}").expect("write to stderr failed");
             } else {
@@ -149,6 +192,281 @@ impl TermcolorStatusBackend {
             prefix = "caused by:";
         }
     }
+
+    /// Show a TeX error in the style of a compiler: with the line of source
+    /// that it's about, a caret pointing at the place where TeX had got to,
+    /// the rest of TeX's context, and a hint for some common errors.
+    fn tex_error(&mut self, location: &SourceLocation, message: &str) {
+        let message = message.trim_end();
+        self.generic_message(MessageKind::Error, None, format_args!("{message}"));
+
+        let number = location.line.map(|n| n.to_string()).unwrap_or_default();
+        let pad = " ".repeat(number.len());
+        let position = TexPosition::find(&location.context);
+        let spec = &self.highlight_spec;
+        let s = &mut self.stderr;
+
+        gutter(s, spec, &format!("{pad}-->"));
+        writeln!(s, " {location}").expect("write to stderr failed");
+
+        let other_context = match &position {
+            Some(pos) => {
+                let (text, start, width) = pos.snippet(location);
+
+                gutter(s, spec, &format!("{pad} |"));
+                writeln!(s).expect("write to stderr failed");
+                gutter(s, spec, &format!("{number} |"));
+                writeln!(s, " {text}").expect("write to stderr failed");
+                gutter(s, spec, &format!("{pad} |"));
+                write!(s, " {}", " ".repeat(start)).expect("write to stderr failed");
+                s.set_color(&self.error_spec)
+                    .expect("write to stderr failed");
+                write!(s, "{}", "^".repeat(width)).expect("write to stderr failed");
+                s.reset().expect("write to stderr failed");
+                writeln!(s).expect("write to stderr failed");
+
+                &location.context[..pos.index]
+            }
+
+            None => &location.context[..],
+        };
+
+        if !other_context.is_empty() {
+            gutter(s, spec, &format!("{pad} ="));
+            writeln!(s, " TeX was reading:").expect("write to stderr failed");
+
+            for line in other_context {
+                writeln!(s, "{pad}     {line}").expect("write to stderr failed");
+            }
+        }
+
+        if let Some(hint) = hint_for(message) {
+            gutter(s, spec, &format!("{pad} ="));
+            writeln!(s, " hint: {hint}").expect("write to stderr failed");
+        }
+
+        self.errors_with_source += 1;
+    }
+
+    /// Draw the progress bar of the current task, replacing whatever was drawn
+    /// before.
+    fn draw_progress(&mut self) {
+        let progress = match &self.progress {
+            Some(p) => p,
+            None => return,
+        };
+
+        let (text, width) = progress_text(progress, self.progress_drawn.map(|(_, w)| w));
+        write!(self.stderr, "{text}").expect("write to stderr failed");
+        self.stderr.flush().expect("write to stderr failed");
+        self.progress_drawn = Some((Instant::now(), width));
+    }
+
+    /// Erase the progress bar, if it's showing, so that other output can be
+    /// printed. It's drawn again on the next update.
+    fn clear_progress(&mut self) {
+        if let Some((_, width)) = self.progress_drawn.take() {
+            write!(self.stderr, "{}", clear_text(width)).expect("write to stderr failed");
+            self.stderr.flush().expect("write to stderr failed");
+        }
+    }
+}
+
+/// Get the text that draws the progress bar of a task over a previous one of
+/// width *previous*, if one is showing, and the width of the new bar.
+fn progress_text(progress: &Progress, previous: Option<usize>) -> (String, usize) {
+    let label: String = progress
+        .label()
+        .chars()
+        .take(PROGRESS_LABEL_WIDTH)
+        .collect();
+
+    let line = match progress.fraction() {
+        Some(f) => {
+            let filled = (f * PROGRESS_BAR_WIDTH as f64).round() as usize;
+            format!(
+                "{:<lw$} [{}{}] {}",
+                label,
+                "=".repeat(filled),
+                " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                progress.describe_amount(),
+                lw = PROGRESS_LABEL_WIDTH,
+            )
+        }
+
+        None => format!(
+            "{:<lw$} {}",
+            label,
+            progress.describe_amount(),
+            lw = PROGRESS_LABEL_WIDTH
+        ),
+    };
+
+    // Blank out anything left over from a longer line drawn before.
+    let width = line.chars().count();
+    let padding = previous.unwrap_or(0).saturating_sub(width);
+    (format!("\r{}{}", line, " ".repeat(padding)), width)
+}
+
+/// Get the text that erases a progress bar of the given width.
+fn clear_text(width: usize) -> String {
+    format!("\r{}\r", " ".repeat(width))
+}
+
+/// Write part of the gutter of a compiler-style error message.
+fn gutter(stream: &mut StandardStream, spec: &ColorSpec, text: &str) {
+    stream.set_color(spec).expect("write to stderr failed");
+    write!(stream, "{text}").expect("write to stderr failed");
+    stream.reset().expect("write to stderr failed");
+}
+
+/// Get a hint to show with a TeX error, if it's a common one.
+fn hint_for(message: &str) -> Option<&'static str> {
+    if message.starts_with("Undefined control sequence") {
+        Some("check the spelling of the command, or load the package that defines it")
+    } else if message.starts_with("Missing $ inserted") {
+        Some("math-only material, like `^`, `_` or a symbol, was used outside math; wrap it in `$`")
+    } else if message.starts_with("Runaway argument")
+        || message.starts_with("Paragraph ended before")
+        || message.starts_with("File ended while scanning")
+    {
+        Some("an argument is probably missing its closing `}`, or contains a blank line")
+    } else {
+        None
+    }
+}
+
+/// How far TeX had got through the current line of a file, according to the
+/// `l.12 ...` lines of the context that it shows with errors.
+#[derive(Debug, Eq, PartialEq)]
+struct TexPosition {
+    /// The index of the `l.12 ...` line in the context.
+    index: usize,
+
+    /// The part of the line that TeX had read, which starts with `...` if TeX
+    /// left some of it out.
+    before: String,
+
+    /// The rest of the line.
+    after: String,
+}
+
+impl TexPosition {
+    fn find(context: &[String]) -> Option<Self> {
+        let index = context.iter().rposition(|l| is_line_marker(l))?;
+        let first = &context[index];
+
+        let before = match first.find(' ') {
+            Some(i) => &first[i + 1..],
+            None => "",
+        };
+
+        // TeX prints the rest of the line on the next line, indented to line
+        // up with the end of the first part.
+        let width = first.chars().count();
+        let after = context
+            .get(index + 1)
+            .filter(|l| l.starts_with(' '))
+            .map(|l| l.chars().skip(width).collect())
+            .unwrap_or_default();
+
+        Some(TexPosition {
+            index,
+            before: before.to_owned(),
+            after,
+        })
+    }
+
+    /// Get the line of source to show, along with the column and width of the
+    /// last thing that TeX read, which is what the caret points at.
+    ///
+    /// The line is read from the source file if possible, since TeX leaves out
+    /// parts of long lines. Otherwise it's pieced together from the context.
+    fn snippet(&self, location: &SourceLocation) -> (String, usize, usize) {
+        let truncated = self.before.starts_with("...");
+        let before = self.before.trim_start_matches("...");
+        let width = last_token_width(before);
+
+        let span = |end: usize| {
+            if width == 0 {
+                (end, 1)
+            } else {
+                (end - width, width)
+            }
+        };
+
+        if let Some(line) = read_source_line(location) {
+            // TeX skips the spaces at the start of a line, so they don't
+            // appear in its context.
+            let indent = line.len() - line.trim_start_matches(' ').len();
+
+            let end = if truncated {
+                line.find(before).map(|i| i + before.len())
+            } else if line[indent..].starts_with(before) {
+                Some(indent + before.len())
+            } else {
+                None
+            };
+
+            if let Some(end) = end {
+                let (start, width) = span(line[..end].chars().count());
+                return (line, start, width);
+            }
+        }
+
+        let (start, width) = span(self.before.chars().count());
+        (format!("{}{}", self.before, self.after), start, width)
+    }
+}
+
+/// Check whether a line of TeX context is the one showing the current line of
+/// a file, as in `l.12 Hello \foo`.
+fn is_line_marker(line: &str) -> bool {
+    match line.strip_prefix("l.") {
+        Some(rest) => {
+            let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+            digits > 0 && (rest.len() == digits || rest[digits..].starts_with(' '))
+        }
+        None => false,
+    }
+}
+
+/// Get the width, in characters, of the last TeX token in *text*: either a
+/// control sequence or a single character.
+fn last_token_width(text: &str) -> usize {
+    let chars: Vec<char> = text.chars().collect();
+    let n = chars.len();
+    let letters = chars
+        .iter()
+        .rev()
+        .take_while(|c| c.is_ascii_alphabetic() || **c == '@')
+        .count();
+
+    if letters > 0 && letters < n && chars[n - letters - 1] == '\\' {
+        letters + 1
+    } else if n >= 2 && chars[n - 2] == '\\' {
+        2
+    } else {
+        n.min(1)
+    }
+}
+
+/// Read the line of source that *location* is about. The engine's name for the
+/// file needn't make sense relative to the current directory, so we only try
+/// if we know where the file really is.
+fn read_source_line(location: &SourceLocation) -> Option<String> {
+    let n = location.line? as usize;
+    let text = fs::read_to_string(location.path.as_ref()?).ok()?;
+    text.lines()
+        .nth(n.checked_sub(1)?)
+        .map(|l| l.trim_end().replace('\t', " "))
+}
+
+/// Count the errors in a TeX log.
+fn count_tex_errors(log: &[u8]) -> usize {
+    log.split(|&b| b == b'\n')
+        .filter(|line| line.starts_with(b"! "))
+        .count()
 }
 
 /// Show formatted text to the user, styled as an error message.
@@ -173,6 +491,31 @@ impl StatusBackend for TermcolorStatusBackend {
         }
     }
 
+    fn report_at(
+        &mut self,
+        kind: MessageKind,
+        location: &SourceLocation,
+        args: Arguments,
+        err: Option<&Error>,
+    ) {
+        if kind != MessageKind::Error || location.context.is_empty() {
+            if kind == MessageKind::Error {
+                self.errors_without_source += 1;
+            }
+
+            self.report(kind, format_args!("{location}: {args}"), err);
+            return;
+        }
+
+        self.tex_error(location, &args.to_string());
+
+        if let Some(e) = err {
+            for item in e.chain() {
+                self.generic_message(kind, Some("caused by:"), format_args!("{item}"));
+            }
+        }
+    }
+
     fn report_error(&mut self, err: &Error) {
         let mut first = true;
         let kind = MessageKind::Error;
@@ -189,6 +532,8 @@ impl StatusBackend for TermcolorStatusBackend {
 
     fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
         if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
+
             let stream = if self.always_stderr {
                 &mut self.stderr
             } else {
@@ -206,6 +551,25 @@ impl StatusBackend for TermcolorStatusBackend {
     }
 
     fn dump_error_logs(&mut self, output: &[u8]) {
+        self.clear_progress();
+
+        // If every error in the log has already been shown, more readably, the
+        // log has nothing to add. TeX starts each error that it logs with
+        // `! `, so logs from other programs never qualify.
+        let with_source = mem::take(&mut self.errors_with_source);
+        let without_source = mem::take(&mut self.errors_without_source);
+
+        if with_source > 0 && without_source == 0 && count_tex_errors(output) == with_source {
+            self.with_stream(MessageKind::Error, |s| {
+                writeln!(
+                    s,
+                    "(the errors are shown above; use `--keep-logs` to save the full log)"
+                )
+                .expect("write to stderr failed");
+            });
+            return;
+        }
+
         tt_error_styled!(
             self,
             "==============================================================================="
@@ -220,4 +584,160 @@ impl StatusBackend for TermcolorStatusBackend {
             "==============================================================================="
         );
     }
+
+    fn progress_start(&mut self, label: &str, total: Option<u64>) {
+        self.clear_progress();
+        self.progress = Some(Progress::new(label, total));
+    }
+
+    fn progress_update(&mut self, done: u64) {
+        let progress = match &mut self.progress {
+            Some(p) => p,
+            None => return,
+        };
+
+        progress.set_done(done);
+
+        if !self.progress_bars {
+            return;
+        }
+
+        let due = match self.progress_drawn {
+            Some((t, _)) => t.elapsed() >= PROGRESS_REDRAW_INTERVAL,
+            None => progress.elapsed() >= PROGRESS_DELAY,
+        };
+
+        if due {
+            self.draw_progress();
+        }
+    }
+
+    fn progress_finish(&mut self) {
+        self.clear_progress();
+        self.progress = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn tex_position_without_source() {
+        let mut loc = SourceLocation::new("nonexistent.tex", Some(5));
+        loc.context = context(&[
+            "<argument> \\foo",
+            "l.5 Some text \\foo",
+            "                   bar",
+        ]);
+
+        let pos = TexPosition::find(&loc.context).unwrap();
+        assert_eq!(pos.index, 1);
+        assert_eq!(pos.before, "Some text \\foo");
+        assert_eq!(pos.after, " bar");
+
+        let (text, start, width) = pos.snippet(&loc);
+        assert_eq!(text, "Some text \\foo bar");
+        assert_eq!((start, width), (10, 4));
+    }
+
+    #[test]
+    fn tex_position_at_line_start() {
+        let ctx = context(&["l.12 ", "     x^2"]);
+        let pos = TexPosition::find(&ctx).unwrap();
+        assert_eq!(pos.before, "");
+        assert_eq!(pos.after, "x^2");
+        assert!(TexPosition::find(&context(&["<*> \\input x"])).is_none());
+    }
+
+    #[test]
+    fn token_widths() {
+        assert_eq!(last_token_width(""), 0);
+        assert_eq!(last_token_width("x^"), 1);
+        assert_eq!(last_token_width("a \\foo"), 4);
+        assert_eq!(last_token_width("a \\%"), 2);
+        assert_eq!(last_token_width("foo"), 1);
+        assert_eq!(last_token_width("\\m@ke"), 5);
+    }
+
+    #[test]
+    fn tex_position_with_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chapter.tex");
+        fs::write(&path, "First line\n  Some text \\foo bar % comment\n").unwrap();
+
+        // TeX strips leading spaces from its context, and the file isn't in
+        // the current directory, so the whole line can only be shown if the
+        // file's path is known.
+        let mut loc = SourceLocation::new("chapter.tex", Some(2));
+        loc.context = context(&["l.2 Some text \\foo", "                  bar % comment"]);
+        let pos = TexPosition::find(&loc.context).unwrap();
+        assert_eq!(read_source_line(&loc), None);
+
+        loc.path = Some(path);
+        assert_eq!(
+            read_source_line(&loc).as_deref(),
+            Some("  Some text \\foo bar % comment")
+        );
+        assert_eq!(
+            pos.snippet(&loc),
+            ("  Some text \\foo bar % comment".to_owned(), 12, 4)
+        );
+    }
+
+    #[test]
+    fn tex_error_counts() {
+        let log = b"This is XeTeX\n! Undefined control sequence.\nl.3 \\foo\n\n\
+                    ! Missing $ inserted.\n<inserted text>\n  !not an error\n";
+        assert_eq!(count_tex_errors(log), 2);
+        assert_eq!(count_tex_errors(b"I couldn't open file name `x.aux'\n"), 0);
+        assert_eq!(count_tex_errors(b""), 0);
+    }
+
+    #[test]
+    fn hints() {
+        assert!(hint_for("Undefined control sequence.").is_some());
+        assert!(hint_for("Missing $ inserted.").is_some());
+        assert!(hint_for("Paragraph ended before \\foo was complete.").is_some());
+        assert!(hint_for("Overfull \\hbox").is_none());
+    }
+
+    #[test]
+    fn progress_bars() {
+        let mut progress = Progress::new("Downloading bundle", Some(2048));
+        progress.set_done(1024);
+
+        let (text, width) = progress_text(&progress, None);
+        assert_eq!(
+            text,
+            format!(
+                "\r{:<30} [{}{}] 1.0 KiB of 2.0 KiB",
+                "Downloading bundle",
+                "=".repeat(13),
+                " ".repeat(12)
+            )
+        );
+        assert_eq!(width, text.chars().count() - 1);
+
+        // A shorter line blanks out the end of the longer one.
+        let short = Progress::new("x", None);
+        let (text, short_width) = progress_text(&short, Some(width));
+        assert_eq!(short_width, 34);
+        assert_eq!(
+            text,
+            format!("\r{:<30} 0 B{}", "x", " ".repeat(width - short_width))
+        );
+
+        // Long labels are cut short, and the bar never overflows.
+        let mut long = Progress::new(&"y".repeat(50), Some(10));
+        long.set_done(20);
+        let (text, _) = progress_text(&long, None);
+        assert!(text.starts_with(&format!("\r{} [{}]", "y".repeat(30), "=".repeat(25))));
+
+        assert_eq!(clear_text(3), "\r   \r");
+    }
 }
@@ -12,20 +12,33 @@ use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error;
 use std::future::Future;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::ops::Bound;
 use std::path::Path;
 use std::pin::Pin;
+use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{env, fs, thread};
 use tectonic::config::PersistentConfig;
 use tectonic::driver::ProcessingSessionBuilder;
-use tectonic::io::OpenResult;
+use tectonic::io::{format_cache::FormatCache, IoProvider, OpenResult};
+use tectonic::status::collecting::CollectingStatusBackend;
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
+use tectonic_bundles::{
+    cache::{Cache, CacheProblem, GcOptions},
+    cas::convert_bundle,
+    create::BundleCreator,
+    export::export_bundle,
+    itar::{IndexedTarBackend, IndexedTarBundle},
+    zip::ZipBundle,
+    Bundle,
+};
 use tokio::net::TcpListener;
 use tokio::runtime;
+use url::Url;
 
 mod util;
 
@@ -93,6 +106,27 @@ impl TarIndex {
 
         Ok(builder.finish())
     }
+
+    /// Load a bundle written by [`BundleCreator`].
+    fn from_created_bundle<P: AsRef<Path>>(tar_path: P) -> io::Result<TarIndex> {
+        let tar_path = tar_path.as_ref();
+        let tar = fs::read(tar_path)?;
+        let index = fs::read(tar_path.with_extension("tar.index.gz"))?;
+
+        let mut index_text = String::new();
+        flate2::read::GzDecoder::new(&index[..]).read_to_string(&mut index_text)?;
+        let mut map = HashMap::new();
+
+        for line in index_text.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            map.insert(
+                (fields[1].parse().unwrap(), fields[2].parse().unwrap()),
+                fields[0].to_owned(),
+            );
+        }
+
+        Ok(TarIndex { tar, index, map })
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -100,10 +134,18 @@ enum TectonicRequest {
     Head(String),
     Index,
     File(String),
+    /// A single request for the data of several adjacent files.
+    Files(Vec<String>),
+    /// A request for one of the service's static files.
+    Static(String),
+    /// A request for something that the service doesn't have.
+    NotFound(String),
 }
 
 struct TarIndexService {
     tar_index: Mutex<TarIndex>,
+    /// Files served as-is, keyed by path.
+    static_files: Mutex<HashMap<String, Vec<u8>>>,
     requests: Mutex<Vec<TectonicRequest>>,
     local_addr: Mutex<Option<SocketAddr>>,
 }
@@ -114,6 +156,7 @@ impl TarIndexService {
     fn new(tar_index: TarIndex) -> TarIndexService {
         TarIndexService {
             tar_index: Mutex::new(tar_index),
+            static_files: Mutex::new(HashMap::new()),
             requests: Mutex::new(Vec::new()),
             local_addr: Mutex::new(None),
         }
@@ -127,6 +170,10 @@ impl TarIndexService {
         *self.tar_index.lock().unwrap() = tar_index;
     }
 
+    fn set_static_files(&self, static_files: HashMap<String, Vec<u8>>) {
+        *self.static_files.lock().unwrap() = static_files;
+    }
+
     fn response(
         &self,
         req: Request<impl Body>,
@@ -158,11 +205,27 @@ impl TarIndexService {
                     range.satisfiable_ranges(u64::MAX).next()
                 {
                     let tar_index = self.tar_index.lock().unwrap();
-                    let name = tar_index
-                        .map
-                        .get(&(l, h - l + 1))
-                        .expect("unknown file data requested");
-                    self.log_request(TectonicRequest::File(name.to_owned()));
+
+                    if let Some(name) = tar_index.map.get(&(l, h - l + 1)) {
+                        self.log_request(TectonicRequest::File(name.to_owned()));
+                    } else {
+                        // A prefetch may coalesce the data of several files
+                        // into one request.
+                        let mut members: Vec<_> = tar_index
+                            .map
+                            .iter()
+                            .filter(|((offset, length), _)| {
+                                *offset >= l && offset + length <= h + 1
+                            })
+                            .map(|((offset, _), name)| (*offset, name.to_owned()))
+                            .collect();
+                        assert!(!members.is_empty(), "unknown file data requested");
+                        members.sort();
+                        self.log_request(TectonicRequest::Files(
+                            members.into_iter().map(|(_, name)| name).collect(),
+                        ));
+                    }
+
                     let mut resp = Response::builder().status(StatusCode::PARTIAL_CONTENT);
                     resp.headers_mut()
                         .unwrap()
@@ -178,12 +241,24 @@ impl TarIndexService {
                 let resp = self.tar_index.lock().unwrap().index.to_vec().into();
                 Box::pin(async move { Response::new(Either::Right(resp)) })
             }
-            _ => Box::pin(async move {
-                Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Either::Left(Empty::new()))
-                    .unwrap()
-            }),
+            (&Method::HEAD, path, None) if self.static_files.lock().unwrap().contains_key(path) => {
+                self.log_request(TectonicRequest::Head(path.to_owned()));
+                Box::pin(async move { Response::new(Either::Left(Empty::new())) })
+            }
+            (&Method::GET, path, None) if self.static_files.lock().unwrap().contains_key(path) => {
+                self.log_request(TectonicRequest::Static(path.to_owned()));
+                let resp = self.static_files.lock().unwrap()[path].clone().into();
+                Box::pin(async move { Response::new(Either::Right(resp)) })
+            }
+            (_, path, _) => {
+                self.log_request(TectonicRequest::NotFound(path.to_owned()));
+                Box::pin(async move {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Either::Left(Empty::new()))
+                        .unwrap()
+                })
+            }
         }
     }
 
@@ -501,6 +576,795 @@ fn test_bundle_update() {
     });
 }
 
+#[test]
+fn test_verify_cache() {
+    let tar_index = {
+        let mut builder = TarIndexBuilder::new();
+        builder
+            .push("plain.tex", b"test")
+            .push("other.tex", b"other content")
+            .push(
+                tectonic::digest::DIGEST_NAME,
+                b"0000000000000000000000000000000000000000000000000000000000000000",
+            );
+        builder.finish()
+    };
+
+    run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let config = PersistentConfig::default();
+
+        let mut cache = config
+            .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        for name in ["plain.tex", "other.tex"] {
+            match cache.input_open_name(name, &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open {name}"),
+            }
+        }
+
+        let info = cache.cache_info().unwrap();
+        assert_eq!(info.n_cached_files, 2);
+        assert_eq!(info.cached_size, 17);
+
+        let verification = cache.verify_cache(&mut status).unwrap().unwrap();
+        assert_eq!(verification.n_checked, 2);
+        assert!(verification.problems.is_empty());
+
+        // Corrupt the cached copy of `plain.tex`, which is stored under the
+        // digest of its contents.
+        let digest = {
+            let mut dc = tectonic::digest::create();
+            tectonic::digest::Digest::update(&mut dc, b"test");
+            tectonic::digest::DigestData::from(dc).to_string()
+        };
+        let data_path = tempdir
+            .path()
+            .join("files")
+            .join(&digest[..2])
+            .join(&digest[2..]);
+        let mut perms = fs::metadata(&data_path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(&data_path, perms).unwrap();
+        fs::write(&data_path, b"TEST").unwrap();
+
+        let verification = cache.verify_cache(&mut status).unwrap().unwrap();
+        assert_eq!(
+            verification.problems,
+            vec![CacheProblem::WrongDigest("plain.tex".to_owned())]
+        );
+    });
+}
+
+#[test]
+fn test_cache_gc() {
+    let tar_index = {
+        let mut builder = TarIndexBuilder::new();
+        builder
+            .push("plain.tex", b"test")
+            .push("other.tex", b"other content")
+            .push(
+                tectonic::digest::DIGEST_NAME,
+                b"0000000000000000000000000000000000000000000000000000000000000000",
+            );
+        builder.finish()
+    };
+
+    run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+
+        {
+            let mut bundle = cache
+                .open::<IndexedTarBackend>(url, false, &mut status)
+                .unwrap();
+
+            for name in ["plain.tex", "other.tex"] {
+                match bundle.input_open_name(name, &mut status) {
+                    OpenResult::Ok(_) => {}
+                    _ => panic!("Failed to open {name}"),
+                }
+            }
+
+            // Garbage collection mustn't happen while the bundle is in use.
+            assert!(cache.gc(&GcOptions::default(), &mut status).is_err());
+        }
+
+        // Seed the cache with a second bundle that shares `plain.tex` with the
+        // first.
+        let archive_dir = tempfile::tempdir().unwrap();
+        fs::write(archive_dir.path().join("plain.tex"), b"test").unwrap();
+        fs::write(
+            archive_dir.path().join(tectonic::digest::DIGEST_NAME),
+            "1".repeat(64),
+        )
+        .unwrap();
+        cache
+            .import_archive(
+                "https://example.com/other.tar",
+                archive_dir.path(),
+                &mut status,
+            )
+            .unwrap();
+
+        let summary = cache.summarize().unwrap();
+        assert_eq!(summary.bundles.len(), 2);
+        assert_eq!(summary.n_data_files, 2);
+        assert_eq!(summary.data_size, 17);
+
+        // Pretend that the first bundle hasn't been used in a long time.
+        filetime::set_file_mtime(
+            tempdir
+                .path()
+                .join("used")
+                .join(format!("{}.txt", "0".repeat(64))),
+            filetime::FileTime::from_unix_time(1_000_000_000, 0),
+        )
+        .unwrap();
+
+        let options = GcOptions {
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+            max_size: None,
+        };
+        let report = cache.gc(&options, &mut status).unwrap();
+        assert_eq!(report.removed_bundles.len(), 1);
+        assert_eq!(report.removed_bundles[0].to_string(), "0".repeat(64));
+        assert_eq!(report.n_files_removed, 1);
+        assert_eq!(report.bytes_freed, 13);
+
+        let summary = cache.summarize().unwrap();
+        assert_eq!(summary.bundles.len(), 1);
+        assert_eq!(summary.bundles[0].digest.to_string(), "1".repeat(64));
+        assert_eq!(summary.n_data_files, 1);
+
+        // Now the size limit.
+        let options = GcOptions {
+            max_age: None,
+            max_size: Some(4),
+        };
+        let report = cache.gc(&options, &mut status).unwrap();
+        assert!(report.removed_bundles.is_empty());
+
+        let options = GcOptions {
+            max_age: None,
+            max_size: Some(3),
+        };
+        let report = cache.gc(&options, &mut status).unwrap();
+        assert_eq!(report.removed_bundles.len(), 1);
+        assert_eq!(report.bytes_freed, 4);
+
+        let summary = cache.summarize().unwrap();
+        assert!(summary.bundles.is_empty());
+        assert_eq!(summary.n_data_files, 0);
+
+        // The cache still works afterwards.
+        let mut bundle = cache
+            .open::<IndexedTarBackend>(url, false, &mut status)
+            .unwrap();
+        match bundle.input_open_name("plain.tex", &mut status) {
+            OpenResult::Ok(_) => {}
+            _ => panic!("Failed to open plain.tex"),
+        }
+    });
+}
+
+#[test]
+fn test_prefetch() {
+    let tar_index = {
+        let mut builder = TarIndexBuilder::new();
+        builder
+            .push("a.tex", b"first")
+            .push("b.tex", b"second")
+            .push("c.tex", b"third")
+            .push("d.tex", b"fourth")
+            .push(
+                tectonic::digest::DIGEST_NAME,
+                b"0000000000000000000000000000000000000000000000000000000000000000",
+            );
+        builder.finish()
+    };
+
+    let requests = run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let config = PersistentConfig::default();
+
+        let names: Vec<String> = ["c.tex", "a.tex", "b.tex", "missing.tex"]
+            .iter()
+            .map(|n| (*n).to_owned())
+            .collect();
+
+        {
+            let mut cache = config
+                .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+
+            // The three adjacent files should be fetched in one request.
+            assert_eq!(cache.prefetch(&names, &mut status).unwrap(), 3);
+
+            for (name, content) in [("a.tex", "first"), ("b.tex", "second"), ("c.tex", "third")] {
+                match cache.input_open_name(name, &mut status) {
+                    OpenResult::Ok(mut ih) => {
+                        let mut data = String::new();
+                        ih.read_to_string(&mut data).unwrap();
+                        assert_eq!(data, content);
+                    }
+                    _ => panic!("Failed to open {name}"),
+                }
+            }
+
+            // Files that weren't prefetched are still fetched on demand.
+            match cache.input_open_name("d.tex", &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open d.tex"),
+            }
+        }
+        {
+            let mut cache = config
+                .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+
+            // Everything is cached now.
+            assert_eq!(cache.prefetch(&names, &mut status).unwrap(), 0);
+        }
+
+        let verification = {
+            let mut cache = config
+                .make_cached_url_provider(url, true, Some(tempdir.path()), &mut status)
+                .unwrap();
+            cache.verify_cache(&mut status).unwrap().unwrap()
+        };
+        assert_eq!(verification.n_checked, 4);
+        assert!(verification.problems.is_empty());
+    });
+
+    check_req_count(&requests, TectonicRequest::Index, 1);
+    check_req_count(
+        &requests,
+        TectonicRequest::Files(vec!["a.tex".into(), "b.tex".into(), "c.tex".into()]),
+        1,
+    );
+    check_req_count(&requests, TectonicRequest::File("a.tex".into()), 0);
+    check_req_count(&requests, TectonicRequest::File("d.tex".into()), 1);
+}
+
+/// The environment variable that tells `test_concurrent_cache_access` that
+/// it's running in one of the child processes that it spawns. Its value gives
+/// the child's number, the bundle URL, and the cache directory.
+const CONCURRENT_CHILD_ENV: &str = "TECTONIC_TEST_CONCURRENT_CACHE_CHILD";
+
+const N_CONCURRENT_CHILDREN: usize = 4;
+const N_CONCURRENT_FILES: usize = 20;
+
+fn concurrent_file_content(i: usize) -> String {
+    format!("content of file {i}\n").repeat(i + 1)
+}
+
+/// What each child process of `test_concurrent_cache_access` does: read every
+/// file of the bundle, starting at a different point from the other children,
+/// and save a format file.
+fn concurrent_cache_child(spec: &str) {
+    let mut bits = spec.splitn(3, ' ');
+    let child: usize = bits.next().unwrap().parse().unwrap();
+    let url = bits.next().unwrap();
+    let root = Path::new(bits.next().unwrap());
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let mut cache = Cache::get_for_custom_directory(root);
+    let mut bundle = cache
+        .open::<IndexedTarBackend>(url, false, &mut status)
+        .unwrap();
+
+    for j in 0..N_CONCURRENT_FILES {
+        let i = (j + child * N_CONCURRENT_FILES / N_CONCURRENT_CHILDREN) % N_CONCURRENT_FILES;
+        let name = format!("file{i}.tex");
+
+        match bundle.input_open_name(&name, &mut status) {
+            OpenResult::Ok(mut ih) => {
+                let mut data = String::new();
+                ih.read_to_string(&mut data).unwrap();
+                assert_eq!(data, concurrent_file_content(i));
+            }
+            _ => panic!("Failed to open {name}"),
+        }
+    }
+
+    let digest = bundle.get_digest(&mut status).unwrap();
+    let mut formats = FormatCache::new(digest, root.join("formats"));
+    formats
+        .write_format("test.fmt", &vec![child as u8; 1 << 20], &mut status)
+        .unwrap();
+}
+
+#[test]
+fn test_concurrent_cache_access() {
+    if let Ok(spec) = env::var(CONCURRENT_CHILD_ENV) {
+        concurrent_cache_child(&spec);
+        return;
+    }
+
+    let names: Vec<String> = (0..N_CONCURRENT_FILES)
+        .map(|i| format!("file{i}.tex"))
+        .collect();
+
+    let tar_index = {
+        let mut builder = TarIndexBuilder::new();
+
+        for (i, name) in names.iter().enumerate() {
+            builder.push(name, concurrent_file_content(i).as_bytes());
+        }
+
+        builder.push(
+            tectonic::digest::DIGEST_NAME,
+            b"0000000000000000000000000000000000000000000000000000000000000000",
+        );
+        builder.finish()
+    };
+
+    let requests = run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        fs::create_dir(tempdir.path().join("formats")).unwrap();
+
+        // Several processes share one cache directory, like CI jobs that share
+        // a home directory.
+        let children: Vec<_> = (0..N_CONCURRENT_CHILDREN)
+            .map(|child| {
+                Command::new(env::current_exe().unwrap())
+                    .args(["--exact", "test_concurrent_cache_access"])
+                    .env(
+                        CONCURRENT_CHILD_ENV,
+                        format!("{} {} {}", child, url, tempdir.path().display()),
+                    )
+                    .stdout(Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+
+        for mut child in children {
+            assert!(child.wait().unwrap().success(), "child process failed");
+        }
+
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+        let mut bundle = cache
+            .open::<IndexedTarBackend>(url, true, &mut status)
+            .unwrap();
+        let verification = bundle.verify(&mut status).unwrap();
+        assert_eq!(verification.n_checked, N_CONCURRENT_FILES);
+        assert!(verification.problems.is_empty());
+
+        // Exactly one of the format files should have been saved, intact.
+        let formats = FormatCache::list_formats(tempdir.path().join("formats")).unwrap();
+        assert_eq!(formats.len(), 1);
+        let data = fs::read(&formats[0].path).unwrap();
+        assert_eq!(data.len(), 1 << 20);
+        assert!(data.iter().all(|b| *b == data[0]));
+    });
+
+    // The processes should have waited for each other rather than fetching
+    // things more than once.
+    check_req_count(&requests, TectonicRequest::Index, 1);
+
+    for name in names {
+        check_req_count(&requests, TectonicRequest::File(name), 1);
+    }
+}
+
+#[test]
+fn test_created_bundle() {
+    let srcdir = tempfile::tempdir().unwrap();
+    let house = srcdir.path().join("house");
+    let texlive = srcdir.path().join("texlive");
+    fs::create_dir_all(house.join("tex/latex/house")).unwrap();
+    fs::create_dir_all(texlive.join("tex/latex/base")).unwrap();
+    fs::create_dir_all(texlive.join("doc")).unwrap();
+    fs::write(house.join("tex/latex/house/article.cls"), b"house").unwrap();
+    fs::write(texlive.join("tex/latex/base/article.cls"), b"upstream").unwrap();
+    fs::write(texlive.join("tex/latex/base/size10.clo"), b"size").unwrap();
+    fs::write(texlive.join("doc/size10.clo"), b"size").unwrap();
+    fs::write(texlive.join("doc/README.tex"), b"docs").unwrap();
+
+    let outdir = tempfile::tempdir().unwrap();
+    let tar_path = outdir.path().join("bundle.tar");
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let created = BundleCreator::new()
+        .source(&house)
+        .source(&texlive)
+        .exclude("doc/**")
+        .unwrap()
+        .create(&tar_path, &mut status)
+        .unwrap();
+
+    assert_eq!(created.n_files, 2);
+    assert_eq!(created.n_duplicates, 1);
+    assert_eq!(created.n_conflicts, 1);
+
+    let tar_index = TarIndex::from_created_bundle(&tar_path).unwrap();
+
+    run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let config = PersistentConfig::default();
+
+        let mut cache = config
+            .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        assert_eq!(
+            cache.get_digest(&mut status).unwrap().to_string(),
+            created.digest.to_string()
+        );
+
+        let mut files = cache.all_files(&mut status).unwrap();
+        files.sort();
+        assert_eq!(files, vec!["SHA256SUM", "article.cls", "size10.clo"]);
+
+        match cache.input_open_name("article.cls", &mut status) {
+            OpenResult::Ok(mut ih) => {
+                let mut content = Vec::new();
+                ih.read_to_end(&mut content).unwrap();
+                assert_eq!(content, b"house");
+            }
+            _ => panic!("Failed to open article.cls"),
+        }
+    });
+}
+
+#[test]
+fn test_local_indexed_tar() {
+    let srcdir = tempfile::tempdir().unwrap();
+    fs::write(srcdir.path().join("article.cls"), b"article").unwrap();
+    fs::write(srcdir.path().join("size10.clo"), b"size").unwrap();
+
+    let outdir = tempfile::tempdir().unwrap();
+    let tar_path = outdir.path().join("bundle.tar");
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let created = BundleCreator::new()
+        .source(srcdir.path())
+        .create(&tar_path, &mut status)
+        .unwrap();
+
+    assert!(IndexedTarBundle::is_indexed_tar(&tar_path));
+    assert!(!IndexedTarBundle::is_indexed_tar(srcdir.path()));
+
+    let cache_dir = tempfile::tempdir().unwrap();
+    let config = PersistentConfig::default();
+    let file_url = Url::from_file_path(&tar_path).unwrap();
+
+    // The bundle can be opened directly, by path, or by `file:` URL, and none
+    // of these should touch the cache.
+    let bundles: Vec<Box<dyn Bundle>> = vec![
+        Box::new(IndexedTarBundle::open(&tar_path).unwrap()),
+        config
+            .make_local_file_provider(tar_path.clone(), &mut status)
+            .unwrap(),
+        config
+            .make_cached_url_provider(
+                file_url.as_str(),
+                false,
+                Some(cache_dir.path()),
+                &mut status,
+            )
+            .unwrap(),
+    ];
+
+    for mut bundle in bundles {
+        assert_eq!(
+            bundle.get_digest(&mut status).unwrap().to_string(),
+            created.digest.to_string()
+        );
+
+        let mut files = bundle.all_files(&mut status).unwrap();
+        files.sort();
+        assert_eq!(files, vec!["SHA256SUM", "article.cls", "size10.clo"]);
+
+        for (name, content) in [("size10.clo", "size"), ("article.cls", "article")] {
+            match bundle.input_open_name(name, &mut status) {
+                OpenResult::Ok(mut ih) => {
+                    let mut data = String::new();
+                    ih.read_to_string(&mut data).unwrap();
+                    assert_eq!(data, content);
+                }
+                _ => panic!("Failed to open {name}"),
+            }
+        }
+
+        assert!(matches!(
+            bundle.input_open_name("missing.tex", &mut status),
+            OpenResult::NotAvailable
+        ));
+    }
+
+    assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_content_addressed_bundle() {
+    // Two versions of a bundle that have a file in common.
+    let srcdir = tempfile::tempdir().unwrap();
+    let v1 = srcdir.path().join("v1");
+    let v2 = srcdir.path().join("v2");
+    fs::create_dir(&v1).unwrap();
+    fs::create_dir(&v2).unwrap();
+    fs::write(v1.join("shared.tex"), b"shared").unwrap();
+    fs::write(v1.join("changed.tex"), b"old").unwrap();
+    fs::write(v2.join("shared.tex"), b"shared").unwrap();
+    fs::write(v2.join("changed.tex"), b"new").unwrap();
+    fs::write(v2.join("added.tex"), b"added").unwrap();
+
+    // Create them as indexed tar bundles, and then convert them into the same
+    // directory.
+    let tardir = tempfile::tempdir().unwrap();
+    let casdir = tempfile::tempdir().unwrap();
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let mut digests = HashMap::new();
+
+    for (name, src, n_new_blobs) in [("v1", &v1, 3), ("v2", &v2, 3)] {
+        let tar_path = tardir.path().join(format!("{name}.tar"));
+        let created = BundleCreator::new()
+            .source(src)
+            .create(&tar_path, &mut status)
+            .unwrap();
+
+        let mut itar = IndexedTarBundle::open(&tar_path).unwrap();
+        let converted = convert_bundle(&mut itar, casdir.path(), name, &mut status).unwrap();
+        assert_eq!(converted.digest.to_string(), created.digest.to_string());
+        assert_eq!(converted.n_files, created.n_files);
+
+        // The second version shares `shared.tex` with the first.
+        assert_eq!(converted.n_new_blobs, n_new_blobs);
+        digests.insert(name, created.digest.to_string());
+    }
+
+    let mut static_files = HashMap::new();
+    collect_static_files(casdir.path(), "", &mut static_files);
+
+    let shared_blob = {
+        let mut dc = tectonic::digest::create();
+        tectonic::digest::Digest::update(&mut dc, b"shared");
+        let hex = tectonic::digest::DigestData::from(dc).to_string();
+        format!("/blobs/{}/{}.zst", &hex[..2], &hex[2..])
+    };
+    assert!(static_files.contains_key(&shared_blob));
+
+    let requests = run_test(Some(TarIndexBuilder::new().finish()), |service, url| {
+        service.set_static_files(static_files);
+        let base_url = url.rsplit_once('/').unwrap().0;
+        let tempdir = tempfile::tempdir().unwrap();
+        let config = PersistentConfig::default();
+
+        let versions = [
+            ("v1", vec![("shared.tex", "shared"), ("changed.tex", "old")]),
+            (
+                "v2",
+                vec![
+                    ("shared.tex", "shared"),
+                    ("changed.tex", "new"),
+                    ("added.tex", "added"),
+                ],
+            ),
+        ];
+
+        for (name, files) in versions {
+            let bundle_url = format!("{base_url}/{name}.cabundle");
+            let mut bundle = config
+                .make_cached_url_provider(&bundle_url, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+
+            assert_eq!(
+                bundle.get_digest(&mut status).unwrap().to_string(),
+                digests[name]
+            );
+
+            for (file, content) in files {
+                match bundle.input_open_name(file, &mut status) {
+                    OpenResult::Ok(mut ih) => {
+                        let mut data = String::new();
+                        ih.read_to_string(&mut data).unwrap();
+                        assert_eq!(data, content);
+                    }
+                    _ => panic!("Failed to open {file}"),
+                }
+            }
+        }
+
+        let mut bundle = config
+            .make_cached_url_provider(
+                &format!("{base_url}/v2.cabundle"),
+                true,
+                Some(tempdir.path()),
+                &mut status,
+            )
+            .unwrap();
+        let verification = bundle.verify_cache(&mut status).unwrap().unwrap();
+        assert_eq!(verification.n_checked, 3);
+        assert!(verification.problems.is_empty());
+    });
+
+    check_req_count(&requests, TectonicRequest::Static("/v1.cabundle".into()), 1);
+    check_req_count(&requests, TectonicRequest::Static("/v2.cabundle".into()), 1);
+
+    // The data of the shared file are only downloaded once.
+    check_req_count(&requests, TectonicRequest::Static(shared_blob), 1);
+}
+
+/// Recursively load the files in *dir* for serving by a [`TarIndexService`],
+/// keyed by their paths relative to *dir*.
+fn collect_static_files(dir: &Path, prefix: &str, files: &mut HashMap<String, Vec<u8>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let path = format!("{}/{}", prefix, entry.file_name().to_str().unwrap());
+
+        if entry.file_type().unwrap().is_dir() {
+            collect_static_files(&entry.path(), &path, files);
+        } else {
+            files.insert(path, fs::read(entry.path()).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_bundle_mirrors() {
+    let requests = run_test(None, |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = CollectingStatusBackend::new();
+        let mut cache = Cache::get_for_custom_directory(tempdir.path());
+
+        // The primary location doesn't exist, but the mirror does.
+        let primary = url.replace("/tectonic-default", "/missing.tar");
+        let mirrors = vec![url.to_owned()];
+
+        {
+            let mut bundle = cache
+                .open_with_mirrors::<IndexedTarBackend>(&primary, &mirrors, false, &mut status)
+                .unwrap();
+
+            match bundle.input_open_name("plain.tex", &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open plain.tex"),
+            }
+        }
+
+        assert!(status
+            .warnings()
+            .any(|w| w.text.starts_with("failed to open the bundle at")));
+
+        // The failure is remembered, so the mirror is tried first this time,
+        // and the file cached from it is reused.
+        {
+            let mut bundle = cache
+                .open_refreshed_with_mirrors::<IndexedTarBackend>(&primary, &mirrors, &mut status)
+                .unwrap();
+
+            match bundle.input_open_name("plain.tex", &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open plain.tex"),
+            }
+        }
+
+        // Without the mirror, the bundle can't be pulled.
+        assert!(cache
+            .open_refreshed::<IndexedTarBackend>(&primary, &mut status)
+            .is_err());
+    });
+
+    check_req_count(
+        &requests,
+        TectonicRequest::NotFound("/missing.tar".into()),
+        2,
+    );
+    check_req_count(&requests, TectonicRequest::Index, 2);
+    check_req_count(&requests, TectonicRequest::File("plain.tex".into()), 1);
+}
+
+#[test]
+fn test_exported_bundle() {
+    let srcdir = tempfile::tempdir().unwrap();
+    fs::write(srcdir.path().join("plain.tex"), b"plain").unwrap();
+    fs::write(srcdir.path().join("hyphen.tex"), b"hyphen").unwrap();
+    fs::write(srcdir.path().join("unused.tex"), b"unused").unwrap();
+
+    let outdir = tempfile::tempdir().unwrap();
+    let tar_path = outdir.path().join("bundle.tar");
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let created = BundleCreator::new()
+        .source(srcdir.path())
+        .create(&tar_path, &mut status)
+        .unwrap();
+    let tar_index = TarIndex::from_created_bundle(&tar_path).unwrap();
+
+    let zip_path = outdir.path().join("export.zip");
+    let export_tar_path = outdir.path().join("export.tar");
+    let mut bundle_url = String::new();
+
+    run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let config = PersistentConfig::default();
+        let mut cache = config
+            .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        let names = vec![
+            "plain.tex".to_owned(),
+            "hyphen.tex".to_owned(),
+            "missing.tex".to_owned(),
+        ];
+
+        for path in [&zip_path, &export_tar_path] {
+            let exported = export_bundle(cache.as_mut(), &names, path, &mut status).unwrap();
+            assert_eq!(exported.digest.to_string(), created.digest.to_string());
+            assert_eq!(exported.n_files, 2);
+            assert_eq!(exported.missing, vec!["missing.tex"]);
+        }
+
+        bundle_url = url.to_owned();
+    });
+
+    // The ZIP archive can be used as a bundle directly.
+    let mut zip = ZipBundle::open(&zip_path).unwrap();
+    let mut files = zip.all_files(&mut status).unwrap();
+    files.sort();
+    assert_eq!(files, vec!["SHA256SUM", "hyphen.tex", "plain.tex"]);
+    assert_eq!(
+        zip.get_digest(&mut status).unwrap().to_string(),
+        created.digest.to_string()
+    );
+
+    // Importing the archives seeds a cache that works with the server gone.
+    let cache_dir = tempfile::tempdir().unwrap();
+    let mut cache = Cache::get_for_custom_directory(cache_dir.path());
+    let imported = cache
+        .import_archive(&bundle_url, &zip_path, &mut status)
+        .unwrap();
+    assert_eq!(imported.n_files, 2);
+    assert_eq!(imported.n_new, 2);
+
+    let imported = cache
+        .import_archive(&bundle_url, &export_tar_path, &mut status)
+        .unwrap();
+    assert_eq!(imported.n_new, 0);
+
+    for only_cached in [true, false] {
+        let config = PersistentConfig::default();
+        let mut bundle = config
+            .make_cached_url_provider(
+                &bundle_url,
+                only_cached,
+                Some(cache_dir.path()),
+                &mut status,
+            )
+            .unwrap();
+
+        assert_eq!(
+            bundle.get_digest(&mut status).unwrap().to_string(),
+            created.digest.to_string()
+        );
+
+        let mut files = bundle.all_files(&mut status).unwrap();
+        files.sort();
+        assert_eq!(files, vec!["hyphen.tex", "plain.tex"]);
+
+        match bundle.input_open_name("plain.tex", &mut status) {
+            OpenResult::Ok(mut ih) => {
+                let mut content = Vec::new();
+                ih.read_to_end(&mut content).unwrap();
+                assert_eq!(content, b"plain");
+            }
+            _ => panic!("Failed to open plain.tex"),
+        }
+
+        assert!(matches!(
+            bundle.input_open_name("unused.tex", &mut status),
+            OpenResult::NotAvailable
+        ));
+    }
+}
+
 #[test]
 fn test_cache_location_redirect() {
     const CACHE_DIR_KEY: &str = "TECTONIC_CACHE_DIR";
@@ -703,6 +703,85 @@ fn web_bundle_overrides() {
     }
 }
 
+/// `bundle export` writes the bundle files used by the document, or all of
+/// them, to an archive.
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_bundle_export() {
+    use tectonic::io::IoProvider;
+    use tectonic_bundles::{zip::ZipBundle, Bundle};
+
+    let (_tempdir, temppath) = setup_v2();
+    let mut status = tectonic::status::NoopStatusBackend::default();
+
+    let output = run_tectonic(&temppath, &["-X", "bundle", "export", "used.zip"]);
+    success_or_panic(&output);
+
+    let mut zip = ZipBundle::open(temppath.join("used.zip")).unwrap();
+    let files = zip.all_files(&mut status).unwrap();
+    assert!(files.iter().any(|f| f == "SHA256SUM"));
+    assert!(files.iter().any(|f| f == "plain.tex"));
+    assert!(files.iter().any(|f| f == "tectonic-format-plain.tex"));
+    assert!(!files.iter().any(|f| f == "LinLibertine_R_G.ttf"));
+    zip.input_open_name("plain.tex", &mut status)
+        .must_exist()
+        .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "bundle", "export", "--all", "all.zip"]);
+    success_or_panic(&output);
+
+    let mut zip = ZipBundle::open(temppath.join("all.zip")).unwrap();
+    let files = zip.all_files(&mut status).unwrap();
+    assert!(files.iter().any(|f| f == "LinLibertine_R_G.ttf"));
+
+    let output = run_tectonic(&temppath, &["-X", "bundle", "export", "used.rar"]);
+    error_or_panic(&output);
+}
+
+/// The first build records the bundle in `Tectonic.lock`, and `--locked`
+/// builds refuse bundles that don't match it.
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_bundle_lock() {
+    let (_tempdir, temppath) = setup_v2();
+    let lock_path = temppath.join("Tectonic.lock");
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--locked"]);
+    error_or_panic(&output);
+    assert!(!lock_path.exists());
+
+    // Commands that only look at the bundle don't write the lock file.
+    let output = run_tectonic(&temppath, &["-X", "bundle", "list"]);
+    success_or_panic(&output);
+    assert!(!lock_path.exists());
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    let lock = fs::read_to_string(&lock_path).unwrap();
+    let zeros = "0".repeat(64);
+    assert!(lock.contains(&zeros));
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--locked"]);
+    success_or_panic(&output);
+
+    // Pretend that the bundle has changed since it was locked.
+    fs::write(&lock_path, lock.replace(&zeros, &"1".repeat(64))).unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--locked"]);
+    error_or_panic(&output);
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("do not match the lock file"));
+
+    let output = run_tectonic(&temppath, &["-X", "bundle", "update"]);
+    success_or_panic(&output);
+    assert_eq!(fs::read_to_string(&lock_path).unwrap(), lock);
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--locked"]);
+    success_or_panic(&output);
+}
+
 /// Test various web bundle overrides for the v2 CLI
 #[cfg(feature = "serialization")]
 #[test]
@@ -767,6 +846,65 @@ fn v2_bundle_overrides() {
     success_or_panic(&output);
 }
 
+/// `cache gc` removes bundles to fit within a size limit, and `cache info`
+/// reports on what's left.
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_cache_gc() {
+    let (_tempdir, temppath) = setup_v2();
+    let cache_dir = temppath.join("cache");
+    let archive = temppath.join("archive");
+    fs::create_dir(&archive).unwrap();
+    fs::write(archive.join("plain.tex"), "test").unwrap();
+    fs::write(archive.join("SHA256SUM"), "1".repeat(64)).unwrap();
+
+    let run = |args: &[&str]| {
+        let mut command = prep_tectonic(&temppath, args);
+        command.env("TECTONIC_CACHE_DIR", &cache_dir);
+        println!("running {command:?}");
+        command.output().expect("tectonic failed to start")
+    };
+
+    let output = run(&[
+        "-X",
+        "bundle",
+        "import",
+        "--url",
+        "https://example.com/bundle.tar",
+        "archive",
+    ]);
+    success_or_panic(&output);
+
+    let output = run(&["-X", "cache", "info"]);
+    success_or_panic(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("bundles:          1"));
+    assert!(stdout.contains(&"1".repeat(64)));
+
+    // The bundle was just used, so it's not old enough to be removed ...
+    let output = run(&["-X", "cache", "gc", "--max-age", "10000"]);
+    success_or_panic(&output);
+    let output = run(&["-X", "cache", "info"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("bundles:          1"));
+
+    // Absurdly long ages mean that nothing expires.
+    let output = run(&["-X", "cache", "gc", "--max-age", "18446744073709551615"]);
+    success_or_panic(&output);
+    let output = run(&["-X", "cache", "info"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("bundles:          1"));
+
+    // ... but it doesn't fit.
+    let output = run(&["-X", "cache", "gc", "--max-age", "10000", "--max-size", "0"]);
+    success_or_panic(&output);
+    let output = run(&["-X", "cache", "info"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("bundles:          0"));
+    assert!(stdout.contains("cached files:     0"));
+
+    let output = run(&["-X", "cache", "gc", "--max-size", "lots"]);
+    error_or_panic(&output);
+}
+
 #[cfg(feature = "serialization")]
 #[test]
 fn v2_build_basic() {
@@ -775,6 +913,40 @@ fn v2_build_basic() {
     success_or_panic(&output);
 }
 
+#[test]
+#[cfg(all(feature = "serialization", not(windows)))] // uses a POSIX shell
+fn v2_build_hooks() {
+    let (_tempdir, temppath) = setup_v2();
+
+    {
+        let mut toml_path = temppath.clone();
+        toml_path.push("Tectonic.toml");
+        let mut file = OpenOptions::new().append(true).open(toml_path).unwrap();
+        writeln!(
+            file,
+            "pre_build = ['echo pre >pre.txt']
+            post_build = ['test -f \"$TECTONIC_OUTPUT_PATH\" && echo $TECTONIC_OUTPUT_NAME >post.txt']"
+        )
+        .unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(temppath.join("pre.txt").exists());
+    assert_eq!(
+        fs::read_to_string(temppath.join("post.txt"))
+            .unwrap()
+            .trim(),
+        "default"
+    );
+
+    // Hooks are disabled for untrusted documents.
+    fs::remove_file(temppath.join("pre.txt")).unwrap();
+    let output = run_tectonic(&temppath, &["-X", "build", "--untrusted"]);
+    success_or_panic(&output);
+    assert!(!temppath.join("pre.txt").exists());
+}
+
 #[test]
 #[cfg(all(feature = "serialization", not(windows)))] // `echo` may not be available
 fn v2_build_open() {
@@ -864,6 +1036,50 @@ fn v2_build_multiple_outputs() {
     success_or_panic(&output);
 }
 
+/// Files in an overlay bundle listed in `Tectonic.toml` are found before those
+/// of the base bundle.
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_overlay_bundle() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let toml_path = temppath.join("Tectonic.toml");
+    let toml = fs::read_to_string(&toml_path)
+        .unwrap()
+        .lines()
+        .map(|line| match line.strip_prefix("bundle = ") {
+            Some(loc) => format!("bundle = [\"house\", {loc}]"),
+            None => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(&toml_path, toml).unwrap();
+
+    fs::create_dir(temppath.join("house")).unwrap();
+    fs::write(
+        temppath.join("house").join("housemacros.tex"),
+        "\\def\\housename{Acme}\n",
+    )
+    .unwrap();
+    fs::write(
+        temppath.join("src").join("_preamble.tex"),
+        "\\input housemacros\n",
+    )
+    .unwrap();
+    fs::write(temppath.join("src").join("index.tex"), "\\housename\n").unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+
+    // The bundle commands see the overlay, too.
+    let output = run_tectonic(&temppath, &["-X", "bundle", "cat", "housemacros.tex"]);
+    success_or_panic(&output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\\def\\housename{Acme}\n"
+    );
+}
+
 #[test]
 #[cfg(feature = "serialization")]
 fn v2_dump_basic() {
@@ -933,6 +1149,206 @@ fn v2_dump_suffix() {
     assert!(saw_first && saw_second);
 }
 
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_init_existing() {
+    util::set_test_root();
+    let tempdir = setup_and_copy_files(&[]);
+    let temppath = tempdir.path().to_owned();
+
+    fs::write(
+        temppath.join("paper.tex"),
+        "\\documentclass{article}\n\\usepackage{minted}\n\\begin{document}\nHi\n\\end{document}\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "init"]);
+    success_or_panic(&output);
+    assert!(!temppath.join("src").exists());
+
+    let toml = fs::read_to_string(temppath.join("Tectonic.toml")).unwrap();
+    assert!(toml.contains("source_dir = \".\""));
+    assert!(toml.contains("inputs = \"paper.tex\""));
+    assert!(toml.contains("shell_escape = true"));
+
+    let output = run_tectonic(&temppath, &["-X", "validate"]);
+    success_or_panic(&output);
+}
+
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_new_template() {
+    util::set_test_root();
+    let tempdir = setup_and_copy_files(&[]);
+    let temppath = tempdir.path().to_owned();
+
+    // Only plain TeX can be built in the test setup, so we make the document
+    // use it.
+    fn use_plain_tex(doc_dir: &Path) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(doc_dir.join("Tectonic.toml"))
+            .unwrap();
+        writeln!(file, "tex_format = 'plain'").unwrap();
+    }
+
+    // A built-in template, with a name that has to be escaped in TeX.
+    let output = run_tectonic(
+        &temppath,
+        &["-X", "new", "--template", "beamer", "my_thesis"],
+    );
+    success_or_panic(&output);
+    let doc_dir = temppath.join("my_thesis");
+    let preamble = fs::read_to_string(doc_dir.join("src/_preamble.tex")).unwrap();
+    assert!(preamble.contains("{beamer}"));
+
+    let title = preamble.lines().find(|l| l.starts_with(r"\title")).unwrap();
+    assert_eq!(title, r"\title{my\_thesis}");
+
+    // Make sure that the title typesets, by putting it into a plain TeX
+    // document.
+    use_plain_tex(&doc_dir);
+    fs::write(
+        doc_dir.join("src/_preamble.tex"),
+        format!("\\def\\title#1{{#1}}\n{title}\n"),
+    )
+    .unwrap();
+    fs::write(doc_dir.join("src/index.tex"), "\n").unwrap();
+    fs::write(doc_dir.join("src/_postamble.tex"), "\\end\n").unwrap();
+    let output = run_tectonic(&doc_dir, &["-X", "build"]);
+    success_or_panic(&output);
+
+    // A template directory without its own `Tectonic.toml`, whose input is
+    // worked out from its files.
+    let tmpl = temppath.join("mytemplate");
+    fs::create_dir_all(tmpl.join("src")).unwrap();
+    fs::write(tmpl.join("src/main.tex"), "@DOCUMENT_NAME@\n\\end\n").unwrap();
+    let output = run_tectonic(
+        &temppath,
+        &["-X", "new", "--template", "./mytemplate", "fromdir"],
+    );
+    success_or_panic(&output);
+    let doc_dir = temppath.join("fromdir");
+    assert_eq!(
+        fs::read_to_string(doc_dir.join("src/main.tex")).unwrap(),
+        "fromdir\n\\end\n"
+    );
+    use_plain_tex(&doc_dir);
+    let output = run_tectonic(&doc_dir, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(doc_dir.join("build/default/default.pdf").exists());
+
+    // A template directory whose inputs can't be worked out.
+    fs::write(tmpl.join("src/other.tex"), "").unwrap();
+    let output = run_tectonic(
+        &temppath,
+        &["-X", "new", "--template", "./mytemplate", "unclear"],
+    );
+    error_or_panic(&output);
+
+    // An unknown template.
+    let output = run_tectonic(&temppath, &["-X", "new", "--template", "nope", "bad"]);
+    error_or_panic(&output);
+}
+
+/// Ensures that the preview server serves the PDF viewer page and the PDF
+#[cfg(all(feature = "serialization", not(target_arch = "mips")))]
+#[test]
+fn v2_serve_pdf() {
+    use std::{io::BufRead, io::BufReader, net::TcpStream, sync::mpsc};
+
+    if KCOV_WORDS.len() > 0 || env::var("TECTONIC_KCOV_RUN").is_ok() {
+        return; // See run_tectonic_until() for an explanation of why this test must be skipped
+    }
+
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    let (_tempdir, temppath) = setup_v2();
+    let pdf = temppath.join("build/default/default.pdf");
+
+    // Let the server pick a free port, and find out which one from its output.
+    let mut command = prep_tectonic(&temppath, &["-X", "serve", "--port", "0"]);
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    println!("running {command:?}");
+    let mut child = command.spawn().expect("tectonic failed to start");
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let reader = thread::spawn(move || {
+        let mut text = String::new();
+
+        for line in stdout.lines() {
+            let line = line.unwrap();
+
+            if let Some(rest) = line.split("http://127.0.0.1:").nth(1) {
+                let port: u16 = rest.trim_end_matches('/').parse().unwrap();
+                let _ = tx.send(port);
+            }
+
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        text
+    });
+
+    let port = rx.recv_timeout(Duration::from_secs(60 * 5)).unwrap();
+
+    // Wait for the initial build to finish.
+    let start = Instant::now();
+    while !pdf.exists() && Instant::now() - start < Duration::from_secs(60 * 5) {
+        thread::sleep(Duration::from_secs(1));
+    }
+    thread::sleep(Duration::from_secs(2));
+
+    let viewer = get(port, "/");
+    let pdf = get(port, "/default.pdf");
+    let pdfjs = get(port, "/_tectonic/pdfjs/pdf.min.js");
+    let outside = get(port, "/../Tectonic.toml");
+
+    let _ = child.kill();
+    let output = child.wait_with_output().unwrap();
+    println!("-- stdout --\n{}\n-- end stdout --", reader.join().unwrap());
+    println!(
+        "-- stderr --\n{}\n-- end stderr --",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(viewer.starts_with("HTTP/1.1 200"));
+    assert!(viewer.contains("\"/default.pdf\""));
+    assert!(viewer.contains("/_tectonic/pdfjs/pdf.min.js"));
+    assert!(!viewer.contains("https://"));
+
+    assert!(pdf.starts_with("HTTP/1.1 200"));
+    assert!(pdf.contains("application/pdf"));
+
+    // PDF.js isn't installed in the test setup.
+    assert!(pdfjs.starts_with("HTTP/1.1 404"));
+
+    assert!(outside.starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_validate() {
+    let (_tempdir, temppath) = setup_v2();
+    let output = run_tectonic(&temppath, &["-X", "validate"]);
+    success_or_panic(&output);
+
+    fs::remove_file(temppath.join("src").join("index.tex")).unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "validate"]);
+    error_or_panic(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("index.tex"));
+}
+
 const SHELL_ESCAPE_TEST_DOC: &str = r"\immediate\write18{mkdir shellwork}
 \immediate\write18{echo 123 >shellwork/persist}
 \ifnum123=\input{shellwork/persist}
@@ -1053,6 +1469,110 @@ fn extra_search_paths() {
     error_or_panic(&output);
 }
 
+/// Ensures that the in-process watch mode doesn't rebuild because of changes
+/// that the build makes itself
+#[cfg(all(feature = "serialization", not(target_arch = "mips"), not(windows)))] // uses a POSIX shell
+#[test]
+fn v2_watch_ignores_build_changes() {
+    if KCOV_WORDS.len() > 0 || env::var("TECTONIC_KCOV_RUN").is_ok() {
+        return; // See run_tectonic_until() for an explanation of why this test must be skipped
+    }
+
+    let (_tempdir, temppath) = setup_v2();
+
+    // A hook that rewrites one of the inputs before every build.
+    {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temppath.join("Tectonic.toml"))
+            .unwrap();
+        writeln!(file, "pre_build = ['echo Generated >src/generated.tex']").unwrap();
+        fs::write(temppath.join("src/index.tex"), "\\input generated\n").unwrap();
+    }
+
+    let output = temppath.join("build/default/default.pdf");
+
+    let thread = thread::spawn(move || {
+        // Wait for the initial build, then long enough for several more.
+        let start = Instant::now();
+        while !output.exists() && Instant::now() - start < Duration::from_secs(60 * 5) {
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        thread::sleep(Duration::from_secs(10));
+    });
+
+    let output = run_tectonic_until(&temppath, &["-X", "watch"], || thread.is_finished());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("-- stdout --\n{}\n-- end stdout --", stdout);
+    println!("-- stderr --\n{}\n-- end stderr --", stderr);
+
+    thread.join().unwrap();
+
+    assert_eq!(stdout.matches("Running xdvipdfmx").count(), 1);
+}
+
+/// Ensures that the in-process watch mode only rebuilds when files that the
+/// build actually read are changed
+#[cfg(all(feature = "serialization", not(target_arch = "mips")))]
+#[test]
+fn v2_watch_ignores_unrelated_files() {
+    if KCOV_WORDS.len() > 0 || env::var("TECTONIC_KCOV_RUN").is_ok() {
+        return; // See run_tectonic_until() for an explanation of why this test must be skipped
+    }
+
+    let (_tempdir, temppath) = setup_v2();
+
+    // Timeout the test after 5 minutes - the rebuild should definitely happen in that range
+    let max_time = Duration::from_secs(60 * 5);
+    let path = temppath.clone();
+
+    let thread = thread::spawn(move || {
+        // Give the process time to start up and do its initial build.
+        thread::sleep(Duration::from_secs(10));
+
+        let output = path.join("build/default/default.pdf");
+        let initial_mod = output.metadata().and_then(|meta| meta.modified()).ok();
+
+        // This file is in a watched directory, but isn't read by the build.
+        {
+            let mut file = File::create(path.join("src/notes.txt")).unwrap();
+            writeln!(file, "Not an input").unwrap();
+        }
+
+        thread::sleep(Duration::from_secs(5));
+
+        {
+            let mut file = File::create(path.join("src/index.tex")).unwrap();
+            writeln!(file, "New Text").unwrap();
+        }
+
+        let start = Instant::now();
+        while Instant::now() - start < max_time {
+            let new_mod = output.metadata().and_then(|meta| meta.modified()).ok();
+            if new_mod.is_some() && new_mod != initial_mod {
+                break;
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        // Give the build time to finish printing its output.
+        thread::sleep(Duration::from_secs(2));
+    });
+
+    let output = run_tectonic_until(&temppath, &["-X", "watch"], || thread.is_finished());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("-- stdout --\n{}\n-- end stdout --", stdout);
+    println!("-- stderr --\n{}\n-- end stderr --", stderr);
+
+    thread.join().unwrap();
+
+    assert_eq!(stdout.matches("Running xdvipdfmx").count(), 2);
+}
+
 /// Ensures that watch command succeeds, and when a file is changed while running it rebuilds
 /// periodically
 #[cfg(all(feature = "serialization", not(target_arch = "mips")))]